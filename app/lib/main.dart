@@ -259,7 +259,8 @@ class _HmiAppState extends State<HmiApp> {
                 HistoryScreen(store: store),
                 AlarmHistoryScreen(
                     api: store.api,
-                    canManage: user?.isOperator ?? false),
+                    canManage: user?.isOperator ?? false,
+                    alarmEvents: store.ws.alarmEvents),
                 BatchRecordScreen(api: store.api),
                 DeviceListScreen(
                     api: store.api,
@@ -60,7 +60,8 @@ enum BatchState {
   heating(1, 'HEATING'),
   holding(2, 'HOLDING'),
   cooling(3, 'COOLING'),
-  complete(4, 'COMPLETE');
+  complete(4, 'COMPLETE'),
+  held(5, 'HELD');
 
   const BatchState(this.code, this.label);
   final int code;
@@ -1,4 +1,7 @@
+import 'dart:async';
+
 import 'package:flutter/material.dart';
+import 'package:flutter/services.dart';
 import 'package:google_fonts/google_fonts.dart';
 import 'package:intl/intl.dart';
 
@@ -11,10 +14,14 @@ class AlarmHistoryScreen extends StatefulWidget {
   final ApiService api;
   final bool canManage;
 
+  /// Live alarm events from the WebSocket — the list refreshes on each.
+  final Stream<Map<String, dynamic>>? alarmEvents;
+
   const AlarmHistoryScreen({
     super.key,
     required this.api,
     required this.canManage,
+    this.alarmEvents,
   });
 
   @override
@@ -28,16 +35,36 @@ class _AlarmHistoryScreenState extends State<AlarmHistoryScreen> {
   int? _priorityFilter;
 
   static const _stateFilters = <String?>[
-    null, 'active', 'acknowledged', 'shelved', 'cleared'
+    null, 'active', 'acknowledged', 'shelved', 'out_of_service', 'cleared'
   ];
   static const _priorityLabels = {
     1: 'Critical', 2: 'High', 3: 'Medium', 4: 'Low', 5: 'Info'
   };
 
+  StreamSubscription<Map<String, dynamic>>? _eventSub;
+
   @override
   void initState() {
     super.initState();
     _load();
+    _eventSub = widget.alarmEvents?.listen(_onAlarmEvent);
+  }
+
+  @override
+  void dispose() {
+    _eventSub?.cancel();
+    super.dispose();
+  }
+
+  void _onAlarmEvent(Map<String, dynamic> msg) {
+    if (msg['type'] != 'alarm') return;
+    // Annunciator for new critical/high alarms
+    final priority = (msg['alarm'] as Map<String, dynamic>?)?['priority'];
+    if ((msg['event'] == 'raise' || msg['event'] == 'escalate') &&
+        (priority == 'critical' || priority == 'high')) {
+      SystemSound.play(SystemSoundType.alert);
+    }
+    _load();
   }
 
   Future<void> _load() async {
@@ -187,7 +214,8 @@ class _AlarmHistoryScreenState extends State<AlarmHistoryScreen> {
                       items: _stateFilters.map((f) {
                         final label = f == null
                             ? 'All States'
-                            : f[0].toUpperCase() + f.substring(1);
+                            : f[0].toUpperCase() +
+                                f.substring(1).replaceAll('_', ' ');
                         return DropdownMenuItem(
                           value: f,
                           child: Text(label,
@@ -325,6 +353,8 @@ class _AlarmHistoryScreenState extends State<AlarmHistoryScreen> {
     final ackedAt = alarm['acked_at'] as String?;
     final shelvedBy = alarm['shelved_by'] as String?;
     final shelvedUntil = alarm['shelved_until'] as String?;
+    final shelveReason = alarm['shelve_reason'] as String?;
+    final firstOut = alarm['first_out'] == true;
     final value = alarm['value'];
     final threshold = alarm['threshold'];
 
@@ -359,6 +389,10 @@ class _AlarmHistoryScreenState extends State<AlarmHistoryScreen> {
             Row(
               children: [
                 _priorityBadge(priority),
+                if (firstOut) ...[
+                  const SizedBox(width: 10),
+                  _firstOutBadge(),
+                ],
                 const SizedBox(width: 16),
                 Expanded(
                   child: Text(
@@ -433,7 +467,8 @@ class _AlarmHistoryScreenState extends State<AlarmHistoryScreen> {
             if (shelvedBy != null) ...[
               const SizedBox(height: 12),
               Text(
-                'Shelved by $shelvedBy until ${_fmtTime(shelvedUntil)}',
+                'Shelved by $shelvedBy until ${_fmtTime(shelvedUntil)}'
+                '${shelveReason != null ? ' — $shelveReason' : ''}',
                 style: GoogleFonts.outfit(
                     fontSize: 18,
                     fontWeight: FontWeight.w500,
@@ -502,11 +537,31 @@ class _AlarmHistoryScreenState extends State<AlarmHistoryScreen> {
     );
   }
 
+  Widget _firstOutBadge() {
+    return Container(
+      padding: const EdgeInsets.symmetric(horizontal: 14, vertical: 6),
+      decoration: BoxDecoration(
+        color: Colors.purple.shade300.withValues(alpha: 0.15),
+        borderRadius: BorderRadius.circular(10),
+      ),
+      child: Text(
+        '1ST OUT',
+        style: GoogleFonts.dmMono(
+          fontSize: 17,
+          fontWeight: FontWeight.w700,
+          color: Colors.purple.shade300,
+          letterSpacing: 0.5,
+        ),
+      ),
+    );
+  }
+
   Widget _stateBadge(String state, ThemeConfig colors) {
     final stateColor = switch (state) {
       'active' => Colors.red.shade300,
       'acknowledged' => Colors.green.shade300,
       'shelved' => Colors.orange.shade300,
+      'out_of_service' => Colors.blueGrey.shade300,
       'cleared' => colors.textSecondary,
       _ => colors.textSecondary,
     };
@@ -517,7 +572,7 @@ class _AlarmHistoryScreenState extends State<AlarmHistoryScreen> {
         borderRadius: BorderRadius.circular(10),
       ),
       child: Text(
-        state.toUpperCase(),
+        state.replaceAll('_', ' ').toUpperCase(),
         style: GoogleFonts.dmMono(
             fontSize: 17,
             fontWeight: FontWeight.w700,
@@ -91,6 +91,12 @@ class _BatchRecordScreenState extends State<BatchRecordScreen> {
       statuses: ['completed', 'aborted'],
       color: Colors.green,
     ),
+    _BoardColumn(
+      title: 'REVIEW',
+      icon: Icons.fact_check_rounded,
+      statuses: ['under_review', 'approved', 'rejected'],
+      color: Colors.purple,
+    ),
   ];
 
   List<Map<String, dynamic>> _batchesForColumn(_BoardColumn col) {
@@ -395,6 +401,8 @@ class _BatchRecordScreenState extends State<BatchRecordScreen> {
                 Divider(color: colColor.withValues(alpha: 0.2), height: 1),
                 const SizedBox(height: 14),
                 _buildStepsTimeline(colColor, colors),
+                const SizedBox(height: 16),
+                _buildBatchAlarms(colors),
               ],
 
               // Expand indicator
@@ -528,6 +536,62 @@ class _BatchRecordScreenState extends State<BatchRecordScreen> {
     );
   }
 
+  // ---- Alarms raised during the batch ----
+
+  Widget _buildBatchAlarms(ThemeConfig colors) {
+    final alarms = (_expandedDetail?['alarms'] as List?) ?? [];
+
+    return Column(
+      crossAxisAlignment: CrossAxisAlignment.start,
+      children: [
+        Text('ALARMS (${alarms.length})',
+            style: GoogleFonts.outfit(
+                fontSize: 20,
+                fontWeight: FontWeight.w700,
+                color: Colors.white,
+                letterSpacing: 1)),
+        const SizedBox(height: 8),
+        if (alarms.isEmpty)
+          Text('No alarms during this batch',
+              style: GoogleFonts.outfit(fontSize: 17, color: Colors.white54)),
+        ...alarms.map((a) {
+          final alarm = a as Map<String, dynamic>;
+          final priority = alarm['priority']?.toString() ?? '';
+          final color = switch (priority) {
+            'critical' => Colors.red.shade300,
+            'high' => Colors.orange.shade300,
+            'medium' => Colors.amber.shade300,
+            _ => Colors.blue.shade200,
+          };
+          return Padding(
+            padding: const EdgeInsets.only(bottom: 6),
+            child: Row(
+              children: [
+                Icon(Icons.warning_amber_rounded, size: 18, color: color),
+                const SizedBox(width: 8),
+                Text(_fmtTime(alarm['timestamp'] as String? ?? ''),
+                    style: GoogleFonts.dmMono(
+                        fontSize: 15, color: Colors.white54)),
+                const SizedBox(width: 10),
+                Expanded(
+                  child: Text(alarm['message'] as String? ?? '',
+                      style: GoogleFonts.outfit(
+                          fontSize: 16, color: Colors.white70),
+                      overflow: TextOverflow.ellipsis),
+                ),
+                Text(priority.toUpperCase(),
+                    style: GoogleFonts.dmMono(
+                        fontSize: 14,
+                        fontWeight: FontWeight.w700,
+                        color: color)),
+              ],
+            ),
+          );
+        }),
+      ],
+    );
+  }
+
   // ---- Helpers ----
 
   Widget _infoRow(IconData icon, String text, ThemeConfig colors) {
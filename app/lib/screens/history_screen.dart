@@ -9,7 +9,6 @@ import 'package:intl/intl.dart';
 
 import '../config/dashboard_config.dart';
 import '../config/hmi_theme_engine.dart';
-import '../models/plc_data.dart';
 import '../stores/dashboard_store.dart';
 import '../theme/hmi_colors.dart';
 
@@ -68,19 +67,25 @@ class _Channel {
 
 class _TimeRange {
   final String label;
-  final int limit;       // raw rows to fetch
-  final int intervalSec; // averaging bucket size
+  final int rangeSec;    // how far back to look
+  final int intervalSec; // server-side aggregation bucket size
 
-  const _TimeRange(this.label, this.limit, this.intervalSec);
+  const _TimeRange(this.label, this.rangeSec, this.intervalSec);
 }
 
 const _timeRanges = <_TimeRange>[
-  _TimeRange('5 min  (30 s avg)', 2400, 30),
-  _TimeRange('10 min (1 min avg)', 4800, 60),
-  _TimeRange('30 min (2 min avg)', 14400, 120),
-  _TimeRange('1 hour (5 min avg)', 28800, 300),
+  _TimeRange('5 min  (30 s avg)', 300, 30),
+  _TimeRange('10 min (1 min avg)', 600, 60),
+  _TimeRange('30 min (2 min avg)', 1800, 120),
+  _TimeRange('1 hour (5 min avg)', 3600, 300),
+  _TimeRange('24 hours (5 min avg)', 86400, 300),
+  _TimeRange('7 days (30 min avg)', 604800, 1800),
+  _TimeRange('30 days (2 h avg)', 2592000, 7200),
 ];
 
+// Registers charted on this screen (temperature … pH)
+const _registers = [1028, 1029, 1030, 1031, 1032, 1033, 1034, 1035];
+
 // ── Screen ─────────────────────────────────────────────────────────────────
 
 class HistoryScreen extends StatefulWidget {
@@ -96,7 +101,7 @@ class _HistoryScreenState extends State<HistoryScreen> {
   List<_Snapshot> _snapshots = [];
   bool _loading = true;
   String? _error;
-  int _limit = 4800; // ~10 min of data (8 registers × 60s × 10min)
+  int _rangeSec = 600; // how far back to chart
   int _intervalSec = 60; // averaging bucket size in seconds
   Timer? _refreshTimer;
   late String _selectedDeviceId;
@@ -124,13 +129,22 @@ class _HistoryScreenState extends State<HistoryScreen> {
       });
     }
     try {
-      final data = await widget.store.fetchHistory(
-        deviceId: _selectedDeviceId,
-        limit: _limit,
-      );
+      // One aggregate query per register: the server buckets the range,
+      // so a 30-day view costs the same as a 5-minute one.
+      final to = DateTime.now().toUtc();
+      final from = to.subtract(Duration(seconds: _rangeSec));
+      final results = await Future.wait(_registers.map(
+        (register) => widget.store.fetchHistoryAggregate(
+          deviceId: _selectedDeviceId,
+          register: register,
+          from: from,
+          to: to,
+          bucketSecs: _intervalSec,
+        ),
+      ));
       if (mounted) {
         setState(() {
-          _snapshots = _groupByTimestamp(data);
+          _snapshots = _mergeBuckets(results);
           _loading = false;
         });
       }
@@ -144,74 +158,40 @@ class _HistoryScreenState extends State<HistoryScreen> {
     }
   }
 
-  List<_Snapshot> _groupByTimestamp(List<PlcData> raw) {
-    // Step 1: Group raw rows into per-second snapshots
-    final Map<String, _Snapshot> secMap = {};
-    for (final d in raw) {
-      final dt = DateTime.tryParse(d.timestamp);
-      if (dt == null) continue;
-      final key = DateFormat('yyyy-MM-dd HH:mm:ss').format(dt.toLocal());
-      final snap = secMap.putIfAbsent(key, () => _Snapshot(dt));
-      switch (d.register) {
-        case 1028:
-          snap.temp = d.value;
-        case 1029:
-          snap.pressure = d.value;
-        case 1030:
-          snap.humidity = d.value;
-        case 1031:
-          snap.flow = d.value;
-        case 1032:
-          snap.batchState = d.value.toInt();
-        case 1033:
-          snap.progress = d.value;
-        case 1034:
-          snap.agitator = d.value;
-        case 1035:
-          snap.pH = d.value / 10.0;
-      }
-    }
-
-    // Step 2: Downsample into N-second buckets by averaging
-    final secList = secMap.values.toList()
-      ..sort((a, b) => a.time.compareTo(b.time));
-
-    if (secList.isEmpty || _intervalSec <= 1) return secList;
-
-    final Map<int, List<_Snapshot>> buckets = {};
-    final epoch0 = secList.first.time.millisecondsSinceEpoch;
-    for (final s in secList) {
-      final offset = s.time.millisecondsSinceEpoch - epoch0;
-      final bucket = offset ~/ (_intervalSec * 1000);
-      buckets.putIfAbsent(bucket, () => []).add(s);
-    }
-
-    final averaged = <_Snapshot>[];
-    for (final entry in buckets.entries) {
-      final group = entry.value;
-      final n = group.length;
-      // Use the midpoint time of the bucket
-      final midTime = group[n ~/ 2].time;
-      final avg = _Snapshot(midTime);
-      avg.temp = group.fold(0.0, (s, e) => s + e.temp) / n;
-      avg.pressure = group.fold(0.0, (s, e) => s + e.pressure) / n;
-      avg.humidity = group.fold(0.0, (s, e) => s + e.humidity) / n;
-      avg.flow = group.fold(0.0, (s, e) => s + e.flow) / n;
-      avg.agitator = group.fold(0.0, (s, e) => s + e.agitator) / n;
-      avg.pH = group.fold(0.0, (s, e) => s + e.pH) / n;
-      avg.progress = group.last.progress;
-      // batch state = most frequent in the bucket
-      final stateFreq = <int, int>{};
-      for (final g in group) {
-        stateFreq[g.batchState] = (stateFreq[g.batchState] ?? 0) + 1;
+  /// Merge the per-register bucket lists (in [_registers] order) into one
+  /// snapshot per bucket. Analog channels chart the bucket average; batch
+  /// state and progress take the last value in the bucket.
+  List<_Snapshot> _mergeBuckets(List<List<Map<String, dynamic>>> results) {
+    final Map<String, _Snapshot> byBucket = {};
+    for (var i = 0; i < _registers.length; i++) {
+      for (final b in results[i]) {
+        final key = b['bucket_start'] as String;
+        final dt = DateTime.tryParse(key);
+        if (dt == null) continue;
+        final snap = byBucket.putIfAbsent(key, () => _Snapshot(dt));
+        final avg = (b['avg'] as num).toDouble();
+        final last = (b['last'] as num).toDouble();
+        switch (_registers[i]) {
+          case 1028:
+            snap.temp = avg;
+          case 1029:
+            snap.pressure = avg;
+          case 1030:
+            snap.humidity = avg;
+          case 1031:
+            snap.flow = avg;
+          case 1032:
+            snap.batchState = last.toInt();
+          case 1033:
+            snap.progress = last;
+          case 1034:
+            snap.agitator = avg;
+          case 1035:
+            snap.pH = avg / 10.0;
+        }
       }
-      avg.batchState = stateFreq.entries
-          .reduce((a, b) => a.value >= b.value ? a : b)
-          .key;
-      averaged.add(avg);
     }
-    averaged.sort((a, b) => a.time.compareTo(b.time));
-    return averaged;
+    return byBucket.values.toList()..sort((a, b) => a.time.compareTo(b.time));
   }
 
   // ── Channel definitions ────────────────────────────────────────
@@ -381,7 +361,7 @@ class _HistoryScreenState extends State<HistoryScreen> {
                   size: 24, color: colors.textSecondary),
               color: colors.surface,
               onSelected: (val) {
-                _limit = val.limit;
+                _rangeSec = val.rangeSec;
                 _intervalSec = val.intervalSec;
                 _fetchHistory();
               },
@@ -393,11 +373,11 @@ class _HistoryScreenState extends State<HistoryScreen> {
                       mainAxisSize: MainAxisSize.min,
                       children: [
                         Icon(
-                          _limit == r.limit
+                          _rangeSec == r.rangeSec
                               ? Icons.radio_button_checked
                               : Icons.radio_button_unchecked,
                           size: 18,
-                          color: _limit == r.limit
+                          color: _rangeSec == r.rangeSec
                               ? colors.accent
                               : colors.textMuted,
                         ),
@@ -406,10 +386,10 @@ class _HistoryScreenState extends State<HistoryScreen> {
                           r.label,
                           style: GoogleFonts.outfit(
                             fontSize: 16,
-                            color: _limit == r.limit
+                            color: _rangeSec == r.rangeSec
                                 ? colors.accent
                                 : colors.textPrimary,
-                            fontWeight: _limit == r.limit
+                            fontWeight: _rangeSec == r.rangeSec
                                 ? FontWeight.w600
                                 : FontWeight.w400,
                           ),
@@ -654,8 +634,8 @@ class _HistoryScreenState extends State<HistoryScreen> {
                           return const SizedBox.shrink();
                         }
                         return Text(
-                          DateFormat('HH:mm').format(
-                              _snapshots[idx].time.toLocal()),
+                          DateFormat(_rangeSec > 86400 ? 'MM/dd' : 'HH:mm')
+                              .format(_snapshots[idx].time.toLocal()),
                           style: GoogleFonts.dmMono(
                               fontSize: 18,
                               color: Colors.white60),
@@ -681,7 +661,10 @@ class _HistoryScreenState extends State<HistoryScreen> {
                         touchedSpots.map((s) {
                       final idx = s.x.toInt();
                       final time = idx >= 0 && idx < _snapshots.length
-                          ? DateFormat('HH:mm:ss').format(
+                          ? DateFormat(_rangeSec > 86400
+                                  ? 'MM/dd HH:mm'
+                                  : 'HH:mm:ss')
+                              .format(
                               _snapshots[idx].time.toLocal())
                           : '';
                       return LineTooltipItem(
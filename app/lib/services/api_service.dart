@@ -66,6 +66,36 @@ class ApiService {
     throw Exception('Failed to fetch history: ${response.statusCode}');
   }
 
+  /// GET /api/history/aggregate — min/max/avg/first/last/count of one
+  /// register per time bucket, for plotting long ranges.
+  Future<List<Map<String, dynamic>>> getHistoryAggregate({
+    required String deviceId,
+    required int register,
+    required DateTime from,
+    required DateTime to,
+    required int bucketSecs,
+  }) async {
+    final uri = Uri.parse('$baseUrl/api/history/aggregate').replace(
+      queryParameters: {
+        'device_id': deviceId,
+        'register': register.toString(),
+        'from': from.toUtc().toIso8601String(),
+        'to': to.toUtc().toIso8601String(),
+        'bucket_secs': bucketSecs.toString(),
+      },
+    );
+    final response = await http.get(uri, headers: _headers);
+
+    if (response.statusCode == 200) {
+      final body = jsonDecode(response.body) as Map<String, dynamic>;
+      if (body['success'] == true && body['data'] != null) {
+        return (body['data'] as List).cast<Map<String, dynamic>>();
+      }
+      throw Exception('Failed to fetch history: ${body['error']}');
+    }
+    throw Exception('Failed to fetch history: ${response.statusCode}');
+  }
+
   /// GET /health — check server health.
   Future<bool> checkHealth() async {
     try {
@@ -329,6 +359,165 @@ class ApiService {
     }
   }
 
+  /// POST /api/batches — start a batch, optionally from an approved recipe.
+  Future<Map<String, dynamic>?> startBatch({
+    required String deviceId,
+    String? recipeName,
+  }) async {
+    return _postBatch('$baseUrl/api/batches', {
+      'device_id': deviceId,
+      if (recipeName != null) 'recipe_name': recipeName,
+    });
+  }
+
+  /// POST /api/batches/:id/{hold,resume,complete} — operator batch command.
+  Future<Map<String, dynamic>?> controlBatch(
+    String batchId,
+    String command, {
+    String? reason,
+  }) async {
+    return _postBatch('$baseUrl/api/batches/$batchId/$command', {
+      if (reason != null) 'reason': reason,
+    });
+  }
+
+  /// POST /api/batches/:id/abort — requires a token from POST /api/auth/esig.
+  Future<Map<String, dynamic>?> abortBatch(String batchId, String esigToken) async {
+    return _postBatch('$baseUrl/api/batches/$batchId/abort', {'esig_token': esigToken});
+  }
+
+  /// POST /api/batches/:id/review — send a completed batch to review.
+  /// Returns the exceptions to sign off, or null on failure.
+  Future<List<dynamic>?> startBatchReview(String batchId) async {
+    try {
+      final response = await http
+          .post(Uri.parse('$baseUrl/api/batches/$batchId/review'), headers: _headers)
+          .timeout(const Duration(seconds: 15));
+
+      if (response.statusCode == 200) {
+        final body = jsonDecode(response.body) as Map<String, dynamic>;
+        if (body['success'] == true) {
+          return body['data'] as List<dynamic>;
+        }
+      }
+      return null;
+    } catch (_) {
+      return null;
+    }
+  }
+
+  /// POST /api/batches/:id/exceptions/:exceptionId/sign-off — signed.
+  Future<Map<String, dynamic>?> signOffBatchException(
+    String batchId,
+    int exceptionId,
+    String esigToken, {
+    String? comment,
+  }) async {
+    return _postBatch('$baseUrl/api/batches/$batchId/exceptions/$exceptionId/sign-off', {
+      'esig_token': esigToken,
+      if (comment != null) 'comment': comment,
+    });
+  }
+
+  /// POST /api/batches/:id/{approve,reject} — admin release decision, signed.
+  Future<Map<String, dynamic>?> decideBatch(
+    String batchId,
+    bool approve,
+    String esigToken, {
+    String? comment,
+  }) async {
+    return _postBatch('$baseUrl/api/batches/$batchId/${approve ? 'approve' : 'reject'}', {
+      'esig_token': esigToken,
+      if (comment != null) 'comment': comment,
+    });
+  }
+
+  Future<Map<String, dynamic>?> _postBatch(String url, Map<String, dynamic> body) async {
+    try {
+      final response = await http
+          .post(Uri.parse(url), headers: _headers, body: jsonEncode(body))
+          .timeout(const Duration(seconds: 15));
+
+      if (response.statusCode == 200) {
+        final body = jsonDecode(response.body) as Map<String, dynamic>;
+        if (body['success'] == true && body['data'] != null) {
+          return body['data'] as Map<String, dynamic>;
+        }
+      }
+      return null;
+    } catch (_) {
+      return null;
+    }
+  }
+
+  /// POST /api/batches/:id/materials — record a consumed or produced lot.
+  Future<Map<String, dynamic>?> recordBatchMaterial(
+    String batchId, {
+    required String direction,
+    required String material,
+    required String lotNumber,
+    required double quantity,
+    required String unit,
+    String? expiry,
+  }) async {
+    return _postBatch('$baseUrl/api/batches/$batchId/materials', {
+      'direction': direction,
+      'material': material,
+      'lot_number': lotNumber,
+      'quantity': quantity,
+      'unit': unit,
+      if (expiry != null) 'expiry': expiry,
+    });
+  }
+
+  /// GET /api/lots/:id/genealogy — trace a lot forward (recall) or backward.
+  Future<Map<String, dynamic>?> getLotGenealogy(int lotId, {bool forward = true}) async {
+    try {
+      final uri = Uri.parse('$baseUrl/api/lots/$lotId/genealogy')
+          .replace(queryParameters: {'direction': forward ? 'forward' : 'backward'});
+      final response = await http.get(uri, headers: _headers).timeout(const Duration(seconds: 15));
+
+      if (response.statusCode == 200) {
+        final body = jsonDecode(response.body) as Map<String, dynamic>;
+        if (body['success'] == true && body['data'] != null) {
+          return body['data'] as Map<String, dynamic>;
+        }
+      }
+      return null;
+    } catch (_) {
+      return null;
+    }
+  }
+
+  /// GET /api/batches/compare — batch trends aligned on start time, the
+  /// golden envelope of their recipe and where each batch leaves it.
+  Future<Map<String, dynamic>?> compareBatches(
+    List<String> batchIds, {
+    List<int>? registers,
+    int? stepSecs,
+    double? k,
+  }) async {
+    try {
+      final params = <String, String>{'batch_ids': batchIds.join(',')};
+      if (registers != null) params['registers'] = registers.join(',');
+      if (stepSecs != null) params['step_secs'] = stepSecs.toString();
+      if (k != null) params['k'] = k.toString();
+
+      final uri = Uri.parse('$baseUrl/api/batches/compare').replace(queryParameters: params);
+      final response = await http.get(uri, headers: _headers).timeout(const Duration(seconds: 15));
+
+      if (response.statusCode == 200) {
+        final body = jsonDecode(response.body) as Map<String, dynamic>;
+        if (body['success'] == true && body['data'] != null) {
+          return body['data'] as Map<String, dynamic>;
+        }
+      }
+      return null;
+    } catch (_) {
+      return null;
+    }
+  }
+
   /// GET /api/batches/:id — get batch record with steps.
   Future<Map<String, dynamic>?> getBatch(String batchId) async {
     try {
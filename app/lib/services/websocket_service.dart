@@ -5,8 +5,8 @@ import 'package:web_socket_channel/web_socket_channel.dart';
 
 import '../models/plc_data.dart';
 
-/// WebSocket service that connects to the Rust server,
-/// parses PlcData JSON, and auto-reconnects on disconnect.
+/// WebSocket service that connects to the Rust server, routes typed
+/// messages (`plc_data`, `alarm`, `alarm_flood`) and auto-reconnects.
 class WebSocketService {
   final String url;
   final Duration reconnectDelay;
@@ -22,9 +22,16 @@ class WebSocketService {
 
   final _controller = StreamController<PlcData>.broadcast();
 
+  final _alarmController = StreamController<Map<String, dynamic>>.broadcast();
+
   /// Stream of parsed PlcData from the server.
   Stream<PlcData> get stream => _controller.stream;
 
+  /// Alarm events (`type` = `alarm` or `alarm_flood`) as raw JSON.
+  /// `alarm` messages carry `event` (raise, ack, shelve, clear, ...)
+  /// and the current `alarm` record.
+  Stream<Map<String, dynamic>> get alarmEvents => _alarmController.stream;
+
   /// Whether currently connected.
   bool get isConnected => _channel != null;
 
@@ -89,8 +96,12 @@ class WebSocketService {
               return;
             }
 
-            final data = PlcData.fromJson(json);
-            _controller.add(data);
+            switch (json['type']) {
+              case 'plc_data':
+                _controller.add(PlcData.fromJson(json));
+              case 'alarm' || 'alarm_flood':
+                _alarmController.add(json);
+            }
           } catch (_) {
             // Malformed message — skip.
           }
@@ -131,5 +142,6 @@ class WebSocketService {
     _subscription?.cancel();
     _channel?.sink.close();
     _controller.close();
+    _alarmController.close();
   }
 }
@@ -443,6 +443,23 @@ abstract class _DashboardStore with Store {
     return _api.getHistory(deviceId: deviceId, limit: limit);
   }
 
+  @action
+  Future<List<Map<String, dynamic>>> fetchHistoryAggregate({
+    required String deviceId,
+    required int register,
+    required DateTime from,
+    required DateTime to,
+    required int bucketSecs,
+  }) async {
+    return _api.getHistoryAggregate(
+      deviceId: deviceId,
+      register: register,
+      from: from,
+      to: to,
+      bucketSecs: bucketSecs,
+    );
+  }
+
   void dispose() {
     _devicePollTimer?.cancel();
     _wsSub?.cancel();
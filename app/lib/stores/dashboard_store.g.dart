@@ -429,6 +429,30 @@ mixin _$DashboardStore on _DashboardStore, Store {
     );
   }
 
+  late final _$fetchHistoryAggregateAsyncAction = AsyncAction(
+    '_DashboardStore.fetchHistoryAggregate',
+    context: context,
+  );
+
+  @override
+  Future<List<Map<String, dynamic>>> fetchHistoryAggregate({
+    required String deviceId,
+    required int register,
+    required DateTime from,
+    required DateTime to,
+    required int bucketSecs,
+  }) {
+    return _$fetchHistoryAggregateAsyncAction.run(
+      () => super.fetchHistoryAggregate(
+        deviceId: deviceId,
+        register: register,
+        from: from,
+        to: to,
+        bucketSecs: bucketSecs,
+      ),
+    );
+  }
+
   late final _$_DashboardStoreActionController = ActionController(
     name: '_DashboardStore',
     context: context,
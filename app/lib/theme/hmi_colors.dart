@@ -37,6 +37,7 @@ abstract class HmiColors {
       'HOLDING' => accent,
       'COOLING' => info,
       'COMPLETE' => healthy,
+      'HELD' => warning,
       _ => textMuted,
     };
   }
@@ -27,6 +27,8 @@ class BatchStateWidget extends StatelessWidget {
         return HmiColors.info;
       case BatchState.complete:
         return HmiColors.healthy;
+      case BatchState.held:
+        return HmiColors.warning;
     }
   }
 
@@ -0,0 +1,151 @@
+//! ISA-18.2 Alarm Performance KPIs
+//!
+//! Computes the standard alarm management metrics (ISA-18.2 §16 /
+//! EEMUA 191) over a time window of the `alarms` table — the numbers the
+//! alarm rationalization committee reviews.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::models::{Alarm, AlarmFrequency, AlarmKpis, AlarmPriority, PriorityShare};
+
+/// A tag raised this many times within `CHATTER_WINDOW_SECS` is chattering.
+pub const CHATTER_RAISES: usize = 3;
+pub const CHATTER_WINDOW_SECS: i64 = 60;
+
+/// A standing alarm older than this is stale.
+pub const STALE_AFTER_HOURS: i64 = 24;
+
+const TEN_MINUTES_SECS: i64 = 600;
+
+/// An alarm tag: device, register and alarm kind.
+type TagKey<'a> = (&'a str, u16, &'a str);
+
+fn raised_at(alarm: &Alarm) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(&alarm.timestamp).ok().map(|t| t.with_timezone(&Utc))
+}
+
+fn round2(x: f64) -> f64 {
+    (x * 100.0).round() / 100.0
+}
+
+fn pct(part: usize, total: usize) -> f64 {
+    if total == 0 { 0.0 } else { round2(part as f64 * 100.0 / total as f64) }
+}
+
+fn pct_of_intervals(part: usize, intervals: i64) -> f64 {
+    if intervals <= 0 { 0.0 } else { round2(part as f64 * 100.0 / intervals as f64) }
+}
+
+/// Compute the KPIs for the alarms raised in `[from, to)`.
+///
+/// `standing` are the currently active/acknowledged alarms (any age), used
+/// for the stale count as of `now`. `operators` is the number of operator
+/// positions sharing the alarm load; more than `flood_threshold` alarms in
+/// a 10-minute interval is a flood (`[alarm_flood] threshold`).
+pub fn compute_kpis(
+    raised: &[Alarm],
+    standing: &[Alarm],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    now: DateTime<Utc>,
+    operators: u32,
+    flood_threshold: usize,
+) -> AlarmKpis {
+    let window_secs = (to - from).num_seconds().max(1);
+    let times: Vec<DateTime<Utc>> = raised
+        .iter()
+        .filter_map(raised_at)
+        .filter(|t| *t >= from && *t < to)
+        .collect();
+    let total = times.len();
+
+    // Average load per operator position per 10 minutes
+    let ten_min_periods = window_secs as f64 / TEN_MINUTES_SECS as f64;
+    let avg = total as f64 / ten_min_periods / operators.max(1) as f64;
+
+    // Time in flood: share of 10-minute intervals above the flood threshold.
+    // Only intervals with alarms are counted, so the window can be any size.
+    let intervals = (window_secs + TEN_MINUTES_SECS - 1) / TEN_MINUTES_SECS;
+    let mut per_interval: HashMap<i64, usize> = HashMap::new();
+    for t in &times {
+        *per_interval.entry((*t - from).num_seconds() / TEN_MINUTES_SECS).or_default() += 1;
+    }
+    let flood_intervals = per_interval.values().filter(|&&n| n > flood_threshold).count();
+
+    // Group by tag (device + register + kind) for frequency and chattering
+    let mut tags: HashMap<TagKey, (&Alarm, Vec<DateTime<Utc>>)> = HashMap::new();
+    for alarm in raised {
+        let Some(t) = raised_at(alarm).filter(|t| *t >= from && *t < to) else { continue };
+        tags.entry((alarm.device_id.as_str(), alarm.register, alarm.kind.as_str()))
+            .or_insert_with(|| (alarm, Vec::new()))
+            .1
+            .push(t);
+    }
+
+    let chattering_count = tags
+        .values()
+        .filter(|(_, ts)| {
+            let mut ts = ts.clone();
+            ts.sort();
+            ts.windows(CHATTER_RAISES)
+                .any(|w| (w[CHATTER_RAISES - 1] - w[0]).num_seconds() <= CHATTER_WINDOW_SECS)
+        })
+        .count();
+
+    let mut top: Vec<AlarmFrequency> = tags
+        .values()
+        .map(|(a, ts)| AlarmFrequency {
+            device_id: a.device_id.clone(),
+            register: a.register,
+            label: a.label.clone(),
+            kind: a.kind,
+            count: ts.len(),
+            pct: pct(ts.len(), total),
+        })
+        .collect();
+    top.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.device_id.cmp(&b.device_id))
+            .then_with(|| a.register.cmp(&b.register))
+    });
+    top.truncate(10);
+
+    let priority_distribution = [
+        AlarmPriority::Critical,
+        AlarmPriority::High,
+        AlarmPriority::Medium,
+        AlarmPriority::Low,
+        AlarmPriority::Info,
+    ]
+    .into_iter()
+    .map(|priority| {
+        let count = raised
+            .iter()
+            .filter(|a| a.priority == priority && raised_at(a).is_some_and(|t| t >= from && t < to))
+            .count();
+        PriorityShare { priority, count, pct: pct(count, total) }
+    })
+    .collect();
+
+    let stale_before = now - chrono::Duration::hours(STALE_AFTER_HOURS);
+    let stale_count = standing
+        .iter()
+        .filter(|a| raised_at(a).is_some_and(|t| t < stale_before))
+        .count();
+
+    AlarmKpis {
+        from: from.to_rfc3339(),
+        to: to.to_rfc3339(),
+        total_alarms: total,
+        operators: operators.max(1),
+        avg_alarms_per_operator_per_10min: round2(avg),
+        flood_time_pct: pct_of_intervals(flood_intervals, intervals),
+        top_alarms: top,
+        chattering_count,
+        stale_count,
+        priority_distribution,
+    }
+}
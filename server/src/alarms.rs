@@ -0,0 +1,657 @@
+//! ISA-18.2 Alarm Engine
+//!
+//! Evaluates the configurable alarm definitions (`alarm_definitions` table)
+//! against every poll scan and drives the raise/clear lifecycle in `db`.
+//! Each definition can carry absolute limits, a rate-of-change limit and a
+//! deviation-from-setpoint limit; each kind raises its own alarm.
+//! Alarms can be suppressed by an expression on other registers
+//! (`suppress_when`), and all of a device's alarms while it is disconnected.
+//! One `AlarmEngine` lives inside each device's polling task.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::auth;
+use crate::config::{AlarmDefinition, AlarmFloodConfig, AlarmLimit};
+use crate::db;
+use crate::models::{Alarm, AlarmKind, AlarmPriority, AlarmState, RaiseAlarmRequest, WsMessage};
+use crate::ws;
+
+/// Window over which the rate of change is measured.
+const RATE_WINDOW_SECS: i64 = 60;
+
+/// Window over which alarms are counted for flood detection.
+const FLOOD_WINDOW_MINS: i64 = 10;
+
+/// Hysteresis on rate-of-change alarms — the rate must fall below this
+/// fraction of the limit before the alarm clears.
+const RATE_CLEAR_RATIO: f64 = 0.95;
+
+/// The four analog limits of an alarm definition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitLevel {
+    HighHigh,
+    High,
+    Low,
+    LowLow,
+}
+
+impl LimitLevel {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::HighHigh => "HH",
+            Self::High => "H",
+            Self::Low => "L",
+            Self::LowLow => "LL",
+        }
+    }
+
+    /// Priority used when the definition does not set one.
+    pub fn default_priority(&self) -> AlarmPriority {
+        match self {
+            Self::HighHigh | Self::LowLow => AlarmPriority::Critical,
+            Self::High | Self::Low => AlarmPriority::High,
+        }
+    }
+
+    /// Message template used when the definition does not set one.
+    pub fn default_message(&self) -> &str {
+        match self {
+            Self::HighHigh => "CRITICAL: {label} {value} exceeds {limit}",
+            Self::High => "WARNING: {label} {value} approaching limit ({limit})",
+            Self::Low => "WARNING: {label} {value} approaching low limit ({limit})",
+            Self::LowLow => "CRITICAL: {label} {value} below {limit}",
+        }
+    }
+
+    /// True for the high-side limits (H, HH).
+    pub fn is_high(&self) -> bool {
+        matches!(self, Self::HighHigh | Self::High)
+    }
+
+    /// True if `value` is on the alarm side of `limit` for this level.
+    pub fn is_breached(&self, value: f64, limit: f64) -> bool {
+        match self {
+            Self::HighHigh | Self::High => value >= limit,
+            Self::Low | Self::LowLow => value <= limit,
+        }
+    }
+}
+
+impl AlarmDefinition {
+    /// Get the limit configured for `level`, if any.
+    pub fn limit(&self, level: LimitLevel) -> Option<&AlarmLimit> {
+        match level {
+            LimitLevel::HighHigh => self.hh.as_ref(),
+            LimitLevel::High => self.h.as_ref(),
+            LimitLevel::Low => self.l.as_ref(),
+            LimitLevel::LowLow => self.ll.as_ref(),
+        }
+    }
+
+    /// Deadband applied to `limit` — explicit, or 5% of the limit.
+    pub fn deadband_for(&self, limit: f64) -> f64 {
+        self.deadband.unwrap_or(limit.abs() * 0.05)
+    }
+
+    /// Deadband applied to the deviation limit — its own, not `deadband`,
+    /// which is sized for the absolute limits.
+    pub fn deviation_deadband_for(&self, limit: f64) -> f64 {
+        self.deviation_deadband.unwrap_or(limit * 0.05)
+    }
+
+    /// Check that the configured limits are ordered LL < L < H < HH.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.label.trim().is_empty() {
+            return Err("Alarm definition label must not be empty".into());
+        }
+        let ordered = [LimitLevel::LowLow, LimitLevel::Low, LimitLevel::High, LimitLevel::HighHigh];
+        let mut prev: Option<(LimitLevel, f64)> = None;
+        for level in ordered {
+            let Some(lim) = self.limit(level) else { continue };
+            if !lim.limit.is_finite() {
+                return Err(format!("{} limit must be a finite number", level.as_str()));
+            }
+            if let Some((prev_level, prev_limit)) = prev
+                && lim.limit <= prev_limit {
+                return Err(format!(
+                    "{} limit ({}) must be above {} limit ({})",
+                    level.as_str(), lim.limit, prev_level.as_str(), prev_limit
+                ));
+            }
+            prev = Some((level, lim.limit));
+        }
+        if let Some(db) = self.deadband
+            && !(db.is_finite() && db >= 0.0) {
+            return Err("Deadband must be a non-negative number".into());
+        }
+        if let Some(roc) = &self.rate_of_change
+            && !(roc.limit.is_finite() && roc.limit > 0.0) {
+            return Err("Rate-of-change limit must be a positive number".into());
+        }
+        if let Some(dev) = &self.deviation {
+            if !(dev.limit.is_finite() && dev.limit > 0.0) {
+                return Err("Deviation limit must be a positive number".into());
+            }
+            if let Some(db) = self.deviation_deadband
+                && !(db.is_finite() && db >= 0.0 && db < dev.limit) {
+                return Err(format!("Deviation deadband must be at least 0 and below the deviation limit ({})", dev.limit));
+            }
+            match self.setpoint_register {
+                None => return Err("Deviation alarm requires a setpoint_register".into()),
+                Some(sp) if sp == self.register => {
+                    return Err("setpoint_register must differ from the alarm register".into());
+                }
+                Some(_) => {}
+            }
+        }
+        let limits = [&self.hh, &self.h, &self.l, &self.ll, &self.rate_of_change, &self.deviation];
+        let expressions = limits.into_iter().flatten().filter_map(|l| l.suppress_when.as_ref());
+        for expr in self.suppress_when.iter().chain(expressions) {
+            eval_condition(expr, &HashMap::new()).map_err(|e| format!("Invalid suppress_when '{expr}': {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// True if any of the HH/H/L/LL limits is configured.
+    pub fn has_limits(&self) -> bool {
+        self.hh.is_some() || self.h.is_some() || self.l.is_some() || self.ll.is_some()
+    }
+}
+
+/// Evaluate a suppression expression against the current scan.
+///
+/// Comparisons of a register on the same device with a number, joined by
+/// `&&` and `||` (`&&` binds tighter), e.g. `1032 == 0 || 1032 == 5`.
+/// Operators: `==` `!=` `<` `<=` `>` `>=`. A register missing from the
+/// scan makes its comparison false.
+pub fn eval_condition(expr: &str, reg_map: &HashMap<u16, f64>) -> Result<bool, String> {
+    let mut any = false;
+    for clause in expr.split("||") {
+        let mut all = true;
+        for cmp in clause.split("&&") {
+            // Evaluate every term so a malformed one is always reported
+            all &= eval_comparison(cmp.trim(), reg_map)?;
+        }
+        any |= all;
+    }
+    Ok(any)
+}
+
+fn eval_comparison(cmp: &str, reg_map: &HashMap<u16, f64>) -> Result<bool, String> {
+    const OPS: [&str; 6] = ["==", "!=", "<=", ">=", "<", ">"];
+    let (pos, op) = OPS
+        .iter()
+        .filter_map(|op| cmp.find(op).map(|pos| (pos, *op)))
+        .min_by_key(|&(pos, op)| (pos, std::cmp::Reverse(op.len())))
+        .ok_or_else(|| format!("'{cmp}' is not a comparison (==, !=, <, <=, >, >=)"))?;
+
+    let register: u16 = cmp[..pos]
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{}' is not a register address", cmp[..pos].trim()))?;
+    let rhs = cmp[pos + op.len()..].trim();
+    let value: f64 = rhs.parse().map_err(|_| format!("'{rhs}' is not a number"))?;
+
+    let Some(&actual) = reg_map.get(&register) else { return Ok(false) };
+    Ok(match op {
+        "==" => actual == value,
+        "!=" => actual != value,
+        "<=" => actual <= value,
+        ">=" => actual >= value,
+        "<" => actual < value,
+        _ => actual > value,
+    })
+}
+
+/// The suppression expression that currently holds for `lim` (or for the
+/// whole definition), if any.
+fn active_suppression(def: &AlarmDefinition, lim: Option<&AlarmLimit>, reg_map: &HashMap<u16, f64>) -> Option<String> {
+    [def.suppress_when.as_ref(), lim.and_then(|l| l.suppress_when.as_ref())]
+        .into_iter()
+        .flatten()
+        .find(|expr| eval_condition(expr, reg_map).unwrap_or(false))
+        .cloned()
+}
+
+/// Most severe limit (HH/LL before H/L) breached by `value`, if any.
+pub fn breached_limit(def: &AlarmDefinition, value: f64) -> Option<LimitLevel> {
+    [LimitLevel::HighHigh, LimitLevel::LowLow, LimitLevel::High, LimitLevel::Low]
+        .into_iter()
+        .find(|&level| def.limit(level).is_some_and(|l| level.is_breached(value, l.limit)))
+}
+
+/// True once `value` has moved back past the least severe limit on each
+/// side by at least the deadband.
+pub fn returned_to_normal(def: &AlarmDefinition, value: f64) -> bool {
+    let high = def.h.as_ref().or(def.hh.as_ref());
+    let low = def.l.as_ref().or(def.ll.as_ref());
+    let below_high = high.is_none_or(|l| value < l.limit - def.deadband_for(l.limit));
+    let above_low = low.is_none_or(|l| value > l.limit + def.deadband_for(l.limit));
+    below_high && above_low
+}
+
+/// True if an open limit alarm of `def` is on the high side (H/HH). Falls
+/// back to the alarm's value when its threshold no longer matches a limit.
+fn is_high_side(def: &AlarmDefinition, alarm: &Alarm) -> bool {
+    [LimitLevel::HighHigh, LimitLevel::High, LimitLevel::Low, LimitLevel::LowLow]
+        .into_iter()
+        .find(|&level| def.limit(level).is_some_and(|l| l.limit == alarm.threshold))
+        .map_or(alarm.value >= alarm.threshold, |level| level.is_high())
+}
+
+/// Substitute `{label}`, `{value}`, `{limit}` and `{device}` in a message template.
+pub fn format_message(template: &str, def: &AlarmDefinition, value: f64, limit: f64) -> String {
+    template
+        .replace("{label}", &def.label)
+        .replace("{value}", &format!("{value:.1}"))
+        .replace("{limit}", &format!("{limit:.0}"))
+        .replace("{device}", &def.device_id)
+}
+
+/// Build the alarm raised when `def` crosses `level` with `value`.
+pub fn build_alarm_request(def: &AlarmDefinition, level: LimitLevel, value: f64) -> Option<RaiseAlarmRequest> {
+    let lim = def.limit(level)?;
+    let template = lim.message.as_deref().unwrap_or(level.default_message());
+    Some(RaiseAlarmRequest {
+        device_id: def.device_id.clone(),
+        register: def.register,
+        label: def.label.clone(),
+        kind: AlarmKind::Limit,
+        priority: lim.priority.unwrap_or(level.default_priority()),
+        value,
+        threshold: lim.limit,
+        message: format_message(template, def, value, lim.limit),
+    })
+}
+
+/// Build the rate-of-change alarm for `rate` (units/min), if it reaches the limit.
+pub fn build_rate_alarm_request(def: &AlarmDefinition, rate: f64) -> Option<RaiseAlarmRequest> {
+    let lim = def.rate_of_change.as_ref().filter(|l| rate.abs() >= l.limit)?;
+    let template = lim.message.as_deref().unwrap_or("WARNING: {label} changing at {value}/min (limit {limit}/min)");
+    Some(RaiseAlarmRequest {
+        device_id: def.device_id.clone(),
+        register: def.register,
+        label: def.label.clone(),
+        kind: AlarmKind::RateOfChange,
+        priority: lim.priority.unwrap_or(AlarmPriority::High),
+        value: rate,
+        threshold: lim.limit,
+        message: format_message(template, def, rate, lim.limit),
+    })
+}
+
+/// Build the deviation alarm for process value `pv` against setpoint `sp`,
+/// if the deviation reaches the limit.
+pub fn build_deviation_alarm_request(def: &AlarmDefinition, pv: f64, sp: f64) -> Option<RaiseAlarmRequest> {
+    let deviation = pv - sp;
+    let lim = def.deviation.as_ref().filter(|l| deviation.abs() >= l.limit)?;
+    let template = lim.message.as_deref().unwrap_or("WARNING: {label} deviates {value} from setpoint (limit ±{limit})");
+    Some(RaiseAlarmRequest {
+        device_id: def.device_id.clone(),
+        register: def.register,
+        label: def.label.clone(),
+        kind: AlarmKind::Deviation,
+        priority: lim.priority.unwrap_or(AlarmPriority::Medium),
+        value: deviation,
+        threshold: lim.limit,
+        message: format_message(template, def, deviation, lim.limit),
+    })
+}
+
+// ── Per-device engine ───────────────────────────────────────────
+
+/// One alarm condition of a definition, evaluated against the current scan.
+struct Condition {
+    kind: AlarmKind,
+    /// Limit breached by a limit condition, if any.
+    level: Option<LimitLevel>,
+    /// Alarm to raise (or escalate to) while the condition is present.
+    alarm: Option<RaiseAlarmRequest>,
+    /// True once the condition has cleared past its deadband/hysteresis.
+    normal: bool,
+    /// Suppression expression that currently holds, if any.
+    suppressed_by: Option<String>,
+}
+
+/// Recent samples of one register, for rate-of-change evaluation.
+#[derive(Debug, Default)]
+struct RateTracker {
+    samples: VecDeque<(DateTime<Utc>, f64)>,
+}
+
+impl RateTracker {
+    /// Record a sample and return the rate (units/min) over the last
+    /// `RATE_WINDOW_SECS`, once a full window of history is available.
+    fn push(&mut self, now: DateTime<Utc>, value: f64) -> Option<f64> {
+        let window_start = now - chrono::Duration::seconds(RATE_WINDOW_SECS);
+        self.samples.push_back((now, value));
+        // Keep exactly one sample at or before the window start
+        while self.samples.get(1).is_some_and(|&(t, _)| t <= window_start) {
+            self.samples.pop_front();
+        }
+        let &(then, old) = self.samples.front()?;
+        if then > window_start {
+            return None;
+        }
+        let minutes = (now - then).num_milliseconds() as f64 / 60_000.0;
+        Some((value - old) / minutes)
+    }
+}
+
+/// On/off-delay timers for one alarm definition.
+#[derive(Debug, Default)]
+struct DelayTimers {
+    /// When the alarm condition was first seen (raise pending on-delay).
+    raise_pending: Option<DateTime<Utc>>,
+    /// When the value first returned to normal (clear pending off-delay).
+    clear_pending: Option<DateTime<Utc>>,
+}
+
+/// True once `since` is at least `secs` seconds before `now`.
+fn delay_elapsed(since: DateTime<Utc>, now: DateTime<Utc>, secs: u32) -> bool {
+    now - since >= chrono::Duration::seconds(secs as i64)
+}
+
+/// Alarm engine for a single device's polling task.
+pub struct AlarmEngine {
+    device_id: String,
+    /// Broadcast channel to WebSocket clients.
+    tx: broadcast::Sender<String>,
+    /// Delay timers keyed by alarm definition ID and alarm kind.
+    timers: HashMap<(i64, AlarmKind), DelayTimers>,
+    /// Rate-of-change history keyed by alarm definition ID.
+    rates: HashMap<i64, RateTracker>,
+    flood: AlarmFloodConfig,
+    /// Flood scope name (device or area) and the devices counted in it.
+    flood_scope: (String, Vec<String>),
+    /// Conditions suppressed during a flood, so each is recorded once.
+    suppressed: HashSet<(i64, AlarmKind)>,
+    /// Conditions suppressed by a `suppress_when` expression, recorded once.
+    state_suppressed: HashSet<(i64, AlarmKind)>,
+}
+
+impl AlarmEngine {
+    pub fn new(device_id: &str, tx: broadcast::Sender<String>) -> Self {
+        Self::with_flood(device_id, tx, AlarmFloodConfig::default())
+    }
+
+    /// Engine with explicit flood detection settings (`[alarm_flood]`).
+    pub fn with_flood(device_id: &str, tx: broadcast::Sender<String>, flood: AlarmFloodConfig) -> Self {
+        let flood_scope = flood.scope_for(device_id);
+        Self {
+            device_id: device_id.to_string(),
+            tx,
+            timers: HashMap::new(),
+            rates: HashMap::new(),
+            flood,
+            flood_scope,
+            suppressed: HashSet::new(),
+            state_suppressed: HashSet::new(),
+        }
+    }
+
+    /// Evaluate every alarm definition for this device against one scan.
+    pub async fn scan(&mut self, db: &SqlitePool, reg_map: &HashMap<u16, f64>) {
+        self.scan_at(db, reg_map, Utc::now()).await;
+    }
+
+    /// Same as `scan`, with an explicit scan time (drives the delay timers
+    /// and rate-of-change window).
+    pub async fn scan_at(&mut self, db: &SqlitePool, reg_map: &HashMap<u16, f64>, now: DateTime<Utc>) {
+        self.check_flood_end(db, now).await;
+
+        let definitions = db::list_alarm_definitions(db, Some(&self.device_id)).await;
+        self.timers.retain(|(id, _), _| definitions.iter().any(|d| d.id == *id));
+        self.suppressed.retain(|(id, _)| definitions.iter().any(|d| d.id == *id));
+        self.state_suppressed.retain(|(id, _)| definitions.iter().any(|d| d.id == *id));
+        self.rates.retain(|id, _| definitions.iter().any(|d| d.id == *id && d.rate_of_change.is_some()));
+        let out_of_service: HashSet<u16> = db::list_points_out_of_service(db, Some(&self.device_id))
+            .await
+            .into_iter()
+            .map(|p| p.register)
+            .collect();
+
+        for def in &definitions {
+            let Some(&val) = reg_map.get(&def.register) else { continue };
+            // Out of service — nothing is raised until it returns to service
+            if out_of_service.contains(&def.register) {
+                self.timers.retain(|(id, _), _| *id != def.id);
+                continue;
+            }
+
+            let mut conditions = Vec::new();
+            if def.has_limits() {
+                let level = breached_limit(def, val);
+                conditions.push(Condition {
+                    kind: AlarmKind::Limit,
+                    level,
+                    alarm: level.and_then(|level| build_alarm_request(def, level, val)),
+                    normal: returned_to_normal(def, val),
+                    suppressed_by: active_suppression(def, level.and_then(|l| def.limit(l)), reg_map),
+                });
+            }
+            if let Some(roc) = &def.rate_of_change
+                && let Some(rate) = self.rates.entry(def.id).or_default().push(now, val) {
+                conditions.push(Condition {
+                    kind: AlarmKind::RateOfChange,
+                    level: None,
+                    alarm: build_rate_alarm_request(def, rate),
+                    normal: rate.abs() < roc.limit * RATE_CLEAR_RATIO,
+                    suppressed_by: active_suppression(def, Some(roc), reg_map),
+                });
+            }
+            if let Some(dev) = &def.deviation
+                && let Some(&sp) = def.setpoint_register.and_then(|r| reg_map.get(&r)) {
+                conditions.push(Condition {
+                    kind: AlarmKind::Deviation,
+                    level: None,
+                    alarm: build_deviation_alarm_request(def, val, sp),
+                    normal: (val - sp).abs() < dev.limit - def.deviation_deadband_for(dev.limit),
+                    suppressed_by: active_suppression(def, Some(dev), reg_map),
+                });
+            }
+
+            for cond in conditions {
+                self.evaluate(db, def, cond, now).await;
+            }
+        }
+    }
+
+    /// Drive the raise/escalate/clear lifecycle of one alarm condition.
+    async fn evaluate(&mut self, db: &SqlitePool, def: &AlarmDefinition, cond: Condition, now: DateTime<Utc>) {
+        let key = (def.id, cond.kind);
+        if cond.alarm.is_none() {
+            self.suppressed.remove(&key);
+        }
+        if cond.alarm.is_none() || cond.suppressed_by.is_none() {
+            self.state_suppressed.remove(&key);
+        }
+
+        let mut open = db::get_open_alarm(db, &self.device_id, def.register, cond.kind).await;
+
+        // Swinging to the other side is a new alarm, not an escalation
+        if let (Some(alarm), Some(level)) = (&open, cond.level)
+            && alarm.state != AlarmState::OutOfService
+            && is_high_side(def, alarm) != level.is_high() {
+            let _ = db::clear_alarm(db, alarm.id).await;
+            ws::publish_alarm_event(&self.tx, db, alarm.id, "clear").await;
+            info!("[{}] ✅ Alarm #{} cleared — {} crossed to the {} limit", self.device_id, alarm.id, def.label, level.as_str());
+            open = None;
+        }
+        let timers = self.timers.entry(key).or_default();
+
+        let Some(alarm) = open else {
+            timers.clear_pending = None;
+            let Some(req) = cond.alarm else {
+                timers.raise_pending = None;
+                return;
+            };
+            // Suppressed by process state — record once instead of raising
+            if let Some(expr) = &cond.suppressed_by {
+                timers.raise_pending = None;
+                if self.state_suppressed.insert(key) {
+                    let detail = format!("Suppressed while {expr}");
+                    db::record_alarm_suppression(db, &req, "state", Some(&detail)).await;
+                    info!("[{}] 🔇 Suppressed while {}: {}", self.device_id, expr, req.message);
+                }
+                return;
+            }
+            let since = *timers.raise_pending.get_or_insert(now);
+            if !delay_elapsed(since, now, def.on_delay_secs) {
+                return;
+            }
+            timers.raise_pending = None;
+
+            if self.flood_suppresses(db, key, &req, now).await {
+                return;
+            }
+            if let Some(id) = db::raise_alarm(db, &req).await {
+                info!("[{}] 🚨 Alarm raised #{} ({}): {}", self.device_id, id, cond.kind.as_str(), req.message);
+                ws::publish_alarm_event(&self.tx, db, id, "raise").await;
+            }
+            return;
+        };
+
+        timers.raise_pending = None;
+
+        // Out of service — suppressed until an admin returns it to service
+        if alarm.state == AlarmState::OutOfService {
+            timers.clear_pending = None;
+            return;
+        }
+
+        // Escalate when the value climbs through a more severe limit on the
+        // same side (a shelved alarm stays shelved — it may still clear below)
+        if alarm.state != AlarmState::Shelved
+            && cond.suppressed_by.is_none()
+            && let Some(req) = &cond.alarm
+            && req.priority.as_i32() < alarm.priority.as_i32() {
+            timers.clear_pending = None;
+            self.escalate(db, alarm.id, alarm.priority, req).await;
+            return;
+        }
+
+        if !cond.normal {
+            timers.clear_pending = None;
+            return;
+        }
+        let since = *timers.clear_pending.get_or_insert(now);
+        if !delay_elapsed(since, now, def.off_delay_secs) {
+            return;
+        }
+        timers.clear_pending = None;
+
+        let _ = db::clear_alarm(db, alarm.id).await;
+        ws::publish_alarm_event(&self.tx, db, alarm.id, "clear").await;
+        info!("[{}] ✅ Alarm #{} auto-cleared ({} {})", self.device_id, alarm.id, def.label, cond.kind.as_str());
+    }
+
+    /// Check for a flood before raising `req`. Starts the flood if this alarm
+    /// pushes the scope over the threshold, and returns true if `req` is
+    /// suppressed (recorded once per condition) instead of raised.
+    async fn flood_suppresses(
+        &mut self,
+        db: &SqlitePool,
+        key: (i64, AlarmKind),
+        req: &RaiseAlarmRequest,
+        now: DateTime<Utc>,
+    ) -> bool {
+        if self.suppressed.contains(&key) {
+            if db::get_active_flood(db, &self.flood_scope.0).await.is_some() {
+                return true;
+            }
+            self.suppressed.remove(&key);
+        }
+
+        let since = (now - chrono::Duration::minutes(FLOOD_WINDOW_MINS)).to_rfc3339();
+        let recent = db::count_recent_alarms(db, &self.flood_scope.1, &since).await;
+        if recent < self.flood.threshold {
+            return false;
+        }
+
+        let (scope, _) = &self.flood_scope;
+        if let Some(flood_id) = db::start_alarm_flood(db, scope, recent + 1).await {
+            tracing::warn!("[{}] 🌊 Alarm flood #{} in '{}' ({} alarms / {} min)", self.device_id, flood_id, scope, recent + 1, FLOOD_WINDOW_MINS);
+            let details = serde_json::json!({
+                "flood_id": flood_id,
+                "scope": scope,
+                "alarm_count": recent + 1,
+                "threshold": self.flood.threshold,
+                "suppress_below": self.flood.suppress_below.as_str(),
+            })
+            .to_string();
+            auth::log_audit(db, "system", "system", "alarm_flood_start", Some(&self.device_id), &details, None).await;
+            self.broadcast_flood(flood_id, true, recent + 1);
+        }
+
+        if req.priority.as_i32() <= self.flood.suppress_below.as_i32() {
+            return false;
+        }
+        let detail = format!("Flood in '{scope}'");
+        db::record_alarm_suppression(db, req, "flood", Some(&detail)).await;
+        info!("[{}] 🔇 Suppressed during flood ({}): {}", self.device_id, req.priority.as_str(), req.message);
+        self.suppressed.insert(key);
+        true
+    }
+
+    /// End this scope's flood once the rate of raised alarms is back below
+    /// the threshold (a flood starts with `threshold` raised plus the one
+    /// that tipped it over, which may have been suppressed).
+    async fn check_flood_end(&mut self, db: &SqlitePool, now: DateTime<Utc>) {
+        let (scope, devices) = &self.flood_scope;
+        let Some(flood_id) = db::get_active_flood(db, scope).await else { return };
+
+        let since = (now - chrono::Duration::minutes(FLOOD_WINDOW_MINS)).to_rfc3339();
+        let recent = db::count_recent_alarms(db, devices, &since).await;
+        if recent >= self.flood.threshold || !db::end_alarm_flood(db, flood_id).await {
+            return;
+        }
+
+        info!("[{}] Alarm flood #{} in '{}' ended", self.device_id, flood_id, scope);
+        let details = serde_json::json!({ "flood_id": flood_id, "scope": scope, "alarm_count": recent }).to_string();
+        auth::log_audit(db, "system", "system", "alarm_flood_end", Some(&self.device_id), &details, None).await;
+        self.broadcast_flood(flood_id, false, recent);
+    }
+
+    fn broadcast_flood(&self, flood_id: i64, active: bool, alarm_count: usize) {
+        let msg = WsMessage::AlarmFlood {
+            flood_id,
+            scope: self.flood_scope.0.clone(),
+            active,
+            alarm_count,
+            threshold: self.flood.threshold,
+        };
+        let _ = self.tx.send(msg.to_json());
+    }
+
+    /// Raise the priority of an active alarm, record it and re-notify clients.
+    async fn escalate(&self, db: &SqlitePool, alarm_id: i64, from: AlarmPriority, req: &RaiseAlarmRequest) {
+        if let Err(e) = db::escalate_alarm(db, alarm_id, req).await {
+            tracing::error!("[{}] {}", self.device_id, e);
+            return;
+        }
+        info!(
+            "[{}] ⏫ Alarm #{} escalated {} → {}: {}",
+            self.device_id, alarm_id, from.as_str(), req.priority.as_str(), req.message
+        );
+
+        let details = serde_json::json!({
+            "alarm_id": alarm_id,
+            "register": req.register,
+            "kind": req.kind.as_str(),
+            "from_priority": from.as_str(),
+            "to_priority": req.priority.as_str(),
+            "value": req.value,
+            "threshold": req.threshold,
+        })
+        .to_string();
+        auth::log_audit(db, "system", "system", "alarm_escalate", Some(&self.device_id), &details, None).await;
+
+        ws::publish_alarm_event(&self.tx, db, alarm_id, "escalate").await;
+    }
+}
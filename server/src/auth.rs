@@ -50,6 +50,7 @@ pub enum Role {
 }
 
 impl Role {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s {
             "admin" => Role::Admin,
@@ -154,6 +155,15 @@ pub struct EsigRequest {
     pub reason: String,
 }
 
+/// A verified e-signature, redeemed from an `esig_token`.
+#[derive(Debug, Clone, Serialize)]
+pub struct EsigGrant {
+    pub user_id: String,
+    pub username: String,
+    pub reason: String,
+    pub signed_at: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct AuditQueryParams {
     pub user_id: Option<String>,
@@ -198,6 +208,22 @@ pub async fn init_auth_tables(pool: &SqlitePool) {
     .await
     .expect("Failed to create audit_trail table");
 
+    // E-signature grants: single-use tokens issued by /api/auth/esig
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS esig_tokens (
+            token TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            username TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            signed_at TEXT NOT NULL,
+            expires_at TEXT NOT NULL,
+            used_at TEXT
+        )"
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create esig_tokens table");
+
     // Seed default admin user if no users exist
     let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users")
         .fetch_one(pool)
@@ -331,16 +357,15 @@ pub fn validate_password_complexity(password: &str) -> Result<(), String> {
 /// Returns Ok(()) if allowed, Err(msg) if locked out.
 pub async fn check_login_throttle(state: &crate::state::AppState, username: &str) -> Result<(), String> {
     let mut attempts = state.login_attempts.lock().await;
-    if let Some((count, first_failure)) = attempts.get(username) {
-        if *count >= MAX_LOGIN_ATTEMPTS {
-            let elapsed = first_failure.elapsed().as_secs();
-            if elapsed < LOCKOUT_DURATION_SECS {
-                let remaining = LOCKOUT_DURATION_SECS - elapsed;
-                return Err(format!("Account locked due to {} failed attempts. Try again in {} seconds.", count, remaining));
-            } else {
-                // Lockout expired — reset
-                attempts.remove(username);
-            }
+    if let Some((count, first_failure)) = attempts.get(username)
+        && *count >= MAX_LOGIN_ATTEMPTS {
+        let elapsed = first_failure.elapsed().as_secs();
+        if elapsed < LOCKOUT_DURATION_SECS {
+            let remaining = LOCKOUT_DURATION_SECS - elapsed;
+            return Err(format!("Account locked due to {} failed attempts. Try again in {} seconds.", count, remaining));
+        } else {
+            // Lockout expired — reset
+            attempts.remove(username);
         }
     }
     Ok(())
@@ -429,13 +454,12 @@ pub async fn auth_middleware(
     match validate_token(&token, &state.jwt_secret) {
         Ok(claims) => {
             // Check session is not revoked
-            if let Some(ref sid) = claims.session_id {
-                if !is_session_valid(&state, sid).await {
-                    return (
-                        StatusCode::UNAUTHORIZED,
-                        Json(serde_json::json!({ "success": false, "error": "Session expired or revoked" })),
-                    ).into_response();
-                }
+            if let Some(ref sid) = claims.session_id
+                && !is_session_valid(&state, sid).await {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    Json(serde_json::json!({ "success": false, "error": "Session expired or revoked" })),
+                ).into_response();
             }
             request.extensions_mut().insert(claims);
             next.run(request).await
@@ -471,13 +495,12 @@ pub async fn require_operator(
 
     match validate_token(&token, &state.jwt_secret) {
         Ok(claims) => {
-            if let Some(ref sid) = claims.session_id {
-                if !is_session_valid(&state, sid).await {
-                    return (
-                        StatusCode::UNAUTHORIZED,
-                        Json(serde_json::json!({ "success": false, "error": "Session expired or revoked" })),
-                    ).into_response();
-                }
+            if let Some(ref sid) = claims.session_id
+                && !is_session_valid(&state, sid).await {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    Json(serde_json::json!({ "success": false, "error": "Session expired or revoked" })),
+                ).into_response();
             }
             let role = Role::from_str(&claims.role);
             if !role.has_permission(&Role::Operator) {
@@ -518,13 +541,12 @@ pub async fn require_admin(
 
     match validate_token(&token, &state.jwt_secret) {
         Ok(claims) => {
-            if let Some(ref sid) = claims.session_id {
-                if !is_session_valid(&state, sid).await {
-                    return (
-                        StatusCode::UNAUTHORIZED,
-                        Json(serde_json::json!({ "success": false, "error": "Session expired or revoked" })),
-                    ).into_response();
-                }
+            if let Some(ref sid) = claims.session_id
+                && !is_session_valid(&state, sid).await {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    Json(serde_json::json!({ "success": false, "error": "Session expired or revoked" })),
+                ).into_response();
             }
             let role = Role::from_str(&claims.role);
             if !role.has_permission(&Role::Admin) {
@@ -681,14 +703,13 @@ pub async fn verify_token_handler(
     match validate_token(&token, &state.jwt_secret) {
         Ok(claims) => {
             // Also verify session is not revoked
-            if let Some(ref sid) = claims.session_id {
-                if !is_session_valid(&state, sid).await {
-                    return (
-                        StatusCode::UNAUTHORIZED,
-                        Json(serde_json::json!({ "success": false, "error": "Session revoked" })),
-                    )
-                        .into_response();
-                }
+            if let Some(ref sid) = claims.session_id
+                && !is_session_valid(&state, sid).await {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    Json(serde_json::json!({ "success": false, "error": "Session revoked" })),
+                )
+                    .into_response();
             }
             (
                 StatusCode::OK,
@@ -763,6 +784,59 @@ pub async fn logout(
     }
 }
 
+/// How long an e-signature token stays redeemable.
+pub const ESIG_TOKEN_TTL_SECS: i64 = 300;
+
+/// Issue a single-use token for a verified e-signature.
+pub async fn issue_esig_token(pool: &SqlitePool, user_id: &str, username: &str, reason: &str) -> Result<(String, String), String> {
+    let token = uuid::Uuid::new_v4().to_string();
+    let now = Utc::now();
+    let expires_at = (now + Duration::seconds(ESIG_TOKEN_TTL_SECS)).to_rfc3339();
+    sqlx::query(
+        "INSERT INTO esig_tokens (token, user_id, username, reason, signed_at, expires_at) VALUES (?, ?, ?, ?, ?, ?)"
+    )
+    .bind(&token)
+    .bind(user_id)
+    .bind(username)
+    .bind(reason)
+    .bind(now.to_rfc3339())
+    .bind(&expires_at)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to issue e-signature token: {e}"))?;
+    Ok((token, expires_at))
+}
+
+/// Redeem an e-signature token for the logged-in user. Each token can be
+/// used once, by the user who signed, before it expires.
+pub async fn consume_esig(pool: &SqlitePool, token: &str, user_id: &str) -> Result<EsigGrant, String> {
+    let now = Utc::now().to_rfc3339();
+    let result = sqlx::query(
+        "UPDATE esig_tokens SET used_at = ?
+         WHERE token = ? AND user_id = ? AND used_at IS NULL AND expires_at > ?"
+    )
+    .bind(&now)
+    .bind(token)
+    .bind(user_id)
+    .bind(&now)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("DB error: {e}"))?;
+
+    if result.rows_affected() == 0 {
+        return Err("Electronic signature required — token missing, expired, already used or signed by another user".into());
+    }
+
+    let (user_id, username, reason, signed_at) = sqlx::query_as::<_, (String, String, String, String)>(
+        "SELECT user_id, username, reason, signed_at FROM esig_tokens WHERE token = ?"
+    )
+    .bind(token)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| format!("DB error: {e}"))?;
+    Ok(EsigGrant { user_id, username, reason, signed_at })
+}
+
 /// POST /api/auth/esig — electronic signature (re-authenticate for critical actions)
 pub async fn electronic_signature(
     State(state): State<crate::state::AppState>,
@@ -821,6 +895,19 @@ pub async fn electronic_signature(
 
     info!("E-signature verified for '{}': {}", username, req.reason);
 
+    // Token the signed action (recipe edit/approval, …) must present
+    let (esig_token, expires_at) = match issue_esig_token(&state.db, &user_id, &username, &req.reason).await {
+        Ok(t) => t,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "error": "Internal server error" })),
+            )
+                .into_response();
+        }
+    };
+
     (
         StatusCode::OK,
         Json(serde_json::json!({
@@ -831,6 +918,8 @@ pub async fn electronic_signature(
                 "username": username,
                 "role": role,
                 "reason": req.reason,
+                "esig_token": esig_token,
+                "expires_at": expires_at,
             }
         })),
     )
@@ -1018,6 +1107,29 @@ pub async fn log_audit(
     .ok();
 }
 
+/// PLC writes and batch commands on a device in `[from, to]`, oldest first —
+/// the audit section of a batch record.
+pub async fn list_batch_audit(pool: &SqlitePool, device_id: &str, from: &str, to: &str) -> Vec<AuditEntry> {
+    sqlx::query_as::<_, (i64, String, String, String, Option<String>, String, String, Option<String>)>(
+        "SELECT id, user_id, username, action, device_id, details, timestamp, ip_address
+         FROM audit_trail
+         WHERE device_id = ? AND timestamp >= ? AND timestamp <= ?
+           AND (action = 'write_register' OR action LIKE 'batch\\_%' ESCAPE '\\')
+         ORDER BY id",
+    )
+    .bind(device_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .map(|(id, user_id, username, action, device_id, details, timestamp, ip_address)| {
+        AuditEntry { id, user_id, username, action, device_id, details, timestamp, ip_address }
+    })
+    .collect()
+}
+
 /// GET /api/audit — query audit trail with filters
 pub async fn get_audit_trail(
     State(state): State<crate::state::AppState>,
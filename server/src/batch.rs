@@ -0,0 +1,604 @@
+//! Batch Execution (ISA-88)
+//!
+//! Operator-driven batch control. Starting a batch optionally downloads an
+//! approved master recipe to the PLC through the device's `WriteCommand`
+//! channel — its named parameters to the `[batch.recipe_registers]`
+//! setpoints, then its register writes — each register read back to
+//! verify it, and every downloaded value is recorded in the parameters of
+//! the batch's first step.
+//!
+//! Start, hold, resume, complete and abort each write the batch-state
+//! register (1032) with read-back before the batch record changes, so the
+//! record never claims a state the PLC did not accept.
+//!
+//! The polling loop reports every change of that register here; each
+//! phase becomes a batch step whose result summarises the key process
+//! values recorded while it ran.
+//!
+//! Ended batches are signed (21 CFR Part 11 §11.50/§11.70): each signature
+//! stores its meaning and a SHA-256 of the record content, so a record
+//! changed after signing shows its signatures as no longer valid.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::auth::{self, Claims, EsigGrant, Role};
+use crate::config::RecipeRegisters;
+use crate::db;
+use crate::models::{BatchRecord, BatchStatus, Recipe, RecordSignature, SignatureMeaning, StartBatchRequest};
+use crate::protocol::{self, BATCH_COMPLETE, BATCH_HEATING, BATCH_HELD, BATCH_IDLE, BATCH_STATE_REGISTER};
+use crate::state::{AppState, WriteCommand};
+
+/// Name of the step that records the recipe download.
+pub const DOWNLOAD_STEP: &str = "Recipe Download";
+
+/// Recipe name recorded for batches started without a master recipe.
+pub const MANUAL_RECIPE: &str = "Reactor Cycle";
+
+/// Process values summarised in the result of each phase step.
+pub const STEP_SUMMARY_REGISTERS: [(&str, u16); 2] = [("temperature", 1028), ("pressure", 1029)];
+
+/// One register written during a recipe download.
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadedValue {
+    /// Named recipe parameter the value came from, if not a register write.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameter: Option<&'static str>,
+    pub register: u16,
+    pub value: u16,
+    /// True once the value was read back from the PLC and matched.
+    pub verified: bool,
+    pub error: Option<String>,
+}
+
+/// Attempts at finding a free batch ID before giving up.
+const MAX_ID_ATTEMPTS: usize = 1000;
+
+/// Render a batch ID template. Placeholders: `{device}`, `{yyyy}`, `{yy}`,
+/// `{mm}`, `{dd}`, `{hh}` (UTC, from `at`) and `{seq}` — `{seq:04}` pads
+/// the sequence with zeros to four digits.
+pub fn format_batch_id(template: &str, device_id: &str, seq: i64, at: DateTime<Utc>) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let close = rest[open..]
+            .find('}')
+            .map(|i| open + i)
+            .ok_or_else(|| format!("Unclosed '{{' in batch ID format '{template}'"))?;
+        let placeholder = &rest[open + 1..close];
+        match placeholder {
+            "device" => out.push_str(device_id),
+            "yyyy" => out.push_str(&at.format("%Y").to_string()),
+            "yy" => out.push_str(&at.format("%y").to_string()),
+            "mm" => out.push_str(&at.format("%m").to_string()),
+            "dd" => out.push_str(&at.format("%d").to_string()),
+            "hh" => out.push_str(&at.format("%H").to_string()),
+            "seq" => out.push_str(&seq.to_string()),
+            _ => {
+                let width = placeholder
+                    .strip_prefix("seq:0")
+                    .and_then(|w| w.parse::<usize>().ok())
+                    .ok_or_else(|| format!("Unknown placeholder '{{{placeholder}}}' in batch ID format"))?;
+                out.push_str(&format!("{seq:0width$}"));
+            }
+        }
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Allocate the next batch ID for a device from its persisted counter,
+/// skipping any ID already taken (e.g. by records from an older format).
+pub async fn next_batch_id(pool: &SqlitePool, template: &str, device_id: &str) -> Result<String, String> {
+    for _ in 0..MAX_ID_ATTEMPTS {
+        let seq = db::next_batch_seq(pool, device_id).await?;
+        let batch_id = format_batch_id(template, device_id, seq, Utc::now())?;
+        if !db::batch_exists(pool, &batch_id).await {
+            return Ok(batch_id);
+        }
+    }
+    Err(format!("No free batch ID for '{device_id}' after {MAX_ID_ATTEMPTS} attempts"))
+}
+
+/// Operator commands on an active batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchCommand {
+    Hold,
+    Resume,
+    Complete,
+    Abort,
+}
+
+impl BatchCommand {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hold => "hold",
+            Self::Resume => "resume",
+            Self::Complete => "complete",
+            Self::Abort => "abort",
+        }
+    }
+
+    /// Batch statuses the command may be issued from.
+    fn allowed_from(&self) -> &'static [BatchStatus] {
+        match self {
+            Self::Hold | Self::Complete => &[BatchStatus::Running],
+            Self::Resume => &[BatchStatus::Held],
+            Self::Abort => &[BatchStatus::Running, BatchStatus::Held],
+        }
+    }
+
+    fn target(&self) -> BatchStatus {
+        match self {
+            Self::Hold => BatchStatus::Held,
+            Self::Resume => BatchStatus::Running,
+            Self::Complete => BatchStatus::Completed,
+            Self::Abort => BatchStatus::Aborted,
+        }
+    }
+
+    /// Batch-state value written to the PLC. Writing Heating to a held
+    /// PLC resumes the phase it was held in.
+    fn plc_state(&self) -> u16 {
+        match self {
+            Self::Hold => BATCH_HELD,
+            Self::Resume => BATCH_HEATING,
+            Self::Complete => BATCH_COMPLETE,
+            Self::Abort => BATCH_IDLE,
+        }
+    }
+}
+
+/// Write one register and wait for the polling task's verified result.
+async fn write_verified(write_tx: &mpsc::Sender<WriteCommand>, register: u16, value: u16) -> Result<(), String> {
+    let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+    let cmd = WriteCommand {
+        register,
+        value,
+        verify: true,
+        response: resp_tx,
+    };
+    write_tx.send(cmd).await.map_err(|e| format!("Failed to queue write: {e}"))?;
+    resp_rx.await.map_err(|_| "Write channel dropped".to_string())?
+}
+
+/// Write channel and writable registers of a device.
+type BatchWriter = (mpsc::Sender<WriteCommand>, Vec<u16>);
+
+/// Writer for a connected device that allows batch-state writes.
+async fn batch_writer(state: &AppState, device_id: &str) -> Result<BatchWriter, String> {
+    let registry = state.devices.read().await;
+    let handle = registry.get(device_id).ok_or_else(|| format!("Unknown device: {device_id}"))?;
+    if handle.task.is_finished() {
+        return Err(format!("Device '{device_id}' is disconnected"));
+    }
+    if !handle.config.writable.contains(&BATCH_STATE_REGISTER) {
+        return Err(format!("Batch state register {BATCH_STATE_REGISTER} is not writable on '{device_id}'"));
+    }
+    Ok((handle.write_tx.clone(), handle.config.writable.clone()))
+}
+
+/// The recipe's named parameters with their values and configured registers.
+fn recipe_parameters(recipe: &Recipe, registers: &RecipeRegisters) -> [(&'static str, f64, Option<u16>); 3] {
+    [
+        ("target_temperature", recipe.target_temperature, registers.target_temperature),
+        ("hold_time_minutes", recipe.hold_time_minutes, registers.hold_time_minutes),
+        ("agitator_rpm", recipe.agitator_rpm, registers.agitator_rpm),
+    ]
+}
+
+/// Plan the download: named parameters with a register (rounded to whole
+/// units) first, then the recipe's register writes.
+fn plan_download(recipe: &Recipe, registers: &RecipeRegisters) -> Result<Vec<DownloadedValue>, String> {
+    let mut planned = Vec::new();
+    for (name, value, register) in recipe_parameters(recipe, registers) {
+        let Some(register) = register else { continue };
+        let rounded = value.round();
+        if !(0.0..=f64::from(u16::MAX)).contains(&rounded) {
+            return Err(format!("Recipe parameter {name} = {value} does not fit register {register}"));
+        }
+        planned.push(DownloadedValue { parameter: Some(name), register, value: rounded as u16, verified: false, error: None });
+    }
+    for w in &recipe.register_writes {
+        if planned.iter().any(|p| p.register == w.register) {
+            return Err(format!("Register {} is set by both a recipe parameter and a register write", w.register));
+        }
+        planned.push(DownloadedValue { parameter: None, register: w.register, value: w.value, verified: false, error: None });
+    }
+    Ok(planned)
+}
+
+/// Download the recipe's parameters and register writes, in order,
+/// stopping at the first failure. Returns every value attempted and the
+/// overall result.
+pub async fn download_recipe(
+    write_tx: &mpsc::Sender<WriteCommand>,
+    writable: &[u16],
+    recipe: &Recipe,
+    registers: &RecipeRegisters,
+) -> (Vec<DownloadedValue>, Result<(), String>) {
+    let planned = match plan_download(recipe, registers) {
+        Ok(planned) => planned,
+        Err(e) => return (Vec::new(), Err(e)),
+    };
+    if let Some(w) = planned.iter().find(|w| !writable.contains(&w.register)) {
+        return (Vec::new(), Err(format!("Register {} is not writable on this device", w.register)));
+    }
+
+    let mut values = Vec::new();
+    for mut v in planned {
+        let result = write_verified(write_tx, v.register, v.value).await;
+        v.verified = result.is_ok();
+        v.error = result.as_ref().err().cloned();
+        let register = v.register;
+        values.push(v);
+        if let Err(e) = result {
+            return (values, Err(format!("Register {register}: {e}")));
+        }
+    }
+    (values, Ok(()))
+}
+
+/// Run the download as step 1 of the batch, recording the parameters and
+/// auditing each verified value as a PLC write.
+async fn download_step(
+    state: &AppState,
+    claims: &Claims,
+    record_id: i64,
+    batch_id: &str,
+    device_id: &str,
+    recipe: &Recipe,
+    (write_tx, writable): &BatchWriter,
+) -> Result<(), String> {
+    let step_id = db::add_batch_step(&state.db, record_id, 1, DOWNLOAD_STEP, None)
+        .await
+        .ok_or("Failed to record the recipe download step")?;
+    let registers = &state.config.batch.recipe_registers;
+    let (values, result) = download_recipe(write_tx, writable, recipe, registers).await;
+    let not_downloaded: Vec<&str> = recipe_parameters(recipe, registers)
+        .into_iter()
+        .filter(|(_, _, register)| register.is_none())
+        .map(|(name, _, _)| name)
+        .collect();
+
+    let parameters = serde_json::json!({
+        "recipe": recipe.name,
+        "version": recipe.version,
+        "target_temperature": recipe.target_temperature,
+        "hold_time_minutes": recipe.hold_time_minutes,
+        "agitator_rpm": recipe.agitator_rpm,
+        "writes": values,
+        "not_downloaded": not_downloaded,
+    })
+    .to_string();
+    db::set_batch_step_parameters(&state.db, step_id, &parameters).await?;
+
+    for v in values.iter().filter(|v| v.verified) {
+        let details = serde_json::json!({
+            "register": v.register,
+            "parameter": v.parameter,
+            "value": v.value,
+            "batch_id": batch_id,
+            "recipe": recipe.name,
+            "version": recipe.version,
+        })
+        .to_string();
+        auth::log_audit(&state.db, &claims.user_id, &claims.sub, "write_register", Some(device_id), &details, None).await;
+    }
+
+    match &result {
+        Ok(()) => {
+            let mut summary = format!("{} value(s) downloaded and verified", values.len());
+            if !not_downloaded.is_empty() {
+                summary.push_str(&format!("; no register configured for {}", not_downloaded.join(", ")));
+            }
+            db::complete_batch_step(&state.db, step_id, "completed", Some(&summary)).await?;
+        }
+        Err(e) => db::complete_batch_step(&state.db, step_id, "failed", Some(e)).await?,
+    }
+    result
+}
+
+/// Start a batch on a device — downloading the approved version of the
+/// requested recipe first, if any — then command the PLC to start.
+/// A failed download or start command aborts the batch.
+pub async fn start_batch(state: &AppState, req: &StartBatchRequest, claims: &Claims) -> Result<BatchRecord, String> {
+    let writer = batch_writer(state, &req.device_id).await?;
+
+    let recipe = match &req.recipe_name {
+        Some(name) => Some(
+            db::get_approved_recipe(&state.db, name)
+                .await
+                .ok_or_else(|| format!("Recipe '{name}' has no approved version"))?,
+        ),
+        None => None,
+    };
+    if let Some((_, running)) = db::get_running_batch(&state.db, &req.device_id).await {
+        return Err(format!("Batch {running} is already active on '{}'", req.device_id));
+    }
+    if let Some(w) = recipe.iter().flat_map(|r| &r.register_writes).find(|w| !writer.1.contains(&w.register)) {
+        return Err(format!("Recipe writes register {}, which is not writable on '{}'", w.register, req.device_id));
+    }
+
+    let batch_id = next_batch_id(&state.db, &state.config.batch.id_format, &req.device_id).await?;
+    let mut details = serde_json::json!({ "batch_id": batch_id });
+    let record_id = match &recipe {
+        Some(recipe) => {
+            details["recipe"] = recipe.name.clone().into();
+            details["version"] = recipe.version.into();
+            db::create_recipe_batch(&state.db, &batch_id, recipe, &req.device_id, &claims.sub).await?
+        }
+        None => db::create_batch(&state.db, &batch_id, MANUAL_RECIPE, &req.device_id, &claims.sub)
+            .await
+            .ok_or("Failed to create batch record")?,
+    };
+    info!("[{}] 🧪 Batch {} started by {}", req.device_id, batch_id, claims.sub);
+
+    let mut result = Ok(());
+    if let Some(recipe) = &recipe {
+        result = download_step(state, claims, record_id, &batch_id, &req.device_id, recipe, &writer)
+            .await
+            .map_err(|e| format!("Recipe download failed: {e}"));
+    }
+    if result.is_ok() {
+        result = write_verified(&writer.0, BATCH_STATE_REGISTER, BATCH_HEATING)
+            .await
+            .map_err(|e| format!("PLC start failed: {e}"));
+    }
+
+    let action = match &result {
+        Ok(()) => "batch_start",
+        Err(e) => {
+            warn!("[{}] Batch {} aborted at start: {}", req.device_id, batch_id, e);
+            db::update_batch_status(&state.db, &batch_id, "aborted", Some(e)).await?;
+            details["error"] = e.clone().into();
+            "batch_start_failed"
+        }
+    };
+    auth::log_audit(&state.db, &claims.user_id, &claims.sub, action, Some(&req.device_id), &details.to_string(), None).await;
+    result?;
+
+    db::get_batch_with_steps(&state.db, &batch_id)
+        .await
+        .map(|(record, _)| record)
+        .ok_or_else(|| "Batch not found".to_string())
+}
+
+/// Check that `command` applies to the batch in its current state and that
+/// its device can take the command. Run before consuming an e-signature,
+/// so a command that cannot run does not use up the signer's token.
+pub async fn check_batch_command(
+    state: &AppState,
+    batch_id: &str,
+    command: BatchCommand,
+) -> Result<(BatchRecord, mpsc::Sender<WriteCommand>), String> {
+    let (record, _) = db::get_batch_with_steps(&state.db, batch_id).await.ok_or("Batch not found")?;
+    if !command.allowed_from().contains(&record.status) {
+        return Err(format!("Cannot {} batch {} while it is {}", command.as_str(), batch_id, record.status.as_str()));
+    }
+    let (write_tx, _) = batch_writer(state, &record.device_id).await?;
+    Ok((record, write_tx))
+}
+
+/// Hold, resume, complete or abort a batch. The PLC is commanded first;
+/// the batch record only changes once the new state is read back.
+/// `reason` is audited, and kept as the batch notes when it ends.
+pub async fn control_batch(
+    state: &AppState,
+    batch_id: &str,
+    command: BatchCommand,
+    claims: &Claims,
+    reason: Option<&str>,
+) -> Result<BatchRecord, String> {
+    let (record, write_tx) = check_batch_command(state, batch_id, command).await?;
+
+    let mut details = serde_json::json!({
+        "batch_id": batch_id,
+        "register": BATCH_STATE_REGISTER,
+        "value": command.plc_state(),
+        "reason": reason,
+    });
+    if let Err(e) = write_verified(&write_tx, BATCH_STATE_REGISTER, command.plc_state()).await {
+        warn!("[{}] Batch {} {} rejected by PLC: {}", record.device_id, batch_id, command.as_str(), e);
+        details["error"] = e.clone().into();
+        let action = format!("batch_{}_failed", command.as_str());
+        auth::log_audit(&state.db, &claims.user_id, &claims.sub, &action, Some(&record.device_id), &details.to_string(), None).await;
+        return Err(format!("PLC rejected {}: {e}", command.as_str()));
+    }
+
+    let target = command.target();
+    let ending = matches!(target, BatchStatus::Completed | BatchStatus::Aborted);
+    let notes = reason.filter(|_| ending);
+    // The polling loop may already have recorded the PLC ending the batch;
+    // the operator who commanded it still owns the end.
+    let mut from = command.allowed_from().to_vec();
+    if ending {
+        let step_status = if command == BatchCommand::Abort { "aborted" } else { "completed" };
+        close_phase_step(&state.db, record.id, &record.device_id, step_status).await?;
+        from.push(target.clone());
+    }
+    db::transition_batch(&state.db, batch_id, &from, target, &claims.sub, notes).await?;
+
+    let action = format!("batch_{}", command.as_str());
+    auth::log_audit(&state.db, &claims.user_id, &claims.sub, &action, Some(&record.device_id), &details.to_string(), None).await;
+    info!("[{}] Batch {} {} by {}", record.device_id, batch_id, command.as_str(), claims.sub);
+
+    db::get_batch_with_steps(&state.db, batch_id)
+        .await
+        .map(|(record, _)| record)
+        .ok_or_else(|| "Batch not found".to_string())
+}
+
+/// Close the batch's open step, if any, with min/max/avg of the
+/// `STEP_SUMMARY_REGISTERS` readings taken while it ran.
+pub async fn close_phase_step(pool: &SqlitePool, record_id: i64, device_id: &str, status: &str) -> Result<(), String> {
+    let Some((step_id, start_time)) = db::get_open_batch_step(pool, record_id).await else {
+        return Ok(());
+    };
+    let now = Utc::now().to_rfc3339();
+    let mut summary = serde_json::Map::new();
+    for (name, register) in STEP_SUMMARY_REGISTERS {
+        let stats = db::register_stats(pool, device_id, register, &start_time, &now).await;
+        summary.insert(name.to_string(), serde_json::json!(stats));
+    }
+    db::complete_batch_step(pool, step_id, status, Some(&serde_json::Value::Object(summary).to_string())).await
+}
+
+/// Record a change of the PLC batch state on the device's active batch:
+/// the open step is closed and, unless the batch has ended, a step named
+/// after the new phase is opened. Falling back to Idle mid-batch closes
+/// the step as aborted.
+pub async fn record_phase_transition(pool: &SqlitePool, device_id: &str, phase: u16) {
+    let Some((record_id, batch_id)) = db::get_running_batch(pool, device_id).await else {
+        return;
+    };
+    let status = if phase == BATCH_IDLE { "aborted" } else { "completed" };
+    if let Err(e) = close_phase_step(pool, record_id, device_id, status).await {
+        warn!("[{}] Batch {}: {}", device_id, batch_id, e);
+    }
+    if phase == BATCH_IDLE || phase == BATCH_COMPLETE {
+        return;
+    }
+
+    let number = db::next_batch_step_number(pool, record_id).await;
+    let name = protocol::batch_phase_name(phase);
+    let parameters = serde_json::json!({ "phase_code": phase }).to_string();
+    if db::add_batch_step(pool, record_id, number, name, Some(&parameters)).await.is_some() {
+        info!("[{}] Batch {} step {}: {}", device_id, batch_id, number, name);
+    }
+}
+
+/// `record_type` of batch record signatures.
+pub const BATCH_RECORD_TYPE: &str = "batch";
+
+/// SHA-256 (hex) over the signed content of a batch record: the header,
+/// steps, material lots and the alarms raised during it. Fields are listed explicitly so
+/// the hash of an unchanged record stays stable as the models grow, and
+/// the release review states hash as "completed" so reviewing a record
+/// does not invalidate the signatures already on it.
+pub async fn batch_content_hash(pool: &SqlitePool, batch_id: &str) -> Option<String> {
+    let (record, steps) = db::get_batch_with_steps(pool, batch_id).await?;
+    let alarms = db::list_batch_alarms(pool, batch_id).await;
+    let materials = db::list_batch_materials(pool, batch_id).await;
+    let status = match record.status {
+        BatchStatus::UnderReview | BatchStatus::Approved | BatchStatus::Rejected => BatchStatus::Completed,
+        status => status,
+    };
+
+    let mut content = serde_json::json!({
+        "batch_id": record.batch_id,
+        "recipe_name": record.recipe_name,
+        "recipe_id": record.recipe_id,
+        "recipe_version": record.recipe_version,
+        "device_id": record.device_id,
+        "operator": record.operator,
+        "status": status.as_str(),
+        "start_time": record.start_time,
+        "end_time": record.end_time,
+        "ended_by": record.ended_by,
+        "notes": record.notes,
+        "steps": steps.iter().map(|s| serde_json::json!({
+            "step_number": s.step_number,
+            "name": s.name,
+            "status": s.status,
+            "start_time": s.start_time,
+            "end_time": s.end_time,
+            "parameters": s.parameters,
+            "result": s.result,
+        })).collect::<Vec<_>>(),
+        "alarms": alarms.iter().map(|a| serde_json::json!({
+            "id": a.id,
+            "register": a.register,
+            "label": a.label,
+            "priority": a.priority.as_str(),
+            "value": a.value,
+            "threshold": a.threshold,
+            "message": a.message,
+            "timestamp": a.timestamp,
+            "first_out": a.first_out,
+        })).collect::<Vec<_>>(),
+    });
+    // Only present when recorded, so records without materials keep the
+    // hash they were signed with
+    if !materials.is_empty() {
+        content["materials"] = materials.iter().map(|m| serde_json::json!({
+            "direction": m.direction.as_str(),
+            "material": m.material,
+            "lot_number": m.lot_number,
+            "expiry": m.expiry,
+            "quantity": m.quantity,
+            "unit": m.unit,
+            "recorded_by": m.recorded_by,
+            "recorded_at": m.recorded_at,
+        })).collect();
+    }
+
+    let digest = Sha256::digest(content.to_string().as_bytes());
+    Some(digest.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Signatures on a batch record, each checked against its current content.
+pub async fn batch_signatures(pool: &SqlitePool, batch_id: &str) -> Vec<RecordSignature> {
+    let current = batch_content_hash(pool, batch_id).await.unwrap_or_default();
+    db::list_signatures(pool, BATCH_RECORD_TYPE, batch_id, &current).await
+}
+
+/// Check that the batch can be signed with `meaning` by `claims`. Run
+/// before consuming the e-signature token.
+pub async fn check_batch_signature(
+    pool: &SqlitePool,
+    batch_id: &str,
+    meaning: SignatureMeaning,
+    claims: &Claims,
+) -> Result<BatchRecord, String> {
+    let (record, _) = db::get_batch_with_steps(pool, batch_id).await.ok_or("Batch not found")?;
+    if !record.status.is_ended() {
+        return Err(format!("Batch {batch_id} is {} — only ended batches can be signed", record.status.as_str()));
+    }
+    if meaning == SignatureMeaning::Approval && !Role::from_str(&claims.role).has_permission(&Role::Admin) {
+        return Err("Approval signatures require the Admin role".to_string());
+    }
+    if batch_signatures(pool, batch_id).await.iter().any(|s| s.user_id == claims.user_id && s.meaning == meaning) {
+        return Err(format!("{} has already signed this record for {}", claims.sub, meaning.as_str()));
+    }
+    Ok(record)
+}
+
+/// Sign an ended batch record with a redeemed e-signature. Approval is
+/// reserved for admins; a user signs each meaning at most once.
+pub async fn sign_batch(
+    pool: &SqlitePool,
+    batch_id: &str,
+    meaning: SignatureMeaning,
+    claims: &Claims,
+    grant: &EsigGrant,
+) -> Result<RecordSignature, String> {
+    let record = check_batch_signature(pool, batch_id, meaning, claims).await?;
+
+    let hash = batch_content_hash(pool, batch_id).await.ok_or("Batch not found")?;
+    let id = db::insert_signature(pool, BATCH_RECORD_TYPE, batch_id, meaning, grant, &hash).await?;
+
+    let details = serde_json::json!({
+        "batch_id": batch_id,
+        "signature_id": id,
+        "meaning": meaning.as_str(),
+        "content_hash": hash,
+        "esig_reason": grant.reason,
+        "esig_signed_at": grant.signed_at,
+    })
+    .to_string();
+    auth::log_audit(pool, &claims.user_id, &claims.sub, "batch_sign", Some(&record.device_id), &details, None).await;
+    info!("Batch {} signed ({}) by {}", batch_id, meaning.as_str(), grant.username);
+
+    batch_signatures(pool, batch_id)
+        .await
+        .into_iter()
+        .find(|s| s.id == id)
+        .ok_or_else(|| "Signature not found".to_string())
+}
@@ -26,7 +26,7 @@ const REG_PH: u32 = 1035;
 
 fn main() {
     // Init logging via env_logger (set RUST_LOG=info to see output)
-    let _ = opcua::console_logging::init();
+    opcua::console_logging::init();
 
     // Detect the machine's LAN IP for discovery URLs so remote clients
     // can find us, but bind to 0.0.0.0 so we accept connections on ALL
@@ -118,9 +118,9 @@ fn main() {
                 let _ = v.set_value(NumericRange::None, DataValue {
                     value: Some(Variant::UInt16(val)),
                     status: Some(StatusCode::Good),
-                    source_timestamp: Some(now.clone()),
+                    source_timestamp: Some(now),
                     source_picoseconds: None,
-                    server_timestamp: Some(now.clone()),
+                    server_timestamp: Some(now),
                     server_picoseconds: None,
                 });
             }
@@ -290,7 +290,7 @@ impl SimState {
             }
             4 => {
                 // Complete → reset after pause
-                if self.tick_count % 60 == 0 {
+                if self.tick_count.is_multiple_of(60) {
                     self.batch_state = 0;
                     self.batch_progress = 0;
                 }
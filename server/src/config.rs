@@ -1,11 +1,27 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
+use crate::models::AlarmPriority;
+
 /// Top-level server configuration loaded from `config.toml`.
 #[derive(Debug, Deserialize, Clone)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub database: DatabaseConfig,
     pub devices: Vec<DeviceConfig>,
+    /// Alarm definitions seeded into the database on startup.
+    #[serde(default)]
+    pub alarms: Vec<AlarmDefinition>,
+    /// Alarm flood detection and suppression.
+    #[serde(default)]
+    pub alarm_flood: AlarmFloodConfig,
+    /// Notifications for alarms left unacknowledged.
+    #[serde(default)]
+    pub escalation: EscalationConfig,
+    /// Batch record settings.
+    #[serde(default)]
+    pub batch: BatchConfig,
 }
 
 /// HTTP server bind address and port.
@@ -48,6 +64,189 @@ pub struct DeviceConfig {
     pub writable: Vec<u16>,
 }
 
+/// One alarm limit (HH, H, L or LL) on an analog register.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct AlarmLimit {
+    pub limit: f64,
+    /// Defaults to Critical for HH/LL and High for H/L.
+    pub priority: Option<AlarmPriority>,
+    /// Message template — `{label}`, `{value}`, `{limit}` and `{device}` are substituted.
+    pub message: Option<String>,
+    /// Suppress this limit while the expression on other registers holds,
+    /// e.g. `"1032 == 0"` (see `alarms::eval_condition`).
+    #[serde(default)]
+    pub suppress_when: Option<String>,
+}
+
+/// Alarm definition for one register on one device (ISA-18.2).
+///
+/// Defined in `config.toml` under `[[alarms]]` and stored in the
+/// `alarm_definitions` table, where it can be edited at runtime.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct AlarmDefinition {
+    /// Database ID — ignored when read from config or a request body.
+    #[serde(default)]
+    pub id: i64,
+    pub device_id: String,
+    pub register: u16,
+    pub label: String,
+    pub hh: Option<AlarmLimit>,
+    pub h: Option<AlarmLimit>,
+    pub l: Option<AlarmLimit>,
+    pub ll: Option<AlarmLimit>,
+    /// Hysteresis (engineering units) the value must move back past a
+    /// HH/H/L/LL limit before the alarm clears. Defaults to 5% of the limit.
+    pub deadband: Option<f64>,
+    /// Rate-of-change limit in engineering units per minute, rising or
+    /// falling. Defaults to High priority.
+    pub rate_of_change: Option<AlarmLimit>,
+    /// Register on the same device holding the setpoint for `deviation`.
+    pub setpoint_register: Option<u16>,
+    /// Deviation limit — alarms when |value − setpoint| reaches it.
+    /// Defaults to Medium priority.
+    pub deviation: Option<AlarmLimit>,
+    /// Hysteresis the deviation must fall back below its limit before the
+    /// alarm clears; less than the deviation limit. Defaults to 5% of it.
+    #[serde(default)]
+    pub deviation_deadband: Option<f64>,
+    /// Seconds the condition must persist before the alarm is raised.
+    #[serde(default)]
+    pub on_delay_secs: u32,
+    /// Seconds the value must stay clear before the alarm is cleared.
+    #[serde(default)]
+    pub off_delay_secs: u32,
+    /// Suppress every alarm of this definition while the expression holds.
+    #[serde(default)]
+    pub suppress_when: Option<String>,
+}
+
+/// ISA-18.2 alarm flood detection. A device (or an area of devices) is in
+/// flood when it raises more than `threshold` alarms in 10 minutes.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct AlarmFloodConfig {
+    pub threshold: usize,
+    /// While in flood, alarms less severe than this are suppressed.
+    pub suppress_below: AlarmPriority,
+    /// Named areas whose devices are counted together, e.g.
+    /// `utilities = ["plc-02", "plc-03"]`. Other devices are their own scope.
+    pub areas: HashMap<String, Vec<String>>,
+}
+
+impl Default for AlarmFloodConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 10,
+            suppress_below: AlarmPriority::High,
+            areas: HashMap::new(),
+        }
+    }
+}
+
+impl AlarmFloodConfig {
+    /// Flood scope for a device: its area name and member devices, or the
+    /// device on its own.
+    pub fn scope_for(&self, device_id: &str) -> (String, Vec<String>) {
+        self.areas
+            .iter()
+            .find(|(_, devices)| devices.iter().any(|d| d == device_id))
+            .map(|(area, devices)| (area.clone(), devices.clone()))
+            .unwrap_or_else(|| (device_id.to_string(), vec![device_id.to_string()]))
+    }
+}
+
+/// Escalation of unacknowledged alarms to the next role tier.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct EscalationConfig {
+    /// How often the background task evaluates the policies.
+    pub check_interval_secs: u64,
+    /// Local SMTP relay (host:port) for email notifications.
+    pub smtp_relay: Option<String>,
+    pub smtp_from: String,
+    pub policies: Vec<EscalationPolicy>,
+}
+
+impl Default for EscalationConfig {
+    fn default() -> Self {
+        Self {
+            check_interval_secs: 30,
+            smtp_relay: None,
+            smtp_from: "vyuh-hmi@localhost".to_string(),
+            policies: Vec::new(),
+        }
+    }
+}
+
+/// One escalation tier: alarms at `priority` (or more severe) still
+/// unacknowledged `after_minutes` after they last became active notify
+/// `notify_role`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct EscalationPolicy {
+    pub name: String,
+    #[serde(default = "default_escalation_priority")]
+    pub priority: AlarmPriority,
+    pub after_minutes: u32,
+    pub notify_role: String,
+    /// `http://` endpoint that receives a JSON POST.
+    pub webhook: Option<String>,
+    /// Recipients, sent through `smtp_relay`.
+    #[serde(default)]
+    pub email: Vec<String>,
+}
+
+fn default_escalation_priority() -> AlarmPriority {
+    AlarmPriority::Critical
+}
+
+/// ISA-88 batch record settings.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct BatchConfig {
+    /// Batch ID template (see `batch::format_batch_id`). `{seq}` is the
+    /// device's persisted batch counter and must be present.
+    pub id_format: String,
+    /// Process limits checked when a batch goes to review; readings
+    /// outside them become review exceptions.
+    pub parameter_limits: Vec<ParameterLimit>,
+    /// PLC registers the recipe's named parameters are downloaded to.
+    pub recipe_registers: RecipeRegisters,
+}
+
+/// Setpoint register for each named recipe parameter. A parameter without
+/// a register is not downloaded, and the download step records that.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct RecipeRegisters {
+    pub target_temperature: Option<u16>,
+    pub hold_time_minutes: Option<u16>,
+    pub agitator_rpm: Option<u16>,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            id_format: "BATCH-{device}-{seq:04}".to_string(),
+            parameter_limits: Vec::new(),
+            recipe_registers: RecipeRegisters::default(),
+        }
+    }
+}
+
+/// Acceptable range of one register during a batch, or only during the
+/// steps of one phase (e.g. "Holding").
+#[derive(Debug, Deserialize, Clone)]
+pub struct ParameterLimit {
+    pub label: String,
+    pub register: u16,
+    #[serde(default)]
+    pub phase: Option<String>,
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
 impl AppConfig {
     /// Load configuration from a TOML file.
     pub fn load(path: &str) -> Self {
@@ -63,6 +262,19 @@ impl AppConfig {
                 config.server.jwt_secret.len()
             );
         }
+        if !config.batch.id_format.contains("{seq") {
+            panic!("batch.id_format must contain {{seq}} so batch IDs stay unique");
+        }
+        if let Err(e) = crate::batch::format_batch_id(&config.batch.id_format, "plc", 1, chrono::Utc::now()) {
+            panic!("Invalid batch.id_format: {e}");
+        }
+        for limit in &config.batch.parameter_limits {
+            if let (Some(min), Some(max)) = (limit.min, limit.max)
+                && min > max
+            {
+                panic!("batch.parameter_limits '{}': min {} is above max {}", limit.label, min, max);
+            }
+        }
         if config.server.jwt_secret.contains("CHANGE-ME") || config.server.jwt_secret.contains("change-me") {
             tracing::warn!("⚠ jwt_secret contains default placeholder — change it before production!");
         }
@@ -1,9 +1,12 @@
-use crate::config::DeviceConfig;
+use crate::auth::EsigGrant;
+use crate::config::{AlarmDefinition, AlarmLimit, DeviceConfig};
 use crate::models::{
-    Alarm, AlarmPriority, AlarmQueryParams, AlarmState, BatchQueryParams, BatchRecord, BatchStep,
-    BatchStatus, PlcData, RaiseAlarmRequest,
+    Alarm, AlarmEvent, AlarmKind, AlarmPointOutOfService, AlarmPriority, AlarmQueryParams, AlarmState, AlarmSuppression,
+    AlarmSuppressionQueryParams, BatchException, BatchMaterial, BatchQueryParams, BatchRecord, BatchStep,
+    BatchStatus, ExceptionKind, LotQueryParams, MaterialDirection, MaterialLot, PlcData, RaiseAlarmRequest, Recipe, RecipeQueryParams, RecipeRequest, RecipeStatus, RecordSignature,
+    RegisterStats, SignatureMeaning,
 };
-use sqlx::{SqlitePool, sqlite::SqlitePoolOptions};
+use sqlx::{Row, SqlitePool, sqlite::{SqlitePoolOptions, SqliteRow}};
 
 //Initialize the DB -> create file + create tables
 
@@ -96,6 +99,100 @@ pub async fn init_db_with_pool(pool: &SqlitePool) {
     .await
     .expect("Failed to create batch_records table");
 
+    // ── 21 CFR Part 11: signatures linked to the record they sign ──
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS signatures (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            record_type TEXT NOT NULL,
+            record_id TEXT NOT NULL,
+            meaning TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            username TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            signed_at TEXT NOT NULL,
+            content_hash TEXT NOT NULL,
+            UNIQUE(record_type, record_id, meaning, user_id)
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create signatures table");
+
+    // Review by exception: deviations each reviewer signs off before release
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS batch_exceptions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            batch_id TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            reference TEXT NOT NULL,
+            description TEXT NOT NULL,
+            occurred_at TEXT NOT NULL,
+            caused_by TEXT,
+            reviewed_by TEXT,
+            reviewed_at TEXT,
+            comment TEXT
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create batch_exceptions table");
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_batch_exceptions_batch ON batch_exceptions(batch_id)")
+        .execute(pool)
+        .await
+        .ok();
+
+    // Material lots and what each batch consumed/produced (genealogy)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS material_lots (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            material TEXT NOT NULL,
+            lot_number TEXT NOT NULL,
+            expiry TEXT,
+            created_at TEXT NOT NULL,
+            UNIQUE(material, lot_number)
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create material_lots table");
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS batch_materials (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            batch_id TEXT NOT NULL,
+            lot_id INTEGER NOT NULL REFERENCES material_lots(id),
+            direction TEXT NOT NULL,
+            quantity REAL NOT NULL,
+            unit TEXT NOT NULL,
+            recorded_by TEXT NOT NULL,
+            recorded_at TEXT NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create batch_materials table");
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_batch_materials_batch ON batch_materials(batch_id)")
+        .execute(pool)
+        .await
+        .ok();
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_batch_materials_lot ON batch_materials(lot_id)")
+        .execute(pool)
+        .await
+        .ok();
+
+    // Per-device batch sequence, so numbering survives restarts
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS batch_counters (
+            device_id TEXT PRIMARY KEY,
+            last_seq INTEGER NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create batch_counters table");
+
     sqlx::query(
         "CREATE TABLE IF NOT EXISTS batch_steps (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -112,6 +209,232 @@ pub async fn init_db_with_pool(pool: &SqlitePool) {
     .execute(pool)
     .await
     .expect("Failed to create batch_steps table");
+
+    // ── ISA-18.2: Alarm definitions (per device + register) ────
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS alarm_definitions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            device_id TEXT NOT NULL,
+            register INTEGER NOT NULL,
+            label TEXT NOT NULL,
+            hh_limit REAL,
+            hh_priority INTEGER,
+            hh_message TEXT,
+            h_limit REAL,
+            h_priority INTEGER,
+            h_message TEXT,
+            l_limit REAL,
+            l_priority INTEGER,
+            l_message TEXT,
+            ll_limit REAL,
+            ll_priority INTEGER,
+            ll_message TEXT,
+            UNIQUE(device_id, register)
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create alarm_definitions table");
+
+    // ── ISA-18.2: Alarm state-transition journal ────────────────
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS alarm_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            alarm_id INTEGER NOT NULL REFERENCES alarms(id),
+            event TEXT NOT NULL,
+            actor TEXT NOT NULL,
+            comment TEXT,
+            value REAL,
+            timestamp TEXT NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create alarm_events table");
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_alarm_events_alarm ON alarm_events(alarm_id)")
+        .execute(pool)
+        .await
+        .expect("Failed to create alarm_events index");
+
+    // ── ISA-18.2: Alarm floods and suppressed alarms ─────────────
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS alarm_floods (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            scope TEXT NOT NULL,
+            started_at TEXT NOT NULL,
+            ended_at TEXT,
+            alarm_count INTEGER NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create alarm_floods table");
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS alarm_suppressions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            device_id TEXT NOT NULL,
+            register INTEGER NOT NULL,
+            label TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            priority INTEGER NOT NULL,
+            value REAL NOT NULL,
+            message TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            detail TEXT,
+            timestamp TEXT NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create alarm_suppressions table");
+
+    // ── ISA-18.2: Escalation notifications sent per alarm ───────
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS alarm_notifications (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            alarm_id INTEGER NOT NULL REFERENCES alarms(id),
+            policy TEXT NOT NULL,
+            role TEXT NOT NULL,
+            channel TEXT NOT NULL,
+            target TEXT NOT NULL,
+            success INTEGER NOT NULL,
+            error TEXT,
+            timestamp TEXT NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create alarm_notifications table");
+
+    // ── ISA-88: Master recipes, one row per version ─────────────
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS recipes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            version INTEGER NOT NULL,
+            status TEXT NOT NULL DEFAULT 'draft',
+            description TEXT,
+            target_temperature REAL NOT NULL,
+            hold_time_minutes REAL NOT NULL,
+            agitator_rpm REAL NOT NULL,
+            register_writes TEXT NOT NULL DEFAULT '[]',
+            created_by TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_by TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            approved_by TEXT,
+            approved_at TEXT,
+            UNIQUE(name, version)
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create recipes table");
+
+    // Batches started from a master recipe record the version they ran
+    add_column_if_missing(pool, "batch_records", "recipe_id", "INTEGER REFERENCES recipes(id)").await;
+    add_column_if_missing(pool, "batch_records", "recipe_version", "INTEGER").await;
+    // Operator who completed or aborted the batch (NULL when the PLC ended it)
+    add_column_if_missing(pool, "batch_records", "ended_by", "TEXT").await;
+    // Release review outcome (approved / rejected)
+    add_column_if_missing(pool, "batch_records", "disposition_by", "TEXT").await;
+    add_column_if_missing(pool, "batch_records", "disposition_at", "TEXT").await;
+    add_column_if_missing(pool, "batch_records", "disposition_reason", "TEXT").await;
+
+    // Shelving: keep the operator's reason with the alarm
+    add_column_if_missing(pool, "alarms", "shelve_reason", "TEXT").await;
+
+    // Escalation clock: when the alarm last (re-)entered 'active'
+    add_column_if_missing(pool, "alarms", "active_since", "TEXT").await;
+    sqlx::query("UPDATE alarms SET active_since = timestamp WHERE active_since IS NULL")
+        .execute(pool)
+        .await
+        .expect("Failed to backfill alarms.active_since");
+
+    // Review exceptions remember who caused them (they may not sign them off)
+    add_column_if_missing(pool, "batch_exceptions", "caused_by", "TEXT").await;
+
+    // State-based suppression: per-definition and per-limit expressions
+    add_column_if_missing(pool, "alarm_definitions", "suppress_when", "TEXT").await;
+    for prefix in ["hh", "h", "l", "ll", "roc", "dev"] {
+        add_column_if_missing(pool, "alarm_definitions", &format!("{prefix}_suppress_when"), "TEXT").await;
+    }
+
+    // Devices whose alarms are suppressed (disconnected from the HMI)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS device_alarm_suppressions (
+            device_id TEXT PRIMARY KEY,
+            reason TEXT NOT NULL,
+            since TEXT NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create device_alarm_suppressions table");
+
+    // Alarm points (device + register) taken out of service for maintenance
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS alarm_points_out_of_service (
+            device_id TEXT NOT NULL,
+            register INTEGER NOT NULL,
+            reason TEXT NOT NULL,
+            set_by TEXT NOT NULL,
+            since TEXT NOT NULL,
+            PRIMARY KEY (device_id, register)
+        )",
+    )
+    .execute(pool)
+    .await
+    .expect("Failed to create alarm_points_out_of_service table");
+
+    // First-out marker for trip analysis
+    add_column_if_missing(pool, "alarms", "first_out", "INTEGER NOT NULL DEFAULT 0").await;
+
+    // Alarm-to-batch linkage: the batch running when the alarm was raised
+    add_column_if_missing(pool, "alarms", "batch_id", "TEXT").await;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_alarms_batch ON alarms(batch_id)")
+        .execute(pool)
+        .await
+        .expect("Failed to create alarms batch index");
+
+    // ISA-18.2 chattering controls: deadband + on/off delay timers
+    add_column_if_missing(pool, "alarm_definitions", "deadband", "REAL").await;
+    add_column_if_missing(pool, "alarm_definitions", "on_delay_secs", "INTEGER NOT NULL DEFAULT 0").await;
+    add_column_if_missing(pool, "alarm_definitions", "off_delay_secs", "INTEGER NOT NULL DEFAULT 0").await;
+
+    // Rate-of-change and deviation-from-setpoint alarms
+    add_column_if_missing(pool, "alarms", "kind", "TEXT NOT NULL DEFAULT 'limit'").await;
+    for (column, decl) in [
+        ("roc_limit", "REAL"),
+        ("roc_priority", "INTEGER"),
+        ("roc_message", "TEXT"),
+        ("setpoint_register", "INTEGER"),
+        ("dev_limit", "REAL"),
+        ("dev_priority", "INTEGER"),
+        ("dev_message", "TEXT"),
+        ("dev_deadband", "REAL"),
+    ] {
+        add_column_if_missing(pool, "alarm_definitions", column, decl).await;
+    }
+}
+
+/// Add a column to an existing table (schema upgrade for older DB files).
+async fn add_column_if_missing(pool: &SqlitePool, table: &str, column: &str, decl: &str) {
+    let columns = sqlx::query_as::<_, (String,)>(&format!("SELECT name FROM pragma_table_info('{table}')"))
+        .fetch_all(pool)
+        .await
+        .unwrap_or_default();
+
+    if columns.iter().any(|(name,)| name == column) {
+        return;
+    }
+
+    sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"))
+        .execute(pool)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to add {table}.{column}: {e}"));
 }
 
 // ── Device persistence ──────────────────────────────────────────
@@ -226,21 +549,36 @@ pub async fn get_history(pool: &SqlitePool, device_id: &str, limit: i64) -> Vec<
 
 // ── ISA-18.2: Alarm operations ──────────────────────────────────
 
+/// Seconds after an alarm within which further alarms on the same device
+/// count as its consequences.
+pub const FIRST_OUT_WINDOW_SECS: i64 = 10;
+
 /// Raise a new alarm (insert into DB). Returns the new alarm ID.
+/// When another alarm follows within the window, the first alarm of that
+/// burst on the device (one with no alarm in its own preceding window) is
+/// marked first-out; a lone alarm is not.
 pub async fn raise_alarm(pool: &SqlitePool, req: &RaiseAlarmRequest) -> Option<i64> {
-    let now = chrono::Utc::now().to_rfc3339();
+    let now = chrono::Utc::now();
+    let window = chrono::Duration::seconds(FIRST_OUT_WINDOW_SECS);
+    let window_start = (now - window).to_rfc3339();
+    let now = now.to_rfc3339();
+    // Stamp the alarm with the device's running batch, if any
+    let batch_id = get_running_batch(pool, &req.device_id).await.map(|(_, batch_id)| batch_id);
     let result = sqlx::query(
-        "INSERT INTO alarms (device_id, register, label, priority, state, value, threshold, message, timestamp)
-         VALUES (?, ?, ?, ?, 'active', ?, ?, ?, ?)",
+        "INSERT INTO alarms (device_id, register, label, kind, priority, state, value, threshold, message, timestamp, active_since, batch_id)
+         VALUES (?, ?, ?, ?, ?, 'active', ?, ?, ?, ?, ?, ?)",
     )
     .bind(&req.device_id)
     .bind(req.register as i64)
     .bind(&req.label)
+    .bind(req.kind.as_str())
     .bind(req.priority.as_i32())
     .bind(req.value)
     .bind(req.threshold)
     .bind(&req.message)
     .bind(&now)
+    .bind(&now)
+    .bind(&batch_id)
     .execute(pool)
     .await
     .map_err(|e| {
@@ -249,41 +587,132 @@ pub async fn raise_alarm(pool: &SqlitePool, req: &RaiseAlarmRequest) -> Option<i
     })
     .ok()?;
 
-    Some(result.last_insert_rowid())
+    let alarm_id = result.last_insert_rowid();
+
+    // This alarm follows `earliest` within the window: if `earliest` opened
+    // its burst, it is the first-out
+    let earliest = sqlx::query_as::<_, (i64, String)>(
+        "SELECT id, timestamp FROM alarms WHERE device_id = ? AND id < ? AND timestamp >= ? ORDER BY id LIMIT 1",
+    )
+    .bind(&req.device_id)
+    .bind(alarm_id)
+    .bind(&window_start)
+    .fetch_optional(pool)
+    .await
+    .ok()
+    .flatten();
+    if let Some((earliest_id, earliest_at)) = earliest
+        && let Ok(earliest_at) = chrono::DateTime::parse_from_rfc3339(&earliest_at)
+    {
+        let earliest_window_start = (earliest_at.with_timezone(&chrono::Utc) - window).to_rfc3339();
+        sqlx::query(
+            "UPDATE alarms SET first_out = 1
+             WHERE id = ? AND NOT EXISTS (SELECT 1 FROM alarms WHERE device_id = ? AND id < ? AND timestamp >= ?)",
+        )
+        .bind(earliest_id)
+        .bind(&req.device_id)
+        .bind(earliest_id)
+        .bind(&earliest_window_start)
+        .execute(pool)
+        .await
+        .map_err(|e| tracing::error!("Failed to mark alarm #{} first-out: {}", earliest_id, e))
+        .ok();
+    }
+
+    record_alarm_event(pool, alarm_id, "raise", "system", Some(&req.message), Some(req.value)).await;
+    Some(alarm_id)
 }
 
-/// Check if there's already an active/unresolved alarm for this device+register.
-pub async fn has_active_alarm(pool: &SqlitePool, device_id: &str, register: u16) -> bool {
-    let row = sqlx::query_as::<_, (i64,)>(
-        "SELECT COUNT(*) FROM alarms WHERE device_id = ? AND register = ? AND state IN ('active', 'acknowledged')",
+/// Append a state transition to the alarm journal.
+pub async fn record_alarm_event(
+    pool: &SqlitePool,
+    alarm_id: i64,
+    event: &str,
+    actor: &str,
+    comment: Option<&str>,
+    value: Option<f64>,
+) {
+    let now = chrono::Utc::now().to_rfc3339();
+    sqlx::query(
+        "INSERT INTO alarm_events (alarm_id, event, actor, comment, value, timestamp)
+         VALUES (?, ?, ?, ?, ?, ?)",
     )
-    .bind(device_id)
-    .bind(register as i64)
-    .fetch_one(pool)
+    .bind(alarm_id)
+    .bind(event)
+    .bind(actor)
+    .bind(comment)
+    .bind(value)
+    .bind(&now)
+    .execute(pool)
     .await
-    .unwrap_or((0,));
+    .map_err(|e| {
+        tracing::error!("CRITICAL: Failed to journal alarm #{} {}: {}", alarm_id, event, e);
+        e
+    })
+    .ok();
+}
 
-    row.0 > 0
+/// Full state-transition history of an alarm, oldest first.
+pub async fn list_alarm_events(pool: &SqlitePool, alarm_id: i64) -> Vec<AlarmEvent> {
+    sqlx::query_as::<_, (i64, i64, String, String, Option<String>, Option<f64>, String)>(
+        "SELECT id, alarm_id, event, actor, comment, value, timestamp
+         FROM alarm_events WHERE alarm_id = ? ORDER BY id",
+    )
+    .bind(alarm_id)
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .map(|(id, alarm_id, event, actor, comment, value, timestamp)| AlarmEvent {
+        id,
+        alarm_id,
+        event,
+        actor,
+        comment,
+        value,
+        timestamp,
+    })
+    .collect()
 }
 
-/// Get the ID of an active alarm for device+register (for auto-clearing).
-pub async fn get_active_alarm_id(pool: &SqlitePool, device_id: &str, register: u16) -> Option<i64> {
-    sqlx::query_as::<_, (i64,)>(
-        "SELECT id FROM alarms WHERE device_id = ? AND register = ? AND state IN ('active', 'acknowledged') ORDER BY id DESC LIMIT 1",
+/// Escalate an active alarm to a more severe limit. An acknowledged alarm
+/// returns to 'active' so the operator has to acknowledge it again, and
+/// its escalation clock restarts.
+pub async fn escalate_alarm(pool: &SqlitePool, alarm_id: i64, req: &RaiseAlarmRequest) -> Result<(), String> {
+    let previous = sqlx::query_as::<_, (i32,)>("SELECT priority FROM alarms WHERE id = ?")
+        .bind(alarm_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("DB error: {e}"))?
+        .map(|(p,)| AlarmPriority::from_i32(p))
+        .ok_or("Alarm not found")?;
+
+    sqlx::query(
+        "UPDATE alarms SET priority = ?, value = ?, threshold = ?, message = ?,
+         active_since = CASE WHEN state = 'active' THEN active_since ELSE ? END,
+         state = 'active', acked_by = NULL, acked_at = NULL
+         WHERE id = ? AND state IN ('active', 'acknowledged')",
     )
-    .bind(device_id)
-    .bind(register as i64)
-    .fetch_optional(pool)
+    .bind(req.priority.as_i32())
+    .bind(req.value)
+    .bind(req.threshold)
+    .bind(&req.message)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(alarm_id)
+    .execute(pool)
     .await
-    .ok()
-    .flatten()
-    .map(|r| r.0)
+    .map_err(|e| format!("Failed to escalate alarm: {e}"))?;
+
+    let comment = format!("{} → {}: {}", previous.as_str(), req.priority.as_str(), req.message);
+    record_alarm_event(pool, alarm_id, "escalate", "system", Some(&comment), Some(req.value)).await;
+    Ok(())
 }
 
-/// Get the most recent running batch for a device.
+/// Get the most recent active (running or held) batch for a device.
 pub async fn get_running_batch(pool: &SqlitePool, device_id: &str) -> Option<(i64, String)> {
     sqlx::query_as::<_, (i64, String)>(
-        "SELECT id, batch_id FROM batch_records WHERE device_id = ? AND status = 'running' ORDER BY id DESC LIMIT 1",
+        "SELECT id, batch_id FROM batch_records WHERE device_id = ? AND status IN ('running', 'held')
+         ORDER BY id DESC LIMIT 1",
     )
     .bind(device_id)
     .fetch_optional(pool)
@@ -302,13 +731,13 @@ pub async fn ack_alarm(
     let now = chrono::Utc::now().to_rfc3339();
 
     // Verify alarm exists and is in 'active' state
-    let row = sqlx::query_as::<_, (String,)>("SELECT state FROM alarms WHERE id = ?")
+    let row = sqlx::query_as::<_, (String, f64)>("SELECT state, value FROM alarms WHERE id = ?")
         .bind(alarm_id)
         .fetch_optional(pool)
         .await
         .map_err(|e| format!("DB error: {e}"))?;
 
-    let Some((state,)) = row else {
+    let Some((state, value)) = row else {
         return Err("Alarm not found".into());
     };
 
@@ -316,23 +745,15 @@ pub async fn ack_alarm(
         return Err(format!("Cannot acknowledge alarm in '{state}' state"));
     }
 
-    let mut message_update = String::new();
-    if let Some(c) = comment {
-        message_update = format!(" — Ack comment: {c}");
-    }
-
-    sqlx::query(
-        "UPDATE alarms SET state = 'acknowledged', acked_by = ?, acked_at = ?,
-         message = message || ? WHERE id = ?",
-    )
-    .bind(username)
-    .bind(&now)
-    .bind(&message_update)
-    .bind(alarm_id)
-    .execute(pool)
-    .await
-    .map_err(|e| format!("Failed to ack alarm: {e}"))?;
+    sqlx::query("UPDATE alarms SET state = 'acknowledged', acked_by = ?, acked_at = ? WHERE id = ?")
+        .bind(username)
+        .bind(&now)
+        .bind(alarm_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to ack alarm: {e}"))?;
 
+    record_alarm_event(pool, alarm_id, "ack", username, comment, Some(value)).await;
     Ok(())
 }
 
@@ -342,7 +763,7 @@ pub async fn shelve_alarm(
     alarm_id: i64,
     username: &str,
     duration_minutes: u32,
-    _reason: &str,
+    reason: &str,
 ) -> Result<(), String> {
     // Max 8 hours
     let mins = duration_minutes.min(480);
@@ -350,30 +771,33 @@ pub async fn shelve_alarm(
         chrono::Utc::now() + chrono::Duration::minutes(mins as i64);
     let until_str = until.to_rfc3339();
 
-    let row = sqlx::query_as::<_, (String,)>("SELECT state FROM alarms WHERE id = ?")
+    let row = sqlx::query_as::<_, (String, f64)>("SELECT state, value FROM alarms WHERE id = ?")
         .bind(alarm_id)
         .fetch_optional(pool)
         .await
         .map_err(|e| format!("DB error: {e}"))?;
 
-    let Some((state,)) = row else {
+    let Some((state, value)) = row else {
         return Err("Alarm not found".into());
     };
 
-    if state == "cleared" {
-        return Err("Cannot shelve a cleared alarm".into());
+    if state == "cleared" || state == "out_of_service" {
+        return Err(format!("Cannot shelve alarm in '{state}' state"));
     }
 
     sqlx::query(
-        "UPDATE alarms SET state = 'shelved', shelved_until = ?, shelved_by = ? WHERE id = ?",
+        "UPDATE alarms SET state = 'shelved', shelved_until = ?, shelved_by = ?, shelve_reason = ? WHERE id = ?",
     )
     .bind(&until_str)
     .bind(username)
+    .bind(reason)
     .bind(alarm_id)
     .execute(pool)
     .await
     .map_err(|e| format!("Failed to shelve alarm: {e}"))?;
 
+    let comment = format!("Shelved for {mins} min: {reason}");
+    record_alarm_event(pool, alarm_id, "shelve", username, Some(&comment), Some(value)).await;
     Ok(())
 }
 
@@ -381,224 +805,1153 @@ pub async fn shelve_alarm(
 pub async fn clear_alarm(pool: &SqlitePool, alarm_id: i64) -> Result<(), String> {
     let now = chrono::Utc::now().to_rfc3339();
 
-    sqlx::query("UPDATE alarms SET state = 'cleared', cleared_at = ? WHERE id = ? AND state != 'cleared'")
+    let result = sqlx::query("UPDATE alarms SET state = 'cleared', cleared_at = ? WHERE id = ? AND state != 'cleared'")
         .bind(&now)
         .bind(alarm_id)
         .execute(pool)
         .await
         .map_err(|e| format!("Failed to clear alarm: {e}"))?;
 
+    if result.rows_affected() > 0 {
+        record_alarm_event(pool, alarm_id, "clear", "system", None, None).await;
+    }
     Ok(())
 }
 
-/// Un-shelve expired alarms (call periodically).
-pub async fn unshelve_expired(pool: &SqlitePool) {
+/// Un-shelve expired alarms (call periodically). Returns the IDs unshelved.
+pub async fn unshelve_expired(pool: &SqlitePool) -> Vec<i64> {
     let now = chrono::Utc::now().to_rfc3339();
-    sqlx::query(
-        "UPDATE alarms SET state = 'active', shelved_until = NULL, shelved_by = NULL
-         WHERE state = 'shelved' AND shelved_until IS NOT NULL AND shelved_until < ?",
+    let expired = sqlx::query_as::<_, (i64,)>(
+        "SELECT id FROM alarms WHERE state = 'shelved' AND shelved_until IS NOT NULL AND shelved_until < ?",
     )
     .bind(&now)
-    .execute(pool)
+    .fetch_all(pool)
     .await
-    .ok();
-}
+    .unwrap_or_default();
 
-/// List alarms with optional filters.
-pub async fn list_alarms(pool: &SqlitePool, params: &AlarmQueryParams) -> Vec<Alarm> {
-    let limit = params.limit.unwrap_or(200);
+    let mut unshelved = Vec::new();
+    for (alarm_id,) in expired {
+        let updated = sqlx::query(
+            "UPDATE alarms SET state = 'active', active_since = ?, shelved_until = NULL, shelved_by = NULL, shelve_reason = NULL
+             WHERE id = ? AND state = 'shelved'",
+        )
+        .bind(&now)
+        .bind(alarm_id)
+        .execute(pool)
+        .await
+        .is_ok_and(|r| r.rows_affected() > 0);
+        if updated {
+            record_alarm_event(pool, alarm_id, "unshelve", "system", Some("Shelve period expired"), None).await;
+            unshelved.push(alarm_id);
+        }
+    }
+    unshelved
+}
 
-    // Build dynamic WHERE clauses
-    let mut conditions = Vec::new();
-    let mut bind_values: Vec<String> = Vec::new();
+/// Un-shelve an alarm before its shelve period ends. It returns to
+/// 'active' so the operator sees (and acknowledges) it again.
+pub async fn unshelve_alarm(pool: &SqlitePool, alarm_id: i64, username: &str) -> Result<(), String> {
+    let result = sqlx::query(
+        "UPDATE alarms SET state = 'active', active_since = ?, shelved_until = NULL, shelved_by = NULL, shelve_reason = NULL
+         WHERE id = ? AND state = 'shelved'",
+    )
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(alarm_id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to unshelve alarm: {e}"))?;
 
-    if let Some(ref device_id) = params.device_id {
-        conditions.push("device_id = ?".to_string());
-        bind_values.push(device_id.clone());
-    }
-    if let Some(ref state) = params.state {
-        conditions.push("state = ?".to_string());
-        bind_values.push(state.clone());
-    }
-    if let Some(priority) = params.priority {
-        conditions.push("priority = ?".to_string());
-        bind_values.push(priority.to_string());
+    if result.rows_affected() == 0 {
+        return match get_alarm(pool, alarm_id).await {
+            Some(a) => Err(format!("Cannot unshelve alarm in '{}' state", a.state.as_str())),
+            None => Err("Alarm not found".into()),
+        };
     }
 
-    let where_clause = if conditions.is_empty() {
-        String::new()
-    } else {
-        format!("WHERE {}", conditions.join(" AND "))
-    };
+    record_alarm_event(pool, alarm_id, "unshelve", username, None, None).await;
+    Ok(())
+}
 
-    let sql = format!(
-        "SELECT id, device_id, register, label, priority, state, value, threshold, message,
-                timestamp, acked_by, acked_at, shelved_until, shelved_by, cleared_at
-         FROM alarms {where_clause} ORDER BY id DESC LIMIT {limit}"
-    );
+/// Take an alarm point out of service (maintenance), whether or not it is
+/// in alarm. No alarms are raised for its device+register until it is
+/// returned to service; its open alarms go out of service with it.
+/// Returns the IDs of those alarms.
+pub async fn set_point_out_of_service(
+    pool: &SqlitePool,
+    device_id: &str,
+    register: u16,
+    username: &str,
+    reason: &str,
+) -> Result<Vec<i64>, String> {
+    sqlx::query(
+        "INSERT INTO alarm_points_out_of_service (device_id, register, reason, set_by, since) VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT(device_id, register) DO UPDATE SET reason = excluded.reason, set_by = excluded.set_by",
+    )
+    .bind(device_id)
+    .bind(register as i64)
+    .bind(reason)
+    .bind(username)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to take alarm point out of service: {e}"))?;
 
-    // We'll use a simple approach: build the query dynamically
-    let rows = sqlx::query_as::<_, (
-        i64, String, i64, String, i32, String, f64, f64, String,
-        String, Option<String>, Option<String>, Option<String>, Option<String>, Option<String>,
-    )>(&sql)
+    let ids: Vec<i64> = sqlx::query_scalar(
+        "UPDATE alarms SET state = 'out_of_service', shelved_until = NULL, shelved_by = NULL, shelve_reason = NULL
+         WHERE device_id = ? AND register = ? AND state IN ('active', 'acknowledged', 'shelved')
+         RETURNING id",
+    )
+    .bind(device_id)
+    .bind(register as i64)
     .fetch_all(pool)
-    .await;
-
-    // Note: sqlx dynamic binding is cumbersome, so for the MVP we'll use
-    // the simpler approach of separate queries per filter combination.
-    // For a more robust solution, consider using sqlx::QueryBuilder.
-
-    // Actually let's just do separate filtered queries:
-    drop(rows); // ignore the above
-
-    let rows = if params.device_id.is_some() && params.state.is_some() && params.priority.is_some() {
-        sqlx::query_as::<_, (
-            i64, String, i64, String, i32, String, f64, f64, String,
-            String, Option<String>, Option<String>, Option<String>, Option<String>, Option<String>,
-        )>(
-            "SELECT id, device_id, register, label, priority, state, value, threshold, message,
-                    timestamp, acked_by, acked_at, shelved_until, shelved_by, cleared_at
-             FROM alarms WHERE device_id = ? AND state = ? AND priority = ? ORDER BY id DESC LIMIT ?"
-        )
-        .bind(params.device_id.as_deref().unwrap())
-        .bind(params.state.as_deref().unwrap())
-        .bind(params.priority.unwrap())
-        .bind(limit)
-        .fetch_all(pool)
-        .await
-        .unwrap_or_default()
-    } else if params.device_id.is_some() && params.state.is_some() {
-        sqlx::query_as::<_, (
-            i64, String, i64, String, i32, String, f64, f64, String,
-            String, Option<String>, Option<String>, Option<String>, Option<String>, Option<String>,
-        )>(
-            "SELECT id, device_id, register, label, priority, state, value, threshold, message,
-                    timestamp, acked_by, acked_at, shelved_until, shelved_by, cleared_at
-             FROM alarms WHERE device_id = ? AND state = ? ORDER BY id DESC LIMIT ?"
-        )
-        .bind(params.device_id.as_deref().unwrap())
-        .bind(params.state.as_deref().unwrap())
-        .bind(limit)
+    .await
+    .map_err(|e| format!("Failed to take alarms out of service: {e}"))?;
+
+    for &alarm_id in &ids {
+        record_alarm_event(pool, alarm_id, "out_of_service", username, Some(reason), None).await;
+    }
+    Ok(ids)
+}
+
+/// Return an alarm point to service. Its out-of-service alarm records are
+/// closed; the engine raises a fresh alarm if the condition is still
+/// present. Returns the IDs of the closed alarms.
+pub async fn return_point_to_service(
+    pool: &SqlitePool,
+    device_id: &str,
+    register: u16,
+    username: &str,
+) -> Result<Vec<i64>, String> {
+    let result = sqlx::query("DELETE FROM alarm_points_out_of_service WHERE device_id = ? AND register = ?")
+        .bind(device_id)
+        .bind(register as i64)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to return alarm point to service: {e}"))?;
+    if result.rows_affected() == 0 {
+        return Err(format!("Alarm point {device_id}/{register} is not out of service"));
+    }
+
+    let ids: Vec<i64> = sqlx::query_scalar(
+        "UPDATE alarms SET state = 'cleared', cleared_at = ?
+         WHERE device_id = ? AND register = ? AND state = 'out_of_service'
+         RETURNING id",
+    )
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(device_id)
+    .bind(register as i64)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to return alarms to service: {e}"))?;
+
+    for &alarm_id in &ids {
+        record_alarm_event(pool, alarm_id, "return_to_service", username, None, None).await;
+    }
+    Ok(ids)
+}
+
+/// Take the point of an open alarm out of service.
+pub async fn set_alarm_out_of_service(
+    pool: &SqlitePool,
+    alarm_id: i64,
+    username: &str,
+    reason: &str,
+) -> Result<Vec<i64>, String> {
+    let alarm = get_alarm(pool, alarm_id).await.ok_or("Alarm not found")?;
+    if !matches!(alarm.state, AlarmState::Active | AlarmState::Acknowledged | AlarmState::Shelved) {
+        return Err(format!("Cannot take alarm in '{}' state out of service", alarm.state.as_str()));
+    }
+    set_point_out_of_service(pool, &alarm.device_id, alarm.register, username, reason).await
+}
+
+/// Return the point of an out-of-service alarm to service.
+pub async fn return_alarm_to_service(pool: &SqlitePool, alarm_id: i64, username: &str) -> Result<Vec<i64>, String> {
+    let alarm = get_alarm(pool, alarm_id).await.ok_or("Alarm not found")?;
+    if alarm.state != AlarmState::OutOfService {
+        return Err(format!("Alarm is not out of service (state '{}')", alarm.state.as_str()));
+    }
+    return_point_to_service(pool, &alarm.device_id, alarm.register, username).await
+}
+
+/// Alarm points currently out of service, optionally for one device.
+pub async fn list_points_out_of_service(pool: &SqlitePool, device_id: Option<&str>) -> Vec<AlarmPointOutOfService> {
+    sqlx::query_as::<_, (String, i64, String, String, String)>(
+        "SELECT device_id, register, reason, set_by, since FROM alarm_points_out_of_service
+         WHERE ? IS NULL OR device_id = ?
+         ORDER BY device_id, register",
+    )
+    .bind(device_id)
+    .bind(device_id)
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .map(|(device_id, register, reason, set_by, since)| AlarmPointOutOfService {
+        device_id,
+        register: register as u16,
+        reason,
+        set_by,
+        since,
+    })
+    .collect()
+}
+
+/// Get the open (not cleared) alarm of `kind` for device+register, including
+/// shelved and out-of-service alarms. Used by the alarm engine.
+pub async fn get_open_alarm(pool: &SqlitePool, device_id: &str, register: u16, kind: AlarmKind) -> Option<Alarm> {
+    sqlx::query(&format!(
+        "SELECT {ALARM_COLUMNS} FROM alarms
+         WHERE device_id = ? AND register = ? AND kind = ? AND state != 'cleared'
+         ORDER BY id DESC LIMIT 1"
+    ))
+    .bind(device_id)
+    .bind(register as i64)
+    .bind(kind.as_str())
+    .fetch_optional(pool)
+    .await
+    .ok()?
+    .as_ref()
+    .map(row_to_alarm)
+}
+
+const ALARM_COLUMNS: &str = "id, device_id, register, label, kind, priority, state, value, threshold, message,
+    timestamp, acked_by, acked_at, shelved_until, shelved_by, shelve_reason, cleared_at, batch_id, first_out, active_since";
+
+fn row_to_alarm(row: &SqliteRow) -> Alarm {
+    Alarm {
+        id: row.get("id"),
+        device_id: row.get("device_id"),
+        register: row.get::<i64, _>("register") as u16,
+        label: row.get("label"),
+        kind: AlarmKind::from_str(row.get("kind")),
+        priority: AlarmPriority::from_i32(row.get("priority")),
+        state: AlarmState::from_str(row.get("state")),
+        value: row.get("value"),
+        threshold: row.get("threshold"),
+        message: row.get("message"),
+        timestamp: row.get("timestamp"),
+        acked_by: row.get("acked_by"),
+        acked_at: row.get("acked_at"),
+        shelved_until: row.get("shelved_until"),
+        shelved_by: row.get("shelved_by"),
+        shelve_reason: row.get("shelve_reason"),
+        cleared_at: row.get("cleared_at"),
+        batch_id: row.get("batch_id"),
+        first_out: row.get("first_out"),
+        active_since: row.get("active_since"),
+    }
+}
+
+/// Alarms raised while `batch_id` was running, oldest first.
+pub async fn list_batch_alarms(pool: &SqlitePool, batch_id: &str) -> Vec<Alarm> {
+    sqlx::query(&format!("SELECT {ALARM_COLUMNS} FROM alarms WHERE batch_id = ? ORDER BY id"))
+        .bind(batch_id)
         .fetch_all(pool)
         .await
         .unwrap_or_default()
-    } else if params.device_id.is_some() {
-        sqlx::query_as::<_, (
-            i64, String, i64, String, i32, String, f64, f64, String,
-            String, Option<String>, Option<String>, Option<String>, Option<String>, Option<String>,
-        )>(
-            "SELECT id, device_id, register, label, priority, state, value, threshold, message,
-                    timestamp, acked_by, acked_at, shelved_until, shelved_by, cleared_at
-             FROM alarms WHERE device_id = ? ORDER BY id DESC LIMIT ?"
-        )
-        .bind(params.device_id.as_deref().unwrap())
-        .bind(limit)
+        .iter()
+        .map(row_to_alarm)
+        .collect()
+}
+
+/// List alarms with optional filters.
+pub async fn list_alarms(pool: &SqlitePool, params: &AlarmQueryParams) -> Vec<Alarm> {
+    let limit = params.limit.unwrap_or(200);
+
+    let mut qb = sqlx::QueryBuilder::<sqlx::Sqlite>::new(format!("SELECT {ALARM_COLUMNS} FROM alarms WHERE 1 = 1"));
+    if let Some(ref device_id) = params.device_id {
+        qb.push(" AND device_id = ").push_bind(device_id);
+    }
+    if let Some(ref state) = params.state {
+        qb.push(" AND state = ").push_bind(state);
+    }
+    if let Some(priority) = params.priority {
+        qb.push(" AND priority = ").push_bind(priority);
+    }
+    if let Some(first_out) = params.first_out {
+        qb.push(" AND first_out = ").push_bind(first_out);
+    }
+    qb.push(" ORDER BY id DESC LIMIT ").push_bind(limit);
+
+    qb.build()
         .fetch_all(pool)
         .await
         .unwrap_or_default()
-    } else if params.state.is_some() {
-        sqlx::query_as::<_, (
-            i64, String, i64, String, i32, String, f64, f64, String,
-            String, Option<String>, Option<String>, Option<String>, Option<String>, Option<String>,
-        )>(
-            "SELECT id, device_id, register, label, priority, state, value, threshold, message,
-                    timestamp, acked_by, acked_at, shelved_until, shelved_by, cleared_at
-             FROM alarms WHERE state = ? ORDER BY id DESC LIMIT ?"
-        )
-        .bind(params.state.as_deref().unwrap())
-        .bind(limit)
+        .iter()
+        .map(row_to_alarm)
+        .collect()
+}
+
+/// Alarms raised in `[from, to)` (RFC 3339), oldest first.
+pub async fn list_alarms_raised_between(
+    pool: &SqlitePool,
+    device_id: Option<&str>,
+    from: &str,
+    to: &str,
+) -> Vec<Alarm> {
+    let mut qb = sqlx::QueryBuilder::<sqlx::Sqlite>::new(format!("SELECT {ALARM_COLUMNS} FROM alarms WHERE timestamp >= "));
+    qb.push_bind(from).push(" AND timestamp < ").push_bind(to);
+    if let Some(device_id) = device_id {
+        qb.push(" AND device_id = ").push_bind(device_id);
+    }
+    qb.push(" ORDER BY timestamp");
+
+    qb.build()
         .fetch_all(pool)
         .await
         .unwrap_or_default()
-    } else if params.priority.is_some() {
-        sqlx::query_as::<_, (
-            i64, String, i64, String, i32, String, f64, f64, String,
-            String, Option<String>, Option<String>, Option<String>, Option<String>, Option<String>,
-        )>(
-            "SELECT id, device_id, register, label, priority, state, value, threshold, message,
-                    timestamp, acked_by, acked_at, shelved_until, shelved_by, cleared_at
-             FROM alarms WHERE priority = ? ORDER BY id DESC LIMIT ?"
-        )
-        .bind(params.priority.unwrap())
-        .bind(limit)
+        .iter()
+        .map(row_to_alarm)
+        .collect()
+}
+
+/// All active/acknowledged alarms, whenever they were raised.
+pub async fn list_standing_alarms(pool: &SqlitePool, device_id: Option<&str>) -> Vec<Alarm> {
+    let mut qb = sqlx::QueryBuilder::<sqlx::Sqlite>::new(format!(
+        "SELECT {ALARM_COLUMNS} FROM alarms WHERE state IN ('active', 'acknowledged')"
+    ));
+    if let Some(device_id) = device_id {
+        qb.push(" AND device_id = ").push_bind(device_id);
+    }
+
+    qb.build()
         .fetch_all(pool)
         .await
         .unwrap_or_default()
-    } else {
-        sqlx::query_as::<_, (
-            i64, String, i64, String, i32, String, f64, f64, String,
-            String, Option<String>, Option<String>, Option<String>, Option<String>, Option<String>,
-        )>(
-            "SELECT id, device_id, register, label, priority, state, value, threshold, message,
-                    timestamp, acked_by, acked_at, shelved_until, shelved_by, cleared_at
-             FROM alarms ORDER BY id DESC LIMIT ?"
-        )
-        .bind(limit)
+        .iter()
+        .map(row_to_alarm)
+        .collect()
+}
+
+/// Get a single alarm by ID.
+pub async fn get_alarm(pool: &SqlitePool, alarm_id: i64) -> Option<Alarm> {
+    sqlx::query(&format!("SELECT {ALARM_COLUMNS} FROM alarms WHERE id = ?"))
+        .bind(alarm_id)
+        .fetch_optional(pool)
+        .await
+        .ok()?
+        .as_ref()
+        .map(row_to_alarm)
+}
+
+// ── ISA-18.2: Flood detection and suppression ───────────────────
+
+/// Alarms raised on `devices` since `since` (RFC 3339). Suppressed alarms
+/// are not counted, so a flood does not keep itself alive.
+pub async fn count_recent_alarms(pool: &SqlitePool, devices: &[String], since: &str) -> usize {
+    let mut qb = sqlx::QueryBuilder::<sqlx::Sqlite>::new("SELECT COUNT(*) FROM alarms WHERE timestamp >= ");
+    qb.push_bind(since).push(" AND device_id IN (");
+    let mut sep = qb.separated(", ");
+    for d in devices {
+        sep.push_bind(d);
+    }
+    qb.push(")");
+    let count: i64 = qb.build_query_scalar().fetch_one(pool).await.unwrap_or(0);
+    count as usize
+}
+
+/// ID of the active (not ended) flood for `scope`, if any.
+pub async fn get_active_flood(pool: &SqlitePool, scope: &str) -> Option<i64> {
+    sqlx::query_as::<_, (i64,)>("SELECT id FROM alarm_floods WHERE scope = ? AND ended_at IS NULL")
+        .bind(scope)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+        .map(|r| r.0)
+}
+
+/// Start a flood for `scope`. Returns the new flood ID, or `None` if a
+/// flood is already active (another device in the area got there first).
+pub async fn start_alarm_flood(pool: &SqlitePool, scope: &str, alarm_count: usize) -> Option<i64> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let result = sqlx::query(
+        "INSERT INTO alarm_floods (scope, started_at, alarm_count)
+         SELECT ?, ?, ? WHERE NOT EXISTS (SELECT 1 FROM alarm_floods WHERE scope = ? AND ended_at IS NULL)",
+    )
+    .bind(scope)
+    .bind(&now)
+    .bind(alarm_count as i64)
+    .bind(scope)
+    .execute(pool)
+    .await
+    .ok()?;
+
+    (result.rows_affected() > 0).then(|| result.last_insert_rowid())
+}
+
+/// End an active flood. Returns false if it had already ended.
+pub async fn end_alarm_flood(pool: &SqlitePool, flood_id: i64) -> bool {
+    let now = chrono::Utc::now().to_rfc3339();
+    sqlx::query("UPDATE alarm_floods SET ended_at = ? WHERE id = ? AND ended_at IS NULL")
+        .bind(&now)
+        .bind(flood_id)
+        .execute(pool)
+        .await
+        .is_ok_and(|r| r.rows_affected() > 0)
+}
+
+/// Suppress (or stop suppressing) every alarm of a device while it is
+/// disconnected. No scans run then, so this only keeps its standing alarms
+/// out of escalation.
+pub async fn set_device_alarms_suppressed(pool: &SqlitePool, device_id: &str, reason: Option<&str>) {
+    let result = match reason {
+        Some(reason) => {
+            sqlx::query(
+                "INSERT INTO device_alarm_suppressions (device_id, reason, since) VALUES (?, ?, ?)
+                 ON CONFLICT(device_id) DO UPDATE SET reason = excluded.reason",
+            )
+            .bind(device_id)
+            .bind(reason)
+            .bind(chrono::Utc::now().to_rfc3339())
+            .execute(pool)
+            .await
+        }
+        None => {
+            sqlx::query("DELETE FROM device_alarm_suppressions WHERE device_id = ?")
+                .bind(device_id)
+                .execute(pool)
+                .await
+        }
+    };
+    if let Err(e) = result {
+        tracing::error!("Failed to update alarm suppression for '{}': {}", device_id, e);
+    }
+}
+
+/// Why a device's alarms are suppressed, if they are.
+pub async fn get_device_alarm_suppression(pool: &SqlitePool, device_id: &str) -> Option<String> {
+    sqlx::query_scalar("SELECT reason FROM device_alarm_suppressions WHERE device_id = ?")
+        .bind(device_id)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Record an alarm that was suppressed instead of raised.
+pub async fn record_alarm_suppression(pool: &SqlitePool, req: &RaiseAlarmRequest, reason: &str, detail: Option<&str>) {
+    let now = chrono::Utc::now().to_rfc3339();
+    sqlx::query(
+        "INSERT INTO alarm_suppressions
+            (device_id, register, label, kind, priority, value, message, reason, detail, timestamp)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&req.device_id)
+    .bind(req.register as i64)
+    .bind(&req.label)
+    .bind(req.kind.as_str())
+    .bind(req.priority.as_i32())
+    .bind(req.value)
+    .bind(&req.message)
+    .bind(reason)
+    .bind(detail)
+    .bind(&now)
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("CRITICAL: Failed to record suppressed alarm {}/{}: {}", req.device_id, req.register, e);
+        e
+    })
+    .ok();
+}
+
+/// List suppressed alarms, newest first.
+pub async fn list_alarm_suppressions(pool: &SqlitePool, params: &AlarmSuppressionQueryParams) -> Vec<AlarmSuppression> {
+    let mut qb = sqlx::QueryBuilder::<sqlx::Sqlite>::new(
+        "SELECT id, device_id, register, label, kind, priority, value, message, reason, detail, timestamp
+         FROM alarm_suppressions WHERE 1 = 1",
+    );
+    if let Some(ref device_id) = params.device_id {
+        qb.push(" AND device_id = ").push_bind(device_id);
+    }
+    if let Some(ref reason) = params.reason {
+        qb.push(" AND reason = ").push_bind(reason);
+    }
+    qb.push(" ORDER BY id DESC LIMIT ").push_bind(params.limit.unwrap_or(200));
+
+    qb.build()
+        .fetch_all(pool)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|row| AlarmSuppression {
+            id: row.get("id"),
+            device_id: row.get("device_id"),
+            register: row.get::<i64, _>("register") as u16,
+            label: row.get("label"),
+            kind: AlarmKind::from_str(row.get("kind")),
+            priority: AlarmPriority::from_i32(row.get("priority")),
+            value: row.get("value"),
+            message: row.get("message"),
+            reason: row.get("reason"),
+            detail: row.get("detail"),
+            timestamp: row.get("timestamp"),
+        })
+        .collect()
+}
+
+// ── ISA-18.2: Escalation notifications ──────────────────────────
+
+/// Unacknowledged ('active') alarms at `priority` or more severe, active
+/// since at or before `active_before`, that `policy` has not successfully
+/// notified about since they last became active. Failed attempts do not
+/// count, so the next check retries them.
+pub async fn list_alarms_due_for_escalation(
+    pool: &SqlitePool,
+    policy: &str,
+    priority: AlarmPriority,
+    active_before: &str,
+) -> Vec<Alarm> {
+    sqlx::query(&format!(
+        "SELECT {ALARM_COLUMNS} FROM alarms
+         WHERE state = 'active' AND priority <= ? AND active_since <= ?
+           AND device_id NOT IN (SELECT device_id FROM device_alarm_suppressions)
+           AND NOT EXISTS (SELECT 1 FROM alarm_notifications n
+                           WHERE n.alarm_id = alarms.id AND n.policy = ? AND n.success = 1
+                             AND n.timestamp >= alarms.active_since)
+         ORDER BY id"
+    ))
+    .bind(priority.as_i32())
+    .bind(active_before)
+    .bind(policy)
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default()
+    .iter()
+    .map(row_to_alarm)
+    .collect()
+}
+
+/// Record one escalation notification attempt.
+pub async fn record_alarm_notification(
+    pool: &SqlitePool,
+    alarm_id: i64,
+    policy: &str,
+    role: &str,
+    channel: &str,
+    target: &str,
+    result: &Result<(), String>,
+) {
+    let now = chrono::Utc::now().to_rfc3339();
+    sqlx::query(
+        "INSERT INTO alarm_notifications (alarm_id, policy, role, channel, target, success, error, timestamp)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(alarm_id)
+    .bind(policy)
+    .bind(role)
+    .bind(channel)
+    .bind(target)
+    .bind(result.is_ok())
+    .bind(result.as_ref().err())
+    .bind(&now)
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to record notification for alarm #{}: {}", alarm_id, e);
+        e
+    })
+    .ok();
+}
+
+// ── ISA-18.2: Alarm definitions ─────────────────────────────────
+
+const ALARM_DEFINITION_COLUMNS: &str = "id, device_id, register, label,
+    hh_limit, hh_priority, hh_message, h_limit, h_priority, h_message,
+    l_limit, l_priority, l_message, ll_limit, ll_priority, ll_message,
+    roc_limit, roc_priority, roc_message, setpoint_register, dev_limit, dev_priority, dev_message, dev_deadband,
+    deadband, on_delay_secs, off_delay_secs, suppress_when,
+    hh_suppress_when, h_suppress_when, l_suppress_when, ll_suppress_when, roc_suppress_when, dev_suppress_when";
+
+/// Read one limit (`hh`, `h`, `l`, `ll`, `roc`, `dev`) from an alarm_definitions row.
+fn row_to_alarm_limit(row: &SqliteRow, prefix: &str) -> Option<AlarmLimit> {
+    let limit: Option<f64> = row.get(format!("{prefix}_limit").as_str());
+    let priority: Option<i32> = row.get(format!("{prefix}_priority").as_str());
+    let message: Option<String> = row.get(format!("{prefix}_message").as_str());
+    let suppress_when: Option<String> = row.get(format!("{prefix}_suppress_when").as_str());
+    limit.map(|limit| AlarmLimit {
+        limit,
+        priority: priority.map(AlarmPriority::from_i32),
+        message,
+        suppress_when,
+    })
+}
+
+fn row_to_alarm_definition(row: &SqliteRow) -> AlarmDefinition {
+    AlarmDefinition {
+        id: row.get("id"),
+        device_id: row.get("device_id"),
+        register: row.get::<i64, _>("register") as u16,
+        label: row.get("label"),
+        hh: row_to_alarm_limit(row, "hh"),
+        h: row_to_alarm_limit(row, "h"),
+        l: row_to_alarm_limit(row, "l"),
+        ll: row_to_alarm_limit(row, "ll"),
+        rate_of_change: row_to_alarm_limit(row, "roc"),
+        setpoint_register: row.get::<Option<i64>, _>("setpoint_register").map(|r| r as u16),
+        deviation: row_to_alarm_limit(row, "dev"),
+        deviation_deadband: row.get("dev_deadband"),
+        deadband: row.get("deadband"),
+        on_delay_secs: row.get::<i64, _>("on_delay_secs") as u32,
+        off_delay_secs: row.get::<i64, _>("off_delay_secs") as u32,
+        suppress_when: row.get("suppress_when"),
+    }
+}
+
+/// Columns of one stored limit: limit, priority, message, suppress_when.
+type AlarmLimitColumns = (Option<f64>, Option<i32>, Option<String>, Option<String>);
+
+/// Flatten an optional limit into its columns.
+fn alarm_limit_columns(lim: &Option<AlarmLimit>) -> AlarmLimitColumns {
+    match lim {
+        Some(l) => (Some(l.limit), l.priority.map(|p| p.as_i32()), l.message.clone(), l.suppress_when.clone()),
+        None => (None, None, None, None),
+    }
+}
+
+/// Insert config-file alarm definitions that are not in the database yet.
+/// Definitions edited at runtime are left untouched.
+pub async fn seed_alarm_definitions(pool: &SqlitePool, defs: &[AlarmDefinition]) {
+    for def in defs {
+        if let Err(e) = def.validate() {
+            tracing::warn!("Skipping alarm definition {}/{}: {}", def.device_id, def.register, e);
+            continue;
+        }
+        if let Err(e) = insert_alarm_definition(pool, def, "INSERT OR IGNORE").await {
+            tracing::error!("Failed to seed alarm definition {}/{}: {}", def.device_id, def.register, e);
+        }
+    }
+}
+
+/// Create a new alarm definition. Returns the new ID.
+pub async fn create_alarm_definition(pool: &SqlitePool, def: &AlarmDefinition) -> Result<i64, String> {
+    insert_alarm_definition(pool, def, "INSERT").await
+}
+
+async fn insert_alarm_definition(pool: &SqlitePool, def: &AlarmDefinition, verb: &str) -> Result<i64, String> {
+    let (hh, hh_p, hh_m, hh_s) = alarm_limit_columns(&def.hh);
+    let (h, h_p, h_m, h_s) = alarm_limit_columns(&def.h);
+    let (l, l_p, l_m, l_s) = alarm_limit_columns(&def.l);
+    let (ll, ll_p, ll_m, ll_s) = alarm_limit_columns(&def.ll);
+    let (roc, roc_p, roc_m, roc_s) = alarm_limit_columns(&def.rate_of_change);
+    let (dev, dev_p, dev_m, dev_s) = alarm_limit_columns(&def.deviation);
+
+    let sql = format!(
+        "{verb} INTO alarm_definitions (device_id, register, label,
+            hh_limit, hh_priority, hh_message, h_limit, h_priority, h_message,
+            l_limit, l_priority, l_message, ll_limit, ll_priority, ll_message,
+            roc_limit, roc_priority, roc_message, setpoint_register, dev_limit, dev_priority, dev_message, dev_deadband,
+            deadband, on_delay_secs, off_delay_secs, suppress_when,
+            hh_suppress_when, h_suppress_when, l_suppress_when, ll_suppress_when, roc_suppress_when, dev_suppress_when)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    );
+    let result = sqlx::query(&sql)
+        .bind(&def.device_id)
+        .bind(def.register as i64)
+        .bind(&def.label)
+        .bind(hh).bind(hh_p).bind(hh_m)
+        .bind(h).bind(h_p).bind(h_m)
+        .bind(l).bind(l_p).bind(l_m)
+        .bind(ll).bind(ll_p).bind(ll_m)
+        .bind(roc).bind(roc_p).bind(roc_m)
+        .bind(def.setpoint_register.map(|r| r as i64))
+        .bind(dev).bind(dev_p).bind(dev_m)
+        .bind(def.deviation_deadband)
+        .bind(def.deadband)
+        .bind(def.on_delay_secs as i64)
+        .bind(def.off_delay_secs as i64)
+        .bind(&def.suppress_when)
+        .bind(hh_s).bind(h_s).bind(l_s).bind(ll_s).bind(roc_s).bind(dev_s)
+        .execute(pool)
+        .await
+        .map_err(|e| {
+            if e.to_string().contains("UNIQUE") {
+                format!("Alarm definition for {}/{} already exists", def.device_id, def.register)
+            } else {
+                format!("Failed to save alarm definition: {e}")
+            }
+        })?;
+
+    Ok(result.last_insert_rowid())
+}
+
+/// Replace an existing alarm definition.
+pub async fn update_alarm_definition(pool: &SqlitePool, id: i64, def: &AlarmDefinition) -> Result<(), String> {
+    let (hh, hh_p, hh_m, hh_s) = alarm_limit_columns(&def.hh);
+    let (h, h_p, h_m, h_s) = alarm_limit_columns(&def.h);
+    let (l, l_p, l_m, l_s) = alarm_limit_columns(&def.l);
+    let (ll, ll_p, ll_m, ll_s) = alarm_limit_columns(&def.ll);
+    let (roc, roc_p, roc_m, roc_s) = alarm_limit_columns(&def.rate_of_change);
+    let (dev, dev_p, dev_m, dev_s) = alarm_limit_columns(&def.deviation);
+
+    let result = sqlx::query(
+        "UPDATE alarm_definitions SET device_id = ?, register = ?, label = ?,
+            hh_limit = ?, hh_priority = ?, hh_message = ?, h_limit = ?, h_priority = ?, h_message = ?,
+            l_limit = ?, l_priority = ?, l_message = ?, ll_limit = ?, ll_priority = ?, ll_message = ?,
+            roc_limit = ?, roc_priority = ?, roc_message = ?, setpoint_register = ?,
+            dev_limit = ?, dev_priority = ?, dev_message = ?, dev_deadband = ?,
+            deadband = ?, on_delay_secs = ?, off_delay_secs = ?, suppress_when = ?,
+            hh_suppress_when = ?, h_suppress_when = ?, l_suppress_when = ?, ll_suppress_when = ?,
+            roc_suppress_when = ?, dev_suppress_when = ?
+         WHERE id = ?",
+    )
+    .bind(&def.device_id)
+    .bind(def.register as i64)
+    .bind(&def.label)
+    .bind(hh).bind(hh_p).bind(hh_m)
+    .bind(h).bind(h_p).bind(h_m)
+    .bind(l).bind(l_p).bind(l_m)
+    .bind(ll).bind(ll_p).bind(ll_m)
+    .bind(roc).bind(roc_p).bind(roc_m)
+    .bind(def.setpoint_register.map(|r| r as i64))
+    .bind(dev).bind(dev_p).bind(dev_m)
+    .bind(def.deviation_deadband)
+    .bind(def.deadband)
+    .bind(def.on_delay_secs as i64)
+    .bind(def.off_delay_secs as i64)
+    .bind(&def.suppress_when)
+    .bind(hh_s).bind(h_s).bind(l_s).bind(ll_s).bind(roc_s).bind(dev_s)
+    .bind(id)
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        if e.to_string().contains("UNIQUE") {
+            format!("Alarm definition for {}/{} already exists", def.device_id, def.register)
+        } else {
+            format!("Failed to update alarm definition: {e}")
+        }
+    })?;
+
+    if result.rows_affected() == 0 {
+        return Err("Alarm definition not found".into());
+    }
+    Ok(())
+}
+
+/// Delete an alarm definition.
+pub async fn delete_alarm_definition(pool: &SqlitePool, id: i64) -> Result<(), String> {
+    let result = sqlx::query("DELETE FROM alarm_definitions WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to delete alarm definition: {e}"))?;
+
+    if result.rows_affected() == 0 {
+        return Err("Alarm definition not found".into());
+    }
+    Ok(())
+}
+
+/// List alarm definitions, optionally for a single device.
+pub async fn list_alarm_definitions(pool: &SqlitePool, device_id: Option<&str>) -> Vec<AlarmDefinition> {
+    let rows = match device_id {
+        Some(device_id) => {
+            sqlx::query(&format!(
+                "SELECT {ALARM_DEFINITION_COLUMNS} FROM alarm_definitions WHERE device_id = ? ORDER BY register"
+            ))
+            .bind(device_id)
+            .fetch_all(pool)
+            .await
+        }
+        None => {
+            sqlx::query(&format!(
+                "SELECT {ALARM_DEFINITION_COLUMNS} FROM alarm_definitions ORDER BY device_id, register"
+            ))
+            .fetch_all(pool)
+            .await
+        }
+    };
+
+    rows.unwrap_or_default().iter().map(row_to_alarm_definition).collect()
+}
+
+/// Get a single alarm definition by ID.
+pub async fn get_alarm_definition(pool: &SqlitePool, id: i64) -> Option<AlarmDefinition> {
+    sqlx::query(&format!("SELECT {ALARM_DEFINITION_COLUMNS} FROM alarm_definitions WHERE id = ?"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .ok()?
+        .as_ref()
+        .map(row_to_alarm_definition)
+}
+
+// ── ISA-88: Batch record operations ─────────────────────────────
+
+/// Advance and return the device's persisted batch sequence (1, 2, …).
+pub async fn next_batch_seq(pool: &SqlitePool, device_id: &str) -> Result<i64, String> {
+    sqlx::query_scalar::<_, i64>(
+        "INSERT INTO batch_counters (device_id, last_seq) VALUES (?, 1)
+         ON CONFLICT(device_id) DO UPDATE SET last_seq = last_seq + 1
+         RETURNING last_seq",
+    )
+    .bind(device_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| format!("Failed to advance batch counter: {e}"))
+}
+
+/// Whether a batch ID is already in use.
+pub async fn batch_exists(pool: &SqlitePool, batch_id: &str) -> bool {
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM batch_records WHERE batch_id = ?")
+        .bind(batch_id)
+        .fetch_one(pool)
+        .await
+        .map(|n| n > 0)
+        .unwrap_or(false)
+}
+
+/// Store a signature over a record's content hash.
+pub async fn insert_signature<'e>(
+    executor: impl sqlx::SqliteExecutor<'e>,
+    record_type: &str,
+    record_id: &str,
+    meaning: SignatureMeaning,
+    grant: &EsigGrant,
+    content_hash: &str,
+) -> Result<i64, String> {
+    let result = sqlx::query(
+        "INSERT INTO signatures (record_type, record_id, meaning, user_id, username, reason, signed_at, content_hash)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(record_type)
+    .bind(record_id)
+    .bind(meaning.as_str())
+    .bind(&grant.user_id)
+    .bind(&grant.username)
+    .bind(&grant.reason)
+    .bind(&grant.signed_at)
+    .bind(content_hash)
+    .execute(executor)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref d) if d.is_unique_violation() => {
+            format!("{} has already signed this record for {}", grant.username, meaning.as_str())
+        }
+        e => format!("Failed to store signature: {e}"),
+    })?;
+    Ok(result.last_insert_rowid())
+}
+
+/// Signatures on a record, oldest first. Each is `valid` while the record
+/// still hashes to `current_hash`.
+pub async fn list_signatures(
+    pool: &SqlitePool,
+    record_type: &str,
+    record_id: &str,
+    current_hash: &str,
+) -> Vec<RecordSignature> {
+    sqlx::query(
+        "SELECT id, record_type, record_id, meaning, user_id, username, reason, signed_at, content_hash
+         FROM signatures WHERE record_type = ? AND record_id = ? ORDER BY id",
+    )
+    .bind(record_type)
+    .bind(record_id)
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default()
+    .iter()
+    .map(|row| {
+        let content_hash: String = row.get("content_hash");
+        RecordSignature {
+            id: row.get("id"),
+            record_type: row.get("record_type"),
+            record_id: row.get("record_id"),
+            meaning: SignatureMeaning::from_str(row.get("meaning")),
+            user_id: row.get("user_id"),
+            username: row.get("username"),
+            reason: row.get("reason"),
+            signed_at: row.get("signed_at"),
+            valid: content_hash == current_hash,
+            content_hash,
+        }
+    })
+    .collect()
+}
+
+/// Record a review exception found in a batch. `caused_by` is the user
+/// whose action raised it, if any.
+pub async fn insert_batch_exception<'e>(
+    executor: impl sqlx::SqliteExecutor<'e>,
+    batch_id: &str,
+    kind: ExceptionKind,
+    reference: &str,
+    description: &str,
+    occurred_at: &str,
+    caused_by: Option<&str>,
+) -> Result<i64, String> {
+    let result = sqlx::query(
+        "INSERT INTO batch_exceptions (batch_id, kind, reference, description, occurred_at, caused_by)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(batch_id)
+    .bind(kind.as_str())
+    .bind(reference)
+    .bind(description)
+    .bind(occurred_at)
+    .bind(caused_by)
+    .execute(executor)
+    .await
+    .map_err(|e| format!("Failed to store batch exception: {e}"))?;
+    Ok(result.last_insert_rowid())
+}
+
+/// Review exceptions of a batch in the order they occurred.
+pub async fn list_batch_exceptions(pool: &SqlitePool, batch_id: &str) -> Vec<BatchException> {
+    sqlx::query(
+        "SELECT id, batch_id, kind, reference, description, occurred_at, caused_by, reviewed_by, reviewed_at, comment
+         FROM batch_exceptions WHERE batch_id = ? ORDER BY occurred_at, id",
+    )
+    .bind(batch_id)
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default()
+    .iter()
+    .map(|row| BatchException {
+        id: row.get("id"),
+        batch_id: row.get("batch_id"),
+        kind: ExceptionKind::from_str(row.get("kind")),
+        reference: row.get("reference"),
+        description: row.get("description"),
+        occurred_at: row.get("occurred_at"),
+        caused_by: row.get("caused_by"),
+        reviewed_by: row.get("reviewed_by"),
+        reviewed_at: row.get("reviewed_at"),
+        comment: row.get("comment"),
+    })
+    .collect()
+}
+
+/// Sign off one open exception of a batch.
+pub async fn sign_off_batch_exception(
+    pool: &SqlitePool,
+    batch_id: &str,
+    exception_id: i64,
+    reviewer: &str,
+    comment: &str,
+) -> Result<(), String> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let result = sqlx::query(
+        "UPDATE batch_exceptions SET reviewed_by = ?, reviewed_at = ?, comment = ?
+         WHERE id = ? AND batch_id = ? AND reviewed_by IS NULL",
+    )
+    .bind(reviewer)
+    .bind(&now)
+    .bind(comment)
+    .bind(exception_id)
+    .bind(batch_id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to sign off exception: {e}"))?;
+    if result.rows_affected() == 0 {
+        return Err(format!("Exception {exception_id} of batch {batch_id} not found or already signed off"));
+    }
+    Ok(())
+}
+
+/// Approve or reject a batch under review.
+pub async fn set_batch_disposition<'e>(
+    executor: impl sqlx::SqliteExecutor<'e>,
+    batch_id: &str,
+    to: BatchStatus,
+    reviewer: &str,
+    reason: &str,
+) -> Result<(), String> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let result = sqlx::query(
+        "UPDATE batch_records SET status = ?, disposition_by = ?, disposition_at = ?, disposition_reason = ?
+         WHERE batch_id = ? AND status = 'under_review'",
+    )
+    .bind(to.as_str())
+    .bind(reviewer)
+    .bind(&now)
+    .bind(reason)
+    .bind(batch_id)
+    .execute(executor)
+    .await
+    .map_err(|e| format!("Failed to update batch: {e}"))?;
+    if result.rows_affected() == 0 {
+        return Err(format!("Batch {batch_id} is not under review"));
+    }
+    Ok(())
+}
+
+const LOT_COLUMNS: &str = "id, material, lot_number, expiry, created_at";
+
+fn row_to_lot(row: &SqliteRow) -> MaterialLot {
+    MaterialLot {
+        id: row.get("id"),
+        material: row.get("material"),
+        lot_number: row.get("lot_number"),
+        expiry: row.get("expiry"),
+        created_at: row.get("created_at"),
+    }
+}
+
+/// Find a lot by material and lot number, registering it on first use.
+/// An expiry fills in an unknown one but must match a recorded one.
+pub async fn get_or_create_lot(
+    pool: &SqlitePool,
+    material: &str,
+    lot_number: &str,
+    expiry: Option<&str>,
+) -> Result<MaterialLot, String> {
+    let now = chrono::Utc::now().to_rfc3339();
+    sqlx::query(
+        "INSERT INTO material_lots (material, lot_number, expiry, created_at) VALUES (?, ?, ?, ?)
+         ON CONFLICT(material, lot_number) DO NOTHING",
+    )
+    .bind(material)
+    .bind(lot_number)
+    .bind(expiry)
+    .bind(&now)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to store lot: {e}"))?;
+
+    let mut lot = sqlx::query(&format!("SELECT {LOT_COLUMNS} FROM material_lots WHERE material = ? AND lot_number = ?"))
+        .bind(material)
+        .bind(lot_number)
+        .fetch_one(pool)
+        .await
+        .map(|row| row_to_lot(&row))
+        .map_err(|e| format!("Failed to load lot: {e}"))?;
+
+    match (expiry, lot.expiry.as_deref()) {
+        (Some(given), Some(recorded)) if given != recorded => {
+            return Err(format!("Lot {lot_number} of {material} is recorded with expiry {recorded}"));
+        }
+        (Some(given), None) => {
+            sqlx::query("UPDATE material_lots SET expiry = ? WHERE id = ?")
+                .bind(given)
+                .bind(lot.id)
+                .execute(pool)
+                .await
+                .map_err(|e| format!("Failed to update lot: {e}"))?;
+            lot.expiry = Some(given.to_string());
+        }
+        _ => {}
+    }
+    Ok(lot)
+}
+
+/// A lot by material and lot number, if it has been registered.
+pub async fn find_lot(pool: &SqlitePool, material: &str, lot_number: &str) -> Option<MaterialLot> {
+    sqlx::query(&format!("SELECT {LOT_COLUMNS} FROM material_lots WHERE material = ? AND lot_number = ?"))
+        .bind(material)
+        .bind(lot_number)
+        .fetch_optional(pool)
+        .await
+        .ok()?
+        .as_ref()
+        .map(row_to_lot)
+}
+
+pub async fn get_lot(pool: &SqlitePool, lot_id: i64) -> Option<MaterialLot> {
+    sqlx::query(&format!("SELECT {LOT_COLUMNS} FROM material_lots WHERE id = ?"))
+        .bind(lot_id)
+        .fetch_optional(pool)
+        .await
+        .ok()?
+        .as_ref()
+        .map(row_to_lot)
+}
+
+/// List lots with optional filters, most recent first.
+pub async fn list_lots(pool: &SqlitePool, params: &LotQueryParams) -> Vec<MaterialLot> {
+    let limit = params.limit.unwrap_or(100);
+
+    let mut qb = sqlx::QueryBuilder::<sqlx::Sqlite>::new(format!("SELECT {LOT_COLUMNS} FROM material_lots WHERE 1 = 1"));
+    if let Some(ref material) = params.material {
+        qb.push(" AND material = ").push_bind(material);
+    }
+    if let Some(ref lot_number) = params.lot_number {
+        qb.push(" AND lot_number = ").push_bind(lot_number);
+    }
+    qb.push(" ORDER BY id DESC LIMIT ").push_bind(limit);
+
+    qb.build()
         .fetch_all(pool)
         .await
         .unwrap_or_default()
-    };
-
-    rows.into_iter()
-        .map(|(id, device_id, register, label, priority, state, value, threshold, message,
-               timestamp, acked_by, acked_at, shelved_until, shelved_by, cleared_at)| {
-            Alarm {
-                id,
-                device_id,
-                register: register as u16,
-                label,
-                priority: AlarmPriority::from_i32(priority),
-                state: AlarmState::from_str(&state),
-                value,
-                threshold,
-                message,
-                timestamp,
-                acked_by,
-                acked_at,
-                shelved_until,
-                shelved_by,
-                cleared_at,
-            }
-        })
+        .iter()
+        .map(row_to_lot)
         .collect()
 }
 
-/// Get a single alarm by ID.
-pub async fn get_alarm(pool: &SqlitePool, alarm_id: i64) -> Option<Alarm> {
-    let row = sqlx::query_as::<_, (
-        i64, String, i64, String, i32, String, f64, f64, String,
-        String, Option<String>, Option<String>, Option<String>, Option<String>, Option<String>,
-    )>(
-        "SELECT id, device_id, register, label, priority, state, value, threshold, message,
-                timestamp, acked_by, acked_at, shelved_until, shelved_by, cleared_at
-         FROM alarms WHERE id = ?",
+/// Record a lot consumed or produced by a batch.
+pub async fn insert_batch_material(
+    pool: &SqlitePool,
+    batch_id: &str,
+    lot_id: i64,
+    direction: MaterialDirection,
+    quantity: f64,
+    unit: &str,
+    recorded_by: &str,
+) -> Result<i64, String> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let result = sqlx::query(
+        "INSERT INTO batch_materials (batch_id, lot_id, direction, quantity, unit, recorded_by, recorded_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
     )
-    .bind(alarm_id)
-    .fetch_optional(pool)
+    .bind(batch_id)
+    .bind(lot_id)
+    .bind(direction.as_str())
+    .bind(quantity)
+    .bind(unit)
+    .bind(recorded_by)
+    .bind(&now)
+    .execute(pool)
     .await
-    .ok()??;
-
-    Some(Alarm {
-        id: row.0,
-        device_id: row.1,
-        register: row.2 as u16,
-        label: row.3,
-        priority: AlarmPriority::from_i32(row.4),
-        state: AlarmState::from_str(&row.5),
-        value: row.6,
-        threshold: row.7,
-        message: row.8,
-        timestamp: row.9,
-        acked_by: row.10,
-        acked_at: row.11,
-        shelved_until: row.12,
-        shelved_by: row.13,
-        cleared_at: row.14,
-    })
+    .map_err(|e| format!("Failed to record material: {e}"))?;
+    Ok(result.last_insert_rowid())
 }
 
-// ── ISA-88: Batch record operations ─────────────────────────────
+const BATCH_MATERIAL_SELECT: &str =
+    "SELECT m.id, m.batch_id, m.lot_id, l.material, l.lot_number, l.expiry, m.direction, m.quantity, m.unit,
+            m.recorded_by, m.recorded_at
+     FROM batch_materials m JOIN material_lots l ON l.id = m.lot_id";
+
+fn row_to_batch_material(row: &SqliteRow) -> BatchMaterial {
+    BatchMaterial {
+        id: row.get("id"),
+        batch_id: row.get("batch_id"),
+        lot_id: row.get("lot_id"),
+        material: row.get("material"),
+        lot_number: row.get("lot_number"),
+        expiry: row.get("expiry"),
+        direction: MaterialDirection::from_str(row.get("direction")),
+        quantity: row.get("quantity"),
+        unit: row.get("unit"),
+        recorded_by: row.get("recorded_by"),
+        recorded_at: row.get("recorded_at"),
+    }
+}
+
+/// Lots a batch consumed and produced, in the order recorded.
+pub async fn list_batch_materials(pool: &SqlitePool, batch_id: &str) -> Vec<BatchMaterial> {
+    sqlx::query(&format!("{BATCH_MATERIAL_SELECT} WHERE m.batch_id = ? ORDER BY m.id"))
+        .bind(batch_id)
+        .fetch_all(pool)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(row_to_batch_material)
+        .collect()
+}
+
+/// Every batch that consumed or produced a lot.
+pub async fn list_lot_links(pool: &SqlitePool, lot_id: i64) -> Vec<BatchMaterial> {
+    sqlx::query(&format!("{BATCH_MATERIAL_SELECT} WHERE m.lot_id = ? ORDER BY m.id"))
+        .bind(lot_id)
+        .fetch_all(pool)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(row_to_batch_material)
+        .collect()
+}
 
 /// Create a new batch record.
 pub async fn create_batch(
@@ -629,6 +1982,44 @@ pub async fn create_batch(
     Some(result.last_insert_rowid())
 }
 
+/// Create a batch record for an approved recipe version.
+pub async fn create_recipe_batch(
+    pool: &SqlitePool,
+    batch_id: &str,
+    recipe: &Recipe,
+    device_id: &str,
+    operator: &str,
+) -> Result<i64, String> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let result = sqlx::query(
+        "INSERT INTO batch_records (batch_id, recipe_name, device_id, operator, status, start_time, recipe_id, recipe_version)
+         VALUES (?, ?, ?, ?, 'running', ?, ?, ?)",
+    )
+    .bind(batch_id)
+    .bind(&recipe.name)
+    .bind(device_id)
+    .bind(operator)
+    .bind(&now)
+    .bind(recipe.id)
+    .bind(recipe.version)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to create batch record: {e}"))?;
+
+    Ok(result.last_insert_rowid())
+}
+
+/// Replace the parameters recorded on a batch step.
+pub async fn set_batch_step_parameters(pool: &SqlitePool, step_id: i64, parameters: &str) -> Result<(), String> {
+    sqlx::query("UPDATE batch_steps SET parameters = ? WHERE id = ?")
+        .bind(parameters)
+        .bind(step_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to update batch step: {e}"))?;
+    Ok(())
+}
+
 /// Update batch status.
 pub async fn update_batch_status(
     pool: &SqlitePool,
@@ -657,6 +2048,49 @@ pub async fn update_batch_status(
     Ok(())
 }
 
+/// Move a batch to `to` on behalf of `operator`, provided it is currently
+/// in one of the `from` states. Completing or aborting stamps the end time
+/// and `ended_by`.
+pub async fn transition_batch<'e>(
+    executor: impl sqlx::SqliteExecutor<'e>,
+    batch_id: &str,
+    from: &[BatchStatus],
+    to: BatchStatus,
+    operator: &str,
+    notes: Option<&str>,
+) -> Result<(), String> {
+    let ending = matches!(to, BatchStatus::Completed | BatchStatus::Aborted);
+    let end_time = ending.then(|| chrono::Utc::now().to_rfc3339());
+    let ended_by = ending.then_some(operator);
+
+    let mut qb = sqlx::QueryBuilder::<sqlx::Sqlite>::new("UPDATE batch_records SET status = ");
+    qb.push_bind(to.as_str())
+        .push(", end_time = COALESCE(")
+        .push_bind(end_time)
+        .push(", end_time), ended_by = COALESCE(")
+        .push_bind(ended_by)
+        .push(", ended_by), notes = COALESCE(")
+        .push_bind(notes)
+        .push(", notes) WHERE batch_id = ")
+        .push_bind(batch_id)
+        .push(" AND status IN (");
+    let mut separated = qb.separated(", ");
+    for status in from {
+        separated.push_bind(status.as_str());
+    }
+    qb.push(")");
+
+    let result = qb
+        .build()
+        .execute(executor)
+        .await
+        .map_err(|e| format!("Failed to update batch: {e}"))?;
+    if result.rows_affected() == 0 {
+        return Err(format!("Batch {batch_id} cannot move to {}", to.as_str()));
+    }
+    Ok(())
+}
+
 /// Add a step to a batch record.
 pub async fn add_batch_step(
     pool: &SqlitePool,
@@ -682,6 +2116,106 @@ pub async fn add_batch_step(
     Some(result.last_insert_rowid())
 }
 
+/// The step of a batch still running (id, start time), if any.
+pub async fn get_open_batch_step(pool: &SqlitePool, batch_record_id: i64) -> Option<(i64, String)> {
+    sqlx::query_as::<_, (i64, String)>(
+        "SELECT id, start_time FROM batch_steps WHERE batch_record_id = ? AND status = 'running'
+         ORDER BY step_number DESC LIMIT 1",
+    )
+    .bind(batch_record_id)
+    .fetch_optional(pool)
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Step number to use for the next step of a batch.
+pub async fn next_batch_step_number(pool: &SqlitePool, batch_record_id: i64) -> i32 {
+    sqlx::query_scalar::<_, i32>("SELECT COALESCE(MAX(step_number), 0) + 1 FROM batch_steps WHERE batch_record_id = ?")
+        .bind(batch_record_id)
+        .fetch_one(pool)
+        .await
+        .unwrap_or(1)
+}
+
+/// Min/max/average of one register's readings in `[from, to]`.
+/// `None` when there were no readings.
+pub async fn register_stats(
+    pool: &SqlitePool,
+    device_id: &str,
+    register: u16,
+    from: &str,
+    to: &str,
+) -> Option<RegisterStats> {
+    let (min, max, avg, samples) = sqlx::query_as::<_, (Option<f64>, Option<f64>, Option<f64>, i64)>(
+        "SELECT MIN(value), MAX(value), AVG(value), COUNT(*) FROM plc_readings
+         WHERE device_id = ? AND register = ? AND timestamp >= ? AND timestamp <= ?",
+    )
+    .bind(device_id)
+    .bind(register as i64)
+    .bind(from)
+    .bind(to)
+    .fetch_one(pool)
+    .await
+    .ok()?;
+
+    Some(RegisterStats {
+        min: min?,
+        max: max?,
+        avg: avg?,
+        samples,
+    })
+}
+
+/// Min/max/average of every register of a device read in `[from, to]`,
+/// ordered by register.
+pub async fn register_stats_by_register(
+    pool: &SqlitePool,
+    device_id: &str,
+    from: &str,
+    to: &str,
+) -> Vec<(u16, RegisterStats)> {
+    sqlx::query_as::<_, (i64, f64, f64, f64, i64)>(
+        "SELECT register, MIN(value), MAX(value), AVG(value), COUNT(*) FROM plc_readings
+         WHERE device_id = ? AND timestamp >= ? AND timestamp <= ?
+         GROUP BY register ORDER BY register",
+    )
+    .bind(device_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .map(|(register, min, max, avg, samples)| (register as u16, RegisterStats { min, max, avg, samples }))
+    .collect()
+}
+
+/// Approved batches of a recipe other than `exclude`, most recent first.
+pub async fn list_approved_batches(pool: &SqlitePool, recipe_name: &str, exclude: &[&str], limit: i64) -> Vec<BatchRecord> {
+    let mut qb = sqlx::QueryBuilder::<sqlx::Sqlite>::new(format!(
+        "SELECT {BATCH_COLUMNS} FROM batch_records WHERE status = 'approved' AND recipe_name = "
+    ));
+    qb.push_bind(recipe_name);
+    if !exclude.is_empty() {
+        qb.push(" AND batch_id NOT IN (");
+        let mut separated = qb.separated(", ");
+        for batch_id in exclude {
+            separated.push_bind(*batch_id);
+        }
+        qb.push(")");
+    }
+    qb.push(" ORDER BY id DESC LIMIT ").push_bind(limit);
+
+    qb.build()
+        .fetch_all(pool)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(row_to_batch)
+        .collect()
+}
+
 /// Complete a batch step.
 pub async fn complete_batch_step(
     pool: &SqlitePool,
@@ -702,91 +2236,61 @@ pub async fn complete_batch_step(
     Ok(())
 }
 
+const BATCH_COLUMNS: &str =
+    "id, batch_id, recipe_name, device_id, operator, status, start_time, end_time, notes, recipe_id, recipe_version, ended_by, \
+     disposition_by, disposition_at, disposition_reason";
+
+fn row_to_batch(row: &SqliteRow) -> BatchRecord {
+    BatchRecord {
+        id: row.get("id"),
+        batch_id: row.get("batch_id"),
+        recipe_name: row.get("recipe_name"),
+        device_id: row.get("device_id"),
+        operator: row.get("operator"),
+        status: BatchStatus::from_str(row.get("status")),
+        start_time: row.get("start_time"),
+        end_time: row.get("end_time"),
+        notes: row.get("notes"),
+        recipe_id: row.get("recipe_id"),
+        recipe_version: row.get("recipe_version"),
+        ended_by: row.get("ended_by"),
+        disposition_by: row.get("disposition_by"),
+        disposition_at: row.get("disposition_at"),
+        disposition_reason: row.get("disposition_reason"),
+    }
+}
+
 /// List batch records with optional filters.
 pub async fn list_batches(pool: &SqlitePool, params: &BatchQueryParams) -> Vec<BatchRecord> {
     let limit = params.limit.unwrap_or(100);
 
-    let rows = if params.device_id.is_some() && params.status.is_some() {
-        sqlx::query_as::<_, (i64, String, String, String, String, String, String, Option<String>, Option<String>)>(
-            "SELECT id, batch_id, recipe_name, device_id, operator, status, start_time, end_time, notes
-             FROM batch_records WHERE device_id = ? AND status = ? ORDER BY id DESC LIMIT ?"
-        )
-        .bind(params.device_id.as_deref().unwrap())
-        .bind(params.status.as_deref().unwrap())
-        .bind(limit)
-        .fetch_all(pool)
-        .await
-        .unwrap_or_default()
-    } else if params.device_id.is_some() {
-        sqlx::query_as::<_, (i64, String, String, String, String, String, String, Option<String>, Option<String>)>(
-            "SELECT id, batch_id, recipe_name, device_id, operator, status, start_time, end_time, notes
-             FROM batch_records WHERE device_id = ? ORDER BY id DESC LIMIT ?"
-        )
-        .bind(params.device_id.as_deref().unwrap())
-        .bind(limit)
-        .fetch_all(pool)
-        .await
-        .unwrap_or_default()
-    } else if params.status.is_some() {
-        sqlx::query_as::<_, (i64, String, String, String, String, String, String, Option<String>, Option<String>)>(
-            "SELECT id, batch_id, recipe_name, device_id, operator, status, start_time, end_time, notes
-             FROM batch_records WHERE status = ? ORDER BY id DESC LIMIT ?"
-        )
-        .bind(params.status.as_deref().unwrap())
-        .bind(limit)
-        .fetch_all(pool)
-        .await
-        .unwrap_or_default()
-    } else {
-        sqlx::query_as::<_, (i64, String, String, String, String, String, String, Option<String>, Option<String>)>(
-            "SELECT id, batch_id, recipe_name, device_id, operator, status, start_time, end_time, notes
-             FROM batch_records ORDER BY id DESC LIMIT ?"
-        )
-        .bind(limit)
+    let mut qb = sqlx::QueryBuilder::<sqlx::Sqlite>::new(format!("SELECT {BATCH_COLUMNS} FROM batch_records WHERE 1 = 1"));
+    if let Some(ref device_id) = params.device_id {
+        qb.push(" AND device_id = ").push_bind(device_id);
+    }
+    if let Some(ref status) = params.status {
+        qb.push(" AND status = ").push_bind(status);
+    }
+    qb.push(" ORDER BY id DESC LIMIT ").push_bind(limit);
+
+    qb.build()
         .fetch_all(pool)
         .await
         .unwrap_or_default()
-    };
-
-    rows.into_iter()
-        .map(|(id, batch_id, recipe_name, device_id, operator, status, start_time, end_time, notes)| {
-            BatchRecord {
-                id,
-                batch_id,
-                recipe_name,
-                device_id,
-                operator,
-                status: BatchStatus::from_str(&status),
-                start_time,
-                end_time,
-                notes,
-            }
-        })
+        .iter()
+        .map(row_to_batch)
         .collect()
 }
 
 /// Get a single batch record with its steps.
 pub async fn get_batch_with_steps(pool: &SqlitePool, batch_id: &str) -> Option<(BatchRecord, Vec<BatchStep>)> {
-    let row = sqlx::query_as::<_, (i64, String, String, String, String, String, String, Option<String>, Option<String>)>(
-        "SELECT id, batch_id, recipe_name, device_id, operator, status, start_time, end_time, notes
-         FROM batch_records WHERE batch_id = ?"
-    )
-    .bind(batch_id)
-    .fetch_optional(pool)
-    .await
-    .ok()??;
-
-    let record = BatchRecord {
-        id: row.0,
-        batch_id: row.1,
-        recipe_name: row.2,
-        device_id: row.3,
-        operator: row.4,
-        status: BatchStatus::from_str(&row.5),
-        start_time: row.6,
-        end_time: row.7,
-        notes: row.8,
-    };
+    let record = sqlx::query(&format!("SELECT {BATCH_COLUMNS} FROM batch_records WHERE batch_id = ?"))
+        .bind(batch_id)
+        .fetch_optional(pool)
+        .await
+        .ok()?
+        .as_ref()
+        .map(row_to_batch)?;
 
     let step_rows = sqlx::query_as::<_, (i64, i64, i32, String, String, String, Option<String>, Option<String>, Option<String>)>(
         "SELECT id, batch_record_id, step_number, name, status, start_time, end_time, parameters, result
@@ -815,4 +2319,273 @@ pub async fn get_batch_with_steps(pool: &SqlitePool, batch_id: &str) -> Option<(
         .collect();
 
     Some((record, steps))
-}
\ No newline at end of file
+}
+
+// ── ISA-88: Master recipes ──────────────────────────────────────
+
+const RECIPE_COLUMNS: &str = "id, name, version, status, description, target_temperature, hold_time_minutes,
+    agitator_rpm, register_writes, created_by, created_at, updated_by, updated_at, approved_by, approved_at";
+
+fn row_to_recipe(row: &SqliteRow) -> Recipe {
+    Recipe {
+        id: row.get("id"),
+        name: row.get("name"),
+        version: row.get("version"),
+        status: RecipeStatus::from_str(row.get("status")),
+        description: row.get("description"),
+        target_temperature: row.get("target_temperature"),
+        hold_time_minutes: row.get("hold_time_minutes"),
+        agitator_rpm: row.get("agitator_rpm"),
+        register_writes: serde_json::from_str(row.get("register_writes")).unwrap_or_default(),
+        created_by: row.get("created_by"),
+        created_at: row.get("created_at"),
+        updated_by: row.get("updated_by"),
+        updated_at: row.get("updated_at"),
+        approved_by: row.get("approved_by"),
+        approved_at: row.get("approved_at"),
+    }
+}
+
+/// List recipe versions, newest first.
+pub async fn list_recipes(pool: &SqlitePool, params: &RecipeQueryParams) -> Vec<Recipe> {
+    let limit = params.limit.unwrap_or(200);
+
+    let mut qb = sqlx::QueryBuilder::<sqlx::Sqlite>::new(format!("SELECT {RECIPE_COLUMNS} FROM recipes WHERE 1 = 1"));
+    if let Some(ref name) = params.name {
+        qb.push(" AND name = ").push_bind(name);
+    }
+    if let Some(ref status) = params.status {
+        qb.push(" AND status = ").push_bind(status);
+    }
+    qb.push(" ORDER BY name, version DESC LIMIT ").push_bind(limit);
+
+    qb.build()
+        .fetch_all(pool)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(row_to_recipe)
+        .collect()
+}
+
+pub async fn get_recipe(pool: &SqlitePool, id: i64) -> Option<Recipe> {
+    sqlx::query(&format!("SELECT {RECIPE_COLUMNS} FROM recipes WHERE id = ?"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .ok()?
+        .as_ref()
+        .map(row_to_recipe)
+}
+
+/// The approved version of a recipe — the one batches run.
+pub async fn get_approved_recipe(pool: &SqlitePool, name: &str) -> Option<Recipe> {
+    sqlx::query(&format!("SELECT {RECIPE_COLUMNS} FROM recipes WHERE name = ? AND status = 'approved'"))
+        .bind(name)
+        .fetch_optional(pool)
+        .await
+        .ok()?
+        .as_ref()
+        .map(row_to_recipe)
+}
+
+/// Create version 1 of a new recipe as a draft. Returns the new ID.
+pub async fn create_recipe(pool: &SqlitePool, req: &RecipeRequest, username: &str) -> Result<i64, String> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let writes = serde_json::to_string(&req.register_writes).unwrap_or_else(|_| "[]".into());
+    let result = sqlx::query(
+        "INSERT INTO recipes (name, version, status, description, target_temperature, hold_time_minutes,
+            agitator_rpm, register_writes, created_by, created_at, updated_by, updated_at)
+         VALUES (?, 1, 'draft', ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(req.name.trim())
+    .bind(&req.description)
+    .bind(req.target_temperature)
+    .bind(req.hold_time_minutes)
+    .bind(req.agitator_rpm)
+    .bind(&writes)
+    .bind(username)
+    .bind(&now)
+    .bind(username)
+    .bind(&now)
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        if e.to_string().contains("UNIQUE") {
+            format!("Recipe '{}' already exists — create a new version instead", req.name.trim())
+        } else {
+            format!("Failed to create recipe: {e}")
+        }
+    })?;
+    Ok(result.last_insert_rowid())
+}
+
+/// Check that a recipe version can be edited with `req`: it must be a
+/// draft and keep its name. Run before consuming the e-signature token.
+pub async fn check_recipe_update(pool: &SqlitePool, id: i64, req: &RecipeRequest) -> Result<Recipe, String> {
+    let recipe = get_recipe(pool, id).await.ok_or("Recipe not found")?;
+    if recipe.status != RecipeStatus::Draft {
+        return Err(format!("Only draft recipes can be edited (v{} is {})", recipe.version, recipe.status.as_str()));
+    }
+    if req.name.trim() != recipe.name {
+        return Err("Recipe name cannot be changed".into());
+    }
+    Ok(recipe)
+}
+
+/// Edit the parameter set of a draft recipe version.
+pub async fn update_recipe(pool: &SqlitePool, id: i64, req: &RecipeRequest, username: &str) -> Result<(), String> {
+    let recipe = check_recipe_update(pool, id, req).await?;
+
+    let now = chrono::Utc::now().to_rfc3339();
+    let writes = serde_json::to_string(&req.register_writes).unwrap_or_else(|_| "[]".into());
+    let result = sqlx::query(
+        "UPDATE recipes SET description = ?, target_temperature = ?, hold_time_minutes = ?, agitator_rpm = ?,
+            register_writes = ?, updated_by = ?, updated_at = ?
+         WHERE id = ? AND status = 'draft'",
+    )
+    .bind(&req.description)
+    .bind(req.target_temperature)
+    .bind(req.hold_time_minutes)
+    .bind(req.agitator_rpm)
+    .bind(&writes)
+    .bind(username)
+    .bind(&now)
+    .bind(id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to update recipe: {e}"))?;
+
+    if result.rows_affected() == 0 {
+        return Err(format!("Recipe v{} is no longer a draft", recipe.version));
+    }
+    Ok(())
+}
+
+/// Check that a recipe version exists to copy into a new draft. Run before
+/// consuming the e-signature token.
+pub async fn check_recipe_new_version(pool: &SqlitePool, id: i64) -> Result<Recipe, String> {
+    get_recipe(pool, id).await.ok_or_else(|| "Recipe not found".to_string())
+}
+
+/// Copy a recipe version into a new draft version. Returns the new ID.
+pub async fn new_recipe_version(pool: &SqlitePool, id: i64, username: &str) -> Result<i64, String> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let result = sqlx::query(
+        "INSERT INTO recipes (name, version, status, description, target_temperature, hold_time_minutes,
+            agitator_rpm, register_writes, created_by, created_at, updated_by, updated_at)
+         SELECT name, (SELECT MAX(version) FROM recipes r WHERE r.name = recipes.name) + 1, 'draft',
+            description, target_temperature, hold_time_minutes, agitator_rpm, register_writes, ?, ?, ?, ?
+         FROM recipes WHERE id = ?",
+    )
+    .bind(username)
+    .bind(&now)
+    .bind(username)
+    .bind(&now)
+    .bind(id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to create recipe version: {e}"))?;
+
+    if result.rows_affected() == 0 {
+        return Err("Recipe not found".into());
+    }
+    Ok(result.last_insert_rowid())
+}
+
+/// Only drafts can be approved, and never by their author or last editor
+/// (separation of duties).
+fn refuse_recipe_approval(recipe: &Recipe, username: &str) -> Result<(), String> {
+    if recipe.status != RecipeStatus::Draft {
+        return Err(format!("Only draft recipes can be approved (v{} is {})", recipe.version, recipe.status.as_str()));
+    }
+    if username == recipe.created_by || username == recipe.updated_by {
+        return Err(format!("'{username}' authored this recipe version and cannot approve it"));
+    }
+    Ok(())
+}
+
+/// Check that `username` may approve a recipe version. Run before
+/// consuming the e-signature token.
+pub async fn check_recipe_approval(pool: &SqlitePool, id: i64, username: &str) -> Result<Recipe, String> {
+    let recipe = get_recipe(pool, id).await.ok_or("Recipe not found")?;
+    refuse_recipe_approval(&recipe, username)?;
+    Ok(recipe)
+}
+
+/// Approve a draft version. The previously approved version of the same
+/// recipe becomes obsolete.
+pub async fn approve_recipe(pool: &SqlitePool, id: i64, username: &str) -> Result<(), String> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let mut tx = pool.begin().await.map_err(|e| format!("DB error: {e}"))?;
+
+    // Checked inside the transaction so a concurrent approval cannot interleave
+    let recipe = sqlx::query(&format!("SELECT {RECIPE_COLUMNS} FROM recipes WHERE id = ?"))
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| format!("DB error: {e}"))?
+        .as_ref()
+        .map(row_to_recipe)
+        .ok_or("Recipe not found")?;
+    refuse_recipe_approval(&recipe, username)?;
+
+    let result = sqlx::query(
+        "UPDATE recipes SET status = 'approved', approved_by = ?, approved_at = ? WHERE id = ? AND status = 'draft'",
+    )
+    .bind(username)
+    .bind(&now)
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| format!("DB error: {e}"))?;
+    if result.rows_affected() == 0 {
+        // Dropping the transaction rolls it back
+        return Err(format!("Recipe v{} is no longer a draft", recipe.version));
+    }
+    sqlx::query(
+        "UPDATE recipes SET status = 'obsolete', updated_by = ?, updated_at = ?
+         WHERE name = ? AND status = 'approved' AND id != ?",
+    )
+    .bind(username)
+    .bind(&now)
+    .bind(&recipe.name)
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| format!("DB error: {e}"))?;
+    tx.commit().await.map_err(|e| format!("DB error: {e}"))?;
+    Ok(())
+}
+
+/// Check that a recipe version exists and is not already obsolete. Run
+/// before consuming the e-signature token.
+pub async fn check_recipe_obsolete(pool: &SqlitePool, id: i64) -> Result<Recipe, String> {
+    let recipe = get_recipe(pool, id).await.ok_or("Recipe not found")?;
+    if recipe.status == RecipeStatus::Obsolete {
+        return Err("Recipe is already obsolete".into());
+    }
+    Ok(recipe)
+}
+
+/// Retire a recipe version so it can no longer be edited or run.
+pub async fn obsolete_recipe(pool: &SqlitePool, id: i64, username: &str) -> Result<(), String> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let result = sqlx::query(
+        "UPDATE recipes SET status = 'obsolete', updated_by = ?, updated_at = ? WHERE id = ? AND status != 'obsolete'",
+    )
+    .bind(username)
+    .bind(&now)
+    .bind(id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("DB error: {e}"))?;
+
+    if result.rows_affected() == 0 {
+        return match get_recipe(pool, id).await {
+            Some(_) => Err("Recipe is already obsolete".into()),
+            None => Err("Recipe not found".into()),
+        };
+    }
+    Ok(())
+}
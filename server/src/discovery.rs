@@ -182,19 +182,17 @@ pub async fn browse_opcua_nodes(
             .map_err(|e| format!("Browse failed: {:?}", e))?;
 
         let mut nodes = Vec::new();
-        if let Some(refs_vec) = results {
-          if let Some(result) = refs_vec.first() {
-            if let Some(refs) = &result.references {
-                for r in refs {
-                    nodes.push(OpcUaNode {
-                        node_id: r.node_id.node_id.to_string(),
-                        browse_name: r.browse_name.name.to_string(),
-                        display_name: r.display_name.text.to_string(),
-                        node_class: format!("{:?}", r.node_class),
-                    });
-                }
+        if let Some(refs_vec) = results
+            && let Some(result) = refs_vec.first()
+            && let Some(refs) = &result.references {
+            for r in refs {
+                nodes.push(OpcUaNode {
+                    node_id: r.node_id.node_id.to_string(),
+                    browse_name: r.browse_name.name.to_string(),
+                    display_name: r.display_name.text.to_string(),
+                    node_class: format!("{:?}", r.node_class),
+                });
             }
-          }
         }
 
         Ok(nodes)
@@ -0,0 +1,162 @@
+//! Unacknowledged-Alarm Escalation
+//!
+//! Background task that walks the configured escalation policies against
+//! the `alarms` table. An alarm still unacknowledged `after_minutes` after
+//! it last became active notifies the policy's role tier by webhook and/or
+//! email. Each policy notifies an alarm once per activation, retrying on
+//! the next check until an attempt succeeds; every attempt is recorded in
+//! `alarm_notifications`, the alarm journal and the audit trail.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+use tokio::task::JoinHandle;
+
+use crate::auth;
+use crate::config::{EscalationConfig, EscalationPolicy};
+use crate::db;
+use crate::models::Alarm;
+use crate::notify;
+
+/// Minutes the alarm has been standing unacknowledged.
+fn unacked_minutes(alarm: &Alarm, now: DateTime<Utc>) -> i64 {
+    DateTime::parse_from_rfc3339(alarm.active_since.as_deref().unwrap_or(&alarm.timestamp))
+        .map(|t| (now - t.with_timezone(&Utc)).num_minutes())
+        .unwrap_or(0)
+}
+
+fn email_subject(alarm: &Alarm) -> String {
+    format!(
+        "[HMI] Unacknowledged {} alarm #{}: {} on {}",
+        alarm.priority.as_str().to_uppercase(),
+        alarm.id,
+        alarm.label,
+        alarm.device_id
+    )
+}
+
+fn email_body(alarm: &Alarm, policy: &EscalationPolicy, minutes: i64) -> String {
+    format!(
+        "Escalation policy '{}' — notifying role '{}'.\n\n\
+         Alarm #{} has not been acknowledged for {} minute(s).\n\n\
+         Device:   {}\nRegister: {}\nLabel:    {}\nPriority: {}\nValue:    {}\nMessage:  {}\nRaised:   {}\n",
+        policy.name,
+        policy.notify_role,
+        alarm.id,
+        minutes,
+        alarm.device_id,
+        alarm.register,
+        alarm.label,
+        alarm.priority.as_str(),
+        alarm.value,
+        alarm.message,
+        alarm.timestamp,
+    )
+}
+
+/// Record one notification attempt in the table, journal and audit trail.
+async fn record(
+    pool: &SqlitePool,
+    alarm: &Alarm,
+    policy: &EscalationPolicy,
+    channel: &str,
+    target: &str,
+    result: &Result<(), String>,
+) {
+    db::record_alarm_notification(pool, alarm.id, &policy.name, &policy.notify_role, channel, target, result).await;
+
+    let outcome = match result {
+        Ok(()) => "sent".to_string(),
+        Err(e) => format!("failed: {e}"),
+    };
+    let summary = format!("{} → {} via {} ({}): {}", policy.name, policy.notify_role, channel, target, outcome);
+    db::record_alarm_event(pool, alarm.id, "notify", "system", Some(&summary), None).await;
+
+    let details = serde_json::json!({
+        "alarm_id": alarm.id,
+        "policy": policy.name,
+        "role": policy.notify_role,
+        "channel": channel,
+        "target": target,
+        "success": result.is_ok(),
+        "error": result.as_ref().err(),
+    });
+    auth::log_audit(pool, "system", "system", "alarm_notify", Some(&alarm.device_id), &details.to_string(), None).await;
+
+    match result {
+        Ok(()) => tracing::info!("[ESCALATION] Alarm #{}: {}", alarm.id, summary),
+        Err(_) => tracing::warn!("[ESCALATION] Alarm #{}: {}", alarm.id, summary),
+    }
+}
+
+/// Evaluate every policy once as of `now`. Returns the number of
+/// notification attempts made (successful or not).
+pub async fn check_escalations(pool: &SqlitePool, config: &EscalationConfig, now: DateTime<Utc>) -> usize {
+    let mut attempts = 0;
+
+    for policy in &config.policies {
+        let active_before = (now - chrono::Duration::minutes(policy.after_minutes as i64)).to_rfc3339();
+        let due = db::list_alarms_due_for_escalation(pool, &policy.name, policy.priority, &active_before).await;
+
+        for alarm in &due {
+            let minutes = unacked_minutes(alarm, now);
+
+            if let Some(url) = &policy.webhook {
+                let payload = serde_json::json!({
+                    "event": "alarm_escalation",
+                    "policy": policy.name,
+                    "notify_role": policy.notify_role,
+                    "unacked_minutes": minutes,
+                    "alarm": alarm,
+                });
+                let result = notify::send_webhook(url, &payload).await;
+                record(pool, alarm, policy, "webhook", url, &result).await;
+                attempts += 1;
+            }
+
+            if !policy.email.is_empty() {
+                let target = policy.email.join(", ");
+                let result = match &config.smtp_relay {
+                    Some(relay) => {
+                        notify::send_email(
+                            relay,
+                            &config.smtp_from,
+                            &policy.email,
+                            &email_subject(alarm),
+                            &email_body(alarm, policy, minutes),
+                        )
+                        .await
+                    }
+                    None => Err("No smtp_relay configured".to_string()),
+                };
+                record(pool, alarm, policy, "email", &target, &result).await;
+                attempts += 1;
+            }
+        }
+    }
+
+    attempts
+}
+
+/// Spawn the periodic escalation check. Returns `None` when no policies
+/// are configured.
+pub fn start_escalation_task(pool: SqlitePool, config: EscalationConfig) -> Option<JoinHandle<()>> {
+    if config.policies.is_empty() {
+        return None;
+    }
+    for policy in &config.policies {
+        if policy.webhook.is_none() && policy.email.is_empty() {
+            tracing::warn!("Escalation policy '{}' has no webhook or email — it will never notify", policy.name);
+        }
+    }
+    tracing::info!("Alarm escalation: {} policy(ies), checking every {}s", config.policies.len(), config.check_interval_secs);
+
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.check_interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            check_escalations(&pool, &config, Utc::now()).await;
+        }
+    }))
+}
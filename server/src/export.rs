@@ -33,9 +33,9 @@ pub async fn export_alarms_csv(
     let mut wtr = csv::Writer::from_writer(Vec::new());
     // Header
     wtr.write_record([
-        "id", "device_id", "register", "label", "priority", "state",
+        "id", "device_id", "register", "label", "kind", "priority", "state",
         "value", "threshold", "message", "timestamp",
-        "acked_by", "acked_at", "shelved_by", "shelved_until", "cleared_at",
+        "acked_by", "acked_at", "shelved_by", "shelved_until", "shelve_reason", "cleared_at", "batch_id", "first_out",
     ]).ok();
 
     for a in &alarms {
@@ -44,6 +44,7 @@ pub async fn export_alarms_csv(
             &a.device_id,
             &a.register.to_string(),
             &a.label,
+            a.kind.as_str(),
             &format!("{:?}", a.priority),
             &format!("{:?}", a.state),
             &a.value.to_string(),
@@ -54,7 +55,10 @@ pub async fn export_alarms_csv(
             a.acked_at.as_deref().unwrap_or(""),
             a.shelved_by.as_deref().unwrap_or(""),
             a.shelved_until.as_deref().unwrap_or(""),
+            a.shelve_reason.as_deref().unwrap_or(""),
             a.cleared_at.as_deref().unwrap_or(""),
+            a.batch_id.as_deref().unwrap_or(""),
+            &a.first_out.to_string(),
         ]).ok();
     }
 
@@ -0,0 +1,169 @@
+//! Material Lots and Genealogy
+//!
+//! Each batch records the lots it consumed (raw materials, with quantity
+//! and expiry) and the lots it produced. A lot is a material plus lot
+//! number, registered on first use; consuming an expired lot is refused.
+//! Materials are part of the signed record, so none can be added once
+//! the batch carries a signature.
+//!
+//! Genealogy walks those links. Forward from a lot reaches every batch
+//! that consumed it, the lots those batches produced, the batches that
+//! consumed those and so on — the list a recall needs. Backward from a
+//! lot reaches the batch that produced it, the lots that batch consumed
+//! and their own origins.
+
+use std::collections::{HashSet, VecDeque};
+
+use sqlx::SqlitePool;
+use tracing::info;
+
+use crate::auth::{self, Claims};
+use crate::batch;
+use crate::db;
+use crate::models::{
+    BatchMaterial, BatchStatus, Genealogy, GenealogyBatch, GenealogyDirection, MaterialDirection, RecordMaterialRequest,
+};
+
+/// Batches further than this from the traced lot are not followed.
+pub const MAX_GENEALOGY_DEPTH: u32 = 20;
+
+/// Record a lot consumed or produced by a batch that is still open for
+/// additions (running, held or completed but not yet signed or under
+/// review). A refused lot is not registered.
+pub async fn record_material(
+    pool: &SqlitePool,
+    batch_id: &str,
+    req: &RecordMaterialRequest,
+    claims: &Claims,
+) -> Result<BatchMaterial, String> {
+    let (material, lot_number, unit) = (req.material.trim(), req.lot_number.trim(), req.unit.trim());
+    if material.is_empty() || lot_number.is_empty() || unit.is_empty() {
+        return Err("material, lot_number and unit are required".to_string());
+    }
+    if !(req.quantity > 0.0 && req.quantity.is_finite()) {
+        return Err("quantity must be a positive number".to_string());
+    }
+    let expiry = req
+        .expiry
+        .as_deref()
+        .map(|e| {
+            chrono::NaiveDate::parse_from_str(e, "%Y-%m-%d").map_err(|_| format!("Invalid expiry '{e}' (expected YYYY-MM-DD)"))
+        })
+        .transpose()?;
+
+    let (record, _) = db::get_batch_with_steps(pool, batch_id).await.ok_or("Batch not found")?;
+    if !matches!(record.status, BatchStatus::Running | BatchStatus::Held | BatchStatus::Completed) {
+        return Err(format!("Batch {batch_id} is {} — materials can no longer be recorded", record.status.as_str()));
+    }
+    if !batch::batch_signatures(pool, batch_id).await.is_empty() {
+        return Err(format!("Batch {batch_id} has been signed — materials can no longer be recorded"));
+    }
+
+    // Check against the lot as registered (or as given) before registering it
+    let expiry = expiry.map(|d| d.format("%Y-%m-%d").to_string());
+    let existing = db::find_lot(pool, material, lot_number).await;
+    match req.direction {
+        MaterialDirection::Consumed => {
+            let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+            let recorded = existing.as_ref().and_then(|l| l.expiry.clone());
+            if let Some(expiry) = recorded.or_else(|| expiry.clone())
+                && expiry < today
+            {
+                return Err(format!("Lot {lot_number} of {material} expired on {expiry}"));
+            }
+        }
+        MaterialDirection::Produced => {
+            if let Some(lot) = &existing {
+                let producer = db::list_lot_links(pool, lot.id)
+                    .await
+                    .into_iter()
+                    .find(|l| l.direction == MaterialDirection::Produced && l.batch_id != batch_id);
+                if let Some(producer) = producer {
+                    return Err(format!("Lot {lot_number} of {material} was produced by batch {}", producer.batch_id));
+                }
+            }
+        }
+    }
+    let lot = db::get_or_create_lot(pool, material, lot_number, expiry.as_deref()).await?;
+
+    let id = db::insert_batch_material(pool, batch_id, lot.id, req.direction, req.quantity, unit, &claims.sub).await?;
+    let details = serde_json::json!({
+        "batch_id": batch_id,
+        "direction": req.direction.as_str(),
+        "material": material,
+        "lot_number": lot_number,
+        "quantity": req.quantity,
+        "unit": unit,
+        "expiry": lot.expiry,
+    })
+    .to_string();
+    auth::log_audit(pool, &claims.user_id, &claims.sub, "batch_material", Some(&record.device_id), &details, None).await;
+    info!("Batch {} {} {} {} of {} lot {}", batch_id, req.direction.as_str(), req.quantity, unit, material, lot_number);
+
+    db::list_batch_materials(pool, batch_id)
+        .await
+        .into_iter()
+        .find(|m| m.id == id)
+        .ok_or_else(|| "Material not found".to_string())
+}
+
+/// Trace a lot forward (where it went) or backward (where it came from).
+/// `None` if the lot does not exist.
+pub async fn trace(pool: &SqlitePool, lot_id: i64, direction: GenealogyDirection) -> Option<Genealogy> {
+    let lot = db::get_lot(pool, lot_id).await?;
+    // Forward: lot → consuming batch → produced lot; backward the reverse
+    let (into_batch, out_of_batch) = match direction {
+        GenealogyDirection::Forward => (MaterialDirection::Consumed, MaterialDirection::Produced),
+        GenealogyDirection::Backward => (MaterialDirection::Produced, MaterialDirection::Consumed),
+    };
+
+    let mut genealogy = Genealogy {
+        lot,
+        direction,
+        batches: Vec::new(),
+        lots: Vec::new(),
+        links: Vec::new(),
+    };
+    let mut seen_lots = HashSet::from([lot_id]);
+    let mut seen_batches = HashSet::new();
+    let mut seen_links = HashSet::new();
+    let mut queue = VecDeque::from([(lot_id, 1)]);
+
+    while let Some((lot_id, depth)) = queue.pop_front() {
+        for link in db::list_lot_links(pool, lot_id).await {
+            if link.direction != into_batch || !seen_links.insert(link.id) {
+                continue;
+            }
+            let batch_id = link.batch_id.clone();
+            genealogy.links.push(link);
+            if depth > MAX_GENEALOGY_DEPTH || !seen_batches.insert(batch_id.clone()) {
+                continue;
+            }
+            let Some((record, _)) = db::get_batch_with_steps(pool, &batch_id).await else {
+                continue;
+            };
+            genealogy.batches.push(GenealogyBatch {
+                depth,
+                batch_id: record.batch_id,
+                recipe_name: record.recipe_name,
+                device_id: record.device_id,
+                status: record.status,
+                start_time: record.start_time,
+            });
+
+            for next in db::list_batch_materials(pool, &batch_id).await {
+                if next.direction != out_of_batch || !seen_links.insert(next.id) {
+                    continue;
+                }
+                if seen_lots.insert(next.lot_id)
+                    && let Some(lot) = db::get_lot(pool, next.lot_id).await
+                {
+                    genealogy.lots.push(lot);
+                    queue.push_back((next.lot_id, depth + 1));
+                }
+                genealogy.links.push(next);
+            }
+        }
+    }
+    Some(genealogy)
+}
@@ -0,0 +1,243 @@
+//! Golden Batch Comparison
+//!
+//! Overlays the process trends of several batches by aligning each on
+//! its start time: the time-series store averages readings into buckets
+//! of `step_secs` seconds since batch start, so batches started at
+//! different times (or on different reactors) share one time axis.
+//!
+//! The golden envelope is built the same way from the approved batches
+//! of a recipe — per register and bucket, mean ± k·σ over the batches
+//! with readings there — and each compared batch is checked against it.
+//! Compared batches never count towards the envelope they are checked
+//! against.
+//! Consecutive buckets outside the envelope are reported as one
+//! excursion, which is how a running batch drifting off the golden
+//! profile shows up while there is still time to act.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+
+use crate::db;
+use crate::models::{
+    BatchCompareParams, BatchComparison, BatchRecord, BatchTrend, EnvelopeExcursion, EnvelopePoint, GoldenEnvelope,
+    RegisterEnvelope, RegisterTrend, TrendPoint,
+};
+use crate::tsdb::TimeSeriesStore;
+
+pub const DEFAULT_STEP_SECS: i64 = 10;
+pub const DEFAULT_K: f64 = 3.0;
+
+/// Most batches overlaid in one request.
+pub const MAX_COMPARED_BATCHES: usize = 20;
+
+/// Most recent approved batches that form the envelope.
+pub const MAX_GOLDEN_BATCHES: i64 = 50;
+
+/// A bucket needs readings from this many golden batches to have an
+/// envelope (a single batch has no spread).
+pub const MIN_ENVELOPE_BATCHES: usize = 2;
+
+/// Mean ± k·σ (sample standard deviation) per register and bucket over
+/// the aligned trends of the golden batches.
+pub fn envelope(golden: &[Vec<RegisterTrend>], k: f64) -> Vec<RegisterEnvelope> {
+    let mut values: BTreeMap<u16, BTreeMap<i64, Vec<f64>>> = BTreeMap::new();
+    for trend in golden.iter().flatten() {
+        for p in &trend.points {
+            values.entry(trend.register).or_default().entry(p.offset_secs).or_default().push(p.value);
+        }
+    }
+
+    values
+        .into_iter()
+        .map(|(register, buckets)| RegisterEnvelope {
+            register,
+            points: buckets
+                .into_iter()
+                .filter(|(_, v)| v.len() >= MIN_ENVELOPE_BATCHES)
+                .map(|(offset_secs, v)| {
+                    let n = v.len() as f64;
+                    let mean = v.iter().sum::<f64>() / n;
+                    let std_dev = (v.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+                    EnvelopePoint {
+                        offset_secs,
+                        mean,
+                        std_dev,
+                        lower: mean - k * std_dev,
+                        upper: mean + k * std_dev,
+                        batches: v.len(),
+                    }
+                })
+                .collect(),
+        })
+        .filter(|e| !e.points.is_empty())
+        .collect()
+}
+
+/// Where a batch's trends leave the envelope, merging consecutive buckets
+/// outside it on the same side into one excursion.
+pub fn excursions(
+    batch_id: &str,
+    trends: &[RegisterTrend],
+    envelope: &[RegisterEnvelope],
+    step_secs: i64,
+) -> Vec<EnvelopeExcursion> {
+    let mut found: Vec<EnvelopeExcursion> = Vec::new();
+    for trend in trends {
+        let Some(env) = envelope.iter().find(|e| e.register == trend.register) else {
+            continue;
+        };
+        let limits: BTreeMap<i64, &EnvelopePoint> = env.points.iter().map(|p| (p.offset_secs, p)).collect();
+        let mut open: Option<EnvelopeExcursion> = None;
+
+        for p in &trend.points {
+            let outside = limits.get(&p.offset_secs).and_then(|e| {
+                if p.value > e.upper {
+                    Some(("above", e.upper, p.value - e.upper))
+                } else if p.value < e.lower {
+                    Some(("below", e.lower, e.lower - p.value))
+                } else {
+                    None
+                }
+            });
+
+            match (outside, open.as_mut()) {
+                (Some((direction, limit, by)), Some(x))
+                    if x.direction == direction && p.offset_secs == x.to_secs + step_secs =>
+                {
+                    x.to_secs = p.offset_secs;
+                    if by > (x.worst_value - x.limit).abs() {
+                        x.worst_value = p.value;
+                        x.limit = limit;
+                    }
+                }
+                (outside, _) => {
+                    found.extend(open.take());
+                    open = outside.map(|(direction, limit, _)| EnvelopeExcursion {
+                        batch_id: batch_id.to_string(),
+                        register: trend.register,
+                        from_secs: p.offset_secs,
+                        to_secs: p.offset_secs,
+                        direction: direction.to_string(),
+                        worst_value: p.value,
+                        limit,
+                    });
+                }
+            }
+        }
+        found.extend(open);
+    }
+    found
+}
+
+/// Aligned trends of one batch, up to its end (or now, while it runs).
+/// An empty `registers` keeps every register read during the batch.
+async fn batch_trends(
+    pool: &SqlitePool,
+    store: &dyn TimeSeriesStore,
+    record: &BatchRecord,
+    registers: &[u16],
+    step_secs: i64,
+) -> Vec<RegisterTrend> {
+    let Ok(start) = DateTime::parse_from_rfc3339(&record.start_time) else {
+        return Vec::new();
+    };
+    let end = record.end_time.clone().unwrap_or_else(|| Utc::now().to_rfc3339());
+    let registers = if registers.is_empty() {
+        db::register_stats_by_register(pool, &record.device_id, &record.start_time, &end)
+            .await
+            .into_iter()
+            .map(|(register, _)| register)
+            .collect()
+    } else {
+        registers.to_vec()
+    };
+
+    let mut trends = Vec::new();
+    for register in registers {
+        let points: Vec<TrendPoint> = store
+            .aggregate_from(&record.device_id, register, &record.start_time, &end, step_secs)
+            .await
+            .into_iter()
+            .map(|b| TrendPoint { offset_secs: b.bucket_start.timestamp() - start.timestamp(), value: b.avg })
+            .collect();
+        if !points.is_empty() {
+            trends.push(RegisterTrend { register, points });
+        }
+    }
+    trends
+}
+
+/// Overlay the requested batches, build the golden envelope of their
+/// recipe and flag where each batch leaves it.
+pub async fn compare_batches(
+    pool: &SqlitePool,
+    store: &dyn TimeSeriesStore,
+    params: &BatchCompareParams,
+) -> Result<BatchComparison, String> {
+    let batch_ids: Vec<&str> = params.batch_ids.split(',').map(str::trim).filter(|s| !s.is_empty()).collect();
+    if batch_ids.is_empty() {
+        return Err("batch_ids is required".to_string());
+    }
+    if batch_ids.len() > MAX_COMPARED_BATCHES {
+        return Err(format!("At most {MAX_COMPARED_BATCHES} batches can be compared"));
+    }
+    let registers = params
+        .registers
+        .as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<u16>().map_err(|_| format!("Invalid register '{s}'")))
+        .collect::<Result<Vec<u16>, String>>()?;
+    let step_secs = params.step_secs.unwrap_or(DEFAULT_STEP_SECS);
+    if step_secs < 1 {
+        return Err("step_secs must be at least 1".to_string());
+    }
+    let k = params.k.unwrap_or(DEFAULT_K);
+    if !(k > 0.0 && k.is_finite()) {
+        return Err("k must be a positive number".to_string());
+    }
+
+    let mut records = Vec::new();
+    for id in &batch_ids {
+        let (record, _) = db::get_batch_with_steps(pool, id).await.ok_or_else(|| format!("Batch {id} not found"))?;
+        records.push(record);
+    }
+
+    let recipe_name = params.recipe.clone().unwrap_or_else(|| records[0].recipe_name.clone());
+    let golden_records = db::list_approved_batches(pool, &recipe_name, &batch_ids, MAX_GOLDEN_BATCHES).await;
+    let mut golden = Vec::new();
+    for record in &golden_records {
+        golden.push(batch_trends(pool, store, record, &registers, step_secs).await);
+    }
+    let envelope = GoldenEnvelope {
+        recipe_name,
+        k,
+        batch_ids: golden_records.into_iter().map(|r| r.batch_id).collect(),
+        registers: envelope(&golden, k),
+    };
+
+    let mut batches = Vec::new();
+    let mut found = Vec::new();
+    for record in records {
+        let trends = batch_trends(pool, store, &record, &registers, step_secs).await;
+        found.extend(excursions(&record.batch_id, &trends, &envelope.registers, step_secs));
+        batches.push(BatchTrend {
+            batch_id: record.batch_id,
+            recipe_name: record.recipe_name,
+            status: record.status,
+            start_time: record.start_time,
+            registers: trends,
+        });
+    }
+
+    Ok(BatchComparison {
+        step_secs,
+        batches,
+        envelope,
+        excursions: found,
+    })
+}
@@ -14,4 +14,13 @@ pub mod routes;
 pub mod modbus;
 pub mod opcua_client;
 pub mod protocol;
+pub mod alarms;
+pub mod alarm_kpi;
+pub mod batch;
+pub mod golden;
+pub mod genealogy;
+pub mod report;
+pub mod review;
+pub mod escalation;
+pub mod notify;
 pub mod discovery;
@@ -1,28 +1,14 @@
-mod state;
-mod models;
-mod ws;
-mod db;
-mod routes;
-mod modbus;
-mod opcua_client;
-mod config;
-mod protocol;
-mod discovery;
-mod auth;
-mod export;
-mod rate_limit;
-mod tsdb;
-
 use axum::middleware as axum_mw;
-use axum::routing::{delete, get, post};
+use axum::routing::{delete, get, post, put};
 use axum::Router;
 use tokio::net::TcpListener;
 use tokio::sync::mpsc;
 use tower_http::cors::{Any, CorsLayer};
 use tracing::{info, warn};
 
-use config::AppConfig;
-use state::{AppState, DeviceHandle};
+use server::config::AppConfig;
+use server::state::{AppState, DeviceHandle};
+use server::{auth, db, escalation, export, modbus, opcua_client, protocol, rate_limit, report, routes, ws};
 
 #[tokio::main]
 async fn main() {
@@ -38,6 +24,12 @@ async fn main() {
     // ── Auth tables + seed default users ──
     auth::init_auth_tables(&pool).await;
 
+    // ── Alarm definitions from config (runtime edits in DB win) ──
+    db::seed_alarm_definitions(&pool, &config.alarms).await;
+
+    // ── Escalation of unacknowledged alarms ──
+    escalation::start_escalation_task(pool.clone(), config.escalation.clone());
+
     // ── App state (no single write_tx anymore — per-device channels) ──
     let app_state = AppState::new(pool.clone(), config.clone());
 
@@ -59,6 +51,8 @@ async fn main() {
             app_state.tx.clone(),
             pool.clone(),
             write_rx,
+            config.alarm_flood.clone(),
+            config.batch.clone(),
         );
 
         let mut registry = app_state.devices.write().await;
@@ -100,6 +94,8 @@ async fn main() {
             app_state.tx.clone(),
             pool.clone(),
             write_rx,
+            config.alarm_flood.clone(),
+            config.batch.clone(),
         );
 
         let mut registry = app_state.devices.write().await;
@@ -136,12 +132,26 @@ async fn main() {
     let protected_routes = Router::new()
         .route("/api/devices", get(routes::get_devices))
         .route("/api/history", get(routes::get_history))
+        .route("/api/history/aggregate", get(routes::get_history_aggregate))
         .route("/api/audit", get(auth::get_audit_trail))
         .route("/api/auth/esig", post(auth::electronic_signature))
         .route("/api/alarms", get(routes::list_alarms))
+        .route("/api/alarms/kpis", get(routes::get_alarm_kpis))
+        .route("/api/alarms/suppressions", get(routes::list_alarm_suppressions))
         .route("/api/alarms/{id}", get(routes::get_alarm))
+        .route("/api/alarms/{id}/events", get(routes::list_alarm_events))
+        .route("/api/alarm-points/out-of-service", get(routes::list_points_out_of_service))
+        .route("/api/alarm-definitions", get(routes::list_alarm_definitions))
+        .route("/api/alarm-definitions/{id}", get(routes::get_alarm_definition))
         .route("/api/batches", get(routes::list_batches))
+        .route("/api/batches/compare", get(routes::compare_batches))
         .route("/api/batches/{id}", get(routes::get_batch))
+        .route("/api/batches/{id}/report", get(report::batch_report))
+        .route("/api/batches/{id}/materials", get(routes::list_batch_materials))
+        .route("/api/lots", get(routes::list_lots))
+        .route("/api/lots/{id}/genealogy", get(routes::lot_genealogy))
+        .route("/api/recipes", get(routes::list_recipes))
+        .route("/api/recipes/{id}", get(routes::get_recipe))
         // CSV export endpoints (Phase 10.1)
         .route("/api/export/alarms.csv", get(export::export_alarms_csv))
         .route("/api/export/batches.csv", get(export::export_batches_csv))
@@ -160,12 +170,36 @@ async fn main() {
         .route("/api/write", post(routes::post_write))
         .route("/api/alarms/{id}/ack", post(routes::ack_alarm))
         .route("/api/alarms/{id}/shelve", post(routes::shelve_alarm))
+        .route("/api/alarms/{id}/unshelve", post(routes::unshelve_alarm))
+        .route("/api/batches", post(routes::start_batch))
+        .route("/api/batches/{id}/hold", post(routes::hold_batch))
+        .route("/api/batches/{id}/resume", post(routes::resume_batch))
+        .route("/api/batches/{id}/complete", post(routes::complete_batch))
+        .route("/api/batches/{id}/abort", post(routes::abort_batch))
+        .route("/api/batches/{id}/sign", post(routes::sign_batch))
+        .route("/api/batches/{id}/materials", post(routes::record_batch_material))
+        .route("/api/batches/{id}/review", post(routes::start_batch_review))
+        .route("/api/batches/{id}/exceptions/{exception_id}/sign-off", post(routes::sign_off_batch_exception))
+        .route("/api/recipes", post(routes::create_recipe))
+        .route("/api/recipes/{id}", put(routes::update_recipe))
+        .route("/api/recipes/{id}/versions", post(routes::new_recipe_version))
         .layer(axum_mw::from_fn_with_state(app_state.clone(), auth::require_operator));
 
     // Admin routes — require Admin role
     let admin_routes = Router::new()
         .route("/api/users", get(auth::list_users))
         .route("/api/users", post(auth::create_user))
+        .route("/api/alarms/{id}/out-of-service", post(routes::alarm_out_of_service))
+        .route("/api/alarms/{id}/return-to-service", post(routes::alarm_return_to_service))
+        .route("/api/alarm-points/{device_id}/{register}/out-of-service", post(routes::alarm_point_out_of_service))
+        .route("/api/alarm-points/{device_id}/{register}/return-to-service", post(routes::alarm_point_return_to_service))
+        .route("/api/alarm-definitions", post(routes::create_alarm_definition))
+        .route("/api/alarm-definitions/{id}", put(routes::update_alarm_definition))
+        .route("/api/alarm-definitions/{id}", delete(routes::delete_alarm_definition))
+        .route("/api/recipes/{id}/approve", post(routes::approve_recipe))
+        .route("/api/recipes/{id}/obsolete", post(routes::obsolete_recipe))
+        .route("/api/batches/{id}/approve", post(routes::approve_batch))
+        .route("/api/batches/{id}/reject", post(routes::reject_batch))
         .layer(axum_mw::from_fn_with_state(app_state.clone(), auth::require_admin));
 
     let app = Router::new()
@@ -9,6 +9,46 @@ pub struct PlcData { // Data coming from PLC (one reading)
     pub timestamp: DateTime<Utc>,
 }
 
+/// Summary of one register's readings over one time bucket.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AggregateBucket {
+    /// Start of the bucket; buckets are aligned to the Unix epoch, or to
+    /// the start of the range for `aggregate_from`.
+    pub bucket_start: DateTime<Utc>,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    /// Earliest and latest reading in the bucket.
+    pub first: f64,
+    pub last: f64,
+    pub count: i64,
+}
+
+/// Message envelope on the WebSocket broadcast channel (`state.tx`).
+/// Serialized with a `type` tag, e.g. `{"type":"plc_data","device_id":...}`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsMessage {
+    /// One register reading — the `PlcData` fields sit next to `type`.
+    PlcData(PlcData),
+    /// An alarm state transition (`event` matches the alarm journal).
+    Alarm { event: String, alarm: Box<Alarm> },
+    /// An alarm flood started (`active`) or ended.
+    AlarmFlood {
+        flood_id: i64,
+        scope: String,
+        active: bool,
+        alarm_count: usize,
+        threshold: usize,
+    },
+}
+
+impl WsMessage {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlcDevice { // A plc device on the network
     pub id: String,
@@ -107,6 +147,8 @@ pub enum AlarmState {
     Acknowledged,  // condition present, acknowledged by operator
     Cleared,       // condition gone, was acknowledged
     Shelved,       // temporarily suppressed
+    #[serde(rename = "out_of_service")]
+    OutOfService,  // point in maintenance — no alarms raised until returned to service
 }
 
 impl AlarmState {
@@ -116,19 +158,51 @@ impl AlarmState {
             Self::Acknowledged => "acknowledged",
             Self::Cleared => "cleared",
             Self::Shelved => "shelved",
+            Self::OutOfService => "out_of_service",
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s {
             "acknowledged" => Self::Acknowledged,
             "cleared" => Self::Cleared,
             "shelved" => Self::Shelved,
+            "out_of_service" => Self::OutOfService,
             _ => Self::Active,
         }
     }
 }
 
+/// What kind of condition an alarm monitors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmKind {
+    #[default]
+    Limit,         // absolute HH/H/L/LL limit
+    RateOfChange,  // value changing faster than the rate limit (units/min)
+    Deviation,     // process value too far from its setpoint register
+}
+
+impl AlarmKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Limit => "limit",
+            Self::RateOfChange => "rate_of_change",
+            Self::Deviation => "deviation",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        match s {
+            "rate_of_change" => Self::RateOfChange,
+            "deviation" => Self::Deviation,
+            _ => Self::Limit,
+        }
+    }
+}
+
 /// A single alarm record.
 #[derive(Debug, Clone, Serialize)]
 pub struct Alarm {
@@ -136,8 +210,12 @@ pub struct Alarm {
     pub device_id: String,
     pub register: u16,
     pub label: String,
+    pub kind: AlarmKind,
     pub priority: AlarmPriority,
     pub state: AlarmState,
+    /// What `kind` measures when raised (or escalated): the register value
+    /// for limit alarms, the rate in units/min for rate-of-change alarms
+    /// and PV − SP for deviation alarms. `threshold` is in the same units.
     pub value: f64,
     pub threshold: f64,
     pub message: String,
@@ -146,7 +224,30 @@ pub struct Alarm {
     pub acked_at: Option<String>,
     pub shelved_until: Option<String>,
     pub shelved_by: Option<String>,
+    pub shelve_reason: Option<String>,
     pub cleared_at: Option<String>,
+    /// Batch running on the device when the alarm was raised.
+    pub batch_id: Option<String>,
+    /// First alarm of a burst on its device — the likely root cause. Set
+    /// once a second alarm follows within the first-out window.
+    pub first_out: bool,
+    /// When the alarm last became 'active' (raised, escalated from
+    /// acknowledged, or un-shelved) — the escalation clock starts here.
+    pub active_since: Option<String>,
+}
+
+/// One state transition in an alarm's journal (`alarm_events` table).
+#[derive(Debug, Clone, Serialize)]
+pub struct AlarmEvent {
+    pub id: i64,
+    pub alarm_id: i64,
+    /// "raise" | "ack" | "shelve" | "unshelve" | "clear" | "escalate"
+    pub event: String,
+    /// Username, or "system" for engine-driven transitions.
+    pub actor: String,
+    pub comment: Option<String>,
+    pub value: Option<f64>,
+    pub timestamp: String,
 }
 
 /// Request to acknowledge an alarm.
@@ -163,12 +264,29 @@ pub struct ShelveAlarmRequest {
     pub reason: String,
 }
 
+/// Request to take an alarm point out of service.
+#[derive(Debug, Deserialize)]
+pub struct OutOfServiceRequest {
+    pub reason: String,
+}
+
+/// An alarm point (device + register) out of service for maintenance.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlarmPointOutOfService {
+    pub device_id: String,
+    pub register: u16,
+    pub reason: String,
+    pub set_by: String,
+    pub since: String,
+}
+
 /// Request to raise an alarm from the polling engine.
 #[derive(Debug, Clone)]
 pub struct RaiseAlarmRequest {
     pub device_id: String,
     pub register: u16,
     pub label: String,
+    pub kind: AlarmKind,
     pub priority: AlarmPriority,
     pub value: f64,
     pub threshold: f64,
@@ -181,19 +299,102 @@ pub struct AlarmQueryParams {
     pub device_id: Option<String>,
     pub state: Option<String>,
     pub priority: Option<i32>,
+    /// Only first-out (true) or consequential (false) alarms.
+    pub first_out: Option<bool>,
     pub limit: Option<i64>,
 }
 
+/// Query params for listing alarm definitions.
+#[derive(Debug, Deserialize)]
+pub struct AlarmDefinitionQueryParams {
+    pub device_id: Option<String>,
+}
+
+/// An alarm that was suppressed instead of raised (e.g. during a flood).
+#[derive(Debug, Clone, Serialize)]
+pub struct AlarmSuppression {
+    pub id: i64,
+    pub device_id: String,
+    pub register: u16,
+    pub label: String,
+    pub kind: AlarmKind,
+    pub priority: AlarmPriority,
+    pub value: f64,
+    pub message: String,
+    /// Why it was suppressed: "flood" or "state".
+    pub reason: String,
+    pub detail: Option<String>,
+    pub timestamp: String,
+}
+
+/// Query params for listing suppressed alarms.
+#[derive(Debug, Deserialize)]
+pub struct AlarmSuppressionQueryParams {
+    pub device_id: Option<String>,
+    pub reason: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Query params for the alarm KPI report. `from`/`to` are RFC 3339;
+/// the window defaults to the last 24 hours.
+#[derive(Debug, Deserialize)]
+pub struct AlarmKpiParams {
+    pub device_id: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// Operator positions sharing the alarm load (default 1).
+    pub operators: Option<u32>,
+}
+
+/// How often one alarm tag was raised in the KPI window.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlarmFrequency {
+    pub device_id: String,
+    pub register: u16,
+    pub label: String,
+    pub kind: AlarmKind,
+    pub count: usize,
+    pub pct: f64,
+}
+
+/// Alarms of one priority in the KPI window.
+#[derive(Debug, Clone, Serialize)]
+pub struct PriorityShare {
+    pub priority: AlarmPriority,
+    pub count: usize,
+    pub pct: f64,
+}
+
+/// ISA-18.2 alarm performance metrics over a time window.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlarmKpis {
+    pub from: String,
+    pub to: String,
+    pub total_alarms: usize,
+    pub operators: u32,
+    pub avg_alarms_per_operator_per_10min: f64,
+    pub flood_time_pct: f64,
+    pub top_alarms: Vec<AlarmFrequency>,
+    pub chattering_count: usize,
+    /// Active/acknowledged alarms standing for more than 24 hours.
+    pub stale_count: usize,
+    pub priority_distribution: Vec<PriorityShare>,
+}
+
 // ── ISA-88 Batch Records ────────────────────────────────────────
 
 /// Batch status.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
+#[serde(rename_all = "snake_case")]
 pub enum BatchStatus {
     Running,
     Completed,
     Aborted,
     Held,
+    /// Completed and awaiting release review.
+    UnderReview,
+    Approved,
+    Rejected,
 }
 
 impl BatchStatus {
@@ -203,17 +404,29 @@ impl BatchStatus {
             Self::Completed => "completed",
             Self::Aborted => "aborted",
             Self::Held => "held",
+            Self::UnderReview => "under_review",
+            Self::Approved => "approved",
+            Self::Rejected => "rejected",
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s {
             "completed" => Self::Completed,
             "aborted" => Self::Aborted,
             "held" => Self::Held,
+            "under_review" => Self::UnderReview,
+            "approved" => Self::Approved,
+            "rejected" => Self::Rejected,
             _ => Self::Running,
         }
     }
+
+    /// Whether the batch has stopped executing (including review states).
+    pub fn is_ended(&self) -> bool {
+        !matches!(self, Self::Running | Self::Held)
+    }
 }
 
 /// A batch record (ISA-88 batch header).
@@ -228,6 +441,15 @@ pub struct BatchRecord {
     pub start_time: String,
     pub end_time: Option<String>,
     pub notes: Option<String>,
+    /// Master recipe version the batch was started from, if any.
+    pub recipe_id: Option<i64>,
+    pub recipe_version: Option<i64>,
+    /// Operator who completed or aborted the batch; `None` when the PLC did.
+    pub ended_by: Option<String>,
+    /// Reviewer who approved or rejected the batch, when, and why.
+    pub disposition_by: Option<String>,
+    pub disposition_at: Option<String>,
+    pub disposition_reason: Option<String>,
 }
 
 /// A step within a batch (ISA-88 phase/step).
@@ -244,10 +466,468 @@ pub struct BatchStep {
     pub result: Option<String>,
 }
 
+/// Summary of one process value over a batch step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisterStats {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub samples: i64,
+}
+
+/// Start a batch on a device (POST /api/batches). With `recipe_name` the
+/// approved version of that recipe is downloaded first.
+#[derive(Debug, Deserialize)]
+pub struct StartBatchRequest {
+    pub device_id: String,
+    #[serde(default)]
+    pub recipe_name: Option<String>,
+}
+
+/// Body for hold/resume/complete/abort. Abort requires `esig_token`
+/// (from POST /api/auth/esig); its reason is kept on the batch.
+#[derive(Debug, Default, Deserialize)]
+pub struct BatchControlRequest {
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub esig_token: Option<String>,
+}
+
+/// Meaning of an electronic signature (21 CFR Part 11 §11.50).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureMeaning {
+    /// The signer performed the work and takes responsibility for it.
+    Responsibility,
+    Review,
+    Approval,
+}
+
+impl SignatureMeaning {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Responsibility => "responsibility",
+            Self::Review => "review",
+            Self::Approval => "approval",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        match s {
+            "review" => Self::Review,
+            "approval" => Self::Approval,
+            _ => Self::Responsibility,
+        }
+    }
+}
+
+/// An electronic signature linked to a record by its content hash.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordSignature {
+    pub id: i64,
+    /// Kind of record signed, e.g. "batch".
+    pub record_type: String,
+    pub record_id: String,
+    pub meaning: SignatureMeaning,
+    pub user_id: String,
+    /// Printed name of the signer.
+    pub username: String,
+    /// Reason given when re-authenticating.
+    pub reason: String,
+    pub signed_at: String,
+    /// SHA-256 of the record content at signing.
+    pub content_hash: String,
+    /// False once the record no longer matches `content_hash`.
+    pub valid: bool,
+}
+
+/// Sign a batch record (POST /api/batches/{id}/sign).
+#[derive(Debug, Deserialize)]
+pub struct SignBatchRequest {
+    pub meaning: SignatureMeaning,
+    pub esig_token: String,
+}
+
+/// What a batch review exception was raised for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExceptionKind {
+    Alarm,
+    /// A register write by a user, outside the recipe download.
+    ManualWrite,
+    OutOfRange,
+    AbortedStep,
+}
+
+impl ExceptionKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Alarm => "alarm",
+            Self::ManualWrite => "manual_write",
+            Self::OutOfRange => "out_of_range",
+            Self::AbortedStep => "aborted_step",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        match s {
+            "manual_write" => Self::ManualWrite,
+            "out_of_range" => Self::OutOfRange,
+            "aborted_step" => Self::AbortedStep,
+            _ => Self::Alarm,
+        }
+    }
+}
+
+/// A deviation found in a batch record that its reviewer must sign off.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchException {
+    pub id: i64,
+    pub batch_id: String,
+    pub kind: ExceptionKind,
+    /// What the exception points at, e.g. "alarm:12" or "step:3:1028".
+    pub reference: String,
+    pub description: String,
+    pub occurred_at: String,
+    /// User whose action raised the exception (e.g. a manual write); they
+    /// cannot sign it off.
+    pub caused_by: Option<String>,
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<String>,
+    pub comment: Option<String>,
+}
+
+/// Sign off an exception, approve or reject a batch under review.
+/// `comment` defaults to the e-signature reason.
+#[derive(Debug, Deserialize)]
+pub struct BatchReviewRequest {
+    #[serde(default)]
+    pub comment: Option<String>,
+    pub esig_token: String,
+}
+
 /// Query params for listing batches.
 #[derive(Debug, Deserialize)]
 pub struct BatchQueryParams {
     pub device_id: Option<String>,
     pub status: Option<String>,
     pub limit: Option<i64>,
-}
\ No newline at end of file
+}
+
+// ── Material Lots and Genealogy ─────────────────────────────────
+
+/// Whether a batch consumed or produced a lot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaterialDirection {
+    Consumed,
+    Produced,
+}
+
+impl MaterialDirection {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Consumed => "consumed",
+            Self::Produced => "produced",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        match s {
+            "produced" => Self::Produced,
+            _ => Self::Consumed,
+        }
+    }
+}
+
+/// A lot of a material, identified by material and lot number.
+#[derive(Debug, Clone, Serialize)]
+pub struct MaterialLot {
+    pub id: i64,
+    pub material: String,
+    pub lot_number: String,
+    /// Expiry date (YYYY-MM-DD), if known.
+    pub expiry: Option<String>,
+    pub created_at: String,
+}
+
+/// A lot consumed or produced by a batch.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchMaterial {
+    pub id: i64,
+    pub batch_id: String,
+    pub lot_id: i64,
+    pub material: String,
+    pub lot_number: String,
+    pub expiry: Option<String>,
+    pub direction: MaterialDirection,
+    pub quantity: f64,
+    pub unit: String,
+    pub recorded_by: String,
+    pub recorded_at: String,
+}
+
+/// Record a consumed or produced lot (POST /api/batches/{id}/materials).
+#[derive(Debug, Deserialize)]
+pub struct RecordMaterialRequest {
+    pub direction: MaterialDirection,
+    pub material: String,
+    pub lot_number: String,
+    pub quantity: f64,
+    pub unit: String,
+    /// Expiry date (YYYY-MM-DD).
+    #[serde(default)]
+    pub expiry: Option<String>,
+}
+
+/// Query params for listing lots.
+#[derive(Debug, Deserialize)]
+pub struct LotQueryParams {
+    pub material: Option<String>,
+    pub lot_number: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Which way to trace a lot: forward to the batches that consumed it and
+/// what they produced (recalls), or backward to the batch that produced
+/// it and what that consumed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GenealogyDirection {
+    #[default]
+    Forward,
+    Backward,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GenealogyParams {
+    #[serde(default)]
+    pub direction: GenealogyDirection,
+}
+
+/// A batch reached while tracing a lot; `depth` 1 used the lot directly.
+#[derive(Debug, Clone, Serialize)]
+pub struct GenealogyBatch {
+    pub depth: u32,
+    pub batch_id: String,
+    pub recipe_name: String,
+    pub device_id: String,
+    pub status: BatchStatus,
+    pub start_time: String,
+}
+
+/// Everything upstream or downstream of a lot.
+#[derive(Debug, Clone, Serialize)]
+pub struct Genealogy {
+    pub lot: MaterialLot,
+    pub direction: GenealogyDirection,
+    pub batches: Vec<GenealogyBatch>,
+    /// Other lots reached, in the order found.
+    pub lots: Vec<MaterialLot>,
+    /// Every consumption/production traversed.
+    pub links: Vec<BatchMaterial>,
+}
+
+// ── Golden Batch Comparison ─────────────────────────────────────
+
+/// Query params for GET /api/batches/compare.
+#[derive(Debug, Deserialize)]
+pub struct BatchCompareParams {
+    /// Comma-separated batch IDs to overlay.
+    pub batch_ids: String,
+    /// Comma-separated registers (default: every register read).
+    pub registers: Option<String>,
+    /// Alignment bucket in seconds since batch start (default 10).
+    pub step_secs: Option<i64>,
+    /// Envelope half-width in standard deviations (default 3).
+    pub k: Option<f64>,
+    /// Recipe whose approved batches form the envelope (default: the
+    /// recipe of the first compared batch).
+    pub recipe: Option<String>,
+}
+
+/// Average of one register over one bucket, `offset_secs` after the batch started.
+#[derive(Debug, Clone, Serialize)]
+pub struct TrendPoint {
+    pub offset_secs: i64,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RegisterTrend {
+    pub register: u16,
+    pub points: Vec<TrendPoint>,
+}
+
+/// One batch's process trends, aligned on its start time.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchTrend {
+    pub batch_id: String,
+    pub recipe_name: String,
+    pub status: BatchStatus,
+    pub start_time: String,
+    pub registers: Vec<RegisterTrend>,
+}
+
+/// Golden-batch statistics of one bucket: mean ± k·σ over the batches
+/// that have readings there.
+#[derive(Debug, Clone, Serialize)]
+pub struct EnvelopePoint {
+    pub offset_secs: i64,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub lower: f64,
+    pub upper: f64,
+    pub batches: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RegisterEnvelope {
+    pub register: u16,
+    pub points: Vec<EnvelopePoint>,
+}
+
+/// Envelope built from the approved batches of a recipe.
+#[derive(Debug, Clone, Serialize)]
+pub struct GoldenEnvelope {
+    pub recipe_name: String,
+    pub k: f64,
+    pub batch_ids: Vec<String>,
+    pub registers: Vec<RegisterEnvelope>,
+}
+
+/// A stretch of consecutive buckets where a batch left the envelope.
+#[derive(Debug, Clone, Serialize)]
+pub struct EnvelopeExcursion {
+    pub batch_id: String,
+    pub register: u16,
+    pub from_secs: i64,
+    pub to_secs: i64,
+    /// "above" or "below".
+    pub direction: String,
+    /// Value furthest outside the envelope, and the limit it crossed.
+    pub worst_value: f64,
+    pub limit: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchComparison {
+    pub step_secs: i64,
+    pub batches: Vec<BatchTrend>,
+    pub envelope: GoldenEnvelope,
+    pub excursions: Vec<EnvelopeExcursion>,
+}
+
+// ── ISA-88 Master Recipes ───────────────────────────────────────
+
+/// Recipe lifecycle state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecipeStatus {
+    Draft,
+    Approved,
+    Obsolete,
+}
+
+impl RecipeStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Draft => "draft",
+            Self::Approved => "approved",
+            Self::Obsolete => "obsolete",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        match s {
+            "approved" => Self::Approved,
+            "obsolete" => Self::Obsolete,
+            _ => Self::Draft,
+        }
+    }
+}
+
+/// A register value written to the PLC when the recipe is downloaded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecipeRegisterWrite {
+    pub register: u16,
+    pub value: u16,
+}
+
+/// One version of a master recipe. Each edit cycle is a new version:
+/// draft → approved → obsolete.
+#[derive(Debug, Clone, Serialize)]
+pub struct Recipe {
+    pub id: i64,
+    pub name: String,
+    pub version: i64,
+    pub status: RecipeStatus,
+    pub description: Option<String>,
+    pub target_temperature: f64,
+    pub hold_time_minutes: f64,
+    pub agitator_rpm: f64,
+    pub register_writes: Vec<RecipeRegisterWrite>,
+    pub created_by: String,
+    pub created_at: String,
+    pub updated_by: String,
+    pub updated_at: String,
+    pub approved_by: Option<String>,
+    pub approved_at: Option<String>,
+}
+
+/// Create or edit a draft recipe (POST /api/recipes, PUT /api/recipes/{id}).
+/// `esig_token` comes from POST /api/auth/esig.
+#[derive(Debug, Deserialize)]
+pub struct RecipeRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub target_temperature: f64,
+    pub hold_time_minutes: f64,
+    pub agitator_rpm: f64,
+    #[serde(default)]
+    pub register_writes: Vec<RecipeRegisterWrite>,
+    pub esig_token: String,
+}
+
+impl RecipeRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Recipe name must not be empty".into());
+        }
+        if !self.target_temperature.is_finite() {
+            return Err("Target temperature must be a finite number".into());
+        }
+        if !(self.hold_time_minutes.is_finite() && self.hold_time_minutes >= 0.0) {
+            return Err("Hold time must be a non-negative number".into());
+        }
+        if !(self.agitator_rpm.is_finite() && self.agitator_rpm >= 0.0) {
+            return Err("Agitator RPM must be a non-negative number".into());
+        }
+        let mut seen = std::collections::HashSet::new();
+        for w in &self.register_writes {
+            if !seen.insert(w.register) {
+                return Err(format!("Register {} is written more than once", w.register));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Signed recipe state change (new version, approve, obsolete).
+#[derive(Debug, Deserialize)]
+pub struct RecipeSignRequest {
+    pub esig_token: String,
+}
+
+/// Query params for listing recipes.
+#[derive(Debug, Deserialize)]
+pub struct RecipeQueryParams {
+    pub name: Option<String>,
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+}
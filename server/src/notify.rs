@@ -0,0 +1,151 @@
+//! Outbound Notifications
+//!
+//! Minimal transports for alarm escalation: a JSON webhook over plain
+//! HTTP and mail submission to a local SMTP relay (no TLS/auth — the
+//! relay handles onward delivery). Both time out after 10 seconds.
+
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Split `http://host[:port]/path` into (host:port, host, path).
+fn parse_http_url(url: &str) -> Result<(String, String, String), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("Unsupported webhook URL '{url}' — only http:// is supported"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() {
+        return Err(format!("Webhook URL '{url}' has no host"));
+    }
+    let host = authority.split(':').next().unwrap_or(authority).to_string();
+    let addr = if authority.contains(':') { authority.to_string() } else { format!("{authority}:80") };
+    Ok((addr, host, path.to_string()))
+}
+
+/// POST `body` as JSON to `url`. Any 2xx response is success.
+pub async fn send_webhook(url: &str, body: &serde_json::Value) -> Result<(), String> {
+    let (addr, host, path) = parse_http_url(url)?;
+    let payload = body.to_string();
+
+    let exchange = async {
+        let mut stream = TcpStream::connect(&addr).await.map_err(|e| format!("connect {addr}: {e}"))?;
+        let request = format!(
+            "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{payload}",
+            payload.len()
+        );
+        stream.write_all(request.as_bytes()).await.map_err(|e| format!("send: {e}"))?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream)
+            .read_line(&mut status_line)
+            .await
+            .map_err(|e| format!("read: {e}"))?;
+        let status = status_line.split_whitespace().nth(1).unwrap_or("");
+        if status.starts_with('2') {
+            Ok(())
+        } else {
+            Err(format!("webhook returned '{}'", status_line.trim()))
+        }
+    };
+
+    tokio::time::timeout(TIMEOUT, exchange)
+        .await
+        .map_err(|_| format!("webhook {url} timed out"))?
+}
+
+/// Read one (possibly multi-line) SMTP reply and check its code class.
+async fn smtp_expect(reader: &mut BufReader<TcpStream>, expect: char) -> Result<(), String> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await.map_err(|e| format!("read: {e}"))? == 0 {
+            return Err("SMTP relay closed the connection".into());
+        }
+        if !line.starts_with(expect) {
+            return Err(format!("SMTP relay replied '{}'", line.trim()));
+        }
+        // "250-..." continues, "250 ..." is the last line
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+async fn smtp_command(reader: &mut BufReader<TcpStream>, cmd: &str, expect: char) -> Result<(), String> {
+    reader.get_mut().write_all(format!("{cmd}\r\n").as_bytes()).await.map_err(|e| format!("send: {e}"))?;
+    smtp_expect(reader, expect).await
+}
+
+/// Encode a header value as RFC 2047 encoded-words (`=?UTF-8?B?…?=`) if it
+/// is not plain ASCII. Each word stays within the 75-character limit and
+/// never splits a UTF-8 sequence; words are folded onto continuation lines.
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+    // 75 − len("=?UTF-8?B?" + "?=") = 63 → at most 45 bytes (60 base64 chars) per word
+    const MAX_BYTES: usize = 45;
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in value.chars() {
+        if chunk.len() + c.len_utf8() > MAX_BYTES {
+            words.push(std::mem::take(&mut chunk));
+        }
+        chunk.push(c);
+    }
+    words.push(chunk);
+    words
+        .iter()
+        .map(|w| format!("=?UTF-8?B?{}?=", BASE64.encode(w)))
+        .collect::<Vec<_>>()
+        .join("\r\n ")
+}
+
+/// Submit a plain-text mail to the SMTP relay at `relay` (host:port).
+pub async fn send_email(relay: &str, from: &str, to: &[String], subject: &str, body: &str) -> Result<(), String> {
+    if to.is_empty() {
+        return Err("No email recipients".into());
+    }
+
+    let exchange = async {
+        let stream = TcpStream::connect(relay).await.map_err(|e| format!("connect {relay}: {e}"))?;
+        let mut reader = BufReader::new(stream);
+        smtp_expect(&mut reader, '2').await?;
+
+        let hostname = hostname::get().ok().and_then(|h| h.into_string().ok()).unwrap_or("localhost".into());
+        smtp_command(&mut reader, &format!("HELO {hostname}"), '2').await?;
+        smtp_command(&mut reader, &format!("MAIL FROM:<{from}>"), '2').await?;
+        for rcpt in to {
+            smtp_command(&mut reader, &format!("RCPT TO:<{rcpt}>"), '2').await?;
+        }
+        smtp_command(&mut reader, "DATA", '3').await?;
+
+        // Dot-stuff lines starting with '.' (RFC 5321 §4.5.2)
+        let body: String = body
+            .lines()
+            .map(|l| if l.starts_with('.') { format!(".{l}\r\n") } else { format!("{l}\r\n") })
+            .collect();
+        let message = format!(
+            "From: <{from}>\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\r\n{body}.",
+            to.iter().map(|t| format!("<{t}>")).collect::<Vec<_>>().join(", "),
+            encode_header(subject),
+            chrono::Utc::now().to_rfc2822(),
+        );
+        smtp_command(&mut reader, &message, '2').await?;
+        let _ = smtp_command(&mut reader, "QUIT", '2').await;
+        Ok(())
+    };
+
+    tokio::time::timeout(TIMEOUT, exchange)
+        .await
+        .map_err(|_| format!("SMTP relay {relay} timed out"))?
+}
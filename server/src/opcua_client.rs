@@ -114,7 +114,7 @@ impl PlcProtocol for OpcUaClient {
             // If it fails, we fall back to regular polling (existing behavior).
             let sub_active = {
                 let session_guard = session.read();
-                match create_data_subscription(&*session_guard, sub_cache, sub_update_count) {
+                match create_data_subscription(&session_guard, sub_cache, sub_update_count) {
                     Ok(()) => {
                         eprintln!("[opcua] Subscription created — push mode active");
                         true
@@ -6,39 +6,31 @@ use tokio::sync::{broadcast, mpsc};
 use tokio::task::JoinHandle;
 use tracing::{error, info, warn};
 
-use crate::config::DeviceConfig;
+use crate::alarms::AlarmEngine;
+use crate::batch;
+use crate::config::{AlarmFloodConfig, BatchConfig, DeviceConfig};
 use crate::db;
-use crate::models::{AlarmPriority, PlcData, RaiseAlarmRequest};
+use crate::models::{PlcData, WsMessage};
 use crate::state::WriteCommand;
 
-/// Alarm threshold definition (hardcoded for known registers).
-struct AlarmThreshold {
-    register: u16,
-    label: &'static str,
-    warn_high: Option<f64>,
-    crit_high: Option<f64>,
-    warn_low: Option<f64>,
-    crit_low: Option<f64>,
-}
-
-/// Known alarm thresholds for the pharma/chemical plant.
-const ALARM_THRESHOLDS: &[AlarmThreshold] = &[
-    AlarmThreshold { register: 1028, label: "Temperature", warn_high: Some(85.0), crit_high: Some(100.0), warn_low: None, crit_low: None },
-    AlarmThreshold { register: 1029, label: "Pressure",    warn_high: Some(1200.0), crit_high: Some(1400.0), warn_low: None, crit_low: None },
-];
+/// Register holding the PLC batch state (read for tracking, written for control).
+pub const BATCH_STATE_REGISTER: u16 = 1032;
 
 /// Batch state codes from the simulator.
-const BATCH_IDLE: u16 = 0;
-const BATCH_HEATING: u16 = 1;
-const BATCH_COMPLETE: u16 = 4;
+pub const BATCH_IDLE: u16 = 0;
+pub const BATCH_HEATING: u16 = 1;
+pub const BATCH_COMPLETE: u16 = 4;
+pub const BATCH_HELD: u16 = 5;
 
-fn batch_phase_name(code: u16) -> &'static str {
+/// Human-readable ISA-88 phase name for a batch state code.
+pub fn batch_phase_name(code: u16) -> &'static str {
     match code {
         0 => "Idle",
         1 => "Heating",
         2 => "Holding",
         3 => "Cooling",
         4 => "Complete",
+        5 => "Held",
         _ => "Unknown",
     }
 }
@@ -76,14 +68,19 @@ pub fn start_device_polling(
     tx: broadcast::Sender<String>,
     db: SqlitePool,
     mut write_rx: mpsc::Receiver<WriteCommand>,
+    alarm_flood: AlarmFloodConfig,
+    batch_config: BatchConfig,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
         let proto = client.protocol_name().to_string();
         info!("[{}] Polling started ({}://{})", device.id, proto, device.address);
 
+        // Polling again — lift the suppression set on disconnect
+        db::set_device_alarms_suppressed(&db, &device.id, None).await;
+        let mut alarm_engine = AlarmEngine::with_flood(&device.id, tx.clone(), alarm_flood);
+
         // Batch tracking state
         let mut prev_batch_state: Option<u16> = None;
-        let mut batch_counter: u32 = 0;
 
         loop {
             info!("[{}] Connecting via {}...", device.id, proto);
@@ -100,9 +97,21 @@ pub fn start_device_polling(
                             // Handle write commands from the REST API
                             Some(cmd) = write_rx.recv() => {
                                 info!("[{}] Writing register {} = {}", device.id, cmd.register, cmd.value);
-                                match client.write_register(cmd.register, cmd.value).await {
+                                let mut result = client.write_register(cmd.register, cmd.value).await;
+                                if result.is_ok() && cmd.verify {
+                                    result = match client.read_registers(cmd.register, 1).await {
+                                        Ok(regs) if regs.first() == Some(&cmd.value) => Ok(()),
+                                        Ok(regs) => Err(format!(
+                                            "Read-back mismatch on reg {}: wrote {}, read {:?}",
+                                            cmd.register, cmd.value, regs.first()
+                                        )),
+                                        Err(e) => Err(format!("Read-back of reg {} failed: {}", cmd.register, e)),
+                                    };
+                                }
+                                match result {
                                     Ok(()) => {
-                                        info!("[{}] Write OK: reg {} = {}", device.id, cmd.register, cmd.value);
+                                        info!("[{}] Write OK: reg {} = {}{}", device.id, cmd.register, cmd.value,
+                                            if cmd.verify { " (verified)" } else { "" });
                                         let _ = cmd.response.send(Ok(()));
                                     }
                                     Err(e) => {
@@ -130,91 +139,47 @@ pub fn start_device_polling(
                                                 timestamp: Utc::now(),
                                             };
                                             db::save_plc_data(&db, &data).await;
-                                            let json = serde_json::to_string(&data).unwrap_or_default();
-                                            let _ = tx.send(json);
+                                            let _ = tx.send(WsMessage::PlcData(data).to_json());
                                         }
 
                                         // ── Alarm Monitoring ──
-                                        for th in ALARM_THRESHOLDS {
-                                            if let Some(&val) = reg_map.get(&th.register) {
-                                                let has_alarm = db::has_active_alarm(&db, &device.id, th.register).await;
-
-                                                // Check critical high
-                                                if let Some(crit) = th.crit_high {
-                                                    if val >= crit && !has_alarm {
-                                                        let req = RaiseAlarmRequest {
-                                                            device_id: device.id.clone(),
-                                                            register: th.register,
-                                                            label: th.label.to_string(),
-                                                            priority: AlarmPriority::Critical,
-                                                            value: val,
-                                                            threshold: crit,
-                                                            message: format!("CRITICAL: {} {:.1} exceeds {:.0}", th.label, val, crit),
-                                                        };
-                                                        if let Some(id) = db::raise_alarm(&db, &req).await {
-                                                            info!("[{}] 🚨 Alarm raised #{}: {}", device.id, id, req.message);
-                                                        }
-                                                    }
-                                                }
-                                                // Check warning high (only if not already critical)
-                                                else if let Some(warn) = th.warn_high {
-                                                    if val >= warn && !has_alarm {
-                                                        let req = RaiseAlarmRequest {
-                                                            device_id: device.id.clone(),
-                                                            register: th.register,
-                                                            label: th.label.to_string(),
-                                                            priority: AlarmPriority::High,
-                                                            value: val,
-                                                            threshold: warn,
-                                                            message: format!("WARNING: {} {:.1} approaching limit ({:.0})", th.label, val, warn),
-                                                        };
-                                                        if let Some(id) = db::raise_alarm(&db, &req).await {
-                                                            info!("[{}] ⚠️ Warning raised #{}: {}", device.id, id, req.message);
-                                                        }
-                                                    }
-                                                }
-
-                                                // Auto-clear when value returns to safe range
-                                                if has_alarm {
-                                                    let below_warn = th.warn_high.map_or(true, |w| val < w * 0.95);
-                                                    let above_low = th.warn_low.map_or(true, |w| val > w * 1.05);
-                                                    if below_warn && above_low {
-                                                        if let Some(alarm_id) = db::get_active_alarm_id(&db, &device.id, th.register).await {
-                                                            let _ = db::clear_alarm(&db, alarm_id).await;
-                                                            info!("[{}] ✅ Alarm #{} auto-cleared ({} = {:.1})", device.id, alarm_id, th.label, val);
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
+                                        alarm_engine.scan(&db, &reg_map).await;
 
                                         // ── Batch Tracking ──
-                                        if let Some(&batch_val) = reg_map.get(&1032) {
+                                        if let Some(&batch_val) = reg_map.get(&BATCH_STATE_REGISTER) {
                                             let batch_state = batch_val as u16;
                                             let prev = prev_batch_state.unwrap_or(batch_state);
 
                                             // Transition: IDLE → HEATING = new batch started
-                                            if prev == BATCH_IDLE && batch_state == BATCH_HEATING {
-                                                batch_counter += 1;
-                                                let batch_id = format!("BATCH-{}-{:04}", device.id, batch_counter);
-                                                info!("[{}] 🧪 Batch started: {}", device.id, batch_id);
-                                                let _ = db::create_batch(&db, &batch_id, "Reactor Cycle", &device.id, "system").await;
+                                            // (unless one was started from a recipe via the API)
+                                            if prev == BATCH_IDLE && batch_state == BATCH_HEATING
+                                                && db::get_running_batch(&db, &device.id).await.is_none() {
+                                                match batch::next_batch_id(&db, &batch_config.id_format, &device.id).await {
+                                                    Ok(batch_id) => {
+                                                        info!("[{}] 🧪 Batch started: {}", device.id, batch_id);
+                                                        let _ = db::create_batch(&db, &batch_id, batch::MANUAL_RECIPE, &device.id, "system").await;
+                                                    }
+                                                    Err(e) => error!("[{}] Batch not recorded: {}", device.id, e),
+                                                }
+                                            }
+
+                                            // Every phase change is a step of the active batch
+                                            if prev != batch_state {
+                                                batch::record_phase_transition(&db, &device.id, batch_state).await;
                                             }
 
                                             // Transition: any → COMPLETE = batch finished
-                                            if prev != BATCH_COMPLETE && batch_state == BATCH_COMPLETE {
-                                                if let Some((_row_id, batch_id)) = db::get_running_batch(&db, &device.id).await {
-                                                    info!("[{}] ✅ Batch completed: {}", device.id, batch_id);
-                                                    let _ = db::update_batch_status(&db, &batch_id, "completed", None).await;
-                                                }
+                                            if prev != BATCH_COMPLETE && batch_state == BATCH_COMPLETE
+                                                && let Some((_row_id, batch_id)) = db::get_running_batch(&db, &device.id).await {
+                                                info!("[{}] ✅ Batch completed: {}", device.id, batch_id);
+                                                let _ = db::update_batch_status(&db, &batch_id, "completed", None).await;
                                             }
 
                                             // Transition: running → IDLE (emergency stop)
-                                            if prev != BATCH_IDLE && prev != BATCH_COMPLETE && batch_state == BATCH_IDLE {
-                                                if let Some((_row_id, batch_id)) = db::get_running_batch(&db, &device.id).await {
-                                                    info!("[{}] 🛑 Batch aborted: {}", device.id, batch_id);
-                                                    let _ = db::update_batch_status(&db, &batch_id, "aborted", Some("Emergency stop")).await;
-                                                }
+                                            if prev != BATCH_IDLE && prev != BATCH_COMPLETE && batch_state == BATCH_IDLE
+                                                && let Some((_row_id, batch_id)) = db::get_running_batch(&db, &device.id).await {
+                                                info!("[{}] 🛑 Batch aborted: {}", device.id, batch_id);
+                                                let _ = db::update_batch_status(&db, &batch_id, "aborted", Some("Emergency stop")).await;
                                             }
 
                                             prev_batch_state = Some(batch_state);
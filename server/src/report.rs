@@ -0,0 +1,930 @@
+//! Electronic Batch Record Report
+//!
+//! Renders the complete record of one batch — header, recipe and
+//! downloaded parameters, material lots, step timeline, alarms, audited
+//! PLC writes, process trend summaries, review exceptions and signature
+//! blocks — as printable HTML or PDF
+//! (GET /api/batches/{id}/report?format=html|pdf).
+//!
+//! Both formats are rendered from the same list of `Block`s so they never
+//! disagree. The PDF writer is deliberately minimal: A4 pages, the
+//! standard Helvetica/Courier fonts and WinAnsi text, no dependencies.
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::auth::{self, AuditEntry, Claims};
+use crate::batch::{self, DOWNLOAD_STEP, STEP_SUMMARY_REGISTERS};
+use crate::db;
+use crate::models::{Alarm, BatchException, BatchMaterial, BatchRecord, BatchStatus, BatchStep, Recipe, RegisterStats, SignatureMeaning};
+use crate::state::AppState;
+
+/// Signature manifestations printed on every batch record, in order.
+pub const SIGNATURE_MEANINGS: [(SignatureMeaning, &str); 3] = [
+    (SignatureMeaning::Responsibility, "Performed by (Operator)"),
+    (SignatureMeaning::Review, "Reviewed by (Production)"),
+    (SignatureMeaning::Approval, "Approved for release by (Quality Assurance)"),
+];
+
+/// One signature block. Electronic signatures fill in the signer, time
+/// and a note with the reason and record hash; unsigned blocks print blank
+/// lines for a wet-ink signature.
+#[derive(Debug, Clone)]
+pub struct SignatureBlock {
+    pub caption: String,
+    pub signer: Option<String>,
+    pub signed_at: Option<String>,
+    pub note: Option<String>,
+}
+
+/// Everything that goes into a batch record report.
+#[derive(Debug, Clone)]
+pub struct BatchReport {
+    pub record: BatchRecord,
+    pub recipe: Option<Recipe>,
+    pub steps: Vec<BatchStep>,
+    pub alarms: Vec<Alarm>,
+    pub audit: Vec<AuditEntry>,
+    /// Per-register summaries over the whole batch, labelled.
+    pub trends: Vec<(String, RegisterStats)>,
+    /// SHA-256 of the record content, as signed.
+    pub content_hash: String,
+    /// Review-by-exception items, once the batch went to review.
+    pub exceptions: Vec<BatchException>,
+    /// Lots consumed and produced.
+    pub materials: Vec<BatchMaterial>,
+    pub signatures: Vec<SignatureBlock>,
+    pub generated_by: String,
+    pub generated_at: String,
+}
+
+/// Gather the report for a batch. `None` if the batch does not exist.
+pub async fn build_batch_report(pool: &SqlitePool, batch_id: &str, generated_by: &str) -> Option<BatchReport> {
+    let (record, steps) = db::get_batch_with_steps(pool, batch_id).await?;
+    let recipe = match record.recipe_id {
+        Some(id) => db::get_recipe(pool, id).await,
+        None => None,
+    };
+    let alarms = db::list_batch_alarms(pool, batch_id).await;
+
+    let generated_at = chrono::Utc::now().to_rfc3339();
+    let end = record.end_time.clone().unwrap_or_else(|| generated_at.clone());
+    let audit = auth::list_batch_audit(pool, &record.device_id, &record.start_time, &end).await;
+
+    let mut labels: HashMap<u16, String> = STEP_SUMMARY_REGISTERS
+        .iter()
+        .map(|(name, register)| (*register, capitalize(name)))
+        .collect();
+    for def in db::list_alarm_definitions(pool, Some(&record.device_id)).await {
+        labels.insert(def.register, def.label);
+    }
+    let trends = db::register_stats_by_register(pool, &record.device_id, &record.start_time, &end)
+        .await
+        .into_iter()
+        .map(|(register, stats)| {
+            let label = labels.get(&register).cloned().unwrap_or_else(|| "Register".to_string());
+            (format!("{label} ({register})"), stats)
+        })
+        .collect();
+
+    let content_hash = batch::batch_content_hash(pool, batch_id).await.unwrap_or_default();
+    let signed = db::list_signatures(pool, batch::BATCH_RECORD_TYPE, batch_id, &content_hash).await;
+    let mut signatures = Vec::new();
+    for (meaning, caption) in SIGNATURE_MEANINGS {
+        let mut blocks: Vec<SignatureBlock> = signed
+            .iter()
+            .filter(|s| s.meaning == meaning)
+            .map(|s| SignatureBlock {
+                caption: caption.to_string(),
+                signer: Some(s.username.clone()),
+                signed_at: Some(s.signed_at.clone()),
+                note: Some(format!(
+                    "Meaning: {} · Reason: {} · Record hash {} — {}",
+                    s.meaning.as_str(),
+                    s.reason,
+                    s.content_hash,
+                    if s.valid { "valid" } else { "INVALID: record changed after signing" }
+                )),
+            })
+            .collect();
+        if blocks.is_empty() {
+            blocks.push(SignatureBlock {
+                caption: caption.to_string(),
+                signer: None,
+                signed_at: None,
+                note: None,
+            });
+        }
+        signatures.extend(blocks);
+    }
+
+    Some(BatchReport {
+        record,
+        recipe,
+        steps,
+        alarms,
+        audit,
+        trends,
+        content_hash,
+        exceptions: db::list_batch_exceptions(pool, batch_id).await,
+        materials: db::list_batch_materials(pool, batch_id).await,
+        signatures,
+        generated_by: generated_by.to_string(),
+        generated_at,
+    })
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// ── Document model ──────────────────────────────────────────────
+
+/// Format-neutral building blocks of the report.
+enum Block {
+    Title(String),
+    Heading(String),
+    Fields(Vec<(&'static str, String)>),
+    /// Columns are (header, relative width).
+    Table {
+        columns: Vec<(&'static str, f64)>,
+        rows: Vec<Vec<String>>,
+    },
+    Note(String),
+    Signatures(Vec<SignatureBlock>),
+}
+
+fn opt(value: &Option<String>) -> String {
+    value.clone().unwrap_or_else(|| "—".to_string())
+}
+
+fn fmt_stats(stats: &RegisterStats) -> [String; 4] {
+    [
+        format!("{:.1}", stats.min),
+        format!("{:.1}", stats.max),
+        format!("{:.2}", stats.avg),
+        stats.samples.to_string(),
+    ]
+}
+
+/// Human-readable step result: process value summaries are expanded,
+/// anything else is shown as recorded.
+fn step_result(result: &Option<String>) -> String {
+    let Some(text) = result else {
+        return String::new();
+    };
+    let Ok(serde_json::Value::Object(summary)) = serde_json::from_str::<serde_json::Value>(text) else {
+        return text.clone();
+    };
+    summary
+        .iter()
+        .map(|(name, value)| match serde_json::from_value::<RegisterStats>(value.clone()) {
+            Ok(s) => format!("{name} {:.1}–{:.1} avg {:.1} (n={})", s.min, s.max, s.avg, s.samples),
+            Err(_) if value.is_null() => format!("{name}: no data"),
+            Err(_) => format!("{name}: {value}"),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Lay out the report as blocks, in print order.
+fn document(r: &BatchReport) -> Vec<Block> {
+    let b = &r.record;
+    let mut blocks = vec![
+        Block::Title(format!("Electronic Batch Record — {}", b.batch_id)),
+        Block::Heading("1. Batch Header".to_string()),
+        Block::Fields(vec![
+            ("Batch ID", b.batch_id.clone()),
+            ("Recipe / product", b.recipe_name.clone()),
+            ("Recipe version", b.recipe_version.map(|v| format!("v{v}")).unwrap_or_else(|| "—".to_string())),
+            ("Equipment (device)", b.device_id.clone()),
+            ("Started by", b.operator.clone()),
+            ("Status", b.status.as_str().to_uppercase()),
+            ("Start time", b.start_time.clone()),
+            ("End time", opt(&b.end_time)),
+            ("Ended by", b.ended_by.clone().unwrap_or_else(|| if b.end_time.is_some() { "PLC" } else { "—" }.to_string())),
+            ("Notes", opt(&b.notes)),
+            (
+                "Release decision",
+                match (&b.disposition_by, &b.disposition_at) {
+                    (Some(by), Some(at)) => {
+                        format!("{} by {by} at {at}: {}", b.status.as_str().to_uppercase(), opt(&b.disposition_reason))
+                    }
+                    _ => "—".to_string(),
+                },
+            ),
+            ("Record hash (SHA-256)", r.content_hash.clone()),
+            ("Report generated", format!("{} by {}", r.generated_at, r.generated_by)),
+        ]),
+        Block::Heading("2. Recipe and Parameters".to_string()),
+    ];
+
+    match &r.recipe {
+        Some(recipe) => {
+            blocks.push(Block::Fields(vec![
+                ("Recipe", format!("{} v{} ({})", recipe.name, recipe.version, recipe.status.as_str())),
+                ("Description", opt(&recipe.description)),
+                ("Target temperature", format!("{} °C", recipe.target_temperature)),
+                ("Hold time", format!("{} min", recipe.hold_time_minutes)),
+                ("Agitator speed", format!("{} RPM", recipe.agitator_rpm)),
+                ("Approved", format!("{} at {}", opt(&recipe.approved_by), opt(&recipe.approved_at))),
+            ]));
+
+            // Download results from the recipe download step
+            let download = r
+                .steps
+                .iter()
+                .find(|s| s.name == DOWNLOAD_STEP)
+                .and_then(|s| s.parameters.as_deref())
+                .and_then(|p| serde_json::from_str::<serde_json::Value>(p).ok())
+                .unwrap_or_default();
+            let writes = download["writes"].as_array().cloned().unwrap_or_default();
+            let status = |w: Option<&serde_json::Value>| match w.and_then(|w| w["verified"].as_bool()) {
+                Some(true) => "Downloaded, verified",
+                Some(false) => "FAILED",
+                None => "Not downloaded",
+            };
+            let no_register = |name: &str| {
+                download["not_downloaded"].as_array().is_some_and(|n| n.iter().any(|p| p == name))
+            };
+
+            let mut rows: Vec<Vec<String>> = [
+                ("target_temperature", recipe.target_temperature),
+                ("hold_time_minutes", recipe.hold_time_minutes),
+                ("agitator_rpm", recipe.agitator_rpm),
+            ]
+            .into_iter()
+            .map(|(name, value)| {
+                let write = writes.iter().find(|w| w["parameter"] == name);
+                let register = write.and_then(|w| w["register"].as_u64()).map_or("—".to_string(), |r| r.to_string());
+                let status = if no_register(name) { "No register configured" } else { status(write) };
+                vec![name.to_string(), register, value.to_string(), status.to_string()]
+            })
+            .collect();
+            rows.extend(recipe.register_writes.iter().map(|w| {
+                let write = writes.iter().find(|d| d["parameter"].is_null() && d["register"] == w.register);
+                vec!["—".to_string(), w.register.to_string(), w.value.to_string(), status(write).to_string()]
+            }));
+            blocks.push(Block::Table {
+                columns: vec![("Parameter", 2.0), ("Register", 1.0), ("Value", 1.0), ("Download", 2.0)],
+                rows,
+            });
+        }
+        None => blocks.push(Block::Note("Started without a master recipe — no parameters were downloaded.".to_string())),
+    }
+
+    blocks.push(Block::Heading("3. Materials".to_string()));
+    if r.materials.is_empty() {
+        blocks.push(Block::Note("No material lots were recorded for the batch.".to_string()));
+    } else {
+        blocks.push(Block::Table {
+            columns: vec![("Direction", 1.2), ("Material", 2.2), ("Lot", 1.6), ("Quantity", 1.2), ("Expiry", 1.2), ("Recorded", 2.6)],
+            rows: r
+                .materials
+                .iter()
+                .map(|m| {
+                    vec![
+                        m.direction.as_str().to_string(),
+                        m.material.clone(),
+                        m.lot_number.clone(),
+                        format!("{} {}", m.quantity, m.unit),
+                        opt(&m.expiry),
+                        format!("{} at {}", m.recorded_by, m.recorded_at),
+                    ]
+                })
+                .collect(),
+        });
+    }
+
+    blocks.push(Block::Heading("4. Step Timeline".to_string()));
+    if r.steps.is_empty() {
+        blocks.push(Block::Note("No steps recorded.".to_string()));
+    } else {
+        blocks.push(Block::Table {
+            columns: vec![("#", 0.3), ("Step", 1.2), ("Status", 0.9), ("Start", 2.0), ("End", 2.0), ("Result", 3.6)],
+            rows: r
+                .steps
+                .iter()
+                .map(|s| {
+                    vec![
+                        s.step_number.to_string(),
+                        s.name.clone(),
+                        s.status.clone(),
+                        s.start_time.clone(),
+                        opt(&s.end_time),
+                        step_result(&s.result),
+                    ]
+                })
+                .collect(),
+        });
+    }
+
+    blocks.push(Block::Heading("5. Alarms During the Batch".to_string()));
+    if r.alarms.is_empty() {
+        blocks.push(Block::Note("No alarms were raised during the batch.".to_string()));
+    } else {
+        blocks.push(Block::Table {
+            columns: vec![("Raised", 2.0), ("Priority", 0.8), ("Alarm", 3.0), ("Value", 0.7), ("State", 1.0), ("Acknowledged", 2.0)],
+            rows: r
+                .alarms
+                .iter()
+                .map(|a| {
+                    let label = if a.first_out { format!("[FIRST OUT] {}", a.message) } else { a.message.clone() };
+                    let acked = match (&a.acked_by, &a.acked_at) {
+                        (Some(by), Some(at)) => format!("{by} at {at}"),
+                        _ => "—".to_string(),
+                    };
+                    vec![a.timestamp.clone(), a.priority.as_str().to_string(), label, a.value.to_string(), a.state.as_str().to_string(), acked]
+                })
+                .collect(),
+        });
+    }
+
+    blocks.push(Block::Heading("6. PLC Writes and Batch Commands (Audit Trail)".to_string()));
+    if r.audit.is_empty() {
+        blocks.push(Block::Note("No PLC writes or batch commands were recorded during the batch.".to_string()));
+    } else {
+        blocks.push(Block::Table {
+            columns: vec![("Time", 2.0), ("User", 1.0), ("Action", 1.3), ("Details", 5.0)],
+            rows: r
+                .audit
+                .iter()
+                .map(|e| vec![e.timestamp.clone(), e.username.clone(), e.action.clone(), e.details.clone()])
+                .collect(),
+        });
+    }
+
+    blocks.push(Block::Heading("7. Process Trend Summary".to_string()));
+    if r.trends.is_empty() {
+        blocks.push(Block::Note("No process readings were recorded during the batch.".to_string()));
+    } else {
+        blocks.push(Block::Table {
+            columns: vec![("Process value", 2.5), ("Min", 1.0), ("Max", 1.0), ("Average", 1.0), ("Samples", 1.0)],
+            rows: r
+                .trends
+                .iter()
+                .map(|(label, stats)| {
+                    let mut row = vec![label.clone()];
+                    row.extend(fmt_stats(stats));
+                    row
+                })
+                .collect(),
+        });
+    }
+
+    blocks.push(Block::Heading("8. Review by Exception".to_string()));
+    if r.exceptions.is_empty() {
+        let note = match b.status {
+            BatchStatus::UnderReview | BatchStatus::Approved | BatchStatus::Rejected => "The review found no exceptions.",
+            _ => "The batch has not been sent to review.",
+        };
+        blocks.push(Block::Note(note.to_string()));
+    } else {
+        blocks.push(Block::Table {
+            columns: vec![("Occurred", 2.0), ("Kind", 1.1), ("Exception", 3.5), ("Signed off", 2.4)],
+            rows: r
+                .exceptions
+                .iter()
+                .map(|e| {
+                    let signed_off = match (&e.reviewed_by, &e.reviewed_at) {
+                        (Some(by), Some(at)) => format!("{by} at {at}: {}", opt(&e.comment)),
+                        _ => "OPEN".to_string(),
+                    };
+                    vec![e.occurred_at.clone(), e.kind.as_str().to_string(), e.description.clone(), signed_off]
+                })
+                .collect(),
+        });
+    }
+
+    blocks.push(Block::Heading("9. Signatures".to_string()));
+    blocks.push(Block::Signatures(r.signatures.clone()));
+    blocks
+}
+
+// ── HTML ────────────────────────────────────────────────────────
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const HTML_STYLE: &str = "\
+body { font-family: Helvetica, Arial, sans-serif; font-size: 10pt; color: #000; margin: 2em; }
+h1 { font-size: 16pt; margin-bottom: 0.5em; }
+h2 { font-size: 12pt; border-bottom: 1px solid #000; margin-top: 1.5em; page-break-after: avoid; }
+table { border-collapse: collapse; width: 100%; }
+th, td { border: 1px solid #999; padding: 3px 5px; text-align: left; vertical-align: top; }
+th { background: #eee; }
+table.fields th { width: 25%; }
+td.mono { font-family: Courier, monospace; font-size: 8.5pt; word-break: break-all; }
+.note { font-style: italic; }
+.signature { border: 1px solid #000; padding: 0.5em 1em; margin: 0.8em 0; page-break-inside: avoid; }
+.signature div { margin: 0.9em 0 0.3em; }
+footer { margin-top: 2em; font-size: 8pt; color: #444; }
+@page { size: A4; margin: 15mm; }
+@media print { body { margin: 0; } }";
+
+/// Render the report as a standalone, printable HTML page.
+pub fn render_html(report: &BatchReport) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>Batch Record {}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n",
+        html_escape(&report.record.batch_id),
+        HTML_STYLE
+    );
+
+    for block in document(report) {
+        match block {
+            Block::Title(text) => html.push_str(&format!("<h1>{}</h1>\n", html_escape(&text))),
+            Block::Heading(text) => html.push_str(&format!("<h2>{}</h2>\n", html_escape(&text))),
+            Block::Fields(fields) => {
+                html.push_str("<table class=\"fields\">\n");
+                for (label, value) in fields {
+                    html.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", label, html_escape(&value)));
+                }
+                html.push_str("</table>\n");
+            }
+            Block::Table { columns, rows } => {
+                html.push_str("<table>\n<tr>");
+                for (header, _) in &columns {
+                    html.push_str(&format!("<th>{header}</th>"));
+                }
+                html.push_str("</tr>\n");
+                for row in rows {
+                    html.push_str("<tr>");
+                    for (i, cell) in row.iter().enumerate() {
+                        let class = if columns[i].0 == "Details" { " class=\"mono\"" } else { "" };
+                        html.push_str(&format!("<td{class}>{}</td>", html_escape(cell)));
+                    }
+                    html.push_str("</tr>\n");
+                }
+                html.push_str("</table>\n");
+            }
+            Block::Note(text) => html.push_str(&format!("<p class=\"note\">{}</p>\n", html_escape(&text))),
+            Block::Signatures(blocks) => {
+                for sig in blocks {
+                    let name = sig.signer.as_deref().map(html_escape).unwrap_or_default();
+                    let signature = if sig.signer.is_some() { "Electronically signed" } else { "" };
+                    let date = sig.signed_at.as_deref().map(html_escape).unwrap_or_default();
+                    let note = sig
+                        .note
+                        .as_deref()
+                        .map(|n| format!("<div class=\"note\">{}</div>", html_escape(n)))
+                        .unwrap_or_default();
+                    html.push_str(&format!(
+                        "<div class=\"signature\"><strong>{}</strong>\n\
+                         <div>Name: {:_<40}</div>\n<div>Signature: {:_<40}</div>\n<div>Date / time: {:_<40}</div>{}</div>\n",
+                        html_escape(&sig.caption),
+                        name,
+                        signature,
+                        date,
+                        note
+                    ));
+                }
+            }
+        }
+    }
+
+    html.push_str(&format!(
+        "<footer>Batch {} — generated {} by {}</footer>\n</body>\n</html>\n",
+        html_escape(&report.record.batch_id),
+        html_escape(&report.generated_at),
+        html_escape(&report.generated_by)
+    ));
+    html
+}
+
+// ── PDF ─────────────────────────────────────────────────────────
+
+const PAGE_WIDTH: f64 = 595.0;
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 40.0;
+const BOTTOM: f64 = 55.0;
+const CONTENT_WIDTH: f64 = PAGE_WIDTH - 2.0 * MARGIN;
+const TABLE_SIZE: f64 = 7.5;
+const BODY_SIZE: f64 = 9.0;
+
+/// Encode text as a PDF literal string body in WinAnsiEncoding.
+fn pdf_text(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            '\n' | '\t' | '\r' => out.push(' '),
+            '→' => out.push_str("->"),
+            _ => {
+                let code = match c {
+                    '\u{a0}'..='\u{ff}' => c as u32,
+                    '€' => 0x80,
+                    '…' => 0x85,
+                    '‘' => 0x91,
+                    '’' => 0x92,
+                    '“' => 0x93,
+                    '”' => 0x94,
+                    '•' => 0x95,
+                    '–' => 0x96,
+                    '—' => 0x97,
+                    _ => '?' as u32,
+                };
+                out.push_str(&format!("\\{code:03o}"));
+            }
+        }
+    }
+    out
+}
+
+/// Helvetica and Helvetica-Bold advance widths (1/1000 em) for ASCII 32–126
+/// in WinAnsiEncoding, from the Adobe core-font AFM files.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+const HELVETICA_BOLD_WIDTHS: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+
+/// Advance width of one character (1/1000 em) as `pdf_text` encodes it.
+fn glyph_width(font: &str, c: char) -> f64 {
+    let table = match font {
+        "F1" => &HELVETICA_WIDTHS,
+        "F2" => &HELVETICA_BOLD_WIDTHS,
+        // Courier: every glyph is 600
+        _ => return if c == '→' { 1200.0 } else { 600.0 },
+    };
+    let ascii = |c: char| f64::from(table[c as usize - 32]);
+    match c {
+        ' '..='~' => ascii(c),
+        '\n' | '\t' | '\r' => ascii(' '),
+        '→' => ascii('-') + ascii('>'),
+        '\u{a0}' => ascii(' '),
+        '°' => 400.0,
+        '·' => 278.0,
+        '‘' | '’' => if font == "F2" { 278.0 } else { 222.0 },
+        '“' | '”' => if font == "F2" { 500.0 } else { 333.0 },
+        '•' => 350.0,
+        '…' | '—' => 1000.0,
+        '×' | '±' => 584.0,
+        // Other Latin-1 letters and symbols are close to the digit width
+        '\u{a1}'..='\u{ff}' | '€' | '–' => 556.0,
+        _ => ascii('?'),
+    }
+}
+
+/// Width of `s` in points when set in `font` (F1–F4) at `size`.
+pub fn text_width(font: &str, size: f64, s: &str) -> f64 {
+    s.chars().map(|c| glyph_width(font, c)).sum::<f64>() * size / 1000.0
+}
+
+/// Greedy word wrap to lines at most `width` points wide in `font` at
+/// `size`; words wider than a line are split.
+fn wrap(text: &str, font: &str, size: f64, width: f64) -> Vec<String> {
+    let fits = |s: &str| text_width(font, size, s) <= width;
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word.to_string();
+        while !fits(&word) {
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            // Longest prefix that fits, but at least one character
+            let mut end = word.chars().next().map_or(0, char::len_utf8);
+            for (i, c) in word.char_indices().skip(1) {
+                if !fits(&word[..i + c.len_utf8()]) {
+                    break;
+                }
+                end = i + c.len_utf8();
+            }
+            lines.push(word[..end].to_string());
+            word = word[end..].to_string();
+        }
+        if !line.is_empty() && !fits(&format!("{line} {word}")) {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Fonts: F1 Helvetica, F2 Helvetica-Bold, F3 Courier, F4 Courier-Bold.
+const PDF_FONTS: [&str; 4] = ["Helvetica", "Helvetica-Bold", "Courier", "Courier-Bold"];
+
+/// Page-by-page content streams with a running cursor.
+struct PdfLayout {
+    pages: Vec<String>,
+    ops: String,
+    y: f64,
+}
+
+impl PdfLayout {
+    fn new() -> Self {
+        Self {
+            pages: Vec::new(),
+            ops: String::new(),
+            y: PAGE_HEIGHT - MARGIN,
+        }
+    }
+
+    fn new_page(&mut self) {
+        self.pages.push(std::mem::take(&mut self.ops));
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    /// Start a new page unless `height` still fits on this one.
+    fn ensure(&mut self, height: f64) {
+        if self.y - height < BOTTOM {
+            self.new_page();
+        }
+    }
+
+    fn text(&mut self, font: &str, size: f64, x: f64, y: f64, s: &str) {
+        self.ops.push_str(&format!("BT /{font} {size} Tf {x:.1} {y:.1} Td ({}) Tj ET\n", pdf_text(s)));
+    }
+
+    fn rule(&mut self, x1: f64, x2: f64, y: f64, width: f64) {
+        self.ops.push_str(&format!("{width} w {x1:.1} {y:.1} m {x2:.1} {y:.1} l S\n"));
+    }
+
+    fn paragraph(&mut self, font: &str, size: f64, x: f64, width: f64, text: &str) {
+        for line in wrap(text, font, size, width) {
+            self.ensure(size + 3.0);
+            self.y -= size + 3.0;
+            self.text(font, size, x, self.y, &line);
+        }
+    }
+
+    fn table_row(&mut self, font: &str, widths: &[f64], cells: &[String]) -> f64 {
+        let wrapped: Vec<Vec<String>> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, w)| wrap(cell, font, TABLE_SIZE, w - 4.0))
+            .collect();
+        let lines = wrapped.iter().map(Vec::len).max().unwrap_or(1);
+        let line_height = TABLE_SIZE + 2.0;
+        let height = lines as f64 * line_height + 4.0;
+
+        let mut x = MARGIN;
+        for (cell_lines, w) in wrapped.iter().zip(widths) {
+            for (i, line) in cell_lines.iter().enumerate() {
+                let y = self.y - 2.0 - (i as f64 + 1.0) * line_height + 2.0;
+                self.text(font, TABLE_SIZE, x + 2.0, y, line);
+            }
+            x += w;
+        }
+        self.y -= height;
+        height
+    }
+
+    fn table(&mut self, columns: &[(&str, f64)], rows: &[Vec<String>]) {
+        let total: f64 = columns.iter().map(|(_, w)| w).sum();
+        let widths: Vec<f64> = columns.iter().map(|(_, w)| w / total * CONTENT_WIDTH).collect();
+        let headers: Vec<String> = columns.iter().map(|(h, _)| h.to_string()).collect();
+
+        self.ensure(30.0);
+        self.y -= 4.0;
+        self.table_row("F4", &widths, &headers);
+        self.rule(MARGIN, MARGIN + CONTENT_WIDTH, self.y, 0.8);
+        for row in rows {
+            let lines = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, w)| wrap(cell, "F3", TABLE_SIZE, w - 4.0).len())
+                .max()
+                .unwrap_or(1);
+            let height = lines as f64 * (TABLE_SIZE + 2.0) + 4.0;
+            if self.y - height < BOTTOM {
+                // Repeat the header on the next page
+                self.new_page();
+                self.table_row("F4", &widths, &headers);
+                self.rule(MARGIN, MARGIN + CONTENT_WIDTH, self.y, 0.8);
+            }
+            self.table_row("F3", &widths, row);
+            self.rule(MARGIN, MARGIN + CONTENT_WIDTH, self.y, 0.2);
+        }
+    }
+
+    fn signature(&mut self, sig: &SignatureBlock) {
+        let note_size = 7.0;
+        let note = sig
+            .note
+            .as_deref()
+            .map(|n| wrap(n, "F1", note_size, CONTENT_WIDTH - 16.0))
+            .unwrap_or_default();
+        let height = 78.0 + note.len() as f64 * (note_size + 3.0);
+        self.ensure(height + 10.0);
+        self.y -= 8.0;
+        let top = self.y;
+        self.ops.push_str(&format!("0.8 w {:.1} {:.1} {:.1} {:.1} re S\n", MARGIN, top - height, CONTENT_WIDTH, height));
+        self.text("F2", 10.0, MARGIN + 8.0, top - 15.0, &sig.caption);
+
+        let filled = [
+            ("Name:", sig.signer.clone().unwrap_or_default()),
+            ("Signature:", if sig.signer.is_some() { "Electronically signed".to_string() } else { String::new() }),
+            ("Date / time:", sig.signed_at.clone().unwrap_or_default()),
+        ];
+        for (i, (label, value)) in filled.iter().enumerate() {
+            let y = top - 35.0 - i as f64 * 17.0;
+            self.text("F1", BODY_SIZE, MARGIN + 8.0, y, label);
+            self.text("F1", BODY_SIZE, MARGIN + 80.0, y + 2.0, value);
+            self.rule(MARGIN + 78.0, MARGIN + CONTENT_WIDTH - 12.0, y - 2.0, 0.4);
+        }
+        for (i, line) in note.iter().enumerate() {
+            self.text("F1", note_size, MARGIN + 8.0, top - 80.0 - i as f64 * (note_size + 3.0), line);
+        }
+        self.y = top - height;
+    }
+}
+
+/// Render the report as a PDF document.
+pub fn render_pdf(report: &BatchReport) -> Vec<u8> {
+    let mut layout = PdfLayout::new();
+
+    for block in document(report) {
+        match block {
+            Block::Title(text) => {
+                layout.y -= 16.0;
+                layout.text("F2", 16.0, MARGIN, layout.y, &text);
+                layout.y -= 6.0;
+            }
+            Block::Heading(text) => {
+                layout.ensure(60.0);
+                layout.y -= 22.0;
+                layout.text("F2", 11.0, MARGIN, layout.y, &text);
+                layout.y -= 4.0;
+                layout.rule(MARGIN, MARGIN + CONTENT_WIDTH, layout.y, 0.8);
+                layout.y -= 2.0;
+            }
+            Block::Fields(fields) => {
+                let label_width = 130.0;
+                for (label, value) in fields {
+                    let lines = wrap(&value, "F1", BODY_SIZE, CONTENT_WIDTH - label_width);
+                    layout.ensure(lines.len() as f64 * (BODY_SIZE + 3.0) + 2.0);
+                    layout.y -= BODY_SIZE + 4.0;
+                    layout.text("F2", BODY_SIZE, MARGIN, layout.y, label);
+                    for (i, line) in lines.iter().enumerate() {
+                        if i > 0 {
+                            layout.y -= BODY_SIZE + 3.0;
+                        }
+                        layout.text("F1", BODY_SIZE, MARGIN + label_width, layout.y, line);
+                    }
+                }
+            }
+            Block::Table { columns, rows } => layout.table(&columns, &rows),
+            Block::Note(text) => {
+                layout.y -= 2.0;
+                layout.paragraph("F1", BODY_SIZE, MARGIN, CONTENT_WIDTH, &text);
+            }
+            Block::Signatures(blocks) => {
+                for sig in &blocks {
+                    layout.signature(sig);
+                }
+            }
+        }
+    }
+    if !layout.ops.is_empty() || layout.pages.is_empty() {
+        layout.new_page();
+    }
+
+    // Footer on every page, now that the page count is known
+    let count = layout.pages.len();
+    let mut pages = std::mem::take(&mut layout.pages);
+    for (i, page) in pages.iter_mut().enumerate() {
+        let footer = format!(
+            "Batch {} · Page {} of {} · Generated {} by {}",
+            report.record.batch_id,
+            i + 1,
+            count,
+            report.generated_at,
+            report.generated_by
+        );
+        page.push_str(&format!("BT /F1 8 Tf {MARGIN} 25 Td ({}) Tj ET\n", pdf_text(&footer)));
+    }
+
+    write_pdf(&pages, &format!("Batch Record {}", report.record.batch_id))
+}
+
+/// Serialize page content streams into a PDF file.
+fn write_pdf(pages: &[String], title: &str) -> Vec<u8> {
+    // Object numbers: 1 catalog, 2 page tree, 3 info, 4..=7 fonts, then a
+    // (content, page) pair per page.
+    let first_page_obj = 4 + PDF_FONTS.len();
+    let page_ids: Vec<usize> = (0..pages.len()).map(|i| first_page_obj + 2 * i + 1).collect();
+
+    let mut objects: Vec<String> = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            page_ids.iter().map(|id| format!("{id} 0 R")).collect::<Vec<_>>().join(" "),
+            pages.len()
+        ),
+        format!("<< /Title ({}) /Producer (Vyuh HMI) >>", pdf_text(title)),
+    ];
+    for font in PDF_FONTS {
+        objects.push(format!("<< /Type /Font /Subtype /Type1 /BaseFont /{font} /Encoding /WinAnsiEncoding >>"));
+    }
+    let font_resources = (0..PDF_FONTS.len())
+        .map(|i| format!("/F{} {} 0 R", i + 1, 4 + i))
+        .collect::<Vec<_>>()
+        .join(" ");
+    for (i, content) in pages.iter().enumerate() {
+        let content_id = first_page_obj + 2 * i;
+        objects.push(format!("<< /Length {} >>\nstream\n{}endstream", content.len(), content));
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {PAGE_WIDTH} {PAGE_HEIGHT}] \
+             /Resources << /Font << {font_resources} >> >> /Contents {content_id} 0 R >>"
+        ));
+    }
+
+    let mut out = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+    }
+    let xref = out.len();
+    out.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        out.extend_from_slice(format!("{offset:010} 00000 n \n").as_bytes());
+    }
+    out.extend_from_slice(
+        format!("trailer\n<< /Size {} /Root 1 0 R /Info 3 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref).as_bytes(),
+    );
+    out
+}
+
+// ── GET /api/batches/{id}/report ────────────────────────────────
+
+/// Query params for the batch report (`format=html|pdf`, default html).
+#[derive(Debug, Deserialize)]
+pub struct ReportParams {
+    pub format: Option<String>,
+}
+
+fn report_error(status: StatusCode, error: &str) -> Response {
+    (status, Json(serde_json::json!({ "success": false, "error": error }))).into_response()
+}
+
+pub async fn batch_report(
+    State(state): State<AppState>,
+    Path(batch_id): Path<String>,
+    Query(params): Query<ReportParams>,
+    request: Request,
+) -> Response {
+    let generated_by = request
+        .extensions()
+        .get::<Claims>()
+        .map(|c| c.sub.clone())
+        .unwrap_or_else(|| "unknown".to_string());
+    let format = params.format.as_deref().unwrap_or("html");
+    if format != "html" && format != "pdf" {
+        return report_error(StatusCode::BAD_REQUEST, "format must be 'html' or 'pdf'");
+    }
+
+    let Some(report) = build_batch_report(&state.db, &batch_id, &generated_by).await else {
+        return report_error(StatusCode::NOT_FOUND, "Batch not found");
+    };
+
+    let (content_type, body) = match format {
+        "pdf" => ("application/pdf", render_pdf(&report)),
+        _ => ("text/html; charset=utf-8", render_html(&report).into_bytes()),
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert("content-type", HeaderValue::from_static(content_type));
+    headers.insert(
+        "content-disposition",
+        HeaderValue::from_str(&format!("inline; filename=\"batch-record-{batch_id}.{format}\""))
+            .unwrap_or_else(|_| HeaderValue::from_static("inline")),
+    );
+    (StatusCode::OK, headers, body).into_response()
+}
@@ -0,0 +1,331 @@
+//! Batch Review by Exception
+//!
+//! Sending a completed batch to review (completed → under_review) scans
+//! its record for exceptions: alarms raised during it, register writes
+//! made by hand rather than by the recipe download, readings outside the
+//! configured `[batch] parameter_limits`, and steps that aborted or
+//! failed. Reviewers e-sign each exception off — except the ones they
+//! caused, such as their own manual writes; everything else in the record
+//! needs no line-by-line review.
+//!
+//! Once no exception is open an admin approves the batch for release,
+//! which also stores their approval signature on the record; a batch
+//! can be rejected at any point of the review.
+
+use sqlx::SqlitePool;
+use tracing::info;
+
+use crate::auth::{self, Claims, EsigGrant};
+use crate::batch::{self, BATCH_RECORD_TYPE};
+use crate::config::ParameterLimit;
+use crate::db;
+use crate::models::{BatchException, BatchRecord, BatchStatus, BatchStep, ExceptionKind, SignatureMeaning};
+
+/// An exception found in a batch record, before it is stored.
+struct Finding {
+    kind: ExceptionKind,
+    reference: String,
+    description: String,
+    occurred_at: String,
+    caused_by: Option<String>,
+}
+
+/// Readings of `limit` outside its range, per step of its phase (or over
+/// the whole batch when it has none).
+async fn out_of_range(
+    pool: &SqlitePool,
+    record: &BatchRecord,
+    steps: &[BatchStep],
+    limit: &ParameterLimit,
+    end: &str,
+) -> Vec<Finding> {
+    // (reference, scope, from, to)
+    let windows: Vec<(String, String, String, String)> = match &limit.phase {
+        Some(phase) => steps
+            .iter()
+            .filter(|s| &s.name == phase)
+            .map(|s| {
+                (
+                    format!("step:{}:{}", s.step_number, limit.register),
+                    format!("step {} ({})", s.step_number, s.name),
+                    s.start_time.clone(),
+                    s.end_time.clone().unwrap_or_else(|| end.to_string()),
+                )
+            })
+            .collect(),
+        None => vec![(
+            format!("batch:{}", limit.register),
+            "the batch".to_string(),
+            record.start_time.clone(),
+            end.to_string(),
+        )],
+    };
+
+    let mut findings = Vec::new();
+    for (reference, scope, from, to) in windows {
+        let Some(stats) = db::register_stats(pool, &record.device_id, limit.register, &from, &to).await else {
+            continue;
+        };
+        let mut breaches = Vec::new();
+        if let Some(min) = limit.min
+            && stats.min < min
+        {
+            breaches.push(format!("min {:.1} below {:.1}", stats.min, min));
+        }
+        if let Some(max) = limit.max
+            && stats.max > max
+        {
+            breaches.push(format!("max {:.1} above {:.1}", stats.max, max));
+        }
+        if !breaches.is_empty() {
+            findings.push(Finding {
+                kind: ExceptionKind::OutOfRange,
+                reference,
+                description: format!("{} during {}: {}", limit.label, scope, breaches.join(", ")),
+                occurred_at: from,
+                caused_by: None,
+            });
+        }
+    }
+    findings
+}
+
+/// Everything in a batch record a reviewer has to look at, oldest first.
+async fn find_exceptions(
+    pool: &SqlitePool,
+    record: &BatchRecord,
+    steps: &[BatchStep],
+    limits: &[ParameterLimit],
+) -> Vec<Finding> {
+    let end = record.end_time.clone().unwrap_or_else(|| chrono::Utc::now().to_rfc3339());
+    let mut findings = Vec::new();
+
+    for alarm in db::list_batch_alarms(pool, &record.batch_id).await {
+        findings.push(Finding {
+            kind: ExceptionKind::Alarm,
+            reference: format!("alarm:{}", alarm.id),
+            description: format!("{} alarm on {}: {}", alarm.priority.as_str(), alarm.label, alarm.message),
+            occurred_at: alarm.timestamp,
+            caused_by: None,
+        });
+    }
+
+    // Recipe downloads carry the batch ID; anything else was typed in
+    for entry in auth::list_batch_audit(pool, &record.device_id, &record.start_time, &end).await {
+        if entry.action != "write_register" {
+            continue;
+        }
+        let details: serde_json::Value = serde_json::from_str(&entry.details).unwrap_or_default();
+        if details.get("batch_id").is_some() {
+            continue;
+        }
+        findings.push(Finding {
+            kind: ExceptionKind::ManualWrite,
+            reference: format!("audit:{}", entry.id),
+            description: format!("Register {} set to {} by {}", details["register"], details["value"], entry.username),
+            occurred_at: entry.timestamp,
+            caused_by: Some(entry.username),
+        });
+    }
+
+    for limit in limits {
+        findings.extend(out_of_range(pool, record, steps, limit, &end).await);
+    }
+
+    for step in steps.iter().filter(|s| s.status == "aborted" || s.status == "failed") {
+        let mut description = format!("Step {} ({}) {}", step.step_number, step.name, step.status);
+        if step.status == "failed"
+            && let Some(result) = &step.result
+        {
+            description.push_str(&format!(": {result}"));
+        }
+        findings.push(Finding {
+            kind: ExceptionKind::AbortedStep,
+            reference: format!("step:{}", step.step_number),
+            description,
+            occurred_at: step.end_time.clone().unwrap_or_else(|| step.start_time.clone()),
+            caused_by: None,
+        });
+    }
+
+    findings.sort_by(|a, b| a.occurred_at.cmp(&b.occurred_at));
+    findings
+}
+
+/// Send a completed batch to review and list the exceptions to sign off.
+pub async fn start_review(
+    pool: &SqlitePool,
+    limits: &[ParameterLimit],
+    batch_id: &str,
+    claims: &Claims,
+) -> Result<Vec<BatchException>, String> {
+    let (record, steps) = db::get_batch_with_steps(pool, batch_id).await.ok_or("Batch not found")?;
+    if record.status != BatchStatus::Completed {
+        return Err(format!("Batch {batch_id} is {} — only completed batches go to review", record.status.as_str()));
+    }
+
+    let findings = find_exceptions(pool, &record, &steps, limits).await;
+
+    // The batch only goes under review together with its exceptions
+    let mut tx = pool.begin().await.map_err(|e| format!("DB error: {e}"))?;
+    db::transition_batch(&mut *tx, batch_id, &[BatchStatus::Completed], BatchStatus::UnderReview, &claims.sub, None)
+        .await?;
+    for f in &findings {
+        db::insert_batch_exception(
+            &mut *tx,
+            batch_id,
+            f.kind,
+            &f.reference,
+            &f.description,
+            &f.occurred_at,
+            f.caused_by.as_deref(),
+        )
+        .await?;
+    }
+    tx.commit().await.map_err(|e| format!("DB error: {e}"))?;
+
+    let details = serde_json::json!({ "batch_id": batch_id, "exceptions": findings.len() }).to_string();
+    auth::log_audit(pool, &claims.user_id, &claims.sub, "batch_review_start", Some(&record.device_id), &details, None).await;
+    info!("Batch {} under review by {}: {} exception(s)", batch_id, claims.sub, findings.len());
+
+    Ok(db::list_batch_exceptions(pool, batch_id).await)
+}
+
+/// Check that `claims` may sign off an exception of a batch under review:
+/// it must still be open and not caused by the reviewer themselves. Run
+/// before consuming the e-signature so a refused sign-off leaves it unspent.
+pub async fn check_sign_off(
+    pool: &SqlitePool,
+    batch_id: &str,
+    exception_id: i64,
+    claims: &Claims,
+) -> Result<BatchRecord, String> {
+    let (record, _) = db::get_batch_with_steps(pool, batch_id).await.ok_or("Batch not found")?;
+    if record.status != BatchStatus::UnderReview {
+        return Err(format!("Batch {batch_id} is not under review"));
+    }
+    let exception = db::list_batch_exceptions(pool, batch_id)
+        .await
+        .into_iter()
+        .find(|e| e.id == exception_id)
+        .ok_or_else(|| format!("Exception {exception_id} of batch {batch_id} not found"))?;
+    if let Some(reviewer) = &exception.reviewed_by {
+        return Err(format!("Exception {exception_id} was already signed off by {reviewer}"));
+    }
+    if exception.caused_by.as_deref() == Some(claims.sub.as_str()) {
+        return Err(format!("{} caused exception {exception_id} and cannot sign it off", claims.sub));
+    }
+    Ok(record)
+}
+
+/// E-sign one exception of a batch under review. The comment defaults to
+/// the signature's reason.
+pub async fn sign_off_exception(
+    pool: &SqlitePool,
+    batch_id: &str,
+    exception_id: i64,
+    claims: &Claims,
+    grant: &EsigGrant,
+    comment: Option<&str>,
+) -> Result<BatchException, String> {
+    let record = check_sign_off(pool, batch_id, exception_id, claims).await?;
+    let comment = comment.unwrap_or(&grant.reason);
+    db::sign_off_batch_exception(pool, batch_id, exception_id, &grant.username, comment).await?;
+
+    let exception = db::list_batch_exceptions(pool, batch_id)
+        .await
+        .into_iter()
+        .find(|e| e.id == exception_id)
+        .ok_or("Exception not found")?;
+    let details = serde_json::json!({
+        "batch_id": batch_id,
+        "exception_id": exception_id,
+        "kind": exception.kind.as_str(),
+        "reference": exception.reference,
+        "comment": comment,
+        "esig_reason": grant.reason,
+        "esig_signed_at": grant.signed_at,
+    })
+    .to_string();
+    auth::log_audit(pool, &claims.user_id, &claims.sub, "batch_exception_signoff", Some(&record.device_id), &details, None)
+        .await;
+
+    Ok(exception)
+}
+
+/// Check that a batch can be approved or rejected: it must be under
+/// review and, for approval, have no exception left open. Run before
+/// consuming the e-signature so a refused decision leaves it unspent.
+pub async fn check_decide(pool: &SqlitePool, batch_id: &str, approve: bool) -> Result<BatchRecord, String> {
+    let (record, _) = db::get_batch_with_steps(pool, batch_id).await.ok_or("Batch not found")?;
+    if record.status != BatchStatus::UnderReview {
+        return Err(format!("Batch {batch_id} is not under review"));
+    }
+    if approve {
+        let open = db::list_batch_exceptions(pool, batch_id)
+            .await
+            .iter()
+            .filter(|e| e.reviewed_by.is_none())
+            .count();
+        if open > 0 {
+            return Err(format!("{open} exception(s) still need sign-off"));
+        }
+    }
+    Ok(record)
+}
+
+/// Approve (release) or reject a batch under review. Approval needs every
+/// exception signed off and stores the reviewer's approval signature.
+pub async fn decide(
+    pool: &SqlitePool,
+    batch_id: &str,
+    approve: bool,
+    claims: &Claims,
+    grant: &EsigGrant,
+    comment: Option<&str>,
+) -> Result<BatchRecord, String> {
+    let record = check_decide(pool, batch_id, approve).await?;
+    let exceptions = db::list_batch_exceptions(pool, batch_id).await;
+
+    let mut approval_hash = None;
+    if approve {
+        let signed = batch::batch_signatures(pool, batch_id)
+            .await
+            .iter()
+            .any(|s| s.meaning == SignatureMeaning::Approval && s.user_id == grant.user_id);
+        if !signed {
+            approval_hash = Some(batch::batch_content_hash(pool, batch_id).await.ok_or("Batch not found")?);
+        }
+    }
+
+    let (to, action) = if approve {
+        (BatchStatus::Approved, "batch_approve")
+    } else {
+        (BatchStatus::Rejected, "batch_reject")
+    };
+    let reason = comment.unwrap_or(&grant.reason);
+
+    // The approval signature is only stored together with the disposition
+    let mut tx = pool.begin().await.map_err(|e| format!("DB error: {e}"))?;
+    if let Some(hash) = &approval_hash {
+        db::insert_signature(&mut *tx, BATCH_RECORD_TYPE, batch_id, SignatureMeaning::Approval, grant, hash).await?;
+    }
+    db::set_batch_disposition(&mut *tx, batch_id, to.clone(), &grant.username, reason).await?;
+    tx.commit().await.map_err(|e| format!("DB error: {e}"))?;
+
+    let details = serde_json::json!({
+        "batch_id": batch_id,
+        "reason": reason,
+        "exceptions": exceptions.len(),
+        "esig_reason": grant.reason,
+        "esig_signed_at": grant.signed_at,
+    })
+    .to_string();
+    auth::log_audit(pool, &claims.user_id, &claims.sub, action, Some(&record.device_id), &details, None).await;
+    info!("Batch {} {} by {}", batch_id, to.as_str(), grant.username);
+
+    db::get_batch_with_steps(pool, batch_id)
+        .await
+        .map(|(record, _)| record)
+        .ok_or_else(|| "Batch not found".to_string())
+}
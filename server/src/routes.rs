@@ -6,18 +6,29 @@ use serde::Deserialize;
 use tokio::sync::mpsc;
 use tracing::info;
 
+use crate::alarm_kpi;
+use crate::batch;
 use crate::auth::{self, Claims};
-use crate::config::DeviceConfig;
+use crate::config::{AlarmDefinition, DeviceConfig};
 use crate::db;
 use crate::discovery;
+use crate::genealogy;
+use crate::golden;
+use crate::review;
 use crate::modbus::ModbusClient;
 use crate::opcua_client::OpcUaClient;
 use crate::models::{
-    AckAlarmRequest, AddDeviceRequest, AlarmQueryParams, ApiResponse, BatchQueryParams,
-    BrowseOpcUaRequest, PlcData, PlcDevice, ScanRequest, ShelveAlarmRequest, WriteRequest,
+    AckAlarmRequest, AddDeviceRequest, AggregateBucket, AlarmDefinitionQueryParams, AlarmKpiParams, AlarmKpis,
+    AlarmPointOutOfService, AlarmQueryParams, AlarmSuppression, AlarmSuppressionQueryParams, ApiResponse,
+    BatchCompareParams, BatchComparison, BatchControlRequest, BatchException, BatchMaterial, Genealogy,
+    GenealogyParams, LotQueryParams, MaterialLot, RecordMaterialRequest, BatchQueryParams, BatchRecord, BatchReviewRequest, StartBatchRequest,
+    BrowseOpcUaRequest, OutOfServiceRequest, PlcData, PlcDevice, Recipe, RecipeQueryParams,
+    RecipeRequest, RecipeSignRequest, RecordSignature, ScanRequest, ShelveAlarmRequest, SignBatchRequest,
+    WriteRequest,
 };
 use crate::protocol;
 use crate::state::{AppState, DeviceHandle, WriteCommand};
+use crate::ws;
 
 // query param for history endpoint
 #[derive(Deserialize)]
@@ -26,6 +37,23 @@ pub struct HistoryParams {
     pub limit: Option<i64>,
 }
 
+/// Query params for GET /api/history/aggregate. The window defaults to
+/// the last 24 hours and the bucket to one that yields about
+/// `DEFAULT_AGGREGATE_BUCKETS` points.
+#[derive(Deserialize)]
+pub struct HistoryAggregateParams {
+    pub device_id: String,
+    pub register: u16,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub bucket_secs: Option<i64>,
+}
+
+/// Points a chart gets when it does not choose a bucket size.
+pub const DEFAULT_AGGREGATE_BUCKETS: i64 = 500;
+/// Most buckets one aggregate query may return.
+pub const MAX_AGGREGATE_BUCKETS: i64 = 10_000;
+
 // ── GET /api/devices ────────────────────────────────────────────
 // List all active devices with REAL connection status.
 pub async fn get_devices(State(state): State<AppState>) -> Json<ApiResponse<Vec<PlcDevice>>> {
@@ -98,6 +126,8 @@ pub async fn add_device(
         state.tx.clone(),
         state.db.clone(),
         write_rx,
+        state.config.alarm_flood.clone(),
+        state.config.batch.clone(),
     );
 
     let device = PlcDevice {
@@ -184,7 +214,8 @@ pub async fn disconnect_device(
     }
 
     handle.task.abort();
-    info!("Device '{}' disconnected (polling stopped)", device_id);
+    db::set_device_alarms_suppressed(&state.db, &device_id, Some("disconnected")).await;
+    info!("Device '{}' disconnected (polling stopped, alarms suppressed)", device_id);
 
     Json(ApiResponse {
         success: true,
@@ -242,6 +273,8 @@ pub async fn connect_device(
         state.tx.clone(),
         state.db.clone(),
         write_rx,
+        state.config.alarm_flood.clone(),
+        state.config.batch.clone(),
     );
 
     // Update registry with new task + write channel
@@ -312,6 +345,60 @@ pub async fn browse_opcua(
     }
 }
 
+// ── GET /api/history/aggregate ──────────────────────────────────
+// Time-bucketed min/max/avg/first/last/count of one register, so long
+// ranges plot without downloading every raw reading.
+pub async fn get_history_aggregate(
+    State(state): State<AppState>,
+    Query(params): Query<HistoryAggregateParams>,
+) -> Json<ApiResponse<Vec<AggregateBucket>>> {
+    let parse = |s: &Option<String>| -> Result<Option<chrono::DateTime<chrono::Utc>>, String> {
+        s.as_deref()
+            .map(|s| {
+                chrono::DateTime::parse_from_rfc3339(s)
+                    .map(|t| t.with_timezone(&chrono::Utc))
+                    .map_err(|e| format!("Invalid timestamp '{s}': {e}"))
+            })
+            .transpose()
+    };
+
+    let result = (|| {
+        let to = parse(&params.to)?.unwrap_or_else(chrono::Utc::now);
+        let from = parse(&params.from)?.unwrap_or(to - chrono::Duration::hours(24));
+        if from >= to {
+            return Err("'from' must be before 'to'".to_string());
+        }
+        let span = (to - from).num_seconds().max(1);
+        let bucket_secs = params.bucket_secs.unwrap_or((span + DEFAULT_AGGREGATE_BUCKETS - 1) / DEFAULT_AGGREGATE_BUCKETS);
+        if bucket_secs < 1 {
+            return Err("bucket_secs must be at least 1".to_string());
+        }
+        if span / bucket_secs > MAX_AGGREGATE_BUCKETS {
+            return Err(format!("Too many buckets — use a bucket_secs of at least {}", span / MAX_AGGREGATE_BUCKETS + 1));
+        }
+        Ok((from, to, bucket_secs))
+    })();
+
+    match result {
+        Ok((from, to, bucket_secs)) => {
+            let buckets = state
+                .tsdb
+                .aggregate(&params.device_id, params.register, &from.to_rfc3339(), &to.to_rfc3339(), bucket_secs)
+                .await;
+            Json(ApiResponse {
+                success: true,
+                data: Some(buckets),
+                error: None,
+            })
+        }
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
 // ── GET /api/history ────────────────────────────────────────────
 pub async fn get_history(
     State(state): State<AppState>,
@@ -383,6 +470,7 @@ pub async fn post_write(
     let cmd = WriteCommand {
         register: req.register,
         value: req.value,
+        verify: false,
         response: resp_tx,
     };
 
@@ -445,7 +533,9 @@ pub async fn list_alarms(
     Query(params): Query<AlarmQueryParams>,
 ) -> Json<ApiResponse<Vec<crate::models::Alarm>>> {
     // Un-shelve any expired alarms first
-    db::unshelve_expired(&state.db).await;
+    for alarm_id in db::unshelve_expired(&state.db).await {
+        ws::publish_alarm_event(&state.tx, &state.db, alarm_id, "unshelve").await;
+    }
 
     let alarms = db::list_alarms(&state.db, &params).await;
     Json(ApiResponse {
@@ -455,6 +545,70 @@ pub async fn list_alarms(
     })
 }
 
+/// GET /api/alarms/kpis — ISA-18.2 alarm performance metrics over a window.
+pub async fn get_alarm_kpis(
+    State(state): State<AppState>,
+    Query(params): Query<AlarmKpiParams>,
+) -> Json<ApiResponse<AlarmKpis>> {
+    let parse = |s: &Option<String>| -> Result<Option<chrono::DateTime<chrono::Utc>>, String> {
+        s.as_deref()
+            .map(|s| {
+                chrono::DateTime::parse_from_rfc3339(s)
+                    .map(|t| t.with_timezone(&chrono::Utc))
+                    .map_err(|e| format!("Invalid timestamp '{s}': {e}"))
+            })
+            .transpose()
+    };
+
+    let now = chrono::Utc::now();
+    let window = parse(&params.from).and_then(|from| Ok((from, parse(&params.to)?)));
+    let (from, to) = match window {
+        Ok((from, to)) => {
+            let to = to.unwrap_or(now);
+            (from.unwrap_or(to - chrono::Duration::hours(24)), to)
+        }
+        Err(e) => {
+            return Json(ApiResponse {
+                success: false,
+                data: None,
+                error: Some(e),
+            });
+        }
+    };
+    if from >= to {
+        return Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some("'from' must be before 'to'".into()),
+        });
+    }
+
+    let device_id = params.device_id.as_deref();
+    let raised = db::list_alarms_raised_between(&state.db, device_id, &from.to_rfc3339(), &to.to_rfc3339()).await;
+    let standing = db::list_standing_alarms(&state.db, device_id).await;
+    let operators = params.operators.unwrap_or(1);
+    let kpis = alarm_kpi::compute_kpis(&raised, &standing, from, to, now, operators, state.config.alarm_flood.threshold);
+
+    Json(ApiResponse {
+        success: true,
+        data: Some(kpis),
+        error: None,
+    })
+}
+
+/// GET /api/alarms/suppressions — alarms hidden by flood suppression (audit).
+pub async fn list_alarm_suppressions(
+    State(state): State<AppState>,
+    Query(params): Query<AlarmSuppressionQueryParams>,
+) -> Json<ApiResponse<Vec<AlarmSuppression>>> {
+    let suppressions = db::list_alarm_suppressions(&state.db, &params).await;
+    Json(ApiResponse {
+        success: true,
+        data: Some(suppressions),
+        error: None,
+    })
+}
+
 /// GET /api/alarms/:id — get a single alarm.
 pub async fn get_alarm(
     State(state): State<AppState>,
@@ -474,6 +628,27 @@ pub async fn get_alarm(
     }
 }
 
+/// GET /api/alarms/:id/events — full state-transition history of an alarm.
+pub async fn list_alarm_events(
+    State(state): State<AppState>,
+    Path(alarm_id): Path<i64>,
+) -> Json<ApiResponse<Vec<crate::models::AlarmEvent>>> {
+    if db::get_alarm(&state.db, alarm_id).await.is_none() {
+        return Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some("Alarm not found".into()),
+        });
+    }
+
+    let events = db::list_alarm_events(&state.db, alarm_id).await;
+    Json(ApiResponse {
+        success: true,
+        data: Some(events),
+        error: None,
+    })
+}
+
 /// POST /api/alarms/:id/ack — acknowledge an alarm (operator+).
 pub async fn ack_alarm(
     State(state): State<AppState>,
@@ -491,6 +666,7 @@ pub async fn ack_alarm(
 
     match db::ack_alarm(&state.db, alarm_id, &username, req.comment.as_deref()).await {
         Ok(()) => {
+            ws::publish_alarm_event(&state.tx, &state.db, alarm_id, "ack").await;
             // Audit trail
             if let Some(ref claims) = claims {
                 auth::log_audit(
@@ -544,6 +720,7 @@ pub async fn shelve_alarm(
 
     match db::shelve_alarm(&state.db, alarm_id, &username, req.duration_minutes, &req.reason).await {
         Ok(()) => {
+            ws::publish_alarm_event(&state.tx, &state.db, alarm_id, "shelve").await;
             if let Some(ref claims) = claims {
                 auth::log_audit(
                     &state.db,
@@ -570,42 +747,906 @@ pub async fn shelve_alarm(
     }
 }
 
-// ── ISA-88: Batch Record Routes ─────────────────────────────────
+/// POST /api/alarms/:id/unshelve — end shelving early (operator+).
+pub async fn unshelve_alarm(
+    State(state): State<AppState>,
+    Path(alarm_id): Path<i64>,
+    request: Request,
+) -> Json<ApiResponse<String>> {
+    let claims = request.extensions().get::<Claims>().cloned();
+    let username = claims.as_ref().map(|c| c.sub.clone()).unwrap_or_default();
 
-/// GET /api/batches — list batch records.
-pub async fn list_batches(
+    match db::unshelve_alarm(&state.db, alarm_id, &username).await {
+        Ok(()) => {
+            ws::publish_alarm_event(&state.tx, &state.db, alarm_id, "unshelve").await;
+            if let Some(ref claims) = claims {
+                auth::log_audit(
+                    &state.db,
+                    &claims.user_id,
+                    &claims.sub,
+                    "alarm_unshelve",
+                    None,
+                    &format!("Unshelved alarm #{alarm_id}"),
+                    None,
+                )
+                .await;
+            }
+            Json(ApiResponse {
+                success: true,
+                data: Some(format!("Alarm #{alarm_id} unshelved")),
+                error: None,
+            })
+        }
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+/// Parse the reason of an out-of-service request.
+async fn parse_out_of_service(request: Request) -> Result<OutOfServiceRequest, String> {
+    let body = axum::body::to_bytes(request.into_body(), 1024 * 4)
+        .await
+        .unwrap_or_default();
+    serde_json::from_slice(&body).map_err(|e| format!("Invalid JSON: {e}"))
+}
+
+/// Notify clients of alarms whose point changed service state, audit the
+/// change and build the response.
+async fn point_service_changed(
+    state: &AppState,
+    claims: Option<&Claims>,
+    result: Result<Vec<i64>, String>,
+    event: &str,
+    message: String,
+) -> Json<ApiResponse<String>> {
+    match result {
+        Ok(alarm_ids) => {
+            for alarm_id in alarm_ids {
+                ws::publish_alarm_event(&state.tx, &state.db, alarm_id, event).await;
+            }
+            if let Some(claims) = claims {
+                let action = format!("alarm_{event}");
+                auth::log_audit(&state.db, &claims.user_id, &claims.sub, &action, None, &message, None).await;
+            }
+            Json(ApiResponse {
+                success: true,
+                data: Some(message),
+                error: None,
+            })
+        }
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+/// POST /api/alarms/:id/out-of-service — take an alarm's point out of service (admin).
+pub async fn alarm_out_of_service(
     State(state): State<AppState>,
-    Query(params): Query<BatchQueryParams>,
-) -> Json<ApiResponse<Vec<crate::models::BatchRecord>>> {
-    let batches = db::list_batches(&state.db, &params).await;
+    Path(alarm_id): Path<i64>,
+    request: Request,
+) -> Json<ApiResponse<String>> {
+    let claims = request.extensions().get::<Claims>().cloned();
+    let username = claims.as_ref().map(|c| c.sub.clone()).unwrap_or_default();
+    let (result, message) = match parse_out_of_service(request).await {
+        Ok(req) => (
+            db::set_alarm_out_of_service(&state.db, alarm_id, &username, &req.reason).await,
+            format!("Alarm #{alarm_id} out of service: {}", req.reason),
+        ),
+        Err(e) => (Err(e), String::new()),
+    };
+    point_service_changed(&state, claims.as_ref(), result, "out_of_service", message).await
+}
+
+/// POST /api/alarms/:id/return-to-service — return an alarm's point to service (admin).
+pub async fn alarm_return_to_service(
+    State(state): State<AppState>,
+    Path(alarm_id): Path<i64>,
+    request: Request,
+) -> Json<ApiResponse<String>> {
+    let claims = request.extensions().get::<Claims>().cloned();
+    let username = claims.as_ref().map(|c| c.sub.clone()).unwrap_or_default();
+    let result = db::return_alarm_to_service(&state.db, alarm_id, &username).await;
+    let message = format!("Alarm #{alarm_id} returned to service");
+    point_service_changed(&state, claims.as_ref(), result, "return_to_service", message).await
+}
+
+/// GET /api/alarm-points/out-of-service — alarm points in maintenance.
+pub async fn list_points_out_of_service(
+    State(state): State<AppState>,
+    Query(params): Query<AlarmDefinitionQueryParams>,
+) -> Json<ApiResponse<Vec<AlarmPointOutOfService>>> {
+    let points = db::list_points_out_of_service(&state.db, params.device_id.as_deref()).await;
     Json(ApiResponse {
         success: true,
-        data: Some(batches),
+        data: Some(points),
         error: None,
     })
 }
 
-/// GET /api/batches/:id — get a single batch with steps.
-pub async fn get_batch(
+/// POST /api/alarm-points/:device_id/:register/out-of-service — take a tag
+/// out of service, e.g. ahead of planned maintenance (admin).
+pub async fn alarm_point_out_of_service(
     State(state): State<AppState>,
-    Path(batch_id): Path<String>,
-) -> Json<ApiResponse<serde_json::Value>> {
-    match db::get_batch_with_steps(&state.db, &batch_id).await {
-        Some((record, steps)) => {
-            let data = serde_json::json!({
-                "record": record,
-                "steps": steps,
+    Path((device_id, register)): Path<(String, u16)>,
+    request: Request,
+) -> Json<ApiResponse<String>> {
+    let claims = request.extensions().get::<Claims>().cloned();
+    let username = claims.as_ref().map(|c| c.sub.clone()).unwrap_or_default();
+    let (result, message) = match parse_out_of_service(request).await {
+        Ok(req) => (
+            db::set_point_out_of_service(&state.db, &device_id, register, &username, &req.reason).await,
+            format!("Alarm point {device_id}/{register} out of service: {}", req.reason),
+        ),
+        Err(e) => (Err(e), String::new()),
+    };
+    point_service_changed(&state, claims.as_ref(), result, "out_of_service", message).await
+}
+
+/// POST /api/alarm-points/:device_id/:register/return-to-service — end a
+/// tag's maintenance (admin).
+pub async fn alarm_point_return_to_service(
+    State(state): State<AppState>,
+    Path((device_id, register)): Path<(String, u16)>,
+    request: Request,
+) -> Json<ApiResponse<String>> {
+    let claims = request.extensions().get::<Claims>().cloned();
+    let username = claims.as_ref().map(|c| c.sub.clone()).unwrap_or_default();
+    let result = db::return_point_to_service(&state.db, &device_id, register, &username).await;
+    let message = format!("Alarm point {device_id}/{register} returned to service");
+    point_service_changed(&state, claims.as_ref(), result, "return_to_service", message).await
+}
+
+// ── ISA-18.2: Alarm Definition Routes ───────────────────────────
+
+/// GET /api/alarm-definitions — list alarm definitions (optionally per device).
+pub async fn list_alarm_definitions(
+    State(state): State<AppState>,
+    Query(params): Query<AlarmDefinitionQueryParams>,
+) -> Json<ApiResponse<Vec<AlarmDefinition>>> {
+    let defs = db::list_alarm_definitions(&state.db, params.device_id.as_deref()).await;
+    Json(ApiResponse {
+        success: true,
+        data: Some(defs),
+        error: None,
+    })
+}
+
+/// GET /api/alarm-definitions/:id — get a single alarm definition.
+pub async fn get_alarm_definition(
+    State(state): State<AppState>,
+    Path(def_id): Path<i64>,
+) -> Json<ApiResponse<AlarmDefinition>> {
+    match db::get_alarm_definition(&state.db, def_id).await {
+        Some(def) => Json(ApiResponse {
+            success: true,
+            data: Some(def),
+            error: None,
+        }),
+        None => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some("Alarm definition not found".into()),
+        }),
+    }
+}
+
+/// Parse and validate an alarm definition request body.
+async fn parse_alarm_definition(request: Request) -> Result<AlarmDefinition, String> {
+    let body = axum::body::to_bytes(request.into_body(), 1024 * 16)
+        .await
+        .map_err(|_| "Invalid request body".to_string())?;
+    let def: AlarmDefinition =
+        serde_json::from_slice(&body).map_err(|e| format!("Invalid JSON: {e}"))?;
+    def.validate()?;
+    Ok(def)
+}
+
+/// POST /api/alarm-definitions — create an alarm definition (admin).
+pub async fn create_alarm_definition(
+    State(state): State<AppState>,
+    request: Request,
+) -> Json<ApiResponse<AlarmDefinition>> {
+    let claims = request.extensions().get::<Claims>().cloned();
+
+    let mut def = match parse_alarm_definition(request).await {
+        Ok(d) => d,
+        Err(e) => {
+            return Json(ApiResponse {
+                success: false,
+                data: None,
+                error: Some(e),
             });
+        }
+    };
+
+    match db::create_alarm_definition(&state.db, &def).await {
+        Ok(id) => {
+            def.id = id;
+            if let Some(ref claims) = claims {
+                let details = serde_json::to_string(&def).unwrap_or_default();
+                auth::log_audit(
+                    &state.db,
+                    &claims.user_id,
+                    &claims.sub,
+                    "alarm_definition_create",
+                    Some(&def.device_id),
+                    &details,
+                    None,
+                )
+                .await;
+            }
+            info!("Alarm definition #{} created for {}/{}", id, def.device_id, def.register);
             Json(ApiResponse {
                 success: true,
-                data: Some(data),
+                data: Some(def),
                 error: None,
             })
         }
-        None => Json(ApiResponse {
+        Err(e) => Json(ApiResponse {
             success: false,
             data: None,
-            error: Some("Batch not found".into()),
+            error: Some(e),
         }),
     }
 }
+
+/// PUT /api/alarm-definitions/:id — replace an alarm definition (admin).
+pub async fn update_alarm_definition(
+    State(state): State<AppState>,
+    Path(def_id): Path<i64>,
+    request: Request,
+) -> Json<ApiResponse<AlarmDefinition>> {
+    let claims = request.extensions().get::<Claims>().cloned();
+
+    let mut def = match parse_alarm_definition(request).await {
+        Ok(d) => d,
+        Err(e) => {
+            return Json(ApiResponse {
+                success: false,
+                data: None,
+                error: Some(e),
+            });
+        }
+    };
+    def.id = def_id;
+
+    let previous = db::get_alarm_definition(&state.db, def_id).await;
+
+    match db::update_alarm_definition(&state.db, def_id, &def).await {
+        Ok(()) => {
+            if let Some(ref claims) = claims {
+                let details = serde_json::json!({
+                    "old": previous,
+                    "new": def,
+                })
+                .to_string();
+                auth::log_audit(
+                    &state.db,
+                    &claims.user_id,
+                    &claims.sub,
+                    "alarm_definition_update",
+                    Some(&def.device_id),
+                    &details,
+                    None,
+                )
+                .await;
+            }
+            info!("Alarm definition #{} updated", def_id);
+            Json(ApiResponse {
+                success: true,
+                data: Some(def),
+                error: None,
+            })
+        }
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+/// DELETE /api/alarm-definitions/:id — delete an alarm definition (admin).
+pub async fn delete_alarm_definition(
+    State(state): State<AppState>,
+    Path(def_id): Path<i64>,
+    request: Request,
+) -> Json<ApiResponse<String>> {
+    let claims = request.extensions().get::<Claims>().cloned();
+
+    let Some(previous) = db::get_alarm_definition(&state.db, def_id).await else {
+        return Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some("Alarm definition not found".into()),
+        });
+    };
+
+    match db::delete_alarm_definition(&state.db, def_id).await {
+        Ok(()) => {
+            if let Some(ref claims) = claims {
+                let details = serde_json::to_string(&previous).unwrap_or_default();
+                auth::log_audit(
+                    &state.db,
+                    &claims.user_id,
+                    &claims.sub,
+                    "alarm_definition_delete",
+                    Some(&previous.device_id),
+                    &details,
+                    None,
+                )
+                .await;
+            }
+            info!("Alarm definition #{} deleted", def_id);
+            Json(ApiResponse {
+                success: true,
+                data: Some(format!("Alarm definition #{def_id} deleted")),
+                error: None,
+            })
+        }
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+// ── ISA-88: Batch Record Routes ─────────────────────────────────
+
+/// GET /api/batches — list batch records.
+pub async fn list_batches(
+    State(state): State<AppState>,
+    Query(params): Query<BatchQueryParams>,
+) -> Json<ApiResponse<Vec<BatchRecord>>> {
+    let batches = db::list_batches(&state.db, &params).await;
+    Json(ApiResponse {
+        success: true,
+        data: Some(batches),
+        error: None,
+    })
+}
+
+/// POST /api/batches — start a batch from an approved recipe and download
+/// its parameter set to the device (operator+).
+pub async fn start_batch(
+    State(state): State<AppState>,
+    request: Request,
+) -> Json<ApiResponse<BatchRecord>> {
+    let result = async {
+        let claims = request
+            .extensions()
+            .get::<Claims>()
+            .cloned()
+            .ok_or_else(|| "Not authenticated".to_string())?;
+        let body = axum::body::to_bytes(request.into_body(), 1024 * 4)
+            .await
+            .map_err(|_| "Invalid request body".to_string())?;
+        let req: StartBatchRequest = serde_json::from_slice(&body).map_err(|e| format!("Invalid JSON: {e}"))?;
+        batch::start_batch(&state, &req, &claims).await
+    };
+
+    match result.await {
+        Ok(record) => Json(ApiResponse {
+            success: true,
+            data: Some(record),
+            error: None,
+        }),
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+/// Claims plus the (optional) control body of a batch command.
+async fn parse_batch_control(request: Request) -> Result<(Claims, BatchControlRequest), String> {
+    let claims = request
+        .extensions()
+        .get::<Claims>()
+        .cloned()
+        .ok_or_else(|| "Not authenticated".to_string())?;
+    let body = axum::body::to_bytes(request.into_body(), 1024 * 4)
+        .await
+        .map_err(|_| "Invalid request body".to_string())?;
+    if body.is_empty() {
+        return Ok((claims, BatchControlRequest::default()));
+    }
+    let req = serde_json::from_slice(&body).map_err(|e| format!("Invalid JSON: {e}"))?;
+    Ok((claims, req))
+}
+
+/// Run a batch command; abort must be e-signed and keeps the signature's reason.
+async fn control_batch(
+    state: &AppState,
+    batch_id: &str,
+    command: batch::BatchCommand,
+    request: Request,
+) -> Json<ApiResponse<BatchRecord>> {
+    let result = async {
+        let (claims, req) = parse_batch_control(request).await?;
+        let reason = match command {
+            batch::BatchCommand::Abort => {
+                let token = req.esig_token.as_deref().ok_or("Abort requires an e-signature (esig_token)")?;
+                batch::check_batch_command(state, batch_id, command).await?;
+                Some(auth::consume_esig(&state.db, token, &claims.user_id).await?.reason)
+            }
+            _ => req.reason,
+        };
+        batch::control_batch(state, batch_id, command, &claims, reason.as_deref()).await
+    };
+
+    match result.await {
+        Ok(record) => Json(ApiResponse {
+            success: true,
+            data: Some(record),
+            error: None,
+        }),
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+/// POST /api/batches/:id/hold — hold a running batch (operator+).
+pub async fn hold_batch(
+    State(state): State<AppState>,
+    Path(batch_id): Path<String>,
+    request: Request,
+) -> Json<ApiResponse<BatchRecord>> {
+    control_batch(&state, &batch_id, batch::BatchCommand::Hold, request).await
+}
+
+/// POST /api/batches/:id/resume — resume a held batch (operator+).
+pub async fn resume_batch(
+    State(state): State<AppState>,
+    Path(batch_id): Path<String>,
+    request: Request,
+) -> Json<ApiResponse<BatchRecord>> {
+    control_batch(&state, &batch_id, batch::BatchCommand::Resume, request).await
+}
+
+/// POST /api/batches/:id/complete — complete a running batch (operator+).
+pub async fn complete_batch(
+    State(state): State<AppState>,
+    Path(batch_id): Path<String>,
+    request: Request,
+) -> Json<ApiResponse<BatchRecord>> {
+    control_batch(&state, &batch_id, batch::BatchCommand::Complete, request).await
+}
+
+/// POST /api/batches/:id/abort — abort a running or held batch (operator+, signed).
+pub async fn abort_batch(
+    State(state): State<AppState>,
+    Path(batch_id): Path<String>,
+    request: Request,
+) -> Json<ApiResponse<BatchRecord>> {
+    control_batch(&state, &batch_id, batch::BatchCommand::Abort, request).await
+}
+
+/// POST /api/batches/:id/sign — sign an ended batch record (operator+, signed).
+/// The signature stores its meaning and the record's content hash.
+pub async fn sign_batch(
+    State(state): State<AppState>,
+    Path(batch_id): Path<String>,
+    request: Request,
+) -> Json<ApiResponse<RecordSignature>> {
+    let result = async {
+        let (claims, req) = parse_signed::<SignBatchRequest>(request).await?;
+        batch::check_batch_signature(&state.db, &batch_id, req.meaning, &claims).await?;
+        let grant = auth::consume_esig(&state.db, &req.esig_token, &claims.user_id).await?;
+        batch::sign_batch(&state.db, &batch_id, req.meaning, &claims, &grant).await
+    };
+
+    match result.await {
+        Ok(signature) => Json(ApiResponse {
+            success: true,
+            data: Some(signature),
+            error: None,
+        }),
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+/// POST /api/batches/:id/review — send a completed batch to review and
+/// list its exceptions (operator+).
+pub async fn start_batch_review(
+    State(state): State<AppState>,
+    Path(batch_id): Path<String>,
+    request: Request,
+) -> Json<ApiResponse<Vec<BatchException>>> {
+    let result = match request.extensions().get::<Claims>() {
+        Some(claims) => review::start_review(&state.db, &state.config.batch.parameter_limits, &batch_id, claims).await,
+        None => Err("Not authenticated".to_string()),
+    };
+
+    match result {
+        Ok(exceptions) => Json(ApiResponse {
+            success: true,
+            data: Some(exceptions),
+            error: None,
+        }),
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+/// POST /api/batches/:id/exceptions/:exception_id/sign-off — sign off one
+/// review exception (operator+, signed).
+pub async fn sign_off_batch_exception(
+    State(state): State<AppState>,
+    Path((batch_id, exception_id)): Path<(String, i64)>,
+    request: Request,
+) -> Json<ApiResponse<BatchException>> {
+    let result = async {
+        let (claims, req) = parse_signed::<BatchReviewRequest>(request).await?;
+        review::check_sign_off(&state.db, &batch_id, exception_id, &claims).await?;
+        let grant = auth::consume_esig(&state.db, &req.esig_token, &claims.user_id).await?;
+        review::sign_off_exception(&state.db, &batch_id, exception_id, &claims, &grant, req.comment.as_deref()).await
+    };
+
+    match result.await {
+        Ok(exception) => Json(ApiResponse {
+            success: true,
+            data: Some(exception),
+            error: None,
+        }),
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+/// Approve or reject a batch under review with an e-signature.
+async fn decide_batch(state: &AppState, batch_id: &str, approve: bool, request: Request) -> Json<ApiResponse<BatchRecord>> {
+    let result = async {
+        let (claims, req) = parse_signed::<BatchReviewRequest>(request).await?;
+        review::check_decide(&state.db, batch_id, approve).await?;
+        let grant = auth::consume_esig(&state.db, &req.esig_token, &claims.user_id).await?;
+        review::decide(&state.db, batch_id, approve, &claims, &grant, req.comment.as_deref()).await
+    };
+
+    match result.await {
+        Ok(record) => Json(ApiResponse {
+            success: true,
+            data: Some(record),
+            error: None,
+        }),
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+/// POST /api/batches/:id/approve — release a reviewed batch (admin, signed).
+pub async fn approve_batch(
+    State(state): State<AppState>,
+    Path(batch_id): Path<String>,
+    request: Request,
+) -> Json<ApiResponse<BatchRecord>> {
+    decide_batch(&state, &batch_id, true, request).await
+}
+
+/// POST /api/batches/:id/reject — reject a batch under review (admin, signed).
+pub async fn reject_batch(
+    State(state): State<AppState>,
+    Path(batch_id): Path<String>,
+    request: Request,
+) -> Json<ApiResponse<BatchRecord>> {
+    decide_batch(&state, &batch_id, false, request).await
+}
+
+/// GET /api/batches/compare — overlay batches by time since start against
+/// the golden envelope of their recipe's approved batches.
+pub async fn compare_batches(
+    State(state): State<AppState>,
+    Query(params): Query<BatchCompareParams>,
+) -> Json<ApiResponse<BatchComparison>> {
+    match golden::compare_batches(&state.db, state.tsdb.as_ref(), &params).await {
+        Ok(comparison) => Json(ApiResponse {
+            success: true,
+            data: Some(comparison),
+            error: None,
+        }),
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+/// GET /api/batches/:id — get a single batch with steps.
+pub async fn get_batch(
+    State(state): State<AppState>,
+    Path(batch_id): Path<String>,
+) -> Json<ApiResponse<serde_json::Value>> {
+    match db::get_batch_with_steps(&state.db, &batch_id).await {
+        Some((record, steps)) => {
+            let alarms = db::list_batch_alarms(&state.db, &record.batch_id).await;
+            let signatures = batch::batch_signatures(&state.db, &record.batch_id).await;
+            let exceptions = db::list_batch_exceptions(&state.db, &record.batch_id).await;
+            let materials = db::list_batch_materials(&state.db, &record.batch_id).await;
+            let data = serde_json::json!({
+                "record": record,
+                "steps": steps,
+                "alarms": alarms,
+                "signatures": signatures,
+                "exceptions": exceptions,
+                "materials": materials,
+            });
+            Json(ApiResponse {
+                success: true,
+                data: Some(data),
+                error: None,
+            })
+        }
+        None => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some("Batch not found".into()),
+        }),
+    }
+}
+
+// ── Material lots and genealogy ─────────────────────────────────
+
+/// POST /api/batches/:id/materials — record a lot the batch consumed or
+/// produced (operator+).
+pub async fn record_batch_material(
+    State(state): State<AppState>,
+    Path(batch_id): Path<String>,
+    request: Request,
+) -> Json<ApiResponse<BatchMaterial>> {
+    let result = async {
+        let claims = request
+            .extensions()
+            .get::<Claims>()
+            .cloned()
+            .ok_or_else(|| "Not authenticated".to_string())?;
+        let body = axum::body::to_bytes(request.into_body(), 1024 * 4)
+            .await
+            .map_err(|_| "Invalid request body".to_string())?;
+        let req: RecordMaterialRequest = serde_json::from_slice(&body).map_err(|e| format!("Invalid JSON: {e}"))?;
+        genealogy::record_material(&state.db, &batch_id, &req, &claims).await
+    };
+
+    match result.await {
+        Ok(material) => Json(ApiResponse {
+            success: true,
+            data: Some(material),
+            error: None,
+        }),
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+/// GET /api/batches/:id/materials — lots a batch consumed and produced.
+pub async fn list_batch_materials(
+    State(state): State<AppState>,
+    Path(batch_id): Path<String>,
+) -> Json<ApiResponse<Vec<BatchMaterial>>> {
+    let materials = db::list_batch_materials(&state.db, &batch_id).await;
+    Json(ApiResponse {
+        success: true,
+        data: Some(materials),
+        error: None,
+    })
+}
+
+/// GET /api/lots — list material lots (filter by material/lot number).
+pub async fn list_lots(
+    State(state): State<AppState>,
+    Query(params): Query<LotQueryParams>,
+) -> Json<ApiResponse<Vec<MaterialLot>>> {
+    let lots = db::list_lots(&state.db, &params).await;
+    Json(ApiResponse {
+        success: true,
+        data: Some(lots),
+        error: None,
+    })
+}
+
+/// GET /api/lots/:id/genealogy — trace a lot forward (recall) or backward.
+pub async fn lot_genealogy(
+    State(state): State<AppState>,
+    Path(lot_id): Path<i64>,
+    Query(params): Query<GenealogyParams>,
+) -> Json<ApiResponse<Genealogy>> {
+    match genealogy::trace(&state.db, lot_id, params.direction).await {
+        Some(genealogy) => Json(ApiResponse {
+            success: true,
+            data: Some(genealogy),
+            error: None,
+        }),
+        None => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some("Lot not found".into()),
+        }),
+    }
+}
+
+// ── ISA-88 Master recipes ───────────────────────────────────────
+// Every change is signed: the client first calls POST /api/auth/esig and
+// passes the returned `esig_token` in the request body.
+
+/// GET /api/recipes — list recipe versions (filter by name/status).
+pub async fn list_recipes(
+    State(state): State<AppState>,
+    Query(params): Query<RecipeQueryParams>,
+) -> Json<ApiResponse<Vec<Recipe>>> {
+    let recipes = db::list_recipes(&state.db, &params).await;
+    Json(ApiResponse {
+        success: true,
+        data: Some(recipes),
+        error: None,
+    })
+}
+
+/// GET /api/recipes/:id — get one recipe version.
+pub async fn get_recipe(
+    State(state): State<AppState>,
+    Path(recipe_id): Path<i64>,
+) -> Json<ApiResponse<Recipe>> {
+    recipe_response(db::get_recipe(&state.db, recipe_id).await.ok_or_else(|| "Recipe not found".to_string()))
+}
+
+fn recipe_response(result: Result<Recipe, String>) -> Json<ApiResponse<Recipe>> {
+    match result {
+        Ok(recipe) => Json(ApiResponse {
+            success: true,
+            data: Some(recipe),
+            error: None,
+        }),
+        Err(e) => Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    }
+}
+
+/// Claims and JSON body of a request that carries an e-signature.
+async fn parse_signed<T: serde::de::DeserializeOwned>(request: Request) -> Result<(Claims, T), String> {
+    let claims = request
+        .extensions()
+        .get::<Claims>()
+        .cloned()
+        .ok_or_else(|| "Not authenticated".to_string())?;
+    let body = axum::body::to_bytes(request.into_body(), 1024 * 16)
+        .await
+        .map_err(|_| "Invalid request body".to_string())?;
+    let req = serde_json::from_slice(&body).map_err(|e| format!("Invalid JSON: {e}"))?;
+    Ok((claims, req))
+}
+
+/// Record a signed recipe change in the audit trail.
+async fn audit_recipe(state: &AppState, claims: &Claims, action: &str, recipe: &Recipe, grant: &auth::EsigGrant) {
+    let details = serde_json::json!({
+        "recipe_id": recipe.id,
+        "name": recipe.name,
+        "version": recipe.version,
+        "status": recipe.status.as_str(),
+        "esig_reason": grant.reason,
+        "esig_signed_at": grant.signed_at,
+    })
+    .to_string();
+    auth::log_audit(&state.db, &claims.user_id, &claims.sub, action, None, &details, None).await;
+    info!("Recipe '{}' v{} {} by {}", recipe.name, recipe.version, action, claims.sub);
+}
+
+/// POST /api/recipes — create version 1 of a recipe as a draft (operator+, signed).
+pub async fn create_recipe(
+    State(state): State<AppState>,
+    request: Request,
+) -> Json<ApiResponse<Recipe>> {
+    let result = async {
+        let (claims, req) = parse_signed::<RecipeRequest>(request).await?;
+        req.validate()?;
+        let grant = auth::consume_esig(&state.db, &req.esig_token, &claims.user_id).await?;
+        let id = db::create_recipe(&state.db, &req, &claims.sub).await?;
+        let recipe = db::get_recipe(&state.db, id).await.ok_or("Recipe not found")?;
+        audit_recipe(&state, &claims, "recipe_create", &recipe, &grant).await;
+        Ok(recipe)
+    };
+    recipe_response(result.await)
+}
+
+/// PUT /api/recipes/:id — edit a draft recipe version (operator+, signed).
+pub async fn update_recipe(
+    State(state): State<AppState>,
+    Path(recipe_id): Path<i64>,
+    request: Request,
+) -> Json<ApiResponse<Recipe>> {
+    let result = async {
+        let (claims, req) = parse_signed::<RecipeRequest>(request).await?;
+        req.validate()?;
+        db::check_recipe_update(&state.db, recipe_id, &req).await?;
+        let grant = auth::consume_esig(&state.db, &req.esig_token, &claims.user_id).await?;
+        db::update_recipe(&state.db, recipe_id, &req, &claims.sub).await?;
+        let recipe = db::get_recipe(&state.db, recipe_id).await.ok_or("Recipe not found")?;
+        audit_recipe(&state, &claims, "recipe_update", &recipe, &grant).await;
+        Ok(recipe)
+    };
+    recipe_response(result.await)
+}
+
+/// POST /api/recipes/:id/versions — copy a version into a new draft (operator+, signed).
+pub async fn new_recipe_version(
+    State(state): State<AppState>,
+    Path(recipe_id): Path<i64>,
+    request: Request,
+) -> Json<ApiResponse<Recipe>> {
+    let result = async {
+        let (claims, req) = parse_signed::<RecipeSignRequest>(request).await?;
+        db::check_recipe_new_version(&state.db, recipe_id).await?;
+        let grant = auth::consume_esig(&state.db, &req.esig_token, &claims.user_id).await?;
+        let id = db::new_recipe_version(&state.db, recipe_id, &claims.sub).await?;
+        let recipe = db::get_recipe(&state.db, id).await.ok_or("Recipe not found")?;
+        audit_recipe(&state, &claims, "recipe_new_version", &recipe, &grant).await;
+        Ok(recipe)
+    };
+    recipe_response(result.await)
+}
+
+/// POST /api/recipes/:id/approve — approve a draft; the previously approved
+/// version becomes obsolete (admin, signed).
+pub async fn approve_recipe(
+    State(state): State<AppState>,
+    Path(recipe_id): Path<i64>,
+    request: Request,
+) -> Json<ApiResponse<Recipe>> {
+    let result = async {
+        let (claims, req) = parse_signed::<RecipeSignRequest>(request).await?;
+        db::check_recipe_approval(&state.db, recipe_id, &claims.sub).await?;
+        let grant = auth::consume_esig(&state.db, &req.esig_token, &claims.user_id).await?;
+        db::approve_recipe(&state.db, recipe_id, &claims.sub).await?;
+        let recipe = db::get_recipe(&state.db, recipe_id).await.ok_or("Recipe not found")?;
+        audit_recipe(&state, &claims, "recipe_approve", &recipe, &grant).await;
+        Ok(recipe)
+    };
+    recipe_response(result.await)
+}
+
+/// POST /api/recipes/:id/obsolete — retire a recipe version (admin, signed).
+pub async fn obsolete_recipe(
+    State(state): State<AppState>,
+    Path(recipe_id): Path<i64>,
+    request: Request,
+) -> Json<ApiResponse<Recipe>> {
+    let result = async {
+        let (claims, req) = parse_signed::<RecipeSignRequest>(request).await?;
+        db::check_recipe_obsolete(&state.db, recipe_id).await?;
+        let grant = auth::consume_esig(&state.db, &req.esig_token, &claims.user_id).await?;
+        db::obsolete_recipe(&state.db, recipe_id, &claims.sub).await?;
+        let recipe = db::get_recipe(&state.db, recipe_id).await.ok_or("Recipe not found")?;
+        audit_recipe(&state, &claims, "recipe_obsolete", &recipe, &grant).await;
+        Ok(recipe)
+    };
+    recipe_response(result.await)
+}
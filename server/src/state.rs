@@ -7,12 +7,15 @@ use tokio::sync::{broadcast, mpsc, Mutex, RwLock};
 use tokio::task::JoinHandle;
 
 use crate::config::{AppConfig, DeviceConfig};
+use crate::tsdb::{SqliteTimeSeries, TimeSeriesStore};
 
 /// A write command routed to a specific device's polling task.
 #[derive(Debug)]
 pub struct WriteCommand {
     pub register: u16,
     pub value: u16,
+    /// Read the register back after writing and fail on a mismatch.
+    pub verify: bool,
     pub response: tokio::sync::oneshot::Sender<Result<(), String>>,
 }
 
@@ -38,6 +41,8 @@ pub type SessionStore = Arc<RwLock<HashMap<String, (String, i64)>>>;
 pub struct AppState {
     pub tx: broadcast::Sender<String>,
     pub db: SqlitePool,
+    /// Historian backend for time-series queries.
+    pub tsdb: Arc<dyn TimeSeriesStore>,
     pub devices: DeviceRegistry,
     pub config: AppConfig,
     /// JWT signing secret loaded from config.
@@ -55,6 +60,7 @@ impl AppState {
         let jwt_secret = config.server.jwt_secret.clone();
         let login_attempts = Arc::new(Mutex::new(HashMap::new()));
         let sessions = Arc::new(RwLock::new(HashMap::new()));
-        Self { tx, db, devices, config, jwt_secret, login_attempts, sessions }
+        let tsdb = Arc::new(SqliteTimeSeries::new(db.clone()));
+        Self { tx, db, tsdb, devices, config, jwt_secret, login_attempts, sessions }
     }
 }
@@ -10,7 +10,7 @@
 use async_trait::async_trait;
 use sqlx::SqlitePool;
 
-use crate::models::PlcData;
+use crate::models::{AggregateBucket, PlcData};
 
 /// Trait for storing and querying time-series PLC readings.
 ///
@@ -29,6 +29,27 @@ pub trait TimeSeriesStore: Send + Sync + 'static {
         to: &str,
         limit: i64,
     ) -> Vec<PlcData>;
+    /// Min/max/avg/first/last/count of one register per `bucket_secs`
+    /// bucket within a time range, oldest bucket first. Empty buckets are
+    /// omitted.
+    async fn aggregate(
+        &self,
+        device_id: &str,
+        register: u16,
+        from: &str,
+        to: &str,
+        bucket_secs: i64,
+    ) -> Vec<AggregateBucket>;
+    /// Like `aggregate`, but buckets are aligned to `from` rather than the
+    /// Unix epoch, e.g. to overlay batches on the time since their start.
+    async fn aggregate_from(
+        &self,
+        device_id: &str,
+        register: u16,
+        from: &str,
+        to: &str,
+        bucket_secs: i64,
+    ) -> Vec<AggregateBucket>;
     /// Delete readings older than `days` days.  Returns count deleted.
     async fn purge_older_than(&self, days: i64) -> u64;
 }
@@ -47,6 +68,62 @@ impl SqliteTimeSeries {
     pub fn new(pool: SqlitePool) -> Self {
         Self { pool }
     }
+
+    /// Buckets of `bucket_secs` counted from `origin` (Unix seconds).
+    async fn aggregate_aligned(
+        &self,
+        device_id: &str,
+        register: u16,
+        from: &str,
+        to: &str,
+        bucket_secs: i64,
+        origin: i64,
+    ) -> Vec<AggregateBucket> {
+        // Bucket on seconds since the origin; row numbers in both directions pick out
+        // each bucket's first and last reading in one pass.
+        sqlx::query_as::<_, (i64, f64, f64, f64, f64, f64, i64)>(
+            "SELECT bucket, MIN(value), MAX(value), AVG(value),
+                    MAX(CASE WHEN rn_first = 1 THEN value END),
+                    MAX(CASE WHEN rn_last = 1 THEN value END),
+                    COUNT(*)
+             FROM (
+                 SELECT bucket, value,
+                        ROW_NUMBER() OVER (PARTITION BY bucket ORDER BY timestamp, id) AS rn_first,
+                        ROW_NUMBER() OVER (PARTITION BY bucket ORDER BY timestamp DESC, id DESC) AS rn_last
+                 FROM (
+                     SELECT (CAST(strftime('%s', timestamp) AS INTEGER) - ?) / ? * ? + ? AS bucket,
+                            value, timestamp, id
+                     FROM plc_readings
+                     WHERE device_id = ? AND register = ? AND timestamp >= ? AND timestamp <= ?
+                 )
+             )
+             GROUP BY bucket ORDER BY bucket",
+        )
+        .bind(origin)
+        .bind(bucket_secs)
+        .bind(bucket_secs)
+        .bind(origin)
+        .bind(device_id)
+        .bind(register as i64)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(bucket, min, max, avg, first, last, count)| {
+            Some(AggregateBucket {
+                bucket_start: chrono::DateTime::from_timestamp(bucket, 0)?,
+                min,
+                max,
+                avg,
+                first,
+                last,
+                count,
+            })
+        })
+        .collect()
+    }
 }
 
 #[async_trait]
@@ -99,6 +176,31 @@ impl TimeSeriesStore for SqliteTimeSeries {
         .collect()
     }
 
+    async fn aggregate(
+        &self,
+        device_id: &str,
+        register: u16,
+        from: &str,
+        to: &str,
+        bucket_secs: i64,
+    ) -> Vec<AggregateBucket> {
+        self.aggregate_aligned(device_id, register, from, to, bucket_secs, 0).await
+    }
+
+    async fn aggregate_from(
+        &self,
+        device_id: &str,
+        register: u16,
+        from: &str,
+        to: &str,
+        bucket_secs: i64,
+    ) -> Vec<AggregateBucket> {
+        let Ok(origin) = chrono::DateTime::parse_from_rfc3339(from) else {
+            return Vec::new();
+        };
+        self.aggregate_aligned(device_id, register, from, to, bucket_secs, origin.timestamp()).await
+    }
+
     async fn purge_older_than(&self, days: i64) -> u64 {
         let cutoff = (chrono::Utc::now() - chrono::Duration::days(days)).to_rfc3339();
         let result = sqlx::query("DELETE FROM plc_readings WHERE timestamp < ?")
@@ -121,5 +223,7 @@ impl TimeSeriesStore for SqliteTimeSeries {
 // impl TimeSeriesStore for InfluxTimeSeries {
 //     async fn insert(&self, data: &PlcData) { /* Influx write */ }
 //     async fn query(&self, device_id: &str, limit: i64) -> Vec<PlcData> { /* Flux query */ }
+//     async fn aggregate(...) -> Vec<AggregateBucket> { /* Flux aggregateWindow() */ }
+//     async fn aggregate_from(...) -> Vec<AggregateBucket> { /* aggregateWindow(offset: ...) */ }
 //     ...
 // }
@@ -5,11 +5,23 @@ use axum::{
 };
 
 use futures::{SinkExt, StreamExt};
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
 use tracing::{info, warn};
 
 use crate::auth;
+use crate::db;
+use crate::models::WsMessage;
 use crate::state::AppState;
 
+/// Push an alarm state transition (`raise`, `ack`, `shelve`, `clear`, ...)
+/// to all WebSocket clients, with the alarm as it is now.
+pub async fn publish_alarm_event(tx: &broadcast::Sender<String>, db: &SqlitePool, alarm_id: i64, event: &str) {
+    if let Some(alarm) = db::get_alarm(db, alarm_id).await {
+        let _ = tx.send(WsMessage::Alarm { event: event.to_string(), alarm: Box::new(alarm) }.to_json());
+    }
+}
+
 /// Handler for /ws — accepts upgrade unconditionally, then authenticates
 /// via the first message (which must be the JWT token).
 ///
@@ -33,14 +45,13 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                 match auth::validate_token(&token, &state.jwt_secret) {
                     Ok(c) => {
                         // Verify session is still valid
-                        if let Some(ref sid) = c.session_id {
-                            if !auth::is_session_valid(&state, sid).await {
-                                warn!("WebSocket auth failed — session revoked");
-                                let _ = sender.send(Message::Text(
-                                    r#"{"error":"session_revoked"}"#.into(),
-                                )).await;
-                                return;
-                            }
+                        if let Some(ref sid) = c.session_id
+                            && !auth::is_session_valid(&state, sid).await {
+                            warn!("WebSocket auth failed — session revoked");
+                            let _ = sender.send(Message::Text(
+                                r#"{"error":"session_revoked"}"#.into(),
+                            )).await;
+                            return;
                         }
                         // Send auth OK acknowledgement
                         let _ = sender.send(Message::Text(
@@ -62,6 +62,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_config_alarm_definitions_are_valid() {
+        let config = server::config::AppConfig::load("config.toml");
+        assert!(!config.alarms.is_empty(), "config should define [[alarms]]");
+
+        for def in &config.alarms {
+            assert!(
+                config.devices.iter().any(|d| d.id == def.device_id),
+                "alarm definition references unknown device '{}'",
+                def.device_id
+            );
+            assert!(def.validate().is_ok(), "invalid alarm definition {}/{}", def.device_id, def.register);
+        }
+    }
+
     // ─────────────────────────────────────────────────────────
     // Database Tests
     // ─────────────────────────────────────────────────────────
@@ -75,6 +90,22 @@ mod tests {
         pool
     }
 
+    /// ID of the active or acknowledged limit alarm on a point, if any.
+    async fn active_limit_alarm_id(pool: &sqlx::SqlitePool, device_id: &str, register: u16) -> Option<i64> {
+        use server::models::{AlarmKind, AlarmState};
+        server::db::get_open_alarm(pool, device_id, register, AlarmKind::Limit)
+            .await
+            .filter(|a| matches!(a.state, AlarmState::Active | AlarmState::Acknowledged))
+            .map(|a| a.id)
+    }
+
+    /// Everything broadcast to WebSocket clients so far, parsed.
+    fn drain_ws(rx: &mut tokio::sync::broadcast::Receiver<String>) -> Vec<serde_json::Value> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|m| serde_json::from_str(&m).unwrap())
+            .collect()
+    }
+
     #[tokio::test]
     async fn test_db_init_creates_tables() {
         let pool = test_pool().await;
@@ -92,6 +123,8 @@ mod tests {
         assert!(table_names.contains(&"alarms"), "alarms table");
         assert!(table_names.contains(&"batch_records"), "batch_records table");
         assert!(table_names.contains(&"batch_steps"), "batch_steps table");
+        assert!(table_names.contains(&"alarm_definitions"), "alarm_definitions table");
+        assert!(table_names.contains(&"alarm_events"), "alarm_events table");
     }
 
     #[tokio::test]
@@ -102,6 +135,7 @@ mod tests {
             device_id: "plc-01".to_string(),
             register: 100,
             label: "Temperature".to_string(),
+            kind: server::models::AlarmKind::Limit,
             priority: server::models::AlarmPriority::High,
             value: 85.0,
             threshold: 80.0,
@@ -111,12 +145,13 @@ mod tests {
         assert!(alarm_id.is_some());
         let alarm_id = alarm_id.unwrap();
 
-        assert!(server::db::has_active_alarm(&pool, "plc-01", 100).await);
+        assert!(active_limit_alarm_id(&pool, "plc-01", 100).await.is_some());
 
         let params = server::models::AlarmQueryParams {
             device_id: Some("plc-01".to_string()),
             state: None,
             priority: None,
+            first_out: None,
             limit: None,
         };
         let alarms = server::db::list_alarms(&pool, &params).await;
@@ -130,7 +165,1073 @@ mod tests {
         let clear_result = server::db::clear_alarm(&pool, alarm_id).await;
         assert!(clear_result.is_ok());
 
-        assert!(!server::db::has_active_alarm(&pool, "plc-01", 100).await);
+        assert!(active_limit_alarm_id(&pool, "plc-01", 100).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_alarm_event_journal() {
+        let pool = test_pool().await;
+
+        let req = server::models::RaiseAlarmRequest {
+            device_id: "plc-01".to_string(),
+            register: 1028,
+            label: "Temperature".to_string(),
+            kind: server::models::AlarmKind::Limit,
+            priority: server::models::AlarmPriority::High,
+            value: 88.0,
+            threshold: 85.0,
+            message: "WARNING: Temperature 88.0 approaching limit (85)".to_string(),
+        };
+        let alarm_id = server::db::raise_alarm(&pool, &req).await.unwrap();
+
+        server::db::ack_alarm(&pool, alarm_id, "operator", Some("Checking jacket valve")).await.unwrap();
+        server::db::shelve_alarm(&pool, alarm_id, "supervisor", 30, "Valve maintenance").await.unwrap();
+        server::db::clear_alarm(&pool, alarm_id).await.unwrap();
+        // Clearing twice is a no-op and must not be journaled again
+        server::db::clear_alarm(&pool, alarm_id).await.unwrap();
+
+        let events = server::db::list_alarm_events(&pool, alarm_id).await;
+        let kinds: Vec<&str> = events.iter().map(|e| e.event.as_str()).collect();
+        assert_eq!(kinds, ["raise", "ack", "shelve", "clear"]);
+
+        assert_eq!(events[0].actor, "system");
+        assert_eq!(events[0].value, Some(88.0));
+        assert_eq!(events[1].actor, "operator");
+        assert_eq!(events[1].comment.as_deref(), Some("Checking jacket valve"));
+        assert_eq!(events[2].actor, "supervisor");
+        assert!(events[2].comment.as_deref().unwrap().contains("Valve maintenance"));
+
+        // The ack comment stays in the journal, not in the alarm message
+        let alarm = server::db::get_alarm(&pool, alarm_id).await.unwrap();
+        assert_eq!(alarm.message, req.message);
+    }
+
+    fn reactor_temperature_definition() -> server::config::AlarmDefinition {
+        server::config::AlarmDefinition {
+            id: 0,
+            device_id: "plc-01".to_string(),
+            register: 1028,
+            label: "Temperature".to_string(),
+            hh: Some(server::config::AlarmLimit { limit: 100.0, priority: None, message: None, suppress_when: None }),
+            h: Some(server::config::AlarmLimit {
+                limit: 85.0,
+                priority: Some(server::models::AlarmPriority::Medium),
+                message: Some("{label} high on {device}: {value}".to_string()),
+                suppress_when: None,
+            }),
+            l: None,
+            ll: None,
+            rate_of_change: None,
+            setpoint_register: None,
+            deviation: None,
+            deviation_deadband: None,
+            deadband: None,
+            on_delay_secs: 0,
+            off_delay_secs: 0,
+            suppress_when: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_alarm_definition_crud() {
+        let pool = test_pool().await;
+        let mut def = reactor_temperature_definition();
+
+        // Seeding is idempotent and never overwrites existing rows
+        server::db::seed_alarm_definitions(&pool, std::slice::from_ref(&def)).await;
+        server::db::seed_alarm_definitions(&pool, std::slice::from_ref(&def)).await;
+        let defs = server::db::list_alarm_definitions(&pool, Some("plc-01")).await;
+        assert_eq!(defs.len(), 1);
+        assert_eq!(defs[0].h, def.h);
+        let id = defs[0].id;
+
+        // Duplicate device+register is rejected
+        assert!(server::db::create_alarm_definition(&pool, &def).await.is_err());
+
+        def.hh = None;
+        server::db::update_alarm_definition(&pool, id, &def).await.unwrap();
+        let updated = server::db::get_alarm_definition(&pool, id).await.unwrap();
+        assert!(updated.hh.is_none());
+        assert_eq!(updated.h.unwrap().limit, 85.0);
+
+        assert!(server::db::list_alarm_definitions(&pool, Some("plc-02")).await.is_empty());
+
+        server::db::delete_alarm_definition(&pool, id).await.unwrap();
+        assert!(server::db::get_alarm_definition(&pool, id).await.is_none());
+        assert!(server::db::delete_alarm_definition(&pool, id).await.is_err());
+    }
+
+    #[test]
+    fn test_alarm_definition_evaluation() {
+        use server::alarms::{build_alarm_request, breached_limit, LimitLevel};
+
+        let def = reactor_temperature_definition();
+        assert_eq!(breached_limit(&def, 50.0), None);
+        assert_eq!(breached_limit(&def, 90.0), Some(LimitLevel::High));
+        assert_eq!(breached_limit(&def, 120.0), Some(LimitLevel::HighHigh));
+
+        let req = build_alarm_request(&def, LimitLevel::High, 90.0).unwrap();
+        assert_eq!(req.priority, server::models::AlarmPriority::Medium);
+        assert_eq!(req.message, "Temperature high on plc-01: 90.0");
+
+        let req = build_alarm_request(&def, LimitLevel::HighHigh, 120.0).unwrap();
+        assert_eq!(req.priority, server::models::AlarmPriority::Critical);
+        assert_eq!(req.threshold, 100.0);
+
+        let mut bad = def.clone();
+        bad.h.as_mut().unwrap().limit = 150.0;
+        assert!(bad.validate().is_err(), "H above HH must be rejected");
+    }
+
+    #[tokio::test]
+    async fn test_low_limit_alarm_lifecycle() {
+        use server::alarms::{breached_limit, returned_to_normal, AlarmEngine, LimitLevel};
+        use std::collections::HashMap;
+
+        let pool = test_pool().await;
+        let mut def = reactor_temperature_definition();
+        def.register = 1029;
+        def.label = "Pressure".to_string();
+        def.hh = None;
+        def.h = None;
+        def.l = Some(server::config::AlarmLimit { limit: 950.0, priority: None, message: None, suppress_when: None });
+        def.ll = Some(server::config::AlarmLimit { limit: 900.0, priority: None, message: None, suppress_when: None });
+        server::db::create_alarm_definition(&pool, &def).await.unwrap();
+
+        assert_eq!(breached_limit(&def, 940.0), Some(LimitLevel::Low));
+        assert_eq!(breached_limit(&def, 850.0), Some(LimitLevel::LowLow));
+
+        let (tx, _rx) = tokio::sync::broadcast::channel(16);
+        let mut engine = AlarmEngine::new("plc-01", tx);
+
+        // Loss of vacuum — LL raises a Critical alarm
+        engine.scan(&pool, &HashMap::from([(1029, 850.0)])).await;
+        let alarm_id = active_limit_alarm_id(&pool, "plc-01", 1029).await.unwrap();
+        let alarm = server::db::get_alarm(&pool, alarm_id).await.unwrap();
+        assert_eq!(alarm.priority, server::models::AlarmPriority::Critical);
+        assert_eq!(alarm.threshold, 900.0);
+
+        // Back above L (with margin) — auto-cleared
+        engine.scan(&pool, &HashMap::from([(1029, 1013.0)])).await;
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1029).await.is_none());
+        let alarm = server::db::get_alarm(&pool, alarm_id).await.unwrap();
+        assert_eq!(alarm.state, server::models::AlarmState::Cleared);
+
+        // Cold chain: a negative L limit clears above it, not below
+        let mut freezer = def.clone();
+        freezer.l = def.ll.clone().map(|l| server::config::AlarmLimit { limit: -20.0, ..l });
+        freezer.ll = None;
+        assert!(!returned_to_normal(&freezer, -20.5));
+        assert!(!returned_to_normal(&freezer, -19.5));
+        assert!(returned_to_normal(&freezer, -18.5));
+    }
+
+    #[tokio::test]
+    async fn test_alarm_deadband_and_delays() {
+        use server::alarms::AlarmEngine;
+        use std::collections::HashMap;
+
+        let pool = test_pool().await;
+        let mut def = reactor_temperature_definition();
+        def.deadband = Some(2.0);
+        def.on_delay_secs = 5;
+        def.off_delay_secs = 10;
+        server::db::create_alarm_definition(&pool, &def).await.unwrap();
+
+        let (tx, _rx) = tokio::sync::broadcast::channel(16);
+        let mut engine = AlarmEngine::new("plc-01", tx);
+        let t0 = chrono::Utc::now();
+        let at = |secs: i64| t0 + chrono::Duration::seconds(secs);
+        let temp = |v: f64| HashMap::from([(1028, v)]);
+
+        // A single noisy sample above H does not raise
+        engine.scan_at(&pool, &temp(86.0), at(0)).await;
+        engine.scan_at(&pool, &temp(84.0), at(1)).await;
+        engine.scan_at(&pool, &temp(86.0), at(2)).await;
+        engine.scan_at(&pool, &temp(86.0), at(6)).await;
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_none());
+
+        // Held for the full on-delay — raised
+        engine.scan_at(&pool, &temp(86.0), at(7)).await;
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_some());
+
+        // Inside the deadband (83 < v < 85) — stays active
+        engine.scan_at(&pool, &temp(84.0), at(8)).await;
+        engine.scan_at(&pool, &temp(84.0), at(30)).await;
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_some());
+
+        // Below the deadband, but not for the full off-delay yet
+        engine.scan_at(&pool, &temp(80.0), at(31)).await;
+        engine.scan_at(&pool, &temp(80.0), at(40)).await;
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_some());
+
+        engine.scan_at(&pool, &temp(80.0), at(41)).await;
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_alarm_priority_escalation() {
+        use server::alarms::AlarmEngine;
+        use server::models::{AlarmPriority, AlarmState};
+        use std::collections::HashMap;
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let mut def = reactor_temperature_definition();
+        def.h.as_mut().unwrap().priority = Some(AlarmPriority::High);
+        server::db::create_alarm_definition(&pool, &def).await.unwrap();
+
+        let (tx, mut rx) = tokio::sync::broadcast::channel(16);
+        let mut engine = AlarmEngine::new("plc-01", tx);
+
+        engine.scan(&pool, &HashMap::from([(1028, 90.0)])).await;
+        let alarm_id = active_limit_alarm_id(&pool, "plc-01", 1028).await.unwrap();
+        server::db::ack_alarm(&pool, alarm_id, "operator", None).await.unwrap();
+        drain_ws(&mut rx);
+
+        // Climbs through HH — same alarm, escalated and back to unacknowledged
+        engine.scan(&pool, &HashMap::from([(1028, 105.0)])).await;
+        assert_eq!(active_limit_alarm_id(&pool, "plc-01", 1028).await, Some(alarm_id));
+        let alarm = server::db::get_alarm(&pool, alarm_id).await.unwrap();
+        assert_eq!(alarm.priority, AlarmPriority::Critical);
+        assert_eq!(alarm.state, AlarmState::Active);
+        assert_eq!(alarm.threshold, 100.0);
+
+        let msgs = drain_ws(&mut rx);
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0]["type"], "alarm");
+        assert_eq!(msgs[0]["event"], "escalate");
+        assert_eq!(msgs[0]["alarm"]["priority"], "critical");
+
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM audit_trail WHERE action = 'alarm_escalate'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(count, 1);
+
+        let events = server::db::list_alarm_events(&pool, alarm_id).await;
+        let kinds: Vec<&str> = events.iter().map(|e| e.event.as_str()).collect();
+        assert_eq!(kinds, ["raise", "ack", "escalate"]);
+
+        // Falling back below HH does not de-escalate or re-notify
+        engine.scan(&pool, &HashMap::from([(1028, 90.0)])).await;
+        let alarm = server::db::get_alarm(&pool, alarm_id).await.unwrap();
+        assert_eq!(alarm.priority, AlarmPriority::Critical);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_alarm_swing_to_opposite_limit_is_a_new_alarm() {
+        use server::alarms::AlarmEngine;
+        use server::models::{AlarmPriority, AlarmState};
+        use std::collections::HashMap;
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let mut def = reactor_temperature_definition();
+        def.h.as_mut().unwrap().priority = Some(AlarmPriority::High);
+        def.ll = Some(server::config::AlarmLimit { limit: 10.0, priority: None, message: None, suppress_when: None });
+        server::db::create_alarm_definition(&pool, &def).await.unwrap();
+
+        let (tx, mut rx) = tokio::sync::broadcast::channel(16);
+        let mut engine = AlarmEngine::new("plc-01", tx);
+        engine.scan(&pool, &HashMap::from([(1028, 90.0)])).await;
+        let high_id = active_limit_alarm_id(&pool, "plc-01", 1028).await.unwrap();
+        server::db::ack_alarm(&pool, high_id, "operator", None).await.unwrap();
+        drain_ws(&mut rx);
+
+        // Swinging from H through to the more severe LL is not an escalation
+        engine.scan(&pool, &HashMap::from([(1028, 5.0)])).await;
+        let high = server::db::get_alarm(&pool, high_id).await.unwrap();
+        assert_eq!((high.state, high.threshold, high.priority), (AlarmState::Cleared, 85.0, AlarmPriority::High));
+        let low_id = active_limit_alarm_id(&pool, "plc-01", 1028).await.unwrap();
+        assert_ne!(low_id, high_id);
+        let low = server::db::get_alarm(&pool, low_id).await.unwrap();
+        assert_eq!((low.state, low.threshold, low.priority), (AlarmState::Active, 10.0, AlarmPriority::Critical));
+        let events: Vec<String> = drain_ws(&mut rx).iter().map(|m| m["event"].as_str().unwrap().to_string()).collect();
+        assert_eq!(events, ["clear", "raise"]);
+    }
+
+    #[tokio::test]
+    async fn test_alarm_shelve_and_out_of_service() {
+        use server::alarms::AlarmEngine;
+        use server::models::AlarmState;
+        use std::collections::HashMap;
+
+        let pool = test_pool().await;
+        server::db::create_alarm_definition(&pool, &reactor_temperature_definition()).await.unwrap();
+        let (tx, _rx) = tokio::sync::broadcast::channel(16);
+        let mut engine = AlarmEngine::new("plc-01", tx);
+
+        engine.scan(&pool, &HashMap::from([(1028, 90.0)])).await;
+        let alarm_id = active_limit_alarm_id(&pool, "plc-01", 1028).await.unwrap();
+
+        // Shelve reason is persisted; a shelved alarm is not raised again
+        server::db::shelve_alarm(&pool, alarm_id, "supervisor", 30, "Sensor recalibration").await.unwrap();
+        let alarm = server::db::get_alarm(&pool, alarm_id).await.unwrap();
+        assert_eq!(alarm.state, AlarmState::Shelved);
+        assert_eq!(alarm.shelve_reason.as_deref(), Some("Sensor recalibration"));
+        engine.scan(&pool, &HashMap::from([(1028, 105.0)])).await;
+        let (open,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM alarms WHERE state != 'cleared'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(open, 1);
+
+        server::db::unshelve_alarm(&pool, alarm_id, "supervisor").await.unwrap();
+        let alarm = server::db::get_alarm(&pool, alarm_id).await.unwrap();
+        assert_eq!(alarm.state, AlarmState::Active);
+        assert!(alarm.shelve_reason.is_none());
+        assert!(server::db::unshelve_alarm(&pool, alarm_id, "supervisor").await.is_err());
+
+        // Out of service suppresses the tag entirely — no clear, no re-raise
+        server::db::set_alarm_out_of_service(&pool, alarm_id, "admin", "Transmitter replaced").await.unwrap();
+        engine.scan(&pool, &HashMap::from([(1028, 50.0)])).await;
+        engine.scan(&pool, &HashMap::from([(1028, 105.0)])).await;
+        let alarm = server::db::get_alarm(&pool, alarm_id).await.unwrap();
+        assert_eq!(alarm.state, AlarmState::OutOfService);
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_none());
+        assert!(server::db::shelve_alarm(&pool, alarm_id, "supervisor", 30, "x").await.is_err());
+
+        // Back in service — a fresh alarm is raised for the live condition
+        server::db::return_alarm_to_service(&pool, alarm_id, "admin").await.unwrap();
+        assert_eq!(server::db::get_alarm(&pool, alarm_id).await.unwrap().state, AlarmState::Cleared);
+        engine.scan(&pool, &HashMap::from([(1028, 105.0)])).await;
+        let new_id = active_limit_alarm_id(&pool, "plc-01", 1028).await.unwrap();
+        assert_ne!(new_id, alarm_id);
+
+        let events = server::db::list_alarm_events(&pool, alarm_id).await;
+        let kinds: Vec<&str> = events.iter().map(|e| e.event.as_str()).collect();
+        assert_eq!(kinds, ["raise", "shelve", "unshelve", "out_of_service", "return_to_service"]);
+        assert_eq!(events[3].comment.as_deref(), Some("Transmitter replaced"));
+    }
+
+    #[tokio::test]
+    async fn test_alarm_point_out_of_service_before_it_alarms() {
+        use server::alarms::AlarmEngine;
+        use std::collections::HashMap;
+
+        let pool = test_pool().await;
+        server::db::create_alarm_definition(&pool, &reactor_temperature_definition()).await.unwrap();
+        let (tx, _rx) = tokio::sync::broadcast::channel(16);
+        let mut engine = AlarmEngine::new("plc-01", tx);
+
+        // Planned maintenance: a tag not in alarm can be taken out of service ahead of time
+        engine.scan(&pool, &HashMap::from([(1028, 50.0)])).await;
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_none());
+        let ids = server::db::set_point_out_of_service(&pool, "plc-01", 1028, "admin", "Planned calibration")
+            .await
+            .unwrap();
+        assert!(ids.is_empty());
+        let points = server::db::list_points_out_of_service(&pool, Some("plc-01")).await;
+        assert_eq!(points.len(), 1);
+        assert_eq!((points[0].register, points[0].reason.as_str()), (1028, "Planned calibration"));
+        engine.scan(&pool, &HashMap::from([(1028, 105.0)])).await;
+        let (raised,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM alarms").fetch_one(&pool).await.unwrap();
+        assert_eq!(raised, 0, "nothing is raised while the point is out of service");
+
+        server::db::return_point_to_service(&pool, "plc-01", 1028, "admin").await.unwrap();
+        assert!(server::db::return_point_to_service(&pool, "plc-01", 1028, "admin").await.is_err());
+        engine.scan(&pool, &HashMap::from([(1028, 105.0)])).await;
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rate_of_change_and_deviation_alarms() {
+        use server::alarms::AlarmEngine;
+        use server::config::AlarmLimit;
+        use server::models::{AlarmKind, AlarmPriority};
+        use std::collections::HashMap;
+
+        let pool = test_pool().await;
+        let mut def = reactor_temperature_definition();
+        def.rate_of_change = Some(AlarmLimit { limit: 5.0, priority: None, message: None, suppress_when: None });
+        def.setpoint_register = Some(1030);
+        def.deviation = Some(AlarmLimit { limit: 10.0, priority: None, message: None, suppress_when: None });
+        server::db::create_alarm_definition(&pool, &def).await.unwrap();
+        assert_eq!(server::db::list_alarm_definitions(&pool, Some("plc-01")).await[0].deviation, def.deviation);
+
+        let mut bad = def.clone();
+        bad.setpoint_register = None;
+        assert!(bad.validate().is_err(), "deviation needs a setpoint register");
+
+        let (tx, _rx) = tokio::sync::broadcast::channel(16);
+        let mut engine = AlarmEngine::new("plc-01", tx);
+        let t0 = chrono::Utc::now();
+        let at = |secs: i64| t0 + chrono::Duration::seconds(secs);
+        let open = |kind| server::db::get_open_alarm(&pool, "plc-01", 1028, kind);
+
+        // 60 → 67 °C over one minute: +7 °C/min, still well below H (85)
+        engine.scan_at(&pool, &HashMap::from([(1028, 60.0), (1030, 60.0)]), at(0)).await;
+        engine.scan_at(&pool, &HashMap::from([(1028, 63.0), (1030, 60.0)]), at(30)).await;
+        assert!(open(AlarmKind::RateOfChange).await.is_none(), "no rate before a full window");
+        engine.scan_at(&pool, &HashMap::from([(1028, 67.0), (1030, 60.0)]), at(60)).await;
+
+        let roc = open(AlarmKind::RateOfChange).await.expect("rate-of-change alarm");
+        assert_eq!(roc.priority, AlarmPriority::High);
+        assert!((roc.value - 7.0).abs() < 1e-9);
+        assert_eq!(roc.threshold, 5.0);
+        assert!(open(AlarmKind::Limit).await.is_none());
+        assert!(open(AlarmKind::Deviation).await.is_none());
+
+        // Holds steady: the rate drops and the alarm clears
+        engine.scan_at(&pool, &HashMap::from([(1028, 67.0), (1030, 60.0)]), at(120)).await;
+        assert!(open(AlarmKind::RateOfChange).await.is_none());
+
+        // Setpoint drops away from the PV: deviation alarm only
+        engine.scan_at(&pool, &HashMap::from([(1028, 67.0), (1030, 55.0)]), at(180)).await;
+        let dev = open(AlarmKind::Deviation).await.expect("deviation alarm");
+        assert_eq!(dev.priority, AlarmPriority::Medium);
+        assert_eq!(dev.value, 12.0);
+
+        // Within the deadband (default 5% of 10) the alarm holds; beyond it, clears
+        engine.scan_at(&pool, &HashMap::from([(1028, 67.0), (1030, 57.2)]), at(240)).await;
+        assert!(open(AlarmKind::Deviation).await.is_some());
+        engine.scan_at(&pool, &HashMap::from([(1028, 67.0), (1030, 62.0)]), at(300)).await;
+        assert!(open(AlarmKind::Deviation).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_deviation_alarm_has_its_own_deadband() {
+        use server::alarms::AlarmEngine;
+        use server::config::AlarmLimit;
+        use server::models::AlarmKind;
+        use std::collections::HashMap;
+
+        let pool = test_pool().await;
+        let mut def = reactor_temperature_definition();
+        def.setpoint_register = Some(1030);
+        def.deviation = Some(AlarmLimit { limit: 10.0, priority: None, message: None, suppress_when: None });
+        // Sized for the absolute limits — larger than the whole deviation limit
+        def.deadband = Some(12.0);
+        def.deviation_deadband = Some(3.0);
+        server::db::create_alarm_definition(&pool, &def).await.unwrap();
+        assert_eq!(server::db::list_alarm_definitions(&pool, Some("plc-01")).await[0].deviation_deadband, Some(3.0));
+
+        let mut bad = def.clone();
+        bad.deviation_deadband = Some(10.0);
+        assert!(bad.validate().is_err(), "deviation deadband must be below the deviation limit");
+
+        let (tx, _rx) = tokio::sync::broadcast::channel(16);
+        let mut engine = AlarmEngine::new("plc-01", tx);
+        let open = |kind| server::db::get_open_alarm(&pool, "plc-01", 1028, kind);
+        engine.scan(&pool, &HashMap::from([(1028, 67.0), (1030, 55.0)])).await;
+        assert!(open(AlarmKind::Deviation).await.is_some());
+
+        // Clears once the deviation is 3 below its limit; `deadband` plays no part
+        engine.scan(&pool, &HashMap::from([(1028, 67.0), (1030, 58.0)])).await;
+        assert!(open(AlarmKind::Deviation).await.is_some());
+        engine.scan(&pool, &HashMap::from([(1028, 67.0), (1030, 61.0)])).await;
+        assert!(open(AlarmKind::Deviation).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_alarm_kpis() {
+        use server::models::{AlarmKind, AlarmPriority, RaiseAlarmRequest};
+
+        let pool = test_pool().await;
+        let t0 = chrono::DateTime::parse_from_rfc3339("2026-03-02T08:00:00Z").unwrap().with_timezone(&chrono::Utc);
+        let raise_at = |register: u16, priority: AlarmPriority, offset_secs: i64| {
+            let pool = pool.clone();
+            async move {
+                let req = RaiseAlarmRequest {
+                    device_id: "plc-01".to_string(),
+                    register,
+                    label: format!("Tag {register}"),
+                    kind: AlarmKind::Limit,
+                    priority,
+                    value: 1.0,
+                    threshold: 0.0,
+                    message: "test".to_string(),
+                };
+                let id = server::db::raise_alarm(&pool, &req).await.unwrap();
+                let ts = (t0 + chrono::Duration::seconds(offset_secs)).to_rfc3339();
+                sqlx::query("UPDATE alarms SET timestamp = ? WHERE id = ?")
+                    .bind(ts)
+                    .bind(id)
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+                id
+            }
+        };
+
+        // First 10 minutes: a flood of 12 alarms, register 1028 chattering
+        for i in 0..4 {
+            let id = raise_at(1028, AlarmPriority::Low, i * 10).await;
+            server::db::clear_alarm(&pool, id).await.unwrap();
+        }
+        for i in 0..8 {
+            let id = raise_at(1029, AlarmPriority::Medium, 100 + i * 60).await;
+            server::db::clear_alarm(&pool, id).await.unwrap();
+        }
+        // Second 10 minutes: one critical alarm still standing
+        raise_at(1030, AlarmPriority::Critical, 900).await;
+        // Outside the window
+        raise_at(1031, AlarmPriority::High, 3600).await;
+
+        let from = t0;
+        let to = t0 + chrono::Duration::minutes(20);
+        let now = t0 + chrono::Duration::hours(30);
+        let raised = server::db::list_alarms_raised_between(&pool, None, &from.to_rfc3339(), &to.to_rfc3339()).await;
+        let standing = server::db::list_standing_alarms(&pool, None).await;
+        let kpis = server::alarm_kpi::compute_kpis(&raised, &standing, from, to, now, 2, 10);
+
+        assert_eq!(kpis.total_alarms, 13);
+        // 13 alarms / 2 ten-minute periods / 2 operators
+        assert_eq!(kpis.avg_alarms_per_operator_per_10min, 3.25);
+        assert_eq!(kpis.flood_time_pct, 50.0);
+        assert_eq!(kpis.chattering_count, 1);
+
+        assert_eq!(kpis.top_alarms[0].register, 1029);
+        assert_eq!(kpis.top_alarms[0].count, 8);
+        assert_eq!(kpis.top_alarms[1].register, 1028);
+        assert_eq!(kpis.top_alarms.len(), 3);
+
+        let dist: Vec<(AlarmPriority, usize)> =
+            kpis.priority_distribution.iter().map(|p| (p.priority, p.count)).collect();
+        assert_eq!(dist[0], (AlarmPriority::Critical, 1));
+        assert_eq!(dist[2], (AlarmPriority::Medium, 8));
+        assert_eq!(dist[3], (AlarmPriority::Low, 4));
+
+        // Stale counts every standing alarm, not just those raised in the window
+        assert_eq!(kpis.stale_count, 2);
+        let now = t0 + chrono::Duration::hours(24) + chrono::Duration::minutes(30);
+        let kpis = server::alarm_kpi::compute_kpis(&raised, &standing, from, to, now, 2, 10);
+        assert_eq!(kpis.stale_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_alarm_kpi_flood_threshold_and_wide_windows() {
+        use server::models::{AlarmKind, AlarmPriority, RaiseAlarmRequest};
+
+        let pool = test_pool().await;
+        let req = RaiseAlarmRequest {
+            device_id: "plc-01".to_string(),
+            register: 1028,
+            label: "Temperature".to_string(),
+            kind: AlarmKind::Limit,
+            priority: AlarmPriority::Low,
+            value: 1.0,
+            threshold: 0.0,
+            message: "test".to_string(),
+        };
+        let id = server::db::raise_alarm(&pool, &req).await.unwrap();
+        let alarm = server::db::get_alarm(&pool, id).await.unwrap();
+        let t0 = chrono::DateTime::parse_from_rfc3339("2026-03-02T08:00:00Z").unwrap().with_timezone(&chrono::Utc);
+        // Six alarms in the first of two 10-minute intervals
+        let raised: Vec<_> = (0..6)
+            .map(|i| server::models::Alarm { timestamp: (t0 + chrono::Duration::minutes(i)).to_rfc3339(), ..alarm.clone() })
+            .collect();
+        let to = t0 + chrono::Duration::minutes(20);
+
+        // The flood threshold is the configured one ([alarm_flood] threshold)
+        let kpis = server::alarm_kpi::compute_kpis(&raised, &[], t0, to, to, 1, 5);
+        assert_eq!(kpis.flood_time_pct, 50.0);
+        let kpis = server::alarm_kpi::compute_kpis(&raised, &[], t0, to, to, 1, 10);
+        assert_eq!(kpis.flood_time_pct, 0.0);
+
+        // A window reaching back to year 1 is fine — intervals are counted sparsely
+        let year_one = chrono::DateTime::parse_from_rfc3339("0001-01-01T00:00:00Z").unwrap().with_timezone(&chrono::Utc);
+        let kpis = server::alarm_kpi::compute_kpis(&raised, &[], year_one, to, to, 1, 5);
+        assert_eq!(kpis.total_alarms, 6);
+        assert_eq!(kpis.flood_time_pct, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_alarm_flood_suppression() {
+        use server::alarms::AlarmEngine;
+        use server::config::{AlarmDefinition, AlarmFloodConfig, AlarmLimit};
+        use server::models::{AlarmPriority, AlarmSuppressionQueryParams};
+        use std::collections::HashMap;
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+
+        // Five low-priority tags on plc-01 plus one critical tag on plc-02
+        for register in 1028..1033 {
+            let def = AlarmDefinition {
+                register,
+                label: format!("Tag {register}"),
+                hh: None,
+                h: Some(AlarmLimit { limit: 50.0, priority: Some(AlarmPriority::Low), message: None, suppress_when: None }),
+                ..reactor_temperature_definition()
+            };
+            server::db::create_alarm_definition(&pool, &def).await.unwrap();
+        }
+        let critical = AlarmDefinition {
+            device_id: "plc-02".to_string(),
+            label: "Trip".to_string(),
+            hh: None,
+            h: Some(AlarmLimit { limit: 50.0, priority: Some(AlarmPriority::Critical), message: None, suppress_when: None }),
+            ..reactor_temperature_definition()
+        };
+        server::db::create_alarm_definition(&pool, &critical).await.unwrap();
+
+        // plc-01 and plc-02 share an area; more than 3 alarms in 10 min is a flood
+        let flood = AlarmFloodConfig {
+            threshold: 3,
+            suppress_below: AlarmPriority::High,
+            areas: HashMap::from([("reactor-hall".to_string(), vec!["plc-01".to_string(), "plc-02".to_string()])]),
+        };
+        let (tx, mut rx) = tokio::sync::broadcast::channel(16);
+        let mut plc01 = AlarmEngine::with_flood("plc-01", tx.clone(), flood.clone());
+        let mut plc02 = AlarmEngine::with_flood("plc-02", tx, flood);
+
+        let trip: HashMap<u16, f64> = (1028..1033).map(|r| (r, 60.0)).collect();
+        plc01.scan(&pool, &trip).await;
+        plc01.scan(&pool, &trip).await; // still in alarm — suppressions are not re-recorded
+
+        let (raised,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM alarms").fetch_one(&pool).await.unwrap();
+        assert_eq!(raised, 3);
+        let params = AlarmSuppressionQueryParams { device_id: None, reason: Some("flood".to_string()), limit: None };
+        let suppressed = server::db::list_alarm_suppressions(&pool, &params).await;
+        assert_eq!(suppressed.len(), 2);
+        assert_eq!(suppressed[0].priority, AlarmPriority::Low);
+        assert!(suppressed[0].detail.as_deref().unwrap().contains("reactor-hall"));
+
+        let floods = |msgs: &[serde_json::Value]| -> Vec<serde_json::Value> {
+            msgs.iter().filter(|m| m["type"] == "alarm_flood").cloned().collect()
+        };
+        let msgs = drain_ws(&mut rx);
+        let started = floods(&msgs);
+        assert_eq!(started.len(), 1, "flood start is broadcast once");
+        assert_eq!(started[0]["scope"], "reactor-hall");
+        assert_eq!(started[0]["active"], true);
+        assert_eq!(msgs.iter().filter(|m| m["event"] == "raise").count(), 3);
+
+        // High-priority alarms still get through during the flood, area-wide
+        plc02.scan(&pool, &HashMap::from([(1028, 60.0)])).await;
+        assert!(active_limit_alarm_id(&pool, "plc-02", 1028).await.is_some());
+        assert!(floods(&drain_ws(&mut rx)).is_empty(), "the area is already in flood");
+
+        // Still in flood on the next scan: 4 alarms raised in the area
+        plc01.scan(&pool, &trip).await;
+        assert!(floods(&drain_ws(&mut rx)).is_empty());
+
+        // 15 minutes on (scan clock) the raised alarms are out of the window;
+        // the recorded suppressions do not keep the flood alive
+        plc01.scan_at(&pool, &trip, chrono::Utc::now() + chrono::Duration::minutes(15)).await;
+        let ended = floods(&drain_ws(&mut rx));
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0]["active"], false);
+        // ...and the suppressed conditions that are still present are raised
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1032).await.is_some());
+
+        let (audited,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM audit_trail WHERE action IN ('alarm_flood_start', 'alarm_flood_end')",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(audited, 2);
+    }
+
+    #[tokio::test]
+    async fn test_alarm_flood_end_counts_raised_alarms_on_the_scan_clock() {
+        use server::alarms::AlarmEngine;
+        use server::config::{AlarmDefinition, AlarmFloodConfig, AlarmLimit};
+        use server::models::AlarmPriority;
+        use std::collections::HashMap;
+
+        // Five low-priority tags; more than 2 alarms in 10 min is a flood
+        let flooded = || async {
+            let pool = test_pool().await;
+            server::auth::init_auth_tables(&pool).await;
+            for register in 1028..1033 {
+                let def = AlarmDefinition {
+                    register,
+                    label: format!("Tag {register}"),
+                    hh: None,
+                    h: Some(AlarmLimit { limit: 50.0, priority: Some(AlarmPriority::Low), message: None, suppress_when: None }),
+                    ..reactor_temperature_definition()
+                };
+                server::db::create_alarm_definition(&pool, &def).await.unwrap();
+            }
+            let flood = AlarmFloodConfig { threshold: 2, suppress_below: AlarmPriority::High, areas: HashMap::new() };
+            let (tx, _rx) = tokio::sync::broadcast::channel(64);
+            let mut engine = AlarmEngine::with_flood("plc-01", tx, flood);
+            engine.scan(&pool, &(1028..1033).map(|r| (r, 60.0)).collect()).await;
+            assert!(server::db::get_active_flood(&pool, "plc-01").await.is_some());
+            (pool, engine)
+        };
+        let quiet: HashMap<u16, f64> = (1028..1033).map(|r| (r, 20.0)).collect();
+
+        // Raised alarms aged out; the three suppressions still in the window do not count
+        let (pool, mut engine) = flooded().await;
+        let old = (chrono::Utc::now() - chrono::Duration::minutes(15)).to_rfc3339();
+        sqlx::query("UPDATE alarms SET timestamp = ?").bind(&old).execute(&pool).await.unwrap();
+        engine.scan(&pool, &quiet).await;
+        assert!(server::db::get_active_flood(&pool, "plc-01").await.is_none());
+
+        // The window follows the scan clock, not the wall clock
+        let (pool, mut engine) = flooded().await;
+        engine.scan_at(&pool, &quiet, chrono::Utc::now() + chrono::Duration::minutes(1)).await;
+        assert!(server::db::get_active_flood(&pool, "plc-01").await.is_some());
+        engine.scan_at(&pool, &quiet, chrono::Utc::now() + chrono::Duration::minutes(15)).await;
+        assert!(server::db::get_active_flood(&pool, "plc-01").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_state_based_alarm_suppression() {
+        use server::alarms::{eval_condition, AlarmEngine};
+        use server::config::AlarmLimit;
+        use std::collections::HashMap;
+
+        let scan = HashMap::from([(1028, 15.0), (1032, 0.0)]);
+        assert!(eval_condition("1032 == 0", &scan).unwrap());
+        assert!(eval_condition("1032 >= 1 || 1028 < 20 && 1032 != 3", &scan).unwrap());
+        assert!(!eval_condition("1033 == 0", &scan).unwrap()); // not in the scan
+        assert!(eval_condition("1032 = 0", &scan).is_err());
+        assert!(eval_condition("idle == 0", &scan).is_err());
+
+        let mut bad = reactor_temperature_definition();
+        bad.suppress_when = Some("1032 ==".to_string());
+        assert!(bad.validate().is_err());
+
+        // Low temperature alarm suppressed while the batch is Idle
+        let pool = test_pool().await;
+        let def = server::config::AlarmDefinition {
+            l: Some(AlarmLimit { limit: 20.0, priority: None, message: None, suppress_when: Some("1032 == 0".to_string()) }),
+            ..reactor_temperature_definition()
+        };
+        def.validate().unwrap();
+        server::db::create_alarm_definition(&pool, &def).await.unwrap();
+        let stored = server::db::list_alarm_definitions(&pool, Some("plc-01")).await;
+        assert_eq!(stored[0].l.as_ref().unwrap().suppress_when.as_deref(), Some("1032 == 0"));
+
+        let (tx, _rx) = tokio::sync::broadcast::channel(16);
+        let mut engine = AlarmEngine::new("plc-01", tx);
+        engine.scan(&pool, &scan).await;
+        engine.scan(&pool, &scan).await; // still suppressed — recorded once
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_none());
+
+        // The high limit is not suppressed while Idle
+        engine.scan(&pool, &HashMap::from([(1028, 90.0), (1032, 0.0)])).await;
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_some());
+        engine.scan(&pool, &HashMap::from([(1028, 50.0), (1032, 0.0)])).await;
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_none());
+
+        // Heating: the low alarm is raised
+        engine.scan(&pool, &HashMap::from([(1028, 15.0), (1032, 1.0)])).await;
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_some());
+
+        let params = server::models::AlarmSuppressionQueryParams {
+            device_id: None,
+            reason: Some("state".to_string()),
+            limit: None,
+        };
+        let suppressions = server::db::list_alarm_suppressions(&pool, &params).await;
+        assert_eq!(suppressions.len(), 1);
+        assert_eq!(suppressions[0].detail.as_deref(), Some("Suppressed while 1032 == 0"));
+
+        // Disconnected device: suppression is recorded until polling resumes
+        server::db::set_device_alarms_suppressed(&pool, "plc-01", Some("disconnected")).await;
+        assert_eq!(server::db::get_device_alarm_suppression(&pool, "plc-01").await.as_deref(), Some("disconnected"));
+        server::db::set_device_alarms_suppressed(&pool, "plc-01", None).await;
+        assert!(server::db::get_device_alarm_suppression(&pool, "plc-01").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ws_alarm_event_envelope() {
+        use server::alarms::AlarmEngine;
+        use server::models::{PlcData, WsMessage};
+        use std::collections::HashMap;
+
+        // Readings keep their fields, tagged with a type
+        let reading = WsMessage::PlcData(PlcData {
+            device_id: "plc-01".to_string(),
+            register: 1028,
+            value: 72.5,
+            timestamp: chrono::Utc::now(),
+        });
+        let json: serde_json::Value = serde_json::from_str(&reading.to_json()).unwrap();
+        assert_eq!(json["type"], "plc_data");
+        assert_eq!(json["device_id"], "plc-01");
+        assert_eq!(json["value"], 72.5);
+
+        let pool = test_pool().await;
+        server::db::create_alarm_definition(&pool, &reactor_temperature_definition()).await.unwrap();
+        let (tx, mut rx) = tokio::sync::broadcast::channel(16);
+        let mut engine = AlarmEngine::new("plc-01", tx.clone());
+
+        engine.scan(&pool, &HashMap::from([(1028, 90.0)])).await;
+        let alarm_id = active_limit_alarm_id(&pool, "plc-01", 1028).await.unwrap();
+        server::db::ack_alarm(&pool, alarm_id, "operator", None).await.unwrap();
+        server::ws::publish_alarm_event(&tx, &pool, alarm_id, "ack").await;
+        engine.scan(&pool, &HashMap::from([(1028, 50.0)])).await;
+
+        let msgs = drain_ws(&mut rx);
+        let events: Vec<&str> = msgs.iter().map(|m| m["event"].as_str().unwrap()).collect();
+        assert_eq!(events, ["raise", "ack", "clear"]);
+        assert!(msgs.iter().all(|m| m["type"] == "alarm" && m["alarm"]["id"] == alarm_id));
+        assert_eq!(msgs[1]["alarm"]["acked_by"], "operator");
+        assert_eq!(msgs[2]["alarm"]["state"], "cleared");
+    }
+
+    /// Accept HTTP requests, answer 200 and forward each body.
+    async fn mock_webhook() -> (String, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks/alarms", listener.local_addr().unwrap());
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut sock, _)) = listener.accept().await {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                // Read until the full Content-Length body has arrived
+                loop {
+                    let n = sock.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&buf).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let len: usize = head
+                            .lines()
+                            .find_map(|l| l.strip_prefix("Content-Length: "))
+                            .unwrap()
+                            .parse()
+                            .unwrap();
+                        if body.len() >= len {
+                            tx.send(body.to_string()).unwrap();
+                            break;
+                        }
+                    }
+                    if n == 0 {
+                        break;
+                    }
+                }
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    /// Accept SMTP sessions and forward each message's DATA section.
+    async fn mock_smtp() -> (String, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((sock, _)) = listener.accept().await {
+                let mut reader = BufReader::new(sock);
+                reader.get_mut().write_all(b"220 mock ESMTP\r\n").await.unwrap();
+                let (mut in_data, mut data) = (false, String::new());
+                let mut line = String::new();
+                while reader.read_line(&mut line).await.unwrap() > 0 {
+                    let reply: &[u8] = if in_data {
+                        if line == ".\r\n" {
+                            in_data = false;
+                            tx.send(std::mem::take(&mut data)).unwrap();
+                            b"250 queued\r\n"
+                        } else {
+                            data.push_str(&line);
+                            b""
+                        }
+                    } else if line.starts_with("DATA") {
+                        in_data = true;
+                        b"354 go ahead\r\n"
+                    } else if line.starts_with("QUIT") {
+                        b"221 bye\r\n"
+                    } else {
+                        b"250 ok\r\n"
+                    };
+                    reader.get_mut().write_all(reply).await.unwrap();
+                    line.clear();
+                }
+            }
+        });
+        (relay, rx)
+    }
+
+    #[tokio::test]
+    async fn test_unacked_alarm_escalation() {
+        use server::config::{EscalationConfig, EscalationPolicy};
+        use server::models::{AlarmKind, AlarmPriority, RaiseAlarmRequest};
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let (url, mut hooks) = mock_webhook().await;
+        let (relay, mut mails) = mock_smtp().await;
+
+        let config = EscalationConfig {
+            smtp_relay: Some(relay),
+            policies: vec![EscalationPolicy {
+                name: "critical-to-supervisor".to_string(),
+                priority: AlarmPriority::Critical,
+                after_minutes: 5,
+                notify_role: "supervisor".to_string(),
+                webhook: Some(url),
+                email: vec!["supervisor@plant.local".to_string()],
+            }],
+            ..EscalationConfig::default()
+        };
+
+        let raise = |register: u16, priority: AlarmPriority| RaiseAlarmRequest {
+            device_id: "plc-01".to_string(),
+            register,
+            label: format!("Tag {register}"),
+            kind: AlarmKind::Limit,
+            priority,
+            value: 99.0,
+            threshold: 95.0,
+            message: "Limit exceeded".to_string(),
+        };
+        let critical = server::db::raise_alarm(&pool, &raise(1028, AlarmPriority::Critical)).await.unwrap();
+        let acked = server::db::raise_alarm(&pool, &raise(1029, AlarmPriority::Critical)).await.unwrap();
+        server::db::raise_alarm(&pool, &raise(1030, AlarmPriority::High)).await.unwrap();
+        server::db::ack_alarm(&pool, acked, "operator", None).await.unwrap();
+
+        // Not yet overdue
+        let now = chrono::Utc::now();
+        assert_eq!(server::escalation::check_escalations(&pool, &config, now).await, 0);
+
+        // Overdue: only the unacknowledged critical alarm, once per channel
+        let later = now + chrono::Duration::minutes(6);
+        assert_eq!(server::escalation::check_escalations(&pool, &config, later).await, 2);
+        assert_eq!(server::escalation::check_escalations(&pool, &config, later).await, 0);
+
+        let hook: serde_json::Value = serde_json::from_str(&hooks.recv().await.unwrap()).unwrap();
+        assert_eq!(hook["policy"], "critical-to-supervisor");
+        assert_eq!(hook["notify_role"], "supervisor");
+        assert_eq!(hook["alarm"]["id"], critical);
+        let mail = mails.recv().await.unwrap();
+        assert!(mail.contains("To: <supervisor@plant.local>"));
+        assert!(mail.contains(&format!("alarm #{critical}")));
+
+        let sent: Vec<(String, bool)> = sqlx::query_as(
+            "SELECT channel, success FROM alarm_notifications WHERE alarm_id = ? ORDER BY id",
+        )
+        .bind(critical)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(sent, [("webhook".to_string(), true), ("email".to_string(), true)]);
+
+        let audited: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM audit_trail WHERE action = 'alarm_notify'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(audited, 2);
+        let events = server::db::list_alarm_events(&pool, critical).await;
+        assert_eq!(events.iter().filter(|e| e.event == "notify").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_escalation_restarts_on_reactivation_and_retries_failed_deliveries() {
+        use server::config::{EscalationConfig, EscalationPolicy};
+        use server::models::{AlarmKind, AlarmPriority, RaiseAlarmRequest};
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let (url, mut hooks) = mock_webhook().await;
+        let (relay, mut mails) = mock_smtp().await;
+        let config = EscalationConfig {
+            smtp_relay: Some(relay),
+            policies: vec![EscalationPolicy {
+                name: "critical-to-supervisor".to_string(),
+                priority: AlarmPriority::Critical,
+                after_minutes: 5,
+                notify_role: "supervisor".to_string(),
+                webhook: Some(url),
+                email: vec!["supervisor@plant.local".to_string()],
+            }],
+            ..EscalationConfig::default()
+        };
+        let req = RaiseAlarmRequest {
+            device_id: "plc-01".to_string(),
+            register: 1028,
+            label: "Reactor jacket temperature 1028 °C".to_string(),
+            kind: AlarmKind::Limit,
+            priority: AlarmPriority::Critical,
+            value: 99.0,
+            threshold: 95.0,
+            message: "Limit exceeded".to_string(),
+        };
+        let critical = server::db::raise_alarm(&pool, &req).await.unwrap();
+        let now = chrono::Utc::now();
+        assert_eq!(server::escalation::check_escalations(&pool, &config, now + chrono::Duration::minutes(6)).await, 2);
+        hooks.recv().await.unwrap();
+        let mail = mails.recv().await.unwrap();
+
+        // Non-ASCII subject goes out as RFC 2047 encoded-words, folded
+        use base64::Engine;
+        let headers = mail.split("\r\n\r\n").next().unwrap().replace("\r\n ", " ");
+        let subject_line = headers.lines().find(|l| l.starts_with("Subject: ")).unwrap();
+        assert!(subject_line.is_ascii());
+        let subject: String = subject_line["Subject: ".len()..]
+            .split(' ')
+            .map(|w| {
+                let b64 = w.strip_prefix("=?UTF-8?B?").unwrap().strip_suffix("?=").unwrap();
+                assert!(w.len() <= 75);
+                String::from_utf8(base64::engine::general_purpose::STANDARD.decode(b64).unwrap()).unwrap()
+            })
+            .collect();
+        assert_eq!(
+            subject,
+            format!("[HMI] Unacknowledged CRITICAL alarm #{critical}: Reactor jacket temperature 1028 °C on plc-01")
+        );
+
+        // Un-shelving makes the alarm active again: the clock restarts there
+        server::db::shelve_alarm(&pool, critical, "operator", 60, "Sensor check").await.unwrap();
+        server::db::unshelve_alarm(&pool, critical, "operator").await.unwrap();
+        assert_eq!(server::escalation::check_escalations(&pool, &config, now + chrono::Duration::minutes(1)).await, 0);
+        let later = now + chrono::Duration::minutes(6);
+        assert_eq!(server::escalation::check_escalations(&pool, &config, later).await, 2);
+        hooks.recv().await.unwrap();
+        mails.recv().await.unwrap();
+
+        // Failed deliveries do not count as notified — the next check retries
+        let unreachable = EscalationConfig {
+            policies: vec![EscalationPolicy {
+                name: "critical-to-manager".to_string(),
+                priority: AlarmPriority::Critical,
+                after_minutes: 5,
+                notify_role: "manager".to_string(),
+                webhook: Some("http://127.0.0.1:1/hook".to_string()),
+                email: vec![],
+            }],
+            ..EscalationConfig::default()
+        };
+        assert_eq!(server::escalation::check_escalations(&pool, &unreachable, later).await, 1);
+        assert_eq!(server::escalation::check_escalations(&pool, &unreachable, later).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_device_suppression_only_holds_back_escalation() {
+        use server::alarms::AlarmEngine;
+        use server::config::{EscalationConfig, EscalationPolicy};
+        use server::models::AlarmPriority;
+        use std::collections::HashMap;
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        server::db::create_alarm_definition(&pool, &reactor_temperature_definition()).await.unwrap();
+        let (tx, _rx) = tokio::sync::broadcast::channel(16);
+        let mut engine = AlarmEngine::new("plc-01", tx);
+
+        // Scans only run while the device is connected, so the flag does not gate them
+        server::db::set_device_alarms_suppressed(&pool, "plc-01", Some("disconnected")).await;
+        engine.scan(&pool, &HashMap::from([(1028, 120.0)])).await;
+        assert!(active_limit_alarm_id(&pool, "plc-01", 1028).await.is_some());
+
+        // ...but a disconnected device's standing alarms are not escalated
+        let config = EscalationConfig {
+            policies: vec![EscalationPolicy {
+                name: "critical-to-manager".to_string(),
+                priority: AlarmPriority::Critical,
+                after_minutes: 5,
+                notify_role: "manager".to_string(),
+                webhook: Some("http://127.0.0.1:1/hook".to_string()),
+                email: vec![],
+            }],
+            ..EscalationConfig::default()
+        };
+        let later = chrono::Utc::now() + chrono::Duration::minutes(6);
+        assert_eq!(server::escalation::check_escalations(&pool, &config, later).await, 0);
+        server::db::set_device_alarms_suppressed(&pool, "plc-01", None).await;
+        assert_eq!(server::escalation::check_escalations(&pool, &config, later).await, 1);
     }
 
     #[tokio::test]
@@ -167,6 +1268,1479 @@ mod tests {
         assert_eq!(steps[0].name, "Mixing");
     }
 
+    #[tokio::test]
+    async fn test_alarms_stamped_with_running_batch() {
+        use server::models::{AlarmKind, AlarmPriority, RaiseAlarmRequest};
+
+        let pool = test_pool().await;
+        let raise = |device_id: &str, register: u16| RaiseAlarmRequest {
+            device_id: device_id.to_string(),
+            register,
+            label: "Temperature".to_string(),
+            kind: AlarmKind::Limit,
+            priority: AlarmPriority::High,
+            value: 88.0,
+            threshold: 85.0,
+            message: "Temperature high".to_string(),
+        };
+
+        let before = server::db::raise_alarm(&pool, &raise("plc-01", 1028)).await.unwrap();
+        server::db::create_batch(&pool, "BATCH-001", "Reactor Cycle", "plc-01", "system").await.unwrap();
+        let during = server::db::raise_alarm(&pool, &raise("plc-01", 1029)).await.unwrap();
+        let other_device = server::db::raise_alarm(&pool, &raise("plc-02", 1028)).await.unwrap();
+        server::db::update_batch_status(&pool, "BATCH-001", "completed", None).await.unwrap();
+        let after = server::db::raise_alarm(&pool, &raise("plc-01", 1030)).await.unwrap();
+
+        for id in [before, other_device, after] {
+            assert!(server::db::get_alarm(&pool, id).await.unwrap().batch_id.is_none());
+        }
+        let alarm = server::db::get_alarm(&pool, during).await.unwrap();
+        assert_eq!(alarm.batch_id.as_deref(), Some("BATCH-001"));
+
+        let batch_alarms = server::db::list_batch_alarms(&pool, "BATCH-001").await;
+        assert_eq!(batch_alarms.iter().map(|a| a.id).collect::<Vec<_>>(), [during]);
+    }
+
+    #[tokio::test]
+    async fn test_first_out_alarm_marker() {
+        use server::models::{AlarmKind, AlarmPriority, AlarmQueryParams, RaiseAlarmRequest};
+
+        let pool = test_pool().await;
+        let raise = |device_id: &str, register: u16| RaiseAlarmRequest {
+            device_id: device_id.to_string(),
+            register,
+            label: format!("Tag {register}"),
+            kind: AlarmKind::Limit,
+            priority: AlarmPriority::Critical,
+            value: 1.0,
+            threshold: 0.0,
+            message: "Interlock trip".to_string(),
+        };
+
+        // A trip: pressure first, then the consequential alarms
+        let cause = server::db::raise_alarm(&pool, &raise("plc-01", 1029)).await.unwrap();
+        let effect1 = server::db::raise_alarm(&pool, &raise("plc-01", 1028)).await.unwrap();
+        let effect2 = server::db::raise_alarm(&pool, &raise("plc-01", 1030)).await.unwrap();
+
+        let first_out = |id| {
+            let pool = pool.clone();
+            async move { server::db::get_alarm(&pool, id).await.unwrap().first_out }
+        };
+        assert!(first_out(cause).await);
+        assert!(!first_out(effect1).await);
+        assert!(!first_out(effect2).await);
+
+        let params = AlarmQueryParams {
+            device_id: Some("plc-01".to_string()),
+            state: None,
+            priority: None,
+            first_out: Some(true),
+            limit: None,
+        };
+        let listed = server::db::list_alarms(&pool, &params).await;
+        assert_eq!(listed.iter().map(|a| a.id).collect::<Vec<_>>(), [cause]);
+    }
+
+    #[tokio::test]
+    async fn test_first_out_needs_a_following_alarm_in_the_window() {
+        use server::models::{AlarmKind, AlarmPriority, RaiseAlarmRequest};
+
+        let pool = test_pool().await;
+        let raise = |device_id: &str, register: u16| RaiseAlarmRequest {
+            device_id: device_id.to_string(),
+            register,
+            label: format!("Tag {register}"),
+            kind: AlarmKind::Limit,
+            priority: AlarmPriority::Critical,
+            value: 1.0,
+            threshold: 0.0,
+            message: "Interlock trip".to_string(),
+        };
+        let first_out = |id| {
+            let pool = pool.clone();
+            async move { server::db::get_alarm(&pool, id).await.unwrap().first_out }
+        };
+
+        // A lone alarm is not a first-out...
+        let lone = server::db::raise_alarm(&pool, &raise("plc-02", 1028)).await.unwrap();
+        assert!(!first_out(lone).await);
+
+        // ...until a second alarm follows within the window
+        let follow = server::db::raise_alarm(&pool, &raise("plc-02", 1029)).await.unwrap();
+        assert!(first_out(lone).await);
+        assert!(!first_out(follow).await);
+
+        // A later alarm outside the window does not make an old one first-out
+        let stale = (chrono::Utc::now() - chrono::Duration::seconds(60)).to_rfc3339();
+        let old = server::db::raise_alarm(&pool, &raise("plc-03", 1028)).await.unwrap();
+        sqlx::query("UPDATE alarms SET timestamp = ? WHERE id = ?").bind(&stale).bind(old).execute(&pool).await.unwrap();
+        let next = server::db::raise_alarm(&pool, &raise("plc-03", 1029)).await.unwrap();
+        assert!(!first_out(old).await);
+        assert!(!first_out(next).await);
+    }
+
+    #[tokio::test]
+    async fn test_recipe_versioning_and_esig() {
+        use server::models::{RecipeRegisterWrite, RecipeRequest, RecipeStatus};
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+
+        // E-signature tokens are single-use and bound to the signer
+        let (token, _) = server::auth::issue_esig_token(&pool, "u-1", "operator", "Create recipe").await.unwrap();
+        assert!(server::auth::consume_esig(&pool, &token, "u-2").await.is_err());
+        let grant = server::auth::consume_esig(&pool, &token, "u-1").await.unwrap();
+        assert_eq!(grant.reason, "Create recipe");
+        assert!(server::auth::consume_esig(&pool, &token, "u-1").await.is_err());
+
+        let mut req = RecipeRequest {
+            name: "Ibuprofen".to_string(),
+            description: Some("Reactor cycle".to_string()),
+            target_temperature: 80.0,
+            hold_time_minutes: 30.0,
+            agitator_rpm: 120.0,
+            register_writes: vec![
+                RecipeRegisterWrite { register: 1028, value: 80 },
+                RecipeRegisterWrite { register: 1034, value: 120 },
+            ],
+            esig_token: String::new(),
+        };
+        req.validate().unwrap();
+        let v1 = server::db::create_recipe(&pool, &req, "operator").await.unwrap();
+        assert!(server::db::create_recipe(&pool, &req, "operator").await.is_err(), "name is unique");
+
+        req.hold_time_minutes = 45.0;
+        server::db::update_recipe(&pool, v1, &req, "operator").await.unwrap();
+        let recipe = server::db::get_recipe(&pool, v1).await.unwrap();
+        assert_eq!((recipe.version, recipe.status), (1, RecipeStatus::Draft));
+        assert_eq!(recipe.hold_time_minutes, 45.0);
+        assert_eq!(recipe.register_writes.len(), 2);
+
+        assert!(server::db::approve_recipe(&pool, v1, "operator").await.is_err(), "author cannot approve");
+        server::db::approve_recipe(&pool, v1, "admin").await.unwrap();
+        assert!(server::db::update_recipe(&pool, v1, &req, "operator").await.is_err(), "approved is frozen");
+        assert!(server::db::approve_recipe(&pool, v1, "admin").await.is_err());
+
+        // A new version starts as a draft copy; approving it obsoletes v1
+        let v2 = server::db::new_recipe_version(&pool, v1, "operator").await.unwrap();
+        let draft = server::db::get_recipe(&pool, v2).await.unwrap();
+        assert_eq!((draft.version, draft.status), (2, RecipeStatus::Draft));
+        assert_eq!(draft.hold_time_minutes, 45.0);
+        assert_eq!(server::db::get_approved_recipe(&pool, "Ibuprofen").await.unwrap().id, v1);
+
+        server::db::approve_recipe(&pool, v2, "admin").await.unwrap();
+        assert_eq!(server::db::get_recipe(&pool, v1).await.unwrap().status, RecipeStatus::Obsolete);
+        let approved = server::db::get_approved_recipe(&pool, "Ibuprofen").await.unwrap();
+        assert_eq!((approved.id, approved.approved_by.as_deref()), (v2, Some("admin")));
+
+        server::db::obsolete_recipe(&pool, v2, "admin").await.unwrap();
+        assert!(server::db::get_approved_recipe(&pool, "Ibuprofen").await.is_none());
+        assert!(server::db::obsolete_recipe(&pool, v2, "admin").await.is_err());
+
+        req.register_writes.push(RecipeRegisterWrite { register: 1028, value: 90 });
+        assert!(req.validate().is_err(), "duplicate register write");
+    }
+
+    #[tokio::test]
+    async fn test_refused_recipe_change_leaves_token_unspent() {
+        use axum::body::Body;
+        use server::models::{RecipeRequest, RecipeStatus};
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let (state, _) = fake_device_state(&pool, "plc-01", None).await;
+        let claims = operator_claims();
+        let req = RecipeRequest {
+            name: "Ibuprofen".to_string(),
+            description: None,
+            target_temperature: 80.0,
+            hold_time_minutes: 30.0,
+            agitator_rpm: 120.0,
+            register_writes: vec![],
+            esig_token: String::new(),
+        };
+        let v1 = server::db::create_recipe(&pool, &req, "operator").await.unwrap();
+        let signed = |body: serde_json::Value| {
+            axum::http::Request::builder()
+                .extension(claims.clone())
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let (token, _) = server::auth::issue_esig_token(&pool, &claims.user_id, &claims.sub, "Approve").await.unwrap();
+
+        // The author cannot approve, and the refusal does not spend the token
+        let response = server::routes::approve_recipe(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(v1),
+            signed(serde_json::json!({ "esig_token": token })),
+        )
+        .await;
+        let err = response.0.error.unwrap_or_default();
+        assert!(err.contains("cannot approve"), "{err}");
+
+        server::db::approve_recipe(&pool, v1, "admin").await.unwrap();
+        let response = server::routes::update_recipe(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(v1),
+            signed(serde_json::json!({
+                "name": "Ibuprofen",
+                "target_temperature": 80.0,
+                "hold_time_minutes": 45.0,
+                "agitator_rpm": 120.0,
+                "esig_token": token,
+            })),
+        )
+        .await;
+        let err = response.0.error.unwrap_or_default();
+        assert!(err.contains("Only draft recipes"), "{err}");
+        let response = server::routes::new_recipe_version(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(999),
+            signed(serde_json::json!({ "esig_token": token })),
+        )
+        .await;
+        assert_eq!(response.0.error.as_deref(), Some("Recipe not found"));
+        let response = server::routes::obsolete_recipe(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(999),
+            signed(serde_json::json!({ "esig_token": token })),
+        )
+        .await;
+        assert_eq!(response.0.error.as_deref(), Some("Recipe not found"));
+        assert!(server::auth::consume_esig(&pool, &token, &claims.user_id).await.is_ok());
+
+        // A repeated approval is refused and leaves the approved version in place
+        let v2 = server::db::new_recipe_version(&pool, v1, "operator").await.unwrap();
+        server::db::approve_recipe(&pool, v2, "admin").await.unwrap();
+        assert!(server::db::approve_recipe(&pool, v2, "qa").await.is_err());
+        assert_eq!(server::db::get_approved_recipe(&pool, "Ibuprofen").await.unwrap().id, v2);
+        assert_eq!(server::db::get_recipe(&pool, v1).await.unwrap().status, RecipeStatus::Obsolete);
+    }
+
+    /// App state with a fake `device_id` whose polling task answers every
+    /// write itself, failing read-back for `bad_register`. Returns the writes seen.
+    async fn fake_device_state(
+        pool: &sqlx::SqlitePool,
+        device_id: &str,
+        bad_register: Option<u16>,
+    ) -> (server::state::AppState, std::sync::Arc<std::sync::Mutex<Vec<(u16, u16, bool)>>>) {
+        let config = server::config::AppConfig::load("config.toml");
+        let device = config.devices.iter().find(|d| d.id == device_id).unwrap().clone();
+        let state = server::state::AppState::new(pool.clone(), config);
+
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (write_tx, mut write_rx) = tokio::sync::mpsc::channel::<server::state::WriteCommand>(32);
+        let log = seen.clone();
+        let task = tokio::spawn(async move {
+            while let Some(cmd) = write_rx.recv().await {
+                log.lock().unwrap().push((cmd.register, cmd.value, cmd.verify));
+                let result = if Some(cmd.register) == bad_register {
+                    Err(format!("Read-back mismatch on reg {}", cmd.register))
+                } else {
+                    Ok(())
+                };
+                let _ = cmd.response.send(result);
+            }
+        });
+        state.devices.write().await.insert(
+            device_id.to_string(),
+            server::state::DeviceHandle { write_tx, task, config: device },
+        );
+        (state, seen)
+    }
+
+    fn operator_claims() -> server::auth::Claims {
+        server::auth::Claims {
+            sub: "operator".to_string(),
+            role: "operator".to_string(),
+            user_id: "u-operator".to_string(),
+            exp: usize::MAX,
+            iat: 0,
+            session_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recipe_download_at_batch_start() {
+        use server::models::{BatchStatus, RecipeRegisterWrite, RecipeRequest, StartBatchRequest};
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let mut recipe = RecipeRequest {
+            name: "Ibuprofen".to_string(),
+            description: None,
+            target_temperature: 80.0,
+            hold_time_minutes: 30.0,
+            agitator_rpm: 120.0,
+            register_writes: vec![RecipeRegisterWrite { register: 1031, value: 40 }],
+            esig_token: String::new(),
+        };
+        let v1 = server::db::create_recipe(&pool, &recipe, "operator").await.unwrap();
+        server::db::approve_recipe(&pool, v1, "admin").await.unwrap();
+
+        let (state, seen) = fake_device_state(&pool, "plc-01", Some(1035)).await;
+        let start = StartBatchRequest { device_id: "plc-01".to_string(), recipe_name: Some("Ibuprofen".to_string()) };
+        let claims = operator_claims();
+
+        let record = server::batch::start_batch(&state, &start, &claims).await.unwrap();
+        assert_eq!(record.operator, "operator");
+        assert_eq!((record.recipe_id, record.recipe_version), (Some(v1), Some(1)));
+        // Named parameters go to their configured registers, then the register writes
+        assert_eq!(*seen.lock().unwrap(), [(1028, 80, true), (1034, 120, true), (1031, 40, true), (1032, 1, true)]);
+
+        let (_, steps) = server::db::get_batch_with_steps(&pool, &record.batch_id).await.unwrap();
+        assert_eq!((steps[0].name.as_str(), steps[0].status.as_str()), (server::batch::DOWNLOAD_STEP, "completed"));
+        let params: serde_json::Value = serde_json::from_str(steps[0].parameters.as_deref().unwrap()).unwrap();
+        assert_eq!(params["version"], 1);
+        assert_eq!(params["writes"][0]["parameter"], "target_temperature");
+        assert_eq!(params["writes"][1]["register"], 1034);
+        assert_eq!(params["writes"][1]["verified"], true);
+        assert!(params["writes"][2].get("parameter").is_none());
+        assert_eq!(params["not_downloaded"], serde_json::json!(["hold_time_minutes"]));
+        assert!(steps[0].result.as_deref().unwrap().contains("no register configured for hold_time_minutes"));
+
+        let writes: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM audit_trail WHERE action = 'write_register'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(writes, 3);
+
+        // One batch per device at a time
+        assert!(server::batch::start_batch(&state, &start, &claims).await.is_err());
+        server::db::update_batch_status(&pool, &record.batch_id, "completed", None).await.unwrap();
+
+        // Read-back failure part-way aborts the batch
+        recipe.register_writes.push(RecipeRegisterWrite { register: 1035, value: 70 });
+        let v2 = server::db::new_recipe_version(&pool, v1, "operator").await.unwrap();
+        server::db::update_recipe(&pool, v2, &recipe, "operator").await.unwrap();
+        server::db::approve_recipe(&pool, v2, "admin").await.unwrap();
+        let err = server::batch::start_batch(&state, &start, &claims).await.unwrap_err();
+        assert!(err.contains("1035"), "{err}");
+
+        let batches = server::db::list_batches(&pool, &server::models::BatchQueryParams {
+            device_id: Some("plc-01".to_string()),
+            status: None,
+            limit: None,
+        })
+        .await;
+        assert_eq!(batches[0].status, BatchStatus::Aborted);
+        let (_, steps) = server::db::get_batch_with_steps(&pool, &batches[0].batch_id).await.unwrap();
+        assert_eq!(steps[0].status, "failed");
+        let params: serde_json::Value = serde_json::from_str(steps[0].parameters.as_deref().unwrap()).unwrap();
+        assert_eq!(params["writes"].as_array().unwrap().len(), 4);
+        assert_eq!(params["writes"][3]["verified"], false);
+
+        // Registers the device does not allow are rejected before any write
+        recipe.register_writes = vec![RecipeRegisterWrite { register: 1029, value: 1000 }];
+        let v3 = server::db::new_recipe_version(&pool, v2, "operator").await.unwrap();
+        server::db::update_recipe(&pool, v3, &recipe, "operator").await.unwrap();
+        server::db::approve_recipe(&pool, v3, "admin").await.unwrap();
+        let before = seen.lock().unwrap().len();
+        assert!(server::batch::start_batch(&state, &start, &claims).await.is_err());
+        assert_eq!(seen.lock().unwrap().len(), before);
+    }
+
+    #[tokio::test]
+    async fn test_recipe_parameters_download_to_setpoint_registers() {
+        use server::config::RecipeRegisters;
+        use server::models::{RecipeRegisterWrite, RecipeRequest};
+
+        let pool = test_pool().await;
+        let (state, seen) = fake_device_state(&pool, "plc-01", None).await;
+        let write_tx = state.devices.read().await["plc-01"].write_tx.clone();
+        let writable = [1028, 1031, 1034];
+        let registers = RecipeRegisters { target_temperature: Some(1028), hold_time_minutes: None, agitator_rpm: Some(1034) };
+        let mut req = RecipeRequest {
+            name: "Ibuprofen".to_string(),
+            description: None,
+            target_temperature: 79.6,
+            hold_time_minutes: 30.0,
+            agitator_rpm: 120.0,
+            register_writes: vec![RecipeRegisterWrite { register: 1031, value: 40 }],
+            esig_token: String::new(),
+        };
+        let id = server::db::create_recipe(&pool, &req, "operator").await.unwrap();
+        let recipe = server::db::get_recipe(&pool, id).await.unwrap();
+
+        // Mapped parameters are rounded and written first; unmapped ones are skipped
+        let (values, result) = server::batch::download_recipe(&write_tx, &writable, &recipe, &registers).await;
+        result.unwrap();
+        let written: Vec<_> = values.iter().map(|v| (v.parameter, v.register, v.value, v.verified)).collect();
+        assert_eq!(written, [
+            (Some("target_temperature"), 1028, 80, true),
+            (Some("agitator_rpm"), 1034, 120, true),
+            (None, 1031, 40, true),
+        ]);
+        assert_eq!(*seen.lock().unwrap(), [(1028, 80, true), (1034, 120, true), (1031, 40, true)]);
+
+        // A parameter register must be writable, must not clash with a
+        // register write, and the value must fit; nothing is written otherwise
+        let unwritable = RecipeRegisters { hold_time_minutes: Some(1035), ..registers.clone() };
+        let (values, result) = server::batch::download_recipe(&write_tx, &writable, &recipe, &unwritable).await;
+        assert!(values.is_empty());
+        assert!(result.unwrap_err().contains("1035 is not writable"));
+
+        req.register_writes = vec![RecipeRegisterWrite { register: 1028, value: 85 }];
+        server::db::update_recipe(&pool, id, &req, "operator").await.unwrap();
+        let recipe = server::db::get_recipe(&pool, id).await.unwrap();
+        let (_, result) = server::batch::download_recipe(&write_tx, &writable, &recipe, &registers).await;
+        assert!(result.unwrap_err().contains("set by both"));
+
+        req.register_writes.clear();
+        req.target_temperature = -5.0;
+        server::db::update_recipe(&pool, id, &req, "operator").await.unwrap();
+        let recipe = server::db::get_recipe(&pool, id).await.unwrap();
+        let (_, result) = server::batch::download_recipe(&write_tx, &writable, &recipe, &registers).await;
+        assert!(result.unwrap_err().contains("does not fit register 1028"));
+        assert_eq!(seen.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_refused_abort_leaves_token_unspent() {
+        use axum::body::Body;
+        use server::models::StartBatchRequest;
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let (state, seen) = fake_device_state(&pool, "plc-01", None).await;
+        let claims = operator_claims();
+        let start = StartBatchRequest { device_id: "plc-01".to_string(), recipe_name: None };
+        let id = server::batch::start_batch(&state, &start, &claims).await.unwrap().batch_id;
+        let (token, _) = server::auth::issue_esig_token(&pool, &claims.user_id, &claims.sub, "Abort").await.unwrap();
+        let abort = |batch_id: &str| {
+            let request = axum::http::Request::builder()
+                .extension(claims.clone())
+                .body(Body::from(serde_json::json!({ "esig_token": token }).to_string()))
+                .unwrap();
+            server::routes::abort_batch(
+                axum::extract::State(state.clone()),
+                axum::extract::Path(batch_id.to_string()),
+                request,
+            )
+        };
+
+        // Unknown batch, batch already ended, device gone: none spend the token
+        assert_eq!(abort("BATCH-missing").await.0.error.as_deref(), Some("Batch not found"));
+        server::db::update_batch_status(&pool, &id, "completed", None).await.unwrap();
+        let err = abort(&id).await.0.error.unwrap_or_default();
+        assert!(err.contains("while it is completed"), "{err}");
+        server::db::update_batch_status(&pool, &id, "running", None).await.unwrap();
+        let handle = state.devices.write().await.remove("plc-01").unwrap();
+        let err = abort(&id).await.0.error.unwrap_or_default();
+        assert!(err.contains("Unknown device"), "{err}");
+        assert_eq!(seen.lock().unwrap().len(), 1, "only the start command was written");
+
+        state.devices.write().await.insert("plc-01".to_string(), handle);
+        assert!(abort(&id).await.0.success);
+        assert!(server::auth::consume_esig(&pool, &token, &claims.user_id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_operator_batch_control() {
+        use axum::body::Body;
+        use server::models::{BatchStatus, StartBatchRequest};
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let (state, seen) = fake_device_state(&pool, "plc-01", None).await;
+        let claims = operator_claims();
+        let start = StartBatchRequest { device_id: "plc-01".to_string(), recipe_name: None };
+
+        let record = server::batch::start_batch(&state, &start, &claims).await.unwrap();
+        assert_eq!((record.operator.as_str(), record.recipe_name.as_str()), ("operator", server::batch::MANUAL_RECIPE));
+        assert_eq!(*seen.lock().unwrap(), [(1032, 1, true)]);
+        let id = record.batch_id.clone();
+
+        // Hold → resume, each commanded on the PLC
+        use server::batch::BatchCommand;
+        let held = server::batch::control_batch(&state, &id, BatchCommand::Hold, &claims, Some("Sampling")).await.unwrap();
+        assert_eq!(held.status, BatchStatus::Held);
+        assert!(server::db::get_running_batch(&pool, "plc-01").await.is_some(), "held batch stays active");
+        assert!(server::batch::control_batch(&state, &id, BatchCommand::Complete, &claims, None).await.is_err());
+        let resumed = server::batch::control_batch(&state, &id, BatchCommand::Resume, &claims, None).await.unwrap();
+        assert_eq!(resumed.status, BatchStatus::Running);
+        assert_eq!(seen.lock().unwrap()[1..], [(1032, 5, true), (1032, 1, true)]);
+
+        // Abort requires an e-signature
+        let abort = |body: serde_json::Value| {
+            axum::http::Request::builder()
+                .extension(claims.clone())
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let response = server::routes::abort_batch(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(id.clone()),
+            abort(serde_json::json!({ "reason": "no signature" })),
+        )
+        .await;
+        assert!(!response.0.success);
+
+        let (token, _) = server::auth::issue_esig_token(&pool, &claims.user_id, &claims.sub, "Contaminated charge")
+            .await
+            .unwrap();
+        let response = server::routes::abort_batch(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(id.clone()),
+            abort(serde_json::json!({ "esig_token": token })),
+        )
+        .await;
+        let aborted = response.0.data.expect("signed abort succeeds");
+        assert_eq!(aborted.status, BatchStatus::Aborted);
+        assert_eq!(aborted.ended_by.as_deref(), Some("operator"));
+        assert_eq!(aborted.notes.as_deref(), Some("Contaminated charge"));
+        assert!(aborted.end_time.is_some());
+        assert_eq!(seen.lock().unwrap().last(), Some(&(1032, 0, true)));
+
+        let actions: Vec<String> = sqlx::query_scalar("SELECT action FROM audit_trail WHERE action LIKE 'batch_%' ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(actions, ["batch_start", "batch_hold", "batch_resume", "batch_abort"]);
+
+        // A PLC that rejects the start command leaves the batch aborted
+        let (state, _) = fake_device_state(&pool, "plc-01", Some(1032)).await;
+        let err = server::batch::start_batch(&state, &start, &claims).await.unwrap_err();
+        assert!(err.contains("PLC start failed"), "{err}");
+        assert!(server::db::get_running_batch(&pool, "plc-01").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_batch_phase_steps_from_state_register() {
+        use server::batch::record_phase_transition;
+
+        let pool = test_pool().await;
+        let reading = |register: u16, value: f64| server::models::PlcData {
+            device_id: "plc-01".to_string(),
+            register,
+            value,
+            timestamp: chrono::Utc::now(),
+        };
+
+        // No active batch → transitions are ignored
+        record_phase_transition(&pool, "plc-01", 1).await;
+        let record_id = server::db::create_batch(&pool, "B-PHASE", "Reactor Cycle", "plc-01", "system").await.unwrap();
+
+        record_phase_transition(&pool, "plc-01", 1).await;
+        for (temp, press) in [(30.0, 1100.0), (50.0, 1200.0), (70.0, 1300.0)] {
+            server::db::save_plc_data(&pool, &reading(1028, temp)).await;
+            server::db::save_plc_data(&pool, &reading(1029, press)).await;
+        }
+        server::db::save_plc_data(&pool, &reading(1030, 99.0)).await; // not summarised
+        record_phase_transition(&pool, "plc-01", 2).await;
+        record_phase_transition(&pool, "plc-01", 4).await;
+
+        let (_, steps) = server::db::get_batch_with_steps(&pool, "B-PHASE").await.unwrap();
+        let names: Vec<_> = steps.iter().map(|s| (s.step_number, s.name.as_str(), s.status.as_str())).collect();
+        assert_eq!(names, [(1, "Heating", "completed"), (2, "Holding", "completed")]);
+        assert!(steps.iter().all(|s| s.end_time.is_some()));
+
+        let heating: serde_json::Value = serde_json::from_str(steps[0].result.as_deref().unwrap()).unwrap();
+        assert_eq!(heating["temperature"]["min"], 30.0);
+        assert_eq!(heating["temperature"]["max"], 70.0);
+        assert_eq!(heating["temperature"]["avg"], 50.0);
+        assert_eq!(heating["temperature"]["samples"], 3);
+        assert_eq!(heating["pressure"]["avg"], 1200.0);
+        assert!(heating.get("humidity").is_none());
+        let holding: serde_json::Value = serde_json::from_str(steps[1].result.as_deref().unwrap()).unwrap();
+        assert!(holding["temperature"].is_null(), "no readings while holding");
+
+        // Dropping to Idle mid-phase closes the step as aborted
+        server::db::update_batch_status(&pool, "B-PHASE", "completed", None).await.unwrap();
+        server::db::create_batch(&pool, "B-ESTOP", "Reactor Cycle", "plc-01", "system").await.unwrap();
+        record_phase_transition(&pool, "plc-01", 1).await;
+        record_phase_transition(&pool, "plc-01", 0).await;
+        let (_, steps) = server::db::get_batch_with_steps(&pool, "B-ESTOP").await.unwrap();
+        assert_eq!((steps.len(), steps[0].status.as_str()), (1, "aborted"));
+        assert!(server::db::get_open_batch_step(&pool, record_id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_batch_phase_steps_follow_download_until_complete() {
+        use server::batch::record_phase_transition;
+        use server::protocol::{BATCH_COMPLETE, BATCH_HEATING, BATCH_HELD};
+
+        let pool = test_pool().await;
+        let record_id = server::db::create_batch(&pool, "B-STEPS", "Reactor Cycle", "plc-01", "system").await.unwrap();
+        let download = server::db::add_batch_step(&pool, record_id, 1, server::batch::DOWNLOAD_STEP, None).await.unwrap();
+        server::db::complete_batch_step(&pool, download, "completed", None).await.unwrap();
+
+        // Phase steps are numbered after the download step
+        record_phase_transition(&pool, "plc-01", BATCH_HEATING).await;
+        let other = server::models::PlcData {
+            device_id: "plc-02".to_string(),
+            register: 1028,
+            value: 150.0,
+            timestamp: chrono::Utc::now(),
+        };
+        server::db::save_plc_data(&pool, &other).await;
+        record_phase_transition(&pool, "plc-01", BATCH_HELD).await;
+        // Complete closes the open step without opening another
+        record_phase_transition(&pool, "plc-01", BATCH_COMPLETE).await;
+
+        let (_, steps) = server::db::get_batch_with_steps(&pool, "B-STEPS").await.unwrap();
+        let names: Vec<_> = steps.iter().map(|s| (s.step_number, s.name.as_str(), s.status.as_str())).collect();
+        assert_eq!(names, [(1, server::batch::DOWNLOAD_STEP, "completed"), (2, "Heating", "completed"), (3, "Held", "completed")]);
+        let heating: serde_json::Value = serde_json::from_str(steps[1].result.as_deref().unwrap()).unwrap();
+        assert!(heating["temperature"].is_null(), "other devices' readings are not summarised");
+        let held: serde_json::Value = serde_json::from_str(steps[2].parameters.as_deref().unwrap()).unwrap();
+        assert_eq!(held["phase_code"], BATCH_HELD);
+        assert!(server::db::get_open_batch_step(&pool, record_id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_batch_id_format_and_persisted_counter() {
+        use chrono::TimeZone;
+        use server::batch::{format_batch_id, next_batch_id};
+
+        let at = chrono::Utc.with_ymd_and_hms(2026, 3, 7, 9, 0, 0).unwrap();
+        let id = format_batch_id("{device}-{yyyy}{mm}{dd}-{seq:04}", "plc-01", 42, at).unwrap();
+        assert_eq!(id, "plc-01-20260307-0042");
+        assert_eq!(format_batch_id("B{yy}/{hh}/{seq}", "x", 7, at).unwrap(), "B26/09/7");
+        assert!(format_batch_id("{device}-{lot}", "x", 1, at).is_err());
+        assert!(format_batch_id("{device}-{seq", "x", 1, at).is_err());
+
+        let pool = test_pool().await;
+        let template = "BATCH-{device}-{seq:04}";
+        // A record left over from before the counter existed is skipped
+        server::db::create_batch(&pool, "BATCH-plc-01-0002", "Reactor Cycle", "plc-01", "system").await.unwrap();
+
+        assert_eq!(next_batch_id(&pool, template, "plc-01").await.unwrap(), "BATCH-plc-01-0001");
+        assert_eq!(next_batch_id(&pool, template, "plc-01").await.unwrap(), "BATCH-plc-01-0003");
+        assert_eq!(next_batch_id(&pool, template, "plc-02").await.unwrap(), "BATCH-plc-02-0001");
+
+        // The counter lives in the database, not the polling task
+        server::db::init_db_with_pool(&pool).await;
+        assert_eq!(next_batch_id(&pool, template, "plc-01").await.unwrap(), "BATCH-plc-01-0004");
+    }
+
+    #[tokio::test]
+    async fn test_batch_record_report_html_and_pdf() {
+        use server::models::{RecipeRegisterWrite, RecipeRequest, StartBatchRequest};
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let recipe = RecipeRequest {
+            name: "Paracetamol <Lot A>".to_string(),
+            // Wide capitals: wraps far sooner than an average-width estimate allows
+            description: Some(format!("Granulation {}", "WMWMW MWMWM ".repeat(12))),
+            target_temperature: 75.0,
+            hold_time_minutes: 20.0,
+            agitator_rpm: 150.0,
+            register_writes: vec![RecipeRegisterWrite { register: 1031, value: 35 }],
+            esig_token: String::new(),
+        };
+        let id = server::db::create_recipe(&pool, &recipe, "operator").await.unwrap();
+        server::db::approve_recipe(&pool, id, "qa").await.unwrap();
+
+        let (state, _) = fake_device_state(&pool, "plc-01", None).await;
+        let start = StartBatchRequest { device_id: "plc-01".to_string(), recipe_name: Some(recipe.name.clone()) };
+        let record = server::batch::start_batch(&state, &start, &operator_claims()).await.unwrap();
+
+        server::batch::record_phase_transition(&pool, "plc-01", 1).await;
+        for temp in [40.0, 60.0] {
+            let data = server::models::PlcData {
+                device_id: "plc-01".to_string(),
+                register: 1028,
+                value: temp,
+                timestamp: chrono::Utc::now(),
+            };
+            server::db::save_plc_data(&pool, &data).await;
+        }
+        let temperature_high = server::models::RaiseAlarmRequest {
+            device_id: "plc-01".to_string(),
+            register: 1028,
+            label: "Temperature".to_string(),
+            kind: server::models::AlarmKind::Limit,
+            priority: server::models::AlarmPriority::High,
+            value: 60.0,
+            threshold: 55.0,
+            message: "Temperature high".to_string(),
+        };
+        server::db::raise_alarm(&pool, &temperature_high).await.unwrap();
+        // A consequential alarm makes the temperature alarm the first-out
+        server::db::raise_alarm(&pool, &server::models::RaiseAlarmRequest {
+            register: 1029,
+            label: "Pressure".to_string(),
+            message: "Pressure high".to_string(),
+            ..temperature_high
+        })
+        .await
+        .unwrap();
+
+        assert!(server::report::build_batch_report(&pool, "NO-SUCH-BATCH", "qa").await.is_none());
+        let report = server::report::build_batch_report(&pool, &record.batch_id, "qa").await.unwrap();
+        assert_eq!(report.alarms.len(), 2);
+        assert_eq!(
+            report.audit.iter().map(|e| e.action.as_str()).collect::<Vec<_>>(),
+            ["write_register", "write_register", "write_register", "batch_start"]
+        );
+        assert_eq!(report.trends.len(), 1);
+        assert_eq!((report.trends[0].1.min, report.trends[0].1.max), (40.0, 60.0));
+        assert_eq!(report.signatures.len(), server::report::SIGNATURE_MEANINGS.len());
+
+        let html = server::report::render_html(&report);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains(&record.batch_id));
+        assert!(html.contains("Paracetamol &lt;Lot A&gt; v1"), "recipe name is escaped");
+        assert!(html.contains("Downloaded, verified"));
+        assert!(html.contains("No register configured"), "hold time has no register");
+        assert!(html.contains("Temperature high"));
+        assert!(html.contains("[FIRST OUT]"));
+        assert!(html.contains("Approved for release by (Quality Assurance)"));
+        for section in ["Batch Header", "Recipe and Parameters", "Step Timeline", "Alarms During the Batch",
+                        "Audit Trail", "Process Trend Summary", "Signatures"] {
+            assert!(html.contains(section), "missing section {section}");
+        }
+
+        let pdf = server::report::render_pdf(&report);
+        assert!(pdf.starts_with(b"%PDF-1.4"));
+        assert!(pdf.ends_with(b"%%EOF\n"));
+        let text = String::from_utf8(pdf.clone()).expect("PDF is written as ASCII");
+        assert!(text.contains(&format!("({})", record.batch_id)));
+        assert!(text.contains("Paracetamol <Lot A>"));
+
+        // Cross-reference table points at each object
+        let startxref: usize = text.rsplit("startxref\n").next().unwrap().lines().next().unwrap().parse().unwrap();
+        assert!(text[startxref..].starts_with("xref"));
+        let offsets: Vec<usize> = text[startxref..]
+            .lines()
+            .skip(3)
+            .take_while(|l| l.ends_with(" n "))
+            .map(|l| l[..10].parse().unwrap())
+            .collect();
+        assert!(!offsets.is_empty());
+        for (i, offset) in offsets.iter().enumerate() {
+            assert!(text[*offset..].starts_with(&format!("{} 0 obj", i + 1)));
+        }
+
+        // Every line of text stays inside its table column, field or page margin
+        let decode = |body: &str| {
+            let mut out = String::new();
+            let mut chars = body.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => match chars.next().unwrap() {
+                        d @ '0'..='7' => {
+                            let octal: String = [d, chars.next().unwrap(), chars.next().unwrap()].into_iter().collect();
+                            let code = u32::from_str_radix(&octal, 8).unwrap();
+                            out.push(match code {
+                                0x96 => '–',
+                                0x97 => '—',
+                                _ => char::from_u32(code).unwrap(),
+                            });
+                        }
+                        escaped => out.push(escaped),
+                    },
+                    c => out.push(c),
+                }
+            }
+            out
+        };
+        let right_margin = 595.0 - 40.0;
+        let mut wrapped_details = false;
+        for page in text.split("endstream").filter(|p| p.contains(" Tj ET")) {
+            let mut header: Vec<f64> = Vec::new();
+            let mut header_y = f64::NAN;
+            for line in page.lines() {
+                let Some(rest) = line.strip_prefix("BT /").and_then(|l| l.strip_suffix(") Tj ET")) else { continue };
+                let (head, body) = rest.split_once(" (").unwrap();
+                let head: Vec<&str> = head.split(' ').collect();
+                let (font, size) = (head[0], head[1].parse::<f64>().unwrap());
+                let (x, y) = (head[3].parse::<f64>().unwrap(), head[4].parse::<f64>().unwrap());
+                let body = decode(body);
+                let end = x + server::report::text_width(font, size, &body);
+                match font {
+                    // Table header: its cells give the column edges
+                    "F4" => {
+                        if y != header_y {
+                            header.clear();
+                            header_y = y;
+                        }
+                        header.push(x);
+                    }
+                    "F3" => {
+                        let column_end = header.iter().copied().find(|&h| h > x).map_or(right_margin, |h| h - 2.0);
+                        assert!(end <= column_end + 0.1, "'{body}' at {x} ends at {end}, past {column_end}");
+                        if body.starts_with("{\"batch_id\"") {
+                            wrapped_details = true;
+                            assert!(!body.ends_with('}'), "long details wrap onto more lines");
+                        }
+                    }
+                    _ => assert!(end <= right_margin + 0.1, "'{body}' at {x} ends at {end}"),
+                }
+            }
+        }
+        assert!(wrapped_details);
+    }
+
+    #[test]
+    fn test_pdf_text_width_uses_helvetica_metrics() {
+        use server::report::text_width;
+
+        // AFM advance widths, not an average: "W" is over four times "i"
+        assert!((text_width("F1", 10.0, "W") - 9.44).abs() < 1e-9);
+        assert!((text_width("F1", 10.0, "i") - 2.22).abs() < 1e-9);
+        assert!((text_width("F2", 10.0, "i") - 2.78).abs() < 1e-9);
+        assert!((text_width("F1", 9.0, "WMWMW") - 9.0 * (3.0 * 0.944 + 2.0 * 0.833)).abs() < 1e-9);
+        // Courier is monospaced; the arrow is drawn as two glyphs
+        assert!((text_width("F3", 7.5, "Wi→") - 7.5 * 0.6 * 4.0).abs() < 1e-9);
+        assert!((text_width("F1", 10.0, "—") - 10.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_batch_record_signatures() {
+        use axum::body::Body;
+        use server::batch::BatchCommand;
+        use server::models::{SignatureMeaning, StartBatchRequest};
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let (state, _) = fake_device_state(&pool, "plc-01", None).await;
+        let claims = operator_claims();
+        let start = StartBatchRequest { device_id: "plc-01".to_string(), recipe_name: None };
+        let id = server::batch::start_batch(&state, &start, &claims).await.unwrap().batch_id;
+
+        let sign = |meaning: &str, token: &str| {
+            axum::http::Request::builder()
+                .extension(claims.clone())
+                .body(Body::from(serde_json::json!({ "meaning": meaning, "esig_token": token }).to_string()))
+                .unwrap()
+        };
+        let esig = |reason: &'static str| {
+            let pool = pool.clone();
+            async move { server::auth::issue_esig_token(&pool, "u-operator", "operator", reason).await.unwrap().0 }
+        };
+
+        // Running batches cannot be signed
+        let response = server::routes::sign_batch(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(id.clone()),
+            sign("responsibility", &esig("Executed").await),
+        )
+        .await;
+        let err = response.0.error.unwrap_or_default();
+        assert!(err.contains("only ended batches"), "{err}");
+
+        server::batch::control_batch(&state, &id, BatchCommand::Complete, &claims, None).await.unwrap();
+        let response = server::routes::sign_batch(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(id.clone()),
+            sign("responsibility", &esig("Executed per recipe").await),
+        )
+        .await;
+        let signature = response.0.data.expect("signing an ended batch succeeds");
+        assert_eq!(signature.meaning, SignatureMeaning::Responsibility);
+        assert_eq!((signature.username.as_str(), signature.reason.as_str()), ("operator", "Executed per recipe"));
+        assert_eq!(signature.content_hash.len(), 64);
+        assert!(signature.valid);
+
+        // One signature per user and meaning; approval is admin-only
+        let response = server::routes::sign_batch(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(id.clone()),
+            sign("responsibility", &esig("Again").await),
+        )
+        .await;
+        let err = response.0.error.unwrap_or_default();
+        assert!(err.contains("already signed"), "{err}");
+        let response = server::routes::sign_batch(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(id.clone()),
+            sign("approval", &esig("Release").await),
+        )
+        .await;
+        let err = response.0.error.unwrap_or_default();
+        assert!(err.contains("Admin"), "{err}");
+
+        let report = server::report::build_batch_report(&pool, &id, "qa").await.unwrap();
+        assert_eq!(report.signatures[0].signer.as_deref(), Some("operator"));
+        assert!(report.signatures[1].signer.is_none());
+        assert!(server::report::render_html(&report).contains(&signature.content_hash));
+
+        // Changing the record afterwards invalidates the signature
+        server::db::update_batch_status(&pool, &id, "completed", Some("Edited after signing")).await.unwrap();
+        let signatures = server::batch::batch_signatures(&pool, &id).await;
+        assert_eq!(signatures.len(), 1);
+        assert!(!signatures[0].valid);
+
+        let signed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM audit_trail WHERE action = 'batch_sign'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(signed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_refused_batch_signature_leaves_token_unspent() {
+        use axum::body::Body;
+        use server::batch::BatchCommand;
+        use server::models::StartBatchRequest;
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let (state, _) = fake_device_state(&pool, "plc-01", None).await;
+        let claims = operator_claims();
+        let start = StartBatchRequest { device_id: "plc-01".to_string(), recipe_name: None };
+        let id = server::batch::start_batch(&state, &start, &claims).await.unwrap().batch_id;
+        let (token, _) = server::auth::issue_esig_token(&pool, &claims.user_id, &claims.sub, "Executed").await.unwrap();
+        let sign = |meaning: &str| {
+            let request = axum::http::Request::builder()
+                .extension(claims.clone())
+                .body(Body::from(serde_json::json!({ "meaning": meaning, "esig_token": token }).to_string()))
+                .unwrap();
+            server::routes::sign_batch(axum::extract::State(state.clone()), axum::extract::Path(id.clone()), request)
+        };
+
+        // Still running, then an approval by a non-admin: the token survives both
+        let err = sign("responsibility").await.0.error.unwrap_or_default();
+        assert!(err.contains("only ended batches"), "{err}");
+        server::batch::control_batch(&state, &id, BatchCommand::Complete, &claims, None).await.unwrap();
+        let err = sign("approval").await.0.error.unwrap_or_default();
+        assert!(err.contains("Admin"), "{err}");
+        assert!(sign("responsibility").await.0.success);
+
+        // A duplicate signature is refused before the new token is spent
+        let (token, _) = server::auth::issue_esig_token(&pool, &claims.user_id, &claims.sub, "Again").await.unwrap();
+        let request = axum::http::Request::builder()
+            .extension(claims.clone())
+            .body(Body::from(serde_json::json!({ "meaning": "responsibility", "esig_token": token }).to_string()))
+            .unwrap();
+        let response = server::routes::sign_batch(axum::extract::State(state.clone()), axum::extract::Path(id.clone()), request).await;
+        let err = response.0.error.unwrap_or_default();
+        assert!(err.contains("already signed"), "{err}");
+        assert!(server::auth::consume_esig(&pool, &token, &claims.user_id).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_batch_review_by_exception() {
+        use axum::body::Body;
+        use server::batch::{BatchCommand, record_phase_transition};
+        use server::models::{BatchStatus, ExceptionKind, SignatureMeaning, StartBatchRequest};
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let (state, _) = fake_device_state(&pool, "plc-01", None).await;
+        let claims = operator_claims();
+        let limits = [server::config::ParameterLimit {
+            label: "Holding temperature".to_string(),
+            register: 1028,
+            phase: Some("Holding".to_string()),
+            min: Some(77.0),
+            max: Some(83.0),
+        }];
+        let reading = |value: f64| server::models::PlcData {
+            device_id: "plc-01".to_string(),
+            register: 1028,
+            value,
+            timestamp: chrono::Utc::now(),
+        };
+        let esig = |user_id: &'static str, username: &'static str, reason: &'static str| {
+            let pool = pool.clone();
+            async move { server::auth::issue_esig_token(&pool, user_id, username, reason).await.unwrap().0 }
+        };
+        let start = StartBatchRequest { device_id: "plc-01".to_string(), recipe_name: None };
+        let id = server::batch::start_batch(&state, &start, &claims).await.unwrap().batch_id;
+
+        // Heating below the holding range is fine; holding above it is not
+        record_phase_transition(&pool, "plc-01", 1).await;
+        server::db::save_plc_data(&pool, &reading(60.0)).await;
+        record_phase_transition(&pool, "plc-01", 2).await;
+        server::db::save_plc_data(&pool, &reading(80.0)).await;
+        server::db::save_plc_data(&pool, &reading(84.5)).await;
+        record_phase_transition(&pool, "plc-01", 3).await;
+        record_phase_transition(&pool, "plc-01", 0).await; // cooling aborted
+        let details = serde_json::json!({ "register": 1034, "value": 200 }).to_string();
+        server::auth::log_audit(&pool, "u-operator", "operator", "write_register", Some("plc-01"), &details, None).await;
+        server::db::raise_alarm(&pool, &server::models::RaiseAlarmRequest {
+            device_id: "plc-01".to_string(),
+            register: 1028,
+            label: "Temperature".to_string(),
+            kind: server::models::AlarmKind::Limit,
+            priority: server::models::AlarmPriority::High,
+            value: 84.5,
+            threshold: 83.0,
+            message: "Temperature high".to_string(),
+        })
+        .await
+        .unwrap();
+
+        let err = server::review::start_review(&pool, &limits, &id, &claims).await.unwrap_err();
+        assert!(err.contains("only completed batches"), "{err}");
+        server::batch::control_batch(&state, &id, BatchCommand::Complete, &claims, None).await.unwrap();
+        let grant = server::auth::consume_esig(&pool, &esig("u-operator", "operator", "Executed").await, "u-operator")
+            .await
+            .unwrap();
+        server::batch::sign_batch(&pool, &id, SignatureMeaning::Responsibility, &claims, &grant).await.unwrap();
+
+        let exceptions = server::review::start_review(&pool, &limits, &id, &claims).await.unwrap();
+        let mut kinds: Vec<_> = exceptions.iter().map(|e| e.kind.as_str()).collect();
+        kinds.sort();
+        assert_eq!(kinds, ["aborted_step", "alarm", "manual_write", "out_of_range"]);
+        let out_of_range = exceptions.iter().find(|e| e.kind == ExceptionKind::OutOfRange).unwrap();
+        assert!(out_of_range.description.contains("max 84.5 above 83.0"), "{}", out_of_range.description);
+        assert!(exceptions.iter().any(|e| e.description.contains("Register 1034 set to 200")));
+        assert!(server::review::start_review(&pool, &limits, &id, &claims).await.is_err(), "already under review");
+
+        let admin = server::auth::Claims {
+            sub: "admin".to_string(),
+            role: "admin".to_string(),
+            user_id: "u-admin".to_string(),
+            exp: usize::MAX,
+            iat: 0,
+            session_id: None,
+        };
+        let approve = |token: String| {
+            axum::http::Request::builder()
+                .extension(admin.clone())
+                .body(Body::from(serde_json::json!({ "esig_token": token }).to_string()))
+                .unwrap()
+        };
+        let response = server::routes::approve_batch(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(id.clone()),
+            approve(esig("u-admin", "admin", "Release").await),
+        )
+        .await;
+        let err = response.0.error.unwrap_or_default();
+        assert!(err.contains("4 exception(s) still need sign-off"), "{err}");
+
+        // Reviewers sign off each exception once, but not the ones they caused
+        let manual_write = exceptions.iter().find(|e| e.kind == ExceptionKind::ManualWrite).unwrap();
+        assert_eq!(manual_write.caused_by.as_deref(), Some("operator"));
+        for exception in &exceptions {
+            let (reviewer, user_id, username) = if exception.id == manual_write.id {
+                (&admin, "u-admin", "admin")
+            } else {
+                (&claims, "u-operator", "operator")
+            };
+            let response = server::routes::sign_off_batch_exception(
+                axum::extract::State(state.clone()),
+                axum::extract::Path((id.clone(), exception.id)),
+                axum::http::Request::builder()
+                    .extension(reviewer.clone())
+                    .body(Body::from(serde_json::json!({ "esig_token": esig(user_id, username, "Assessed, no impact").await }).to_string()))
+                    .unwrap(),
+            )
+            .await;
+            let signed = response.0.data.expect("sign-off succeeds");
+            assert_eq!(signed.reviewed_by.as_deref(), Some(username));
+            assert_eq!(signed.comment.as_deref(), Some("Assessed, no impact"));
+        }
+        let grant = server::auth::consume_esig(&pool, &esig("u-operator", "operator", "Again").await, "u-operator")
+            .await
+            .unwrap();
+        assert!(server::review::sign_off_exception(&pool, &id, exceptions[0].id, &claims, &grant, None).await.is_err());
+
+        let response = server::routes::approve_batch(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(id.clone()),
+            approve(esig("u-admin", "admin", "Release").await),
+        )
+        .await;
+        let record = response.0.data.expect("approval succeeds once exceptions are signed off");
+        assert_eq!(record.status, BatchStatus::Approved);
+        assert_eq!((record.disposition_by.as_deref(), record.disposition_reason.as_deref()), (Some("admin"), Some("Release")));
+
+        // Review and release leave the earlier signature valid and add the approval
+        let signatures = server::batch::batch_signatures(&pool, &id).await;
+        assert_eq!(signatures.iter().map(|s| s.meaning).collect::<Vec<_>>(), [SignatureMeaning::Responsibility, SignatureMeaning::Approval]);
+        assert!(signatures.iter().all(|s| s.valid));
+
+        let grant = server::auth::consume_esig(&pool, &esig("u-admin", "admin", "Too late").await, "u-admin").await.unwrap();
+        let err = server::review::decide(&pool, &id, false, &admin, &grant, None).await.unwrap_err();
+        assert!(err.contains("not under review"), "{err}");
+
+        let report = server::report::build_batch_report(&pool, &id, "qa").await.unwrap();
+        let html = server::report::render_html(&report);
+        assert!(html.contains("Review by Exception"));
+        assert!(html.contains("Assessed, no impact"));
+        assert!(html.contains("APPROVED by admin"));
+
+        let actions: Vec<String> = sqlx::query_scalar(
+            "SELECT action FROM audit_trail WHERE action LIKE 'batch\\_%' ESCAPE '\\' AND action NOT IN ('batch_start', 'batch_complete', 'batch_sign') ORDER BY id",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(actions, ["batch_review_start", "batch_exception_signoff", "batch_exception_signoff",
+                             "batch_exception_signoff", "batch_exception_signoff", "batch_approve"]);
+    }
+
+    #[tokio::test]
+    async fn test_refused_review_decision_leaves_token_unspent() {
+        use axum::body::Body;
+        use server::batch::BatchCommand;
+        use server::models::{BatchStatus, SignatureMeaning, StartBatchRequest};
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let (state, _) = fake_device_state(&pool, "plc-01", None).await;
+        let claims = operator_claims();
+        let admin = server::auth::Claims {
+            sub: "admin".to_string(),
+            role: "admin".to_string(),
+            user_id: "u-admin".to_string(),
+            exp: usize::MAX,
+            iat: 0,
+            session_id: None,
+        };
+        let start = StartBatchRequest { device_id: "plc-01".to_string(), recipe_name: None };
+        let id = server::batch::start_batch(&state, &start, &claims).await.unwrap().batch_id;
+        let details = serde_json::json!({ "register": 1034, "value": 200 }).to_string();
+        server::auth::log_audit(&pool, "u-operator", "operator", "write_register", Some("plc-01"), &details, None).await;
+        server::batch::control_batch(&state, &id, BatchCommand::Complete, &claims, None).await.unwrap();
+        let signed = |who: &server::auth::Claims, token: &str| {
+            axum::http::Request::builder()
+                .extension(who.clone())
+                .body(Body::from(serde_json::json!({ "esig_token": token }).to_string()))
+                .unwrap()
+        };
+        let (token, _) = server::auth::issue_esig_token(&pool, "u-admin", "admin", "Release").await.unwrap();
+
+        // Not yet under review, then an exception still open: the token survives both
+        let response = server::routes::approve_batch(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(id.clone()),
+            signed(&admin, &token),
+        )
+        .await;
+        assert_eq!(response.0.error, Some(format!("Batch {id} is not under review")));
+        let exceptions = server::review::start_review(&pool, &[], &id, &claims).await.unwrap();
+        let response = server::routes::approve_batch(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(id.clone()),
+            signed(&admin, &token),
+        )
+        .await;
+        assert_eq!(response.0.error.as_deref(), Some("1 exception(s) still need sign-off"));
+
+        // The operator caused the manual write and cannot sign it off
+        let (own, _) = server::auth::issue_esig_token(&pool, "u-operator", "operator", "Assessed").await.unwrap();
+        let response = server::routes::sign_off_batch_exception(
+            axum::extract::State(state.clone()),
+            axum::extract::Path((id.clone(), exceptions[0].id)),
+            signed(&claims, &own),
+        )
+        .await;
+        let err = response.0.error.unwrap_or_default();
+        assert!(err.contains("cannot sign it off"), "{err}");
+        assert!(server::auth::consume_esig(&pool, &own, "u-operator").await.is_ok());
+
+        let grant = server::auth::consume_esig(&pool, &token, "u-admin").await.unwrap();
+        server::review::sign_off_exception(&pool, &id, exceptions[0].id, &admin, &grant, None).await.unwrap();
+
+        // A disposition that fails rolls back the approval signature with it
+        sqlx::query(
+            "CREATE TRIGGER block_release BEFORE UPDATE OF status ON batch_records
+             WHEN NEW.status = 'approved' BEGIN SELECT RAISE(ABORT, 'release blocked'); END",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert!(server::review::decide(&pool, &id, true, &admin, &grant, None).await.is_err());
+        assert!(server::batch::batch_signatures(&pool, &id).await.is_empty());
+        sqlx::query("DROP TRIGGER block_release").execute(&pool).await.unwrap();
+
+        let record = server::review::decide(&pool, &id, true, &admin, &grant, None).await.unwrap();
+        assert_eq!(record.status, BatchStatus::Approved);
+        let signatures = server::batch::batch_signatures(&pool, &id).await;
+        assert_eq!(signatures.iter().map(|s| s.meaning).collect::<Vec<_>>(), [SignatureMeaning::Approval]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_trends_bucket_from_batch_start() {
+        use server::tsdb::TimeSeriesStore;
+
+        let pool = test_pool().await;
+        let start: chrono::DateTime<chrono::Utc> = "2025-01-01T00:00:07Z".parse().unwrap();
+        for (secs, register, value) in [(0, 1028, 10.0), (9, 1028, 20.0), (10, 1028, 30.0), (25, 1028, 40.0), (5, 1029, 1013.0)] {
+            let timestamp = start + chrono::Duration::seconds(secs);
+            server::db::save_plc_data(&pool, &server::models::PlcData { device_id: "plc-01".to_string(), register, value, timestamp })
+                .await;
+        }
+
+        // Buckets count from the batch start, not the epoch, one register at a time
+        let store = server::tsdb::SqliteTimeSeries::new(pool.clone());
+        let end = (start + chrono::Duration::minutes(1)).to_rfc3339();
+        let buckets = store.aggregate_from("plc-01", 1028, &start.to_rfc3339(), &end, 10).await;
+        let points: Vec<_> = buckets.iter().map(|b| ((b.bucket_start - start).num_seconds(), b.avg, b.count)).collect();
+        assert_eq!(points, [(0, 15.0, 2), (10, 30.0, 1), (20, 40.0, 1)]);
+        let epoch = store.aggregate("plc-01", 1028, &start.to_rfc3339(), &end, 10).await;
+        assert_eq!(epoch.iter().map(|b| b.count).collect::<Vec<_>>(), [1, 2, 1]);
+
+        // Batches being compared never count towards their own golden envelope
+        for id in ["G1", "G2", "G3"] {
+            server::db::create_batch(&pool, id, "Reactor Cycle", "plc-01", "system").await.unwrap();
+            server::db::update_batch_status(&pool, id, "approved", None).await.unwrap();
+        }
+        let golden = server::db::list_approved_batches(&pool, "Reactor Cycle", &["G1"], 50).await;
+        assert_eq!(golden.iter().map(|b| b.batch_id.as_str()).collect::<Vec<_>>(), ["G3", "G2"]);
+    }
+
+    #[tokio::test]
+    async fn test_golden_batch_comparison() {
+        use server::models::BatchCompareParams;
+
+        let pool = test_pool().await;
+        let base: chrono::DateTime<chrono::Utc> = "2025-01-01T00:00:00Z".parse().unwrap();
+        let batch = |id: &'static str, device: &'static str, hours: i64, status: &'static str, values: Vec<(i64, f64)>| {
+            let pool = pool.clone();
+            async move {
+                server::db::create_batch(&pool, id, "Reactor Cycle", device, "system").await.unwrap();
+                let start = base + chrono::Duration::hours(hours);
+                let end = (status != "running").then(|| (start + chrono::Duration::minutes(30)).to_rfc3339());
+                sqlx::query("UPDATE batch_records SET start_time = ?, end_time = ?, status = ? WHERE batch_id = ?")
+                    .bind(start.to_rfc3339())
+                    .bind(end)
+                    .bind(status)
+                    .bind(id)
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+                for (secs, value) in values {
+                    let timestamp = start + chrono::Duration::seconds(secs);
+                    for (register, value) in [(1028, value), (1029, 1013.0)] {
+                        let data = server::models::PlcData { device_id: device.to_string(), register, value, timestamp };
+                        server::db::save_plc_data(&pool, &data).await;
+                    }
+                }
+            }
+        };
+        batch("G1", "plc-01", 0, "approved", vec![(0, 25.0), (10, 40.0), (20, 60.0)]).await;
+        batch("G2", "plc-01", 1, "approved", vec![(0, 27.0), (10, 42.0), (20, 62.0)]).await;
+        batch("G3", "plc-02", 2, "approved", vec![(0, 26.0), (10, 41.0), (20, 61.0)]).await;
+        batch("C1", "plc-01", 3, "completed", vec![(0, 90.0), (10, 90.0)]).await; // not golden
+        batch("R1", "plc-02", 4, "running", vec![(0, 25.0), (5, 27.0), (10, 50.0), (20, 72.0)]).await;
+
+        let params = |batch_ids: &str, registers: Option<&str>| BatchCompareParams {
+            batch_ids: batch_ids.to_string(),
+            registers: registers.map(str::to_string),
+            step_secs: None,
+            k: None,
+            recipe: None,
+        };
+        let store = server::tsdb::SqliteTimeSeries::new(pool.clone());
+        let cmp = server::golden::compare_batches(&pool, &store, &params("C1, R1", Some("1028"))).await.unwrap();
+        assert_eq!(cmp.step_secs, 10);
+        assert_eq!(cmp.envelope.batch_ids, ["G3", "G2", "G1"]);
+        let env = &cmp.envelope.registers[0];
+        assert_eq!((cmp.envelope.registers.len(), env.register), (1, 1028));
+        assert_eq!(env.points.iter().map(|p| p.offset_secs).collect::<Vec<_>>(), [0, 10, 20]);
+        assert_eq!((env.points[1].mean, env.points[1].std_dev, env.points[1].upper), (41.0, 1.0, 44.0));
+
+        // Batches overlay on time since their own start
+        let running = &cmp.batches[1];
+        assert_eq!(running.batch_id, "R1");
+        let points: Vec<_> = running.registers[0].points.iter().map(|p| (p.offset_secs, p.value)).collect();
+        assert_eq!(points, [(0, 26.0), (10, 50.0), (20, 72.0)]);
+
+        // The running batch leaves the envelope from 10 s on, as one excursion
+        let x = cmp.excursions.iter().find(|x| x.batch_id == "R1").unwrap();
+        assert_eq!(cmp.excursions.iter().filter(|x| x.batch_id == "R1").count(), 1);
+        assert_eq!((x.batch_id.as_str(), x.register, x.from_secs, x.to_secs, x.direction.as_str()), ("R1", 1028, 10, 20, "above"));
+        assert_eq!((x.worst_value, x.limit), (72.0, 64.0));
+
+        // Every register by default; a flat golden register has no spread to leave
+        let cmp = server::golden::compare_batches(&pool, &store, &params("R1", None)).await.unwrap();
+        assert_eq!(cmp.batches[0].registers.iter().map(|r| r.register).collect::<Vec<_>>(), [1028, 1029]);
+        assert!(cmp.excursions.iter().all(|x| x.register == 1028));
+
+        for (ids, registers, expected) in [("", None, "batch_ids"), ("NOPE", None, "not found"), ("R1", Some("temp"), "Invalid register")] {
+            let err = server::golden::compare_batches(&pool, &store, &params(ids, registers)).await.unwrap_err();
+            assert!(err.contains(expected), "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_material_lots_and_genealogy() {
+        use server::genealogy::{record_material, trace};
+        use server::models::{GenealogyDirection, LotQueryParams, MaterialDirection, RecordMaterialRequest};
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let claims = operator_claims();
+        for id in ["API-1", "TAB-1", "TAB-2", "OTHER"] {
+            server::db::create_batch(&pool, id, "Reactor Cycle", "plc-01", "operator").await.unwrap();
+        }
+        let req = |direction: MaterialDirection, material: &str, lot: &str, quantity: f64, expiry: Option<&str>| RecordMaterialRequest {
+            direction,
+            material: material.to_string(),
+            lot_number: lot.to_string(),
+            quantity,
+            unit: "kg".to_string(),
+            expiry: expiry.map(str::to_string),
+        };
+        use MaterialDirection::{Consumed, Produced};
+        for (batch, r) in [
+            ("API-1", req(Consumed, "Lactose", "L1", 100.0, Some("2099-12-31"))),
+            ("API-1", req(Consumed, "Starch", "S1", 20.0, None)),
+            ("API-1", req(Produced, "Granulate", "G1", 120.0, None)),
+            ("TAB-1", req(Consumed, "Granulate", "G1", 60.0, None)),
+            ("TAB-1", req(Produced, "Tablets", "T1", 58.5, None)),
+            ("TAB-2", req(Consumed, "Granulate", "G1", 60.0, None)),
+            ("TAB-2", req(Produced, "Tablets", "T2", 59.0, None)),
+            ("OTHER", req(Consumed, "Lactose", "L2", 10.0, None)),
+        ] {
+            record_material(&pool, batch, &r, &claims).await.unwrap();
+        }
+        let materials = server::db::list_batch_materials(&pool, "API-1").await;
+        assert_eq!(materials.len(), 3);
+        assert_eq!((materials[0].expiry.as_deref(), materials[0].recorded_by.as_str()), (Some("2099-12-31"), "operator"));
+
+        for (batch, r, expected) in [
+            ("OTHER", req(Consumed, "Lactose", "E1", 5.0, Some("2000-01-01")), "expired on 2000-01-01"),
+            ("OTHER", req(Consumed, "Lactose", "L1", 5.0, Some("2098-01-01")), "recorded with expiry 2099-12-31"),
+            ("OTHER", req(Consumed, "Lactose", "L1", 5.0, Some("31/12/2099")), "Invalid expiry"),
+            ("TAB-2", req(Produced, "Granulate", "G1", 1.0, None), "produced by batch API-1"),
+            ("OTHER", req(Consumed, "Lactose", "L1", 0.0, None), "quantity"),
+            ("NOPE", req(Consumed, "Lactose", "L1", 1.0, None), "not found"),
+        ] {
+            let err = record_material(&pool, batch, &r, &claims).await.unwrap_err();
+            assert!(err.contains(expected), "{err}");
+        }
+
+        let lot_id = |material: &'static str, lot: &'static str| {
+            let pool = pool.clone();
+            async move {
+                let params = LotQueryParams { material: Some(material.to_string()), lot_number: Some(lot.to_string()), limit: None };
+                server::db::list_lots(&pool, &params).await[0].id
+            }
+        };
+
+        // Recall: everything downstream of the lactose lot
+        let forward = trace(&pool, lot_id("Lactose", "L1").await, GenealogyDirection::Forward).await.unwrap();
+        let batches: Vec<_> = forward.batches.iter().map(|b| (b.batch_id.as_str(), b.depth)).collect();
+        assert_eq!(batches, [("API-1", 1), ("TAB-1", 2), ("TAB-2", 2)]);
+        let lots: Vec<_> = forward.lots.iter().map(|l| l.lot_number.as_str()).collect();
+        assert_eq!(lots, ["G1", "T1", "T2"]);
+        assert_eq!(forward.links.len(), 6);
+
+        // Origin of a tablet lot
+        let backward = trace(&pool, lot_id("Tablets", "T1").await, GenealogyDirection::Backward).await.unwrap();
+        let batches: Vec<_> = backward.batches.iter().map(|b| (b.batch_id.as_str(), b.depth)).collect();
+        assert_eq!(batches, [("TAB-1", 1), ("API-1", 2)]);
+        let lots: Vec<_> = backward.lots.iter().map(|l| l.lot_number.as_str()).collect();
+        assert_eq!(lots, ["G1", "L1", "S1"]);
+        assert!(trace(&pool, 9999, GenealogyDirection::Forward).await.is_none());
+
+        // Materials are part of the record: none can be added once it is in review
+        server::db::update_batch_status(&pool, "OTHER", "under_review", None).await.unwrap();
+        let err = record_material(&pool, "OTHER", &req(Consumed, "Starch", "S1", 1.0, None), &claims).await.unwrap_err();
+        assert!(err.contains("can no longer be recorded"), "{err}");
+        let report = server::report::build_batch_report(&pool, "API-1", "qa").await.unwrap();
+        assert!(server::report::render_html(&report).contains("Granulate"));
+
+        let audited: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM audit_trail WHERE action = 'batch_material'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(audited, 8);
+    }
+
+    #[tokio::test]
+    async fn test_refused_material_is_not_recorded() {
+        use server::genealogy::record_material;
+        use server::models::{MaterialDirection, RecordMaterialRequest};
+
+        let pool = test_pool().await;
+        server::auth::init_auth_tables(&pool).await;
+        let claims = operator_claims();
+        server::db::create_batch(&pool, "B-MAT", "Reactor Cycle", "plc-01", "operator").await.unwrap();
+        let req = |lot: &str, expiry: Option<&str>| RecordMaterialRequest {
+            direction: MaterialDirection::Consumed,
+            material: "Lactose".to_string(),
+            lot_number: lot.to_string(),
+            quantity: 5.0,
+            unit: "kg".to_string(),
+            expiry: expiry.map(str::to_string),
+        };
+
+        // An expired lot is refused before it is registered
+        let err = record_material(&pool, "B-MAT", &req("E1", Some("2000-01-01")), &claims).await.unwrap_err();
+        assert!(err.contains("expired on 2000-01-01"), "{err}");
+        assert!(server::db::find_lot(&pool, "Lactose", "E1").await.is_none());
+
+        // Once the record carries a signature it takes no more materials
+        let grant = server::auth::EsigGrant {
+            user_id: claims.user_id.clone(),
+            username: claims.sub.clone(),
+            reason: "Executed".to_string(),
+            signed_at: chrono::Utc::now().to_rfc3339(),
+        };
+        let hash = server::batch::batch_content_hash(&pool, "B-MAT").await.unwrap();
+        let meaning = server::models::SignatureMeaning::Responsibility;
+        server::db::insert_signature(&pool, server::batch::BATCH_RECORD_TYPE, "B-MAT", meaning, &grant, &hash).await.unwrap();
+        let err = record_material(&pool, "B-MAT", &req("L1", None), &claims).await.unwrap_err();
+        assert!(err.contains("has been signed"), "{err}");
+        assert!(server::db::find_lot(&pool, "Lactose", "L1").await.is_none());
+        assert!(server::db::list_batch_materials(&pool, "B-MAT").await.is_empty());
+        assert!(server::batch::batch_signatures(&pool, "B-MAT").await[0].valid, "the signed content is unchanged");
+    }
+
+    #[tokio::test]
+    async fn test_history_aggregate() {
+        use server::routes::HistoryAggregateParams;
+        use server::tsdb::{SqliteTimeSeries, TimeSeriesStore};
+
+        let pool = test_pool().await;
+        let base: chrono::DateTime<chrono::Utc> = "2025-01-01T00:00:00Z".parse().unwrap();
+        let at = |millis: i64| base + chrono::Duration::milliseconds(millis);
+        for (device, register, millis, value) in [
+            ("plc-01", 1028, 0, 10.0),
+            ("plc-01", 1028, 30_000, 20.0),
+            ("plc-01", 1028, 59_500, 30.0),
+            ("plc-01", 1028, 60_000, 5.0),
+            ("plc-01", 1028, 90_000, 15.0),
+            ("plc-01", 1029, 10_000, 999.0),
+            ("plc-02", 1028, 10_000, 999.0),
+        ] {
+            let data = server::models::PlcData { device_id: device.to_string(), register, value, timestamp: at(millis) };
+            server::db::save_plc_data(&pool, &data).await;
+        }
+
+        let store = SqliteTimeSeries::new(pool.clone());
+        let buckets = store.aggregate("plc-01", 1028, &base.to_rfc3339(), &at(600_000).to_rfc3339(), 60).await;
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].bucket_start, base);
+        let summary = |b: &server::models::AggregateBucket| (b.min, b.max, b.avg, b.first, b.last, b.count);
+        assert_eq!(summary(&buckets[0]), (10.0, 30.0, 20.0, 10.0, 30.0, 3));
+        assert_eq!(buckets[1].bucket_start, at(60_000));
+        assert_eq!(summary(&buckets[1]), (5.0, 15.0, 10.0, 5.0, 15.0, 2));
+
+        // Range bounds are inclusive and applied before bucketing
+        let buckets = store.aggregate("plc-01", 1028, &at(30_000).to_rfc3339(), &at(60_000).to_rfc3339(), 3600).await;
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(summary(&buckets[0]), (5.0, 30.0, 55.0 / 3.0, 20.0, 5.0, 3));
+
+        let (state, _) = fake_device_state(&pool, "plc-01", None).await;
+        let request = |from: i64, to: i64, bucket_secs: Option<i64>| {
+            axum::extract::Query(HistoryAggregateParams {
+                device_id: "plc-01".to_string(),
+                register: 1028,
+                from: Some(at(from).to_rfc3339()),
+                to: Some(at(to).to_rfc3339()),
+                bucket_secs,
+            })
+        };
+        let response = server::routes::get_history_aggregate(axum::extract::State(state.clone()), request(0, 120_000, Some(60))).await;
+        assert_eq!(response.0.data.unwrap().len(), 2);
+        // Default bucket: the 2-minute window in at most 500 buckets → 1 s
+        let response = server::routes::get_history_aggregate(axum::extract::State(state.clone()), request(0, 120_000, None)).await;
+        assert_eq!(response.0.data.unwrap().len(), 5);
+
+        let thirty_days = 30 * 24 * 3600 * 1000;
+        for (from, to, bucket_secs, expected) in [
+            (0, 120_000, Some(0), "at least 1"),
+            (120_000, 0, None, "before"),
+            (0, thirty_days, Some(1), "Too many buckets"),
+        ] {
+            let response = server::routes::get_history_aggregate(axum::extract::State(state.clone()), request(from, to, bucket_secs)).await;
+            let err = response.0.error.unwrap_or_default();
+            assert!(err.contains(expected), "{err}");
+        }
+    }
+
     // ─────────────────────────────────────────────────────────
     // Auth Tests
     // ─────────────────────────────────────────────────────────
@@ -10,7 +10,7 @@ Registers (Holding Registers, address 1028-1035):
   1029 = Pressure (mbar)          [READ-ONLY — driven by simulation]
   1030 = Humidity (%)              [READ-ONLY — driven by simulation]
   1031 = Flow Rate (L/min)         [READ-ONLY — driven by simulation]
-  1032 = Batch State               [WRITABLE — 0=IDLE resets batch]
+  1032 = Batch State               [WRITABLE — 0=STOP 1=START/RESUME 4=COMPLETE 5=HOLD]
   1033 = Batch Progress (0-100%)   [READ-ONLY — driven by simulation]
   1034 = Agitator Speed (RPM)      [WRITABLE — operator override]
   1035 = pH Level (×10)            [READ-ONLY — driven by simulation]
@@ -37,6 +37,7 @@ HEATING = 1
 HOLDING = 2
 COOLING = 3
 COMPLETE = 4
+HELD = 5
 
 STATE_NAMES = {
     IDLE: "IDLE",
@@ -44,6 +45,7 @@ STATE_NAMES = {
     HOLDING: "HOLDING",
     COOLING: "COOLING",
     COMPLETE: "COMPLETE",
+    HELD: "HELD",
 }
 
 # --- Process Parameters ---
@@ -80,6 +82,7 @@ class BatchReactor:
         # Phase 4: operator overrides
         self.agitator_override = None  # None = simulation-driven, int = operator-set RPM
         self.emergency_stopped = False  # True = stay in IDLE, don't auto-restart
+        self.held_from = None  # (state, state_timer) frozen by an operator HOLD
         # Setpoint overrides (None = simulation-driven)
         self.temp_setpoint = None      # temperature override °C
         self.flow_setpoint = None      # flow rate override L/min
@@ -147,6 +150,11 @@ class BatchReactor:
                 self.batch_count += 1
                 self._transition(IDLE)
 
+        elif self.state == HELD:
+            # Phase frozen: feed stopped, temperature and progress held
+            self.flow_rate = 0
+            self.pressure = 1013.0 + (self.temperature - AMBIENT_TEMP) * 5 + random.gauss(0, 2)
+
     def _transition(self, new_state):
         print(f"  ⚙️  State: {STATE_NAMES[self.state]} → {STATE_NAMES[new_state]}")
         self.state = new_state
@@ -158,7 +166,8 @@ class BatchReactor:
         Writable registers:
           1028: Temperature — setpoint override °C (0 clears)
           1031: Flow Rate — setpoint override L/min (0 clears)
-          1032: Batch State — writing 0 forces IDLE (emergency stop)
+          1032: Batch State — 0 forces IDLE (emergency stop), 1 starts or
+                resumes, 4 completes, 5 holds the running phase
           1034: Agitator Speed — operator override RPM (0 clears)
           1035: pH Level — setpoint override x10 (0 clears)
         """
@@ -182,14 +191,28 @@ class BatchReactor:
             if value == IDLE:
                 print(f"  🛑 EMERGENCY STOP — operator forced IDLE (locked)")
                 self._transition(IDLE)
+                self.held_from = None
                 self.agitator_override = None  # clear agitator override too
                 self.emergency_stopped = True  # stay stopped until operator restarts
+            elif value == HEATING and self.state == HELD:
+                state, timer = self.held_from
+                print(f"  ▶️  OPERATOR RESUME — back to {STATE_NAMES[state]}")
+                self.state, self.state_timer = state, timer
+                self.held_from = None
             elif value == HEATING:
                 print(f"  ▶️  OPERATOR START — resuming batch from IDLE")
                 self.emergency_stopped = False
                 self._transition(HEATING)
+            elif value == HELD and self.state in (HEATING, HOLDING, COOLING):
+                print(f"  ⏸️  OPERATOR HOLD — {STATE_NAMES[self.state]} frozen")
+                self.held_from = (self.state, self.state_timer)
+                self._transition(HELD)
+            elif value == COMPLETE and self.state != IDLE:
+                print(f"  ✅ OPERATOR COMPLETE — batch ended")
+                self.held_from = None
+                self._transition(COMPLETE)
             else:
-                print(f"  ⚠️  Write to batch state ignored (only 0=STOP, 1=START allowed)")
+                print(f"  ⚠️  Write to batch state ignored (0=STOP, 1=START/RESUME, 4=COMPLETE, 5=HOLD)")
         elif register == 1034:
             if value == 0:
                 print(f"  🔄 Agitator override CLEARED — returning to auto")
@@ -300,7 +323,7 @@ if __name__ == "__main__":
     print("  1029 = Pressure (mbar)          [READ-ONLY]")
     print("  1030 = Humidity (%)              [READ-ONLY]")
     print("  1031 = Flow Rate (L/min)         [WRITABLE → setpoint override]")
-    print("  1032 = Batch State               [WRITABLE → 0=IDLE stops batch]")
+    print("  1032 = Batch State               [WRITABLE → 0=STOP 1=START/RESUME 4=COMPLETE 5=HOLD]")
     print("  1033 = Batch Progress (0-100%)   [READ-ONLY]")
     print("  1034 = Agitator Speed (RPM)      [WRITABLE → operator override]")
     print("  1035 = pH Level (×10)            [WRITABLE → setpoint override]")